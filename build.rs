@@ -0,0 +1,34 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Writes the versions of the embedded migrations to `migration_versions.rs`, so the server can
+/// compare them with the ones the database has run
+fn main() {
+    let dir = if env::var_os("CARGO_FEATURE_POSTGRES").is_some() {
+        "migrations_postgres"
+    } else {
+        "migrations"
+    };
+    println!("cargo:rerun-if-changed={}", dir);
+
+    // Same as diesel_migrations: the part of the directory name before the first `_`, without `-`
+    let mut versions: Vec<String> = fs::read_dir(dir)
+        .expect("could not read the migrations directory")
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let version = name.split('_').next()?.replace('-', "");
+            Some(version)
+        })
+        .collect();
+    versions.sort();
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("migration_versions.rs");
+    fs::write(
+        out,
+        format!("pub const MIGRATION_VERSIONS: &[&str] = &{:?};\n", versions),
+    )
+    .unwrap();
+}
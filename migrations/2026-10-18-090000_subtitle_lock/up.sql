@@ -0,0 +1,17 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS "subtitle_lock" (
+	"id"	INTEGER NOT NULL UNIQUE,
+	"project"	INTEGER NOT NULL,
+	"owner"	INTEGER NOT NULL,
+	"subtitle"	INTEGER,
+	"start"	INTEGER,
+	"end"	INTEGER,
+	"expires"	BIGINT NOT NULL,
+	FOREIGN KEY("project") REFERENCES "project"("id") ON DELETE CASCADE,
+	FOREIGN KEY("owner") REFERENCES "user"("id") ON DELETE CASCADE,
+	FOREIGN KEY("subtitle") REFERENCES "subtitle"("id") ON DELETE CASCADE,
+	PRIMARY KEY("id" AUTOINCREMENT)
+);
+CREATE INDEX IF NOT EXISTS "subtitle_lock_project_index" ON "subtitle_lock" (
+	"project"
+);
@@ -0,0 +1,22 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS "subtitle_revision" (
+	"id"	INTEGER NOT NULL UNIQUE,
+	"project"	INTEGER NOT NULL,
+	"subtitle"	INTEGER NOT NULL,
+	"user"	INTEGER,
+	"timestamp"	BIGINT NOT NULL,
+	"action"	TEXT NOT NULL,
+	"before_start"	INTEGER,
+	"before_end"	INTEGER,
+	"before_text"	TEXT,
+	"after_start"	INTEGER,
+	"after_end"	INTEGER,
+	"after_text"	TEXT,
+	FOREIGN KEY("project") REFERENCES "project"("id") ON DELETE CASCADE,
+	FOREIGN KEY("user") REFERENCES "user"("id") ON DELETE SET NULL,
+	PRIMARY KEY("id" AUTOINCREMENT)
+);
+CREATE INDEX IF NOT EXISTS "subtitle_revision_index" ON "subtitle_revision" (
+	"project",
+	"subtitle"
+);
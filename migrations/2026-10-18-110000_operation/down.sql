@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "subtitle_revision" DROP COLUMN "operation";
+DROP TABLE IF EXISTS "operation";
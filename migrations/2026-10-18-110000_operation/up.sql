@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS "operation" (
+	"id"	INTEGER NOT NULL UNIQUE,
+	"project"	INTEGER NOT NULL,
+	"user"	INTEGER NOT NULL,
+	"timestamp"	BIGINT NOT NULL,
+	"undone"	INTEGER NOT NULL DEFAULT 0,
+	FOREIGN KEY("project") REFERENCES "project"("id") ON DELETE CASCADE,
+	FOREIGN KEY("user") REFERENCES "user"("id") ON DELETE CASCADE,
+	PRIMARY KEY("id" AUTOINCREMENT)
+);
+CREATE INDEX IF NOT EXISTS "operation_index" ON "operation" (
+	"project",
+	"user"
+);
+ALTER TABLE "subtitle_revision" ADD COLUMN "operation" INTEGER REFERENCES "operation"("id") ON DELETE SET NULL;
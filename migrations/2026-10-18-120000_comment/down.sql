@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS "comment_mention";
+DROP TABLE IF EXISTS "comment";
@@ -0,0 +1,30 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS "comment" (
+	"id"	INTEGER NOT NULL UNIQUE,
+	"project"	INTEGER NOT NULL,
+	"parent"	INTEGER,
+	"author"	INTEGER NOT NULL,
+	"subtitle"	INTEGER,
+	"start"	INTEGER,
+	"end"	INTEGER,
+	"text"	TEXT NOT NULL,
+	"created"	BIGINT NOT NULL,
+	"edited"	BIGINT,
+	"resolved"	INTEGER NOT NULL DEFAULT 0,
+	"resolved_by"	INTEGER,
+	FOREIGN KEY("project") REFERENCES "project"("id") ON DELETE CASCADE,
+	FOREIGN KEY("parent") REFERENCES "comment"("id") ON DELETE CASCADE,
+	FOREIGN KEY("author") REFERENCES "user"("id") ON DELETE CASCADE,
+	FOREIGN KEY("resolved_by") REFERENCES "user"("id") ON DELETE SET NULL,
+	PRIMARY KEY("id" AUTOINCREMENT)
+);
+CREATE INDEX IF NOT EXISTS "comment_project_index" ON "comment" (
+	"project"
+);
+CREATE TABLE IF NOT EXISTS "comment_mention" (
+	"comment"	INTEGER NOT NULL,
+	"user"	INTEGER NOT NULL,
+	FOREIGN KEY("comment") REFERENCES "comment"("id") ON DELETE CASCADE,
+	FOREIGN KEY("user") REFERENCES "user"("id") ON DELETE CASCADE,
+	PRIMARY KEY("comment", "user")
+);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS "subtitle_status_change";
+ALTER TABLE "subtitle" DROP COLUMN "status";
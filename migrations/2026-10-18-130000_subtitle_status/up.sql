@@ -0,0 +1,18 @@
+-- Your SQL goes here
+ALTER TABLE "subtitle" ADD COLUMN "status" TEXT NOT NULL DEFAULT 'draft';
+CREATE TABLE IF NOT EXISTS "subtitle_status_change" (
+	"id"	INTEGER NOT NULL UNIQUE,
+	"project"	INTEGER NOT NULL,
+	"subtitle"	INTEGER NOT NULL,
+	"user"	INTEGER,
+	"timestamp"	BIGINT NOT NULL,
+	"from"	TEXT NOT NULL,
+	"to"	TEXT NOT NULL,
+	FOREIGN KEY("project") REFERENCES "project"("id") ON DELETE CASCADE,
+	FOREIGN KEY("user") REFERENCES "user"("id") ON DELETE SET NULL,
+	PRIMARY KEY("id" AUTOINCREMENT)
+);
+CREATE INDEX IF NOT EXISTS "subtitle_status_change_index" ON "subtitle_status_change" (
+	"project",
+	"subtitle"
+);
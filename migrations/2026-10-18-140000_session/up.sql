@@ -0,0 +1,15 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS "session" (
+	"id"	INTEGER NOT NULL UNIQUE,
+	"token"	TEXT NOT NULL UNIQUE,
+	"user"	INTEGER NOT NULL,
+	"created"	BIGINT NOT NULL,
+	"last_seen"	BIGINT NOT NULL,
+	"user_agent"	TEXT,
+	"expires"	BIGINT NOT NULL,
+	FOREIGN KEY("user") REFERENCES "user"("id") ON DELETE CASCADE,
+	PRIMARY KEY("id" AUTOINCREMENT)
+);
+CREATE INDEX IF NOT EXISTS "session_user_index" ON "session" (
+	"user"
+);
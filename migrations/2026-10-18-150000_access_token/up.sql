@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS "access_token" (
+	"id"	INTEGER NOT NULL UNIQUE,
+	"user"	INTEGER NOT NULL,
+	"name"	TEXT NOT NULL,
+	"token"	TEXT NOT NULL UNIQUE,
+	"scopes"	TEXT NOT NULL,
+	"created"	BIGINT NOT NULL,
+	"expires"	BIGINT,
+	"last_used"	BIGINT,
+	FOREIGN KEY("user") REFERENCES "user"("id") ON DELETE CASCADE,
+	PRIMARY KEY("id" AUTOINCREMENT)
+);
+CREATE INDEX IF NOT EXISTS "access_token_user_index" ON "access_token" (
+	"user"
+);
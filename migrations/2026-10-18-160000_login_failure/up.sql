@@ -0,0 +1,8 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS "login_failure" (
+	"key"	TEXT NOT NULL UNIQUE,
+	"failures"	INTEGER NOT NULL,
+	"last_failure"	BIGINT NOT NULL,
+	"locked_until"	BIGINT,
+	PRIMARY KEY("key")
+);
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS "recovery_code";
+ALTER TABLE "session" DROP COLUMN "pending";
+ALTER TABLE "workspace" DROP COLUMN "require_2fa";
+ALTER TABLE "user" DROP COLUMN "totp_last_step";
+ALTER TABLE "user" DROP COLUMN "totp_enabled";
+ALTER TABLE "user" DROP COLUMN "totp_secret";
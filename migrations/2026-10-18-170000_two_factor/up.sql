@@ -0,0 +1,16 @@
+-- Your SQL goes here
+ALTER TABLE "user" ADD COLUMN "totp_secret" TEXT;
+ALTER TABLE "user" ADD COLUMN "totp_enabled" INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE "user" ADD COLUMN "totp_last_step" BIGINT;
+ALTER TABLE "workspace" ADD COLUMN "require_2fa" INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE "session" ADD COLUMN "pending" INTEGER NOT NULL DEFAULT 0;
+CREATE TABLE IF NOT EXISTS "recovery_code" (
+	"id"	INTEGER NOT NULL UNIQUE,
+	"user"	INTEGER NOT NULL,
+	"code"	TEXT NOT NULL,
+	FOREIGN KEY("user") REFERENCES "user"("id") ON DELETE CASCADE,
+	PRIMARY KEY("id" AUTOINCREMENT)
+);
+CREATE INDEX IF NOT EXISTS "recovery_code_index" ON "recovery_code" (
+	"user"
+);
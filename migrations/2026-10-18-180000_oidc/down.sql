@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS "user_oidc_subject_index";
+ALTER TABLE "user" DROP COLUMN "oidc_subject";
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE "user" ADD COLUMN "oidc_subject" TEXT;
+CREATE UNIQUE INDEX IF NOT EXISTS "user_oidc_subject_index" ON "user" (
+	"oidc_subject"
+);
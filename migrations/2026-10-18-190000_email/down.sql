@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS "email_token";
+DROP TABLE IF EXISTS "email_outbox";
+ALTER TABLE "user" DROP COLUMN "email_verified";
@@ -0,0 +1,28 @@
+-- Your SQL goes here
+ALTER TABLE "user" ADD COLUMN "email_verified" INTEGER NOT NULL DEFAULT 0;
+CREATE TABLE IF NOT EXISTS "email_outbox" (
+	"id"	INTEGER NOT NULL UNIQUE,
+	"recipient"	TEXT NOT NULL,
+	"subject"	TEXT NOT NULL,
+	"body"	TEXT NOT NULL,
+	"created"	BIGINT NOT NULL,
+	"attempts"	INTEGER NOT NULL DEFAULT 0,
+	"next_attempt"	BIGINT NOT NULL,
+	"last_error"	TEXT,
+	PRIMARY KEY("id" AUTOINCREMENT)
+);
+CREATE TABLE IF NOT EXISTS "email_token" (
+	"id"	INTEGER NOT NULL UNIQUE,
+	"user"	INTEGER NOT NULL,
+	"purpose"	TEXT NOT NULL,
+	"token"	TEXT NOT NULL UNIQUE,
+	"email"	TEXT,
+	"created"	BIGINT NOT NULL,
+	"expires"	BIGINT NOT NULL,
+	FOREIGN KEY("user") REFERENCES "user"("id") ON DELETE CASCADE,
+	PRIMARY KEY("id" AUTOINCREMENT)
+);
+CREATE INDEX IF NOT EXISTS "email_token_index" ON "email_token" (
+	"user",
+	"purpose"
+);
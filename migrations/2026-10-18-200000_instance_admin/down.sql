@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS "invitation";
+DROP TABLE IF EXISTS "setting";
+ALTER TABLE "user" DROP COLUMN "disabled";
+ALTER TABLE "user" DROP COLUMN "instance_admin";
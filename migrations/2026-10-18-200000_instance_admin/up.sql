@@ -0,0 +1,21 @@
+-- Your SQL goes here
+ALTER TABLE "user" ADD COLUMN "instance_admin" INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE "user" ADD COLUMN "disabled" INTEGER NOT NULL DEFAULT 0;
+CREATE TABLE IF NOT EXISTS "setting" (
+	"key"	TEXT NOT NULL UNIQUE,
+	"value"	TEXT NOT NULL,
+	PRIMARY KEY("key")
+);
+CREATE TABLE IF NOT EXISTS "invitation" (
+	"id"	INTEGER NOT NULL UNIQUE,
+	"token"	TEXT NOT NULL UNIQUE,
+	"email"	TEXT,
+	"created_by"	INTEGER,
+	"created"	BIGINT NOT NULL,
+	"expires"	BIGINT NOT NULL,
+	"used"	BIGINT,
+	"used_by"	INTEGER,
+	FOREIGN KEY("created_by") REFERENCES "user"("id") ON DELETE SET NULL,
+	FOREIGN KEY("used_by") REFERENCES "user"("id") ON DELETE SET NULL,
+	PRIMARY KEY("id" AUTOINCREMENT)
+);
@@ -0,0 +1,17 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE IF NOT EXISTS "snapshot_old" (
+	"project"	INTEGER NOT NULL,
+	"timestamp"	BIGINT NOT NULL,
+	"name" TEXT,
+	"subtitles"	TEXT NOT NULL,
+	PRIMARY KEY("project", "timestamp"),
+	FOREIGN KEY("project") REFERENCES "project"("id")
+);
+INSERT INTO "snapshot_old" ("project", "timestamp", "name", "subtitles")
+	SELECT "project", "timestamp", "name", "subtitles" FROM "snapshot";
+DROP TABLE "snapshot";
+ALTER TABLE "snapshot_old" RENAME TO "snapshot";
+CREATE UNIQUE INDEX IF NOT EXISTS "snapshot_index" ON "snapshot" (
+	"project",
+	"timestamp"
+);
@@ -0,0 +1,20 @@
+-- Your SQL goes here
+-- SQLite can't change a foreign key in place, so the table is rebuilt.
+-- Snapshots of projects that no longer exist are left behind.
+CREATE TABLE IF NOT EXISTS "snapshot_new" (
+	"project"	INTEGER NOT NULL,
+	"timestamp"	BIGINT NOT NULL,
+	"name" TEXT,
+	"subtitles"	TEXT NOT NULL,
+	PRIMARY KEY("project", "timestamp"),
+	FOREIGN KEY("project") REFERENCES "project"("id") ON DELETE CASCADE
+);
+INSERT INTO "snapshot_new" ("project", "timestamp", "name", "subtitles")
+	SELECT "project", "timestamp", "name", "subtitles" FROM "snapshot"
+	WHERE "project" IN (SELECT "id" FROM "project");
+DROP TABLE "snapshot";
+ALTER TABLE "snapshot_new" RENAME TO "snapshot";
+CREATE UNIQUE INDEX IF NOT EXISTS "snapshot_index" ON "snapshot" (
+	"project",
+	"timestamp"
+);
@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "video" DROP COLUMN "sample_rate";
+ALTER TABLE "video" DROP COLUMN "height";
+ALTER TABLE "video" DROP COLUMN "width";
+ALTER TABLE "video" DROP COLUMN "frame_rate_den";
+ALTER TABLE "video" DROP COLUMN "frame_rate_num";
+ALTER TABLE "video" DROP COLUMN "language";
+ALTER TABLE "video" DROP COLUMN "published";
+ALTER TABLE "video" DROP COLUMN "channel";
+ALTER TABLE "video" DROP COLUMN "title";
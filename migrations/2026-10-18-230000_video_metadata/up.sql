@@ -0,0 +1,10 @@
+-- Your SQL goes here
+ALTER TABLE "video" ADD COLUMN "title" TEXT;
+ALTER TABLE "video" ADD COLUMN "channel" TEXT;
+ALTER TABLE "video" ADD COLUMN "published" BIGINT;
+ALTER TABLE "video" ADD COLUMN "language" TEXT;
+ALTER TABLE "video" ADD COLUMN "frame_rate_num" INTEGER;
+ALTER TABLE "video" ADD COLUMN "frame_rate_den" INTEGER;
+ALTER TABLE "video" ADD COLUMN "width" INTEGER;
+ALTER TABLE "video" ADD COLUMN "height" INTEGER;
+ALTER TABLE "video" ADD COLUMN "sample_rate" INTEGER;
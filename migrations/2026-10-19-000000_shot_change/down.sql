@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS "shot_change";
+ALTER TABLE "video" DROP COLUMN "shot_changes_detected";
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+ALTER TABLE "video" ADD COLUMN "shot_changes_detected" BIGINT;
+CREATE TABLE IF NOT EXISTS "shot_change" (
+	"video"	INTEGER NOT NULL,
+	"time"	INTEGER NOT NULL,
+	FOREIGN KEY("video") REFERENCES "video"("id") ON DELETE CASCADE,
+	PRIMARY KEY("video", "time")
+);
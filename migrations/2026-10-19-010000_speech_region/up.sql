@@ -0,0 +1,9 @@
+-- Your SQL goes here
+ALTER TABLE "video" ADD COLUMN "speech_detected" BIGINT;
+CREATE TABLE IF NOT EXISTS "speech_region" (
+	"video"	INTEGER NOT NULL,
+	"start"	INTEGER NOT NULL,
+	"end"	INTEGER NOT NULL,
+	FOREIGN KEY("video") REFERENCES "video"("id") ON DELETE CASCADE,
+	PRIMARY KEY("video", "start")
+);
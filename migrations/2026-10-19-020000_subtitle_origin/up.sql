@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE "subtitle" ADD COLUMN "origin" TEXT;
+ALTER TABLE "subtitle" ADD COLUMN "confidence" REAL;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "subtitle_revision" DROP COLUMN "after_status";
+ALTER TABLE "subtitle_revision" DROP COLUMN "before_status";
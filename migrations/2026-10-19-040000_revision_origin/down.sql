@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "subtitle_revision" DROP COLUMN "after_confidence";
+ALTER TABLE "subtitle_revision" DROP COLUMN "after_origin";
+ALTER TABLE "subtitle_revision" DROP COLUMN "before_confidence";
+ALTER TABLE "subtitle_revision" DROP COLUMN "before_origin";
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE "subtitle_revision" ADD COLUMN "before_origin" TEXT;
+ALTER TABLE "subtitle_revision" ADD COLUMN "before_confidence" REAL;
+ALTER TABLE "subtitle_revision" ADD COLUMN "after_origin" TEXT;
+ALTER TABLE "subtitle_revision" ADD COLUMN "after_confidence" REAL;
@@ -0,0 +1,22 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS "invitation";
+DROP TABLE IF EXISTS "setting";
+DROP TABLE IF EXISTS "email_token";
+DROP TABLE IF EXISTS "email_outbox";
+DROP TABLE IF EXISTS "recovery_code";
+DROP TABLE IF EXISTS "login_failure";
+DROP TABLE IF EXISTS "access_token";
+DROP TABLE IF EXISTS "session";
+DROP TABLE IF EXISTS "snapshot";
+DROP TABLE IF EXISTS "subtitle_status_change";
+DROP TABLE IF EXISTS "comment_mention";
+DROP TABLE IF EXISTS "comment";
+DROP TABLE IF EXISTS "subtitle_revision";
+DROP TABLE IF EXISTS "operation";
+DROP TABLE IF EXISTS "subtitle_lock";
+DROP TABLE IF EXISTS "subtitle";
+DROP TABLE IF EXISTS "project";
+DROP TABLE IF EXISTS "workspace_member";
+DROP TABLE IF EXISTS "workspace";
+DROP TABLE IF EXISTS "video";
+DROP TABLE IF EXISTS "user";
@@ -0,0 +1,244 @@
+-- Your SQL goes here
+-- The schema of migrations/ up to 2026-10-18-210000_snapshot_cascade, for PostgreSQL.
+-- Later migrations have to be added to both directories.
+CREATE TABLE IF NOT EXISTS "user" (
+	"id"	SERIAL PRIMARY KEY,
+	"username"	TEXT NOT NULL UNIQUE,
+	"password"	TEXT NOT NULL,
+	"email"	TEXT,
+	"display_name"	TEXT,
+	"totp_secret"	TEXT,
+	"totp_enabled"	INTEGER NOT NULL DEFAULT 0,
+	"totp_last_step"	BIGINT,
+	"oidc_subject"	TEXT,
+	"email_verified"	INTEGER NOT NULL DEFAULT 0,
+	"instance_admin"	INTEGER NOT NULL DEFAULT 0,
+	"disabled"	INTEGER NOT NULL DEFAULT 0
+);
+CREATE UNIQUE INDEX IF NOT EXISTS "user_oidc_subject_index" ON "user" (
+	"oidc_subject"
+);
+CREATE TABLE IF NOT EXISTS "video" (
+	"id"	SERIAL PRIMARY KEY,
+	"source"	TEXT NOT NULL,
+	"identifier"	TEXT NOT NULL,
+	"duration"	INTEGER,
+	"waveform"	BYTEA
+);
+CREATE TABLE IF NOT EXISTS "workspace" (
+	"id"	SERIAL PRIMARY KEY,
+	"name"	TEXT NOT NULL,
+	"owner"	INTEGER NOT NULL,
+	"shared"	INTEGER NOT NULL DEFAULT 0,
+	"require_2fa"	INTEGER NOT NULL DEFAULT 0,
+	FOREIGN KEY("owner") REFERENCES "user"("id")
+);
+CREATE TABLE IF NOT EXISTS "workspace_member" (
+	"workspace"	INTEGER NOT NULL,
+	"user"	INTEGER NOT NULL,
+	"role"	INTEGER NOT NULL DEFAULT 0,
+	FOREIGN KEY("user") REFERENCES "user"("id") ON DELETE CASCADE,
+	FOREIGN KEY("workspace") REFERENCES "workspace"("id") ON DELETE CASCADE,
+	PRIMARY KEY("workspace", "user")
+);
+CREATE TABLE IF NOT EXISTS "project" (
+	"id"	SERIAL PRIMARY KEY,
+	"workspace"	INTEGER NOT NULL,
+	"name"	TEXT NOT NULL,
+	"video"	INTEGER,
+	FOREIGN KEY("workspace") REFERENCES "workspace"("id") ON DELETE CASCADE,
+	FOREIGN KEY("video") REFERENCES "video"("id")
+);
+CREATE TABLE IF NOT EXISTS "subtitle" (
+	"id"	SERIAL PRIMARY KEY,
+	"project"	INTEGER NOT NULL,
+	"start"	INTEGER NOT NULL,
+	"end"	INTEGER NOT NULL,
+	"text"	TEXT NOT NULL,
+	"status"	TEXT NOT NULL DEFAULT 'draft',
+	FOREIGN KEY("project") REFERENCES "project"("id") ON DELETE CASCADE
+);
+CREATE TABLE IF NOT EXISTS "subtitle_lock" (
+	"id"	SERIAL PRIMARY KEY,
+	"project"	INTEGER NOT NULL,
+	"owner"	INTEGER NOT NULL,
+	"subtitle"	INTEGER,
+	"start"	INTEGER,
+	"end"	INTEGER,
+	"expires"	BIGINT NOT NULL,
+	FOREIGN KEY("project") REFERENCES "project"("id") ON DELETE CASCADE,
+	FOREIGN KEY("owner") REFERENCES "user"("id") ON DELETE CASCADE,
+	FOREIGN KEY("subtitle") REFERENCES "subtitle"("id") ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS "subtitle_lock_project_index" ON "subtitle_lock" (
+	"project"
+);
+CREATE TABLE IF NOT EXISTS "operation" (
+	"id"	SERIAL PRIMARY KEY,
+	"project"	INTEGER NOT NULL,
+	"user"	INTEGER NOT NULL,
+	"timestamp"	BIGINT NOT NULL,
+	"undone"	INTEGER NOT NULL DEFAULT 0,
+	FOREIGN KEY("project") REFERENCES "project"("id") ON DELETE CASCADE,
+	FOREIGN KEY("user") REFERENCES "user"("id") ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS "operation_index" ON "operation" (
+	"project",
+	"user"
+);
+CREATE TABLE IF NOT EXISTS "subtitle_revision" (
+	"id"	SERIAL PRIMARY KEY,
+	"project"	INTEGER NOT NULL,
+	"subtitle"	INTEGER NOT NULL,
+	"user"	INTEGER,
+	"timestamp"	BIGINT NOT NULL,
+	"action"	TEXT NOT NULL,
+	"before_start"	INTEGER,
+	"before_end"	INTEGER,
+	"before_text"	TEXT,
+	"after_start"	INTEGER,
+	"after_end"	INTEGER,
+	"after_text"	TEXT,
+	"operation"	INTEGER,
+	FOREIGN KEY("project") REFERENCES "project"("id") ON DELETE CASCADE,
+	FOREIGN KEY("user") REFERENCES "user"("id") ON DELETE SET NULL,
+	FOREIGN KEY("operation") REFERENCES "operation"("id") ON DELETE SET NULL
+);
+CREATE INDEX IF NOT EXISTS "subtitle_revision_index" ON "subtitle_revision" (
+	"project",
+	"subtitle"
+);
+CREATE TABLE IF NOT EXISTS "comment" (
+	"id"	SERIAL PRIMARY KEY,
+	"project"	INTEGER NOT NULL,
+	"parent"	INTEGER,
+	"author"	INTEGER NOT NULL,
+	"subtitle"	INTEGER,
+	"start"	INTEGER,
+	"end"	INTEGER,
+	"text"	TEXT NOT NULL,
+	"created"	BIGINT NOT NULL,
+	"edited"	BIGINT,
+	"resolved"	INTEGER NOT NULL DEFAULT 0,
+	"resolved_by"	INTEGER,
+	FOREIGN KEY("project") REFERENCES "project"("id") ON DELETE CASCADE,
+	FOREIGN KEY("parent") REFERENCES "comment"("id") ON DELETE CASCADE,
+	FOREIGN KEY("author") REFERENCES "user"("id") ON DELETE CASCADE,
+	FOREIGN KEY("resolved_by") REFERENCES "user"("id") ON DELETE SET NULL
+);
+CREATE INDEX IF NOT EXISTS "comment_project_index" ON "comment" (
+	"project"
+);
+CREATE TABLE IF NOT EXISTS "comment_mention" (
+	"comment"	INTEGER NOT NULL,
+	"user"	INTEGER NOT NULL,
+	FOREIGN KEY("comment") REFERENCES "comment"("id") ON DELETE CASCADE,
+	FOREIGN KEY("user") REFERENCES "user"("id") ON DELETE CASCADE,
+	PRIMARY KEY("comment", "user")
+);
+CREATE TABLE IF NOT EXISTS "subtitle_status_change" (
+	"id"	SERIAL PRIMARY KEY,
+	"project"	INTEGER NOT NULL,
+	"subtitle"	INTEGER NOT NULL,
+	"user"	INTEGER,
+	"timestamp"	BIGINT NOT NULL,
+	"from"	TEXT NOT NULL,
+	"to"	TEXT NOT NULL,
+	FOREIGN KEY("project") REFERENCES "project"("id") ON DELETE CASCADE,
+	FOREIGN KEY("user") REFERENCES "user"("id") ON DELETE SET NULL
+);
+CREATE INDEX IF NOT EXISTS "subtitle_status_change_index" ON "subtitle_status_change" (
+	"project",
+	"subtitle"
+);
+CREATE TABLE IF NOT EXISTS "snapshot" (
+	"project"	INTEGER NOT NULL,
+	"timestamp"	BIGINT NOT NULL,
+	"name"	TEXT,
+	"subtitles"	TEXT NOT NULL,
+	PRIMARY KEY("project", "timestamp"),
+	FOREIGN KEY("project") REFERENCES "project"("id") ON DELETE CASCADE
+);
+CREATE TABLE IF NOT EXISTS "session" (
+	"id"	SERIAL PRIMARY KEY,
+	"token"	TEXT NOT NULL UNIQUE,
+	"user"	INTEGER NOT NULL,
+	"created"	BIGINT NOT NULL,
+	"last_seen"	BIGINT NOT NULL,
+	"user_agent"	TEXT,
+	"expires"	BIGINT NOT NULL,
+	"pending"	INTEGER NOT NULL DEFAULT 0,
+	FOREIGN KEY("user") REFERENCES "user"("id") ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS "session_user_index" ON "session" (
+	"user"
+);
+CREATE TABLE IF NOT EXISTS "access_token" (
+	"id"	SERIAL PRIMARY KEY,
+	"user"	INTEGER NOT NULL,
+	"name"	TEXT NOT NULL,
+	"token"	TEXT NOT NULL UNIQUE,
+	"scopes"	TEXT NOT NULL,
+	"created"	BIGINT NOT NULL,
+	"expires"	BIGINT,
+	"last_used"	BIGINT,
+	FOREIGN KEY("user") REFERENCES "user"("id") ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS "access_token_user_index" ON "access_token" (
+	"user"
+);
+CREATE TABLE IF NOT EXISTS "login_failure" (
+	"key"	TEXT PRIMARY KEY,
+	"failures"	INTEGER NOT NULL,
+	"last_failure"	BIGINT NOT NULL,
+	"locked_until"	BIGINT
+);
+CREATE TABLE IF NOT EXISTS "recovery_code" (
+	"id"	SERIAL PRIMARY KEY,
+	"user"	INTEGER NOT NULL,
+	"code"	TEXT NOT NULL,
+	FOREIGN KEY("user") REFERENCES "user"("id") ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS "recovery_code_index" ON "recovery_code" (
+	"user"
+);
+CREATE TABLE IF NOT EXISTS "email_outbox" (
+	"id"	SERIAL PRIMARY KEY,
+	"recipient"	TEXT NOT NULL,
+	"subject"	TEXT NOT NULL,
+	"body"	TEXT NOT NULL,
+	"created"	BIGINT NOT NULL,
+	"attempts"	INTEGER NOT NULL DEFAULT 0,
+	"next_attempt"	BIGINT NOT NULL,
+	"last_error"	TEXT
+);
+CREATE TABLE IF NOT EXISTS "email_token" (
+	"id"	SERIAL PRIMARY KEY,
+	"user"	INTEGER NOT NULL,
+	"purpose"	TEXT NOT NULL,
+	"token"	TEXT NOT NULL UNIQUE,
+	"email"	TEXT,
+	"created"	BIGINT NOT NULL,
+	"expires"	BIGINT NOT NULL,
+	FOREIGN KEY("user") REFERENCES "user"("id") ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS "email_token_index" ON "email_token" (
+	"user",
+	"purpose"
+);
+CREATE TABLE IF NOT EXISTS "setting" (
+	"key"	TEXT PRIMARY KEY,
+	"value"	TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS "invitation" (
+	"id"	SERIAL PRIMARY KEY,
+	"token"	TEXT NOT NULL UNIQUE,
+	"email"	TEXT,
+	"created_by"	INTEGER,
+	"created"	BIGINT NOT NULL,
+	"expires"	BIGINT NOT NULL,
+	"used"	BIGINT,
+	"used_by"	INTEGER,
+	FOREIGN KEY("created_by") REFERENCES "user"("id") ON DELETE SET NULL,
+	FOREIGN KEY("used_by") REFERENCES "user"("id") ON DELETE SET NULL
+);
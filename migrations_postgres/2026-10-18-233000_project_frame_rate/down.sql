@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "project" DROP COLUMN "snap_to_frames";
+ALTER TABLE "project" DROP COLUMN "drop_frame";
+ALTER TABLE "project" DROP COLUMN "frame_rate_den";
+ALTER TABLE "project" DROP COLUMN "frame_rate_num";
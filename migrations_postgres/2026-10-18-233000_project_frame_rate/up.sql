@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE "project" ADD COLUMN "frame_rate_num" INTEGER;
+ALTER TABLE "project" ADD COLUMN "frame_rate_den" INTEGER;
+ALTER TABLE "project" ADD COLUMN "drop_frame" INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE "project" ADD COLUMN "snap_to_frames" INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS "speech_region";
+ALTER TABLE "video" DROP COLUMN "speech_detected";
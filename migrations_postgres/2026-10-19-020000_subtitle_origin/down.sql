@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "subtitle" DROP COLUMN "confidence";
+ALTER TABLE "subtitle" DROP COLUMN "origin";
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE "subtitle_revision" ADD COLUMN "before_status" TEXT;
+ALTER TABLE "subtitle_revision" ADD COLUMN "after_status" TEXT;
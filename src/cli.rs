@@ -0,0 +1,511 @@
+//! Subcommands for operating the server from a shell, like `uptitle-server user create alice`.
+//! Without a subcommand the server starts as usual. The commands use the same configuration
+//! as the server, so they work on the database in Rocket.toml or `ROCKET_DATABASES`.
+
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use rocket::config::LogLevel;
+use rocket::{Build, Rocket};
+
+use crate::diesel::connection::SimpleConnection;
+use crate::diesel::prelude::*;
+use crate::media;
+use crate::models::*;
+use crate::schema::*;
+use crate::speech::SpeechSettings;
+use crate::timecode::FrameRate;
+use crate::{
+    detect_shot_changes, detect_speech_regions, download_youtube_audio, embedded_migrations,
+    generate_token, generate_waveform, hash_password, load_admin_projects, load_admin_users,
+    load_admin_workspaces, reset_user_password, unix_timestamp, DbConn,
+};
+
+#[derive(Parser)]
+#[command(about = "Collaborative subtitle editor server")]
+pub struct Cli {
+    /// Refuse to start the server if the database has pending or unknown migrations,
+    /// instead of running the pending ones
+    #[arg(long)]
+    pub check_migrations: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Start the server, the default without a subcommand
+    Serve,
+    /// Run database migrations that haven't been run yet
+    Migrate,
+    #[command(subcommand)]
+    User(UserCommand),
+    /// List all workspaces
+    Workspaces,
+    /// List all projects, with their sizes
+    Projects,
+    /// Download a video's audio again and regenerate its waveform
+    Waveform {
+        /// YouTube ID of the video
+        video: String,
+    },
+    /// Detect the shot changes of a project's video again
+    ShotChanges {
+        project: i32,
+        /// How different a frame has to be from the one before it to count as a shot
+        /// change, between 0 and 1
+        #[arg(long, default_value_t = media::DEFAULT_SCENE_THRESHOLD)]
+        threshold: f64,
+    },
+    /// Use a video file on this server for a project, replacing its video.
+    /// Reads its metadata with ffprobe, generates the waveform and detects speech and shot
+    /// changes.
+    AttachFile { project: i32, path: PathBuf },
+    /// Give a project a new frame rate, like "25" or "29.97", and snap all its cues to the
+    /// frames of that rate. This is an offline maintenance command: it doesn't go through the
+    /// server, so editors that have the project open aren't told and need to reload it. The
+    /// moved cues show up in their history without a user, but nobody can undo the change.
+    Requantise {
+        project: i32,
+        frame_rate: String,
+        /// Write timecodes as drop-frame, for 29.97 and 59.94
+        #[arg(long)]
+        drop_frame: bool,
+    },
+    /// Rebuild the database file to reclaim unused space
+    Vacuum,
+    /// Write a copy of the database to a new file. Safe while the server is running.
+    /// Only for SQLite, use pg_dump for PostgreSQL.
+    Backup { path: PathBuf },
+}
+
+/// Manage user accounts
+#[derive(Subcommand)]
+pub enum UserCommand {
+    /// List all users
+    List,
+    /// Create a user. Prints a generated password if none is given.
+    Create {
+        username: String,
+        #[arg(long)]
+        password: Option<String>,
+        /// Make the user an instance admin
+        #[arg(long)]
+        admin: bool,
+    },
+    /// Make a user an instance admin
+    Promote { username: String },
+    /// Take away a user's instance admin role
+    Demote { username: String },
+    /// Set a new password and log the user out everywhere.
+    /// Prints a generated password if none is given.
+    ResetPassword {
+        username: String,
+        #[arg(long)]
+        password: Option<String>,
+    },
+}
+
+/// Run a subcommand other than `serve`
+pub async fn run(command: Command, rocket: Rocket<Build>) -> Result<(), String> {
+    // Only the output of the command itself should end up on the terminal
+    let figment = rocket.figment().clone().merge(("log_level", LogLevel::Off));
+    let rocket = rocket
+        .configure(figment)
+        .ignite()
+        .await
+        .map_err(|e| e.to_string())?;
+    let db = DbConn::get_one(&rocket)
+        .await
+        .ok_or("could not connect to the database")?;
+
+    match command {
+        Command::Serve => unreachable!("serve is handled in main"),
+        Command::Migrate => db
+            .run(|conn| embedded_migrations::run_with_output(conn, &mut std::io::stdout()))
+            .await
+            .map_err(|e| e.to_string()),
+        Command::User(command) => run_user_command(&db, command).await,
+        Command::Workspaces => {
+            let workspaces = db
+                .run(|conn| load_admin_workspaces(conn))
+                .await
+                .map_err(|e| e.to_string())?;
+            println!(
+                "{:>5}  {:<24} {:<16} {:>7} {:>8}",
+                "id", "name", "owner", "members", "projects"
+            );
+            for workspace in workspaces {
+                println!(
+                    "{:>5}  {:<24} {:<16} {:>7} {:>8}",
+                    workspace.id,
+                    workspace.name,
+                    workspace.owner,
+                    workspace.members,
+                    workspace.projects
+                );
+            }
+            Ok(())
+        }
+        Command::Projects => {
+            let projects = db
+                .run(|conn| load_admin_projects(conn))
+                .await
+                .map_err(|e| e.to_string())?;
+            println!(
+                "{:>5}  {:<24} {:>9} {:>9} {:>9} {:>14}",
+                "id", "name", "workspace", "subtitles", "snapshots", "waveform bytes"
+            );
+            for project in projects {
+                println!(
+                    "{:>5}  {:<24} {:>9} {:>9} {:>9} {:>14}",
+                    project.id,
+                    project.name,
+                    project.workspace,
+                    project.subtitles,
+                    project.snapshots,
+                    project.waveform_bytes
+                );
+            }
+            Ok(())
+        }
+        Command::Waveform { video } => {
+            let identifier = video.clone();
+            let exists = db
+                .run(move |conn| {
+                    video::table
+                        .filter(video::identifier.eq(identifier))
+                        .count()
+                        .get_result::<i64>(conn)
+                })
+                .await
+                .map_err(|e| e.to_string())?;
+            if exists == 0 {
+                return Err(format!("no video with ID {}", video));
+            }
+            download_youtube_audio(&db, &video)
+                .await
+                .map_err(|_| "could not generate the waveform".to_string())?;
+            println!("Waveform of {} regenerated", video);
+            Ok(())
+        }
+        Command::ShotChanges { project, threshold } => {
+            if !(threshold > 0.0 && threshold < 1.0) {
+                return Err("the threshold must be between 0 and 1".to_string());
+            }
+            let video_id = db
+                .run(move |conn| {
+                    project::table
+                        .find(project)
+                        .select(project::video)
+                        .first::<Option<i32>>(conn)
+                        .optional()
+                })
+                .await
+                .map_err(|e| e.to_string())?
+                .ok_or("project not found")?
+                .ok_or("project has no video")?;
+            let count = detect_shot_changes(&db, video_id, threshold)
+                .await
+                .map_err(|_| "could not detect shot changes".to_string())?;
+            println!("Found {} shot changes", count);
+            Ok(())
+        }
+        Command::AttachFile { project, path } => attach_file(&db, project, path).await,
+        Command::Requantise {
+            project,
+            frame_rate,
+            drop_frame,
+        } => requantise(&db, project, &frame_rate, drop_frame).await,
+        Command::Vacuum => db
+            .run(|conn| conn.batch_execute("VACUUM"))
+            .await
+            .map_err(|e| e.to_string()),
+        #[cfg(feature = "postgres")]
+        Command::Backup { .. } => Err("use pg_dump to back up a PostgreSQL database".to_string()),
+        #[cfg(feature = "sqlite")]
+        Command::Backup { path } => {
+            if path.exists() {
+                return Err(format!("{} already exists", path.display()));
+            }
+            let path = path
+                .to_str()
+                .ok_or("backup path is not valid UTF-8")?
+                .replace('\'', "''");
+            db.run(move |conn| conn.batch_execute(&format!("VACUUM INTO '{}'", path)))
+                .await
+                .map_err(|e| e.to_string())
+        }
+    }
+}
+
+async fn run_user_command(db: &DbConn, command: UserCommand) -> Result<(), String> {
+    match command {
+        UserCommand::List => {
+            let users = db
+                .run(|conn| load_admin_users(conn))
+                .await
+                .map_err(|e| e.to_string())?;
+            println!(
+                "{:>5}  {:<20} {:<32} {:<5} {:<8}",
+                "id", "username", "email", "admin", "disabled"
+            );
+            for user in users {
+                println!(
+                    "{:>5}  {:<20} {:<32} {:<5} {:<8}",
+                    user.id,
+                    user.username,
+                    user.email.unwrap_or_default(),
+                    user.instance_admin,
+                    user.disabled
+                );
+            }
+            Ok(())
+        }
+        UserCommand::Create {
+            username,
+            password,
+            admin,
+        } => {
+            let (password, generated) = password_or_generated(password)?;
+            let password_hash = hash_password(&password).map_err(|e| e.to_string())?;
+            let id = db
+                .run(move |conn| {
+                    conn.transaction(|| {
+                        let existing = user::table
+                            .filter(user::username.eq(&username))
+                            .count()
+                            .get_result::<i64>(conn)?;
+                        if existing > 0 {
+                            return Ok(None);
+                        }
+                        let id = insert_returning_id!(
+                            conn,
+                            diesel::insert_into(user::table).values(&NewUser {
+                                username,
+                                password: password_hash,
+                            }),
+                            user::id
+                        )?;
+                        diesel::update(user::table.find(id))
+                            .set(user::instance_admin.eq(admin as i32))
+                            .execute(conn)?;
+                        Ok(Some(id))
+                    })
+                })
+                .await
+                .map_err(|e: diesel::result::Error| e.to_string())?
+                .ok_or("username not available")?;
+
+            println!("Created user {}", id);
+            if generated {
+                println!("Password: {}", password);
+            }
+            Ok(())
+        }
+        UserCommand::Promote { username } => set_instance_admin(db, username, true).await,
+        UserCommand::Demote { username } => set_instance_admin(db, username, false).await,
+        UserCommand::ResetPassword { username, password } => {
+            let (password, generated) = password_or_generated(password)?;
+            let password_hash = hash_password(&password).map_err(|e| e.to_string())?;
+            let found = db
+                .run(move |conn| {
+                    conn.transaction(|| {
+                        let user = match user::table
+                            .filter(user::username.eq(username))
+                            .first::<User>(conn)
+                            .optional()?
+                        {
+                            Some(user) => user,
+                            None => return Ok(false),
+                        };
+                        reset_user_password(conn, &user, &password_hash)?;
+                        Ok(true)
+                    })
+                })
+                .await
+                .map_err(|e: diesel::result::Error| e.to_string())?;
+            if !found {
+                return Err("user not found".to_string());
+            }
+
+            println!("Password changed, the user has been logged out everywhere");
+            if generated {
+                println!("Password: {}", password);
+            }
+            Ok(())
+        }
+    }
+}
+
+async fn attach_file(db: &DbConn, project_id: i32, path: PathBuf) -> Result<(), String> {
+    let path = path
+        .canonicalize()
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let identifier = path
+        .to_str()
+        .ok_or("file path is not valid UTF-8")?
+        .to_string();
+    let exists = db
+        .run(move |conn| {
+            project::table
+                .find(project_id)
+                .count()
+                .get_result::<i64>(conn)
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+    if exists == 0 {
+        return Err("project not found".to_string());
+    }
+
+    let (metadata, duration) =
+        rocket::tokio::task::spawn_blocking(move || media::probe_file(&path))
+            .await
+            .map_err(|e| e.to_string())??;
+    let waveform = generate_waveform(identifier.clone())
+        .await
+        .map_err(|_| "could not generate the waveform".to_string())?;
+
+    let frame_rate = metadata
+        .frame_rate_num
+        .zip(metadata.frame_rate_den)
+        .map(|(num, den)| format!("{:.3} fps", num as f64 / den as f64))
+        .unwrap_or_else(|| "unknown frame rate".to_string());
+    let video_id = db
+        .run(move |conn| {
+            conn.transaction(|| {
+                let video_id = insert_returning_id!(
+                    conn,
+                    diesel::insert_into(video::table).values(NewVideo {
+                        source: "file".to_string(),
+                        identifier,
+                        duration,
+                        waveform: Some(waveform),
+                        language: None,
+                    }),
+                    video::id
+                )?;
+                diesel::update(video::table.find(video_id))
+                    .set(&metadata)
+                    .execute(conn)?;
+                diesel::update(project::table.find(project_id))
+                    .set(project::video.eq(Some(video_id)))
+                    .execute(conn)?;
+                Ok(video_id)
+            })
+        })
+        .await
+        .map_err(|e: diesel::result::Error| e.to_string())?;
+    println!("Attached to project {}, {}", project_id, frame_rate);
+
+    let speech = db
+        .run(move |conn| detect_speech_regions(conn, video_id, &SpeechSettings::default()))
+        .await
+        .map_err(|(_, message)| message.to_string())?;
+    println!("Found {} speech regions", speech.len());
+
+    let shot_changes = detect_shot_changes(db, video_id, media::DEFAULT_SCENE_THRESHOLD)
+        .await
+        .map_err(|_| "could not detect shot changes".to_string())?;
+    println!("Found {} shot changes", shot_changes);
+    Ok(())
+}
+
+async fn requantise(
+    db: &DbConn,
+    project_id: i32,
+    frame_rate: &str,
+    drop_frame: bool,
+) -> Result<(), String> {
+    let rate = FrameRate::parse(frame_rate)?;
+    if drop_frame && !rate.supports_drop_frame() {
+        return Err("drop-frame timecode only exists for 29.97 and 59.94".to_string());
+    }
+
+    let moved = db
+        .run(move |conn| {
+            conn.transaction(|| {
+                let updated = diesel::update(project::table.find(project_id))
+                    .set((
+                        project::frame_rate_num.eq(Some(rate.num)),
+                        project::frame_rate_den.eq(Some(rate.den)),
+                        project::drop_frame.eq(drop_frame as i32),
+                    ))
+                    .execute(conn)?;
+                if updated == 0 {
+                    return Ok(None);
+                }
+
+                let subtitles = subtitle::table
+                    .filter(subtitle::project.eq(project_id))
+                    .load::<Subtitle>(conn)?;
+                let timestamp = unix_timestamp();
+                let mut revisions = Vec::new();
+                for original in subtitles {
+                    let (start, end) = rate.snap_cue(original.start, original.end);
+                    if (start, end) == (original.start, original.end) {
+                        continue;
+                    }
+                    let subtitle = Subtitle {
+                        start,
+                        end,
+                        ..original.clone()
+                    };
+                    diesel::update(subtitle::table.find(subtitle.id))
+                        .set((subtitle::start.eq(start), subtitle::end.eq(end)))
+                        .execute(conn)?;
+                    // Shows up in the history without a user, and can't be undone
+                    revisions.push(NewSubtitleRevision {
+                        user: None,
+                        ..NewSubtitleRevision::new(
+                            "edit",
+                            0,
+                            timestamp,
+                            Some(&original),
+                            Some(&subtitle),
+                        )
+                    });
+                }
+                if !revisions.is_empty() {
+                    diesel::insert_into(subtitle_revision::table)
+                        .values(&revisions)
+                        .execute(conn)?;
+                }
+                Ok(Some(revisions.len()))
+            })
+        })
+        .await
+        .map_err(|e: diesel::result::Error| e.to_string())?
+        .ok_or("project not found")?;
+
+    println!("Moved {} cues to frames of {:.3} fps", moved, rate.fps());
+    Ok(())
+}
+
+/// The given password if it's long enough, or a random one
+fn password_or_generated(password: Option<String>) -> Result<(String, bool), String> {
+    match password {
+        Some(password) if password.len() < 8 => {
+            Err("password must be at least 8 characters".to_string())
+        }
+        Some(password) => Ok((password, false)),
+        None => Ok((generate_token()[..16].to_string(), true)),
+    }
+}
+
+async fn set_instance_admin(db: &DbConn, username: String, admin: bool) -> Result<(), String> {
+    let updated = db
+        .run(move |conn| {
+            diesel::update(user::table)
+                .filter(user::username.eq(username))
+                .set(user::instance_admin.eq(admin as i32))
+                .execute(conn)
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+    if updated == 0 {
+        return Err("user not found".to_string());
+    }
+    Ok(())
+}
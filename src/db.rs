@@ -0,0 +1,61 @@
+//! The database backend, chosen with the `sqlite` (default) or `postgres` cargo feature.
+//! Queries that differ between the backends go through the macros here.
+
+#[cfg(all(feature = "sqlite", feature = "postgres"))]
+compile_error!("the sqlite and postgres features can't be enabled together");
+#[cfg(not(any(feature = "sqlite", feature = "postgres")))]
+compile_error!("enable either the sqlite or the postgres feature");
+
+#[cfg(feature = "sqlite")]
+pub type DbConnection = diesel::sqlite::SqliteConnection;
+#[cfg(feature = "postgres")]
+pub type DbConnection = diesel::pg::PgConnection;
+
+// diesel 1.4 only supports RETURNING on Postgres, on SQLite the id is read back from the
+// same connection right after the insert
+#[cfg(feature = "sqlite")]
+no_arg_sql_function!(
+    last_insert_rowid,
+    diesel::sql_types::Integer,
+    "Represents the SQL last_insert_row() function"
+);
+
+/// Run an insert statement and get the id of the new row
+#[cfg(feature = "sqlite")]
+macro_rules! insert_returning_id {
+    ($conn:expr, $insert:expr, $id:expr) => {
+        $insert
+            .execute($conn)
+            .and_then(|_| diesel::select(crate::db::last_insert_rowid).get_result::<i32>($conn))
+    };
+}
+
+/// Run an insert statement and get the id of the new row
+#[cfg(feature = "postgres")]
+macro_rules! insert_returning_id {
+    ($conn:expr, $insert:expr, $id:expr) => {
+        $insert.returning($id).get_result::<i32>($conn)
+    };
+}
+
+/// Insert a row, or replace the row with the same primary key
+#[cfg(feature = "sqlite")]
+macro_rules! upsert {
+    ($conn:expr, $table:expr, $key:expr, $values:expr) => {
+        diesel::replace_into($table).values($values).execute($conn)
+    };
+}
+
+/// Insert a row, or replace the row with the same primary key
+#[cfg(feature = "postgres")]
+macro_rules! upsert {
+    ($conn:expr, $table:expr, $key:expr, $values:expr) => {{
+        let values = $values;
+        diesel::insert_into($table)
+            .values(values)
+            .on_conflict($key)
+            .do_update()
+            .set(values)
+            .execute($conn)
+    }};
+}
@@ -0,0 +1,148 @@
+//! Outgoing email over SMTP. Emails are queued in the `email_outbox` table and sent by a
+//! background worker, see `run_email_worker` in main.rs.
+
+use lettre::message::{header::ContentType, Mailbox};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use rocket::serde::Deserialize;
+use rocket::tokio::sync::Notify;
+
+/// The `email` table in Rocket.toml, or `ROCKET_EMAIL={smtp_host="...",from="...",...}`.
+/// For a local SMTP sink, use `smtp_security = "none"`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct EmailConfig {
+    pub smtp_host: String,
+    pub smtp_port: Option<u16>,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    #[serde(default)]
+    pub smtp_security: SmtpSecurity,
+    /// Sender address, like `Uptitle <noreply@example.com>`
+    pub from: String,
+    /// Address of the frontend, used for links in emails
+    pub public_url: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    None,
+    #[default]
+    Starttls,
+    Tls,
+}
+
+/// The emails we send
+pub enum Template<'a> {
+    PasswordReset { username: &'a str, token: &'a str },
+    VerifyEmail { username: &'a str, token: &'a str },
+    Invitation { token: &'a str },
+}
+
+pub struct Mailer {
+    config: EmailConfig,
+    from: Mailbox,
+    transport: SmtpTransport,
+    /// Wakes the worker up when something is queued
+    queued: Notify,
+}
+
+impl Mailer {
+    pub fn new(config: EmailConfig) -> Result<Mailer, String> {
+        let from = config
+            .from
+            .parse()
+            .map_err(|e| format!("invalid sender address: {}", e))?;
+
+        let builder = match config.smtp_security {
+            SmtpSecurity::None => SmtpTransport::builder_dangerous(&config.smtp_host),
+            SmtpSecurity::Starttls => SmtpTransport::starttls_relay(&config.smtp_host)
+                .map_err(|e| format!("invalid SMTP host: {}", e))?,
+            SmtpSecurity::Tls => SmtpTransport::relay(&config.smtp_host)
+                .map_err(|e| format!("invalid SMTP host: {}", e))?,
+        };
+        let builder = match config.smtp_port {
+            Some(port) => builder.port(port),
+            None => builder,
+        };
+        let builder = match (&config.smtp_username, &config.smtp_password) {
+            (Some(username), Some(password)) => {
+                builder.credentials(Credentials::new(username.clone(), password.clone()))
+            }
+            _ => builder,
+        };
+
+        Ok(Mailer {
+            from,
+            transport: builder.build(),
+            config,
+            queued: Notify::new(),
+        })
+    }
+
+    /// Subject and body of an email
+    pub fn render(&self, template: &Template) -> (String, String) {
+        let public_url = self.config.public_url.trim_end_matches('/');
+        match template {
+            Template::PasswordReset { username, token } => (
+                "Reset your Uptitle password".to_string(),
+                format!(
+                    "Hi {},\n\n\
+                     Someone asked to reset the password of your Uptitle account. \
+                     If that was you, choose a new password here:\n\n\
+                     {}/reset-password?token={}\n\n\
+                     This link works once and expires in an hour. \
+                     If you didn't ask for this, you can ignore this email.\n",
+                    username, public_url, token
+                ),
+            ),
+            Template::VerifyEmail { username, token } => (
+                "Confirm your email address".to_string(),
+                format!(
+                    "Hi {},\n\n\
+                     Please confirm that this is the email address of your Uptitle account:\n\n\
+                     {}/verify-email?token={}\n\n\
+                     If you don't have an Uptitle account, you can ignore this email.\n",
+                    username, public_url, token
+                ),
+            ),
+            Template::Invitation { token } => (
+                "You're invited to Uptitle".to_string(),
+                format!(
+                    "Hi,\n\n\
+                     You've been invited to create an account on Uptitle:\n\n\
+                     {}/register?invite={}\n\n\
+                     This link works once and expires in a week.\n",
+                    public_url, token
+                ),
+            ),
+        }
+    }
+
+    /// Send an email right away. This blocks, so call it from `spawn_blocking`.
+    pub fn send(&self, recipient: &str, subject: &str, body: &str) -> Result<(), String> {
+        let to: Mailbox = recipient
+            .parse()
+            .map_err(|e| format!("invalid recipient: {}", e))?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body.to_string())
+            .map_err(|e| e.to_string())?;
+        self.transport
+            .send(&message)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn wake(&self) {
+        self.queued.notify_one();
+    }
+
+    pub async fn queued(&self) {
+        self.queued.notified().await
+    }
+}
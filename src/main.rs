@@ -4,22 +4,33 @@ extern crate rocket;
 extern crate rocket_sync_db_pools;
 #[macro_use]
 extern crate diesel as diesel2;
+#[macro_use]
+extern crate diesel_migrations;
 
 use self::diesel::prelude::*;
 
 use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    password_hash::{
+        rand_core::{OsRng, RngCore},
+        PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+    },
     Argon2,
 };
 
+use clap::Parser;
+use rocket::fairing::AdHoc;
 use rocket::response::stream::{Event, EventStream};
-use rocket::response::Debug;
-use rocket::serde::{json::Json, Deserialize, Serialize};
+use rocket::response::{Debug, Redirect};
+use rocket::serde::{
+    json::{serde_json, Json},
+    Deserialize, Serialize,
+};
 use rocket::tokio::select;
 use rocket::tokio::sync::broadcast::{channel, error::RecvError, Sender};
-use rocket::{http::Status, Shutdown, State};
+use rocket::tokio::time::sleep;
+use rocket::{http::Status, Build, Rocket, Shutdown, State};
 use rocket::{
-    http::{Cookie, CookieJar},
+    http::{Cookie, CookieJar, SameSite},
     tokio::task,
 };
 use rocket::{
@@ -27,24 +38,102 @@ use rocket::{
     tokio::time::Instant,
 };
 use rocket_sync_db_pools::diesel;
-use std::time::{SystemTime, UNIX_EPOCH};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use std::env;
 use std::process::{Command, Stdio};
 
-use self::diesel::sqlite::SqliteConnection;
+use crate::db::DbConnection;
+use diesel_migrations::MigrationConnection;
 
+#[macro_use]
+mod db;
+mod cli;
+mod email;
+mod media;
 pub mod models;
+mod oidc;
 pub mod schema;
+mod speech;
+mod timecode;
+mod totp;
+mod transcribe;
+mod translate;
+
+#[cfg(test)]
+mod tests;
 
+use crate::email::{EmailConfig, Mailer};
+use crate::media::{ShotChanges, DEFAULT_SCENE_THRESHOLD};
 use crate::models::*;
+use crate::oidc::{Oidc, OidcConfig};
 use crate::schema::*;
+use crate::speech::{AlignmentSettings, CueSettings, Peaks, SpeechSettings};
+use crate::timecode::{FrameRate, Timecode};
+use crate::transcribe::{Transcriber, TranscriberConfig, TranscriptionRequest};
+use crate::translate::{Translator, TranslatorConfig};
 
 #[database("diesel")]
-pub struct DbConn(SqliteConnection);
+pub struct DbConn(DbConnection);
+
+// The migrations directory of the backend is compiled into the binary. Pending ones run when the
+// server starts, see `migrations_fairing`, or with `uptitle-server migrate`.
+#[cfg(feature = "sqlite")]
+embed_migrations!("migrations");
+#[cfg(feature = "postgres")]
+embed_migrations!("migrations_postgres");
+include!(concat!(env!("OUT_DIR"), "/migration_versions.rs"));
 
 type Result<T, E = Debug<diesel::result::Error>> = std::result::Result<T, E>;
 
+fn unix_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("we're in the past")
+        .as_secs() as i64
+}
+
+/// Get a project if the user is a member of its workspace, along with their role in it.
+/// The workspace owner always counts as an admin.
+/// Workspaces that require two-factor authentication are hidden from users without it. That's
+/// only checked here and in `get_workspace_as_member`, so routes should find projects and
+/// workspaces through these. The one exception is `list_workspaces`, which still lists such
+/// workspaces, just without their projects.
+async fn get_project_as_member(
+    db: &DbConn,
+    project_id: i32,
+    user: &User,
+) -> Result<(Project, i32), Status> {
+    let user_id = user.id;
+    let totp_enabled = user.totp_enabled;
+    let (project, owner, role): (Project, i32, Option<i32>) = db
+        .run(move |conn| {
+            project::table
+                .inner_join(workspace::table.left_join(workspace_member::table))
+                .filter(workspace_member::user.eq(user_id))
+                .filter(workspace::require_2fa.le(totp_enabled))
+                .filter(project::id.eq(project_id))
+                .select((
+                    project::all_columns,
+                    workspace::owner,
+                    workspace_member::role.nullable(),
+                ))
+                .first::<(Project, i32, Option<i32>)>(conn)
+        })
+        .await
+        .map_err(|_| Status::NotFound)?;
+
+    if owner == user_id {
+        Ok((project, role::ADMIN))
+    } else {
+        Ok((project, role.unwrap_or(role::MEMBER)))
+    }
+}
+
 // Workspace
 #[derive(Serialize, Debug)]
 #[serde(crate = "rocket::serde")]
@@ -52,8 +141,10 @@ struct WorkspaceInfo {
     id: i32,
     name: String,
     shared: i32,
+    require_2fa: bool,
     members: Vec<WorkspaceMemberInfo>,
-    projects: Vec<ProjectInfo>,
+    /// Number of projects, which are listed by /workspace/<id>/projects
+    projects: i64,
 }
 
 #[derive(Serialize, Debug)]
@@ -68,116 +159,335 @@ struct ProjectInfo {
     id: i32,
     workspace: i32,
     name: String,
-    source: String,
+    /// The video fields are empty for projects without a video
+    source: Option<String>,
     video: Option<VideoInfo>,
-    thumbnail: String,
+    thumbnail: Option<String>,
     duration: i32,
+    /// Frames per second used for timecodes, like 29.97
+    frame_rate: Option<f64>,
+    drop_frame: bool,
+    snap_to_frames: bool,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone, Default)]
 #[serde(crate = "rocket::serde")]
 struct VideoInfo {
     id: String,
     duration: i32,
+    title: Option<String>,
+    channel: Option<String>,
+    published: Option<i64>,
+    language: Option<String>,
+    /// Frames per second, like 29.97
+    frame_rate: Option<f64>,
+    width: Option<i32>,
+    height: Option<i32>,
+    sample_rate: Option<i32>,
+}
+
+impl VideoInfo {
+    fn new(video: VideoSummary) -> VideoInfo {
+        let metadata = video.metadata;
+        VideoInfo {
+            id: video.identifier,
+            duration: video.duration.unwrap_or(0),
+            title: metadata.title,
+            channel: metadata.channel,
+            published: metadata.published,
+            language: metadata.language,
+            frame_rate: metadata
+                .frame_rate_num
+                .zip(metadata.frame_rate_den)
+                .map(|(num, den)| num as f64 / den as f64),
+            width: metadata.width,
+            height: metadata.height,
+            sample_rate: metadata.sample_rate,
+        }
+    }
+}
+
+impl ProjectInfo {
+    fn new(project: Project, video: Option<VideoSummary>) -> ProjectInfo {
+        let timecode = project.timecode();
+        ProjectInfo {
+            frame_rate: timecode.map(|timecode| timecode.rate.fps()),
+            drop_frame: timecode.is_some_and(|timecode| timecode.drop_frame),
+            snap_to_frames: project.snap_to_frames != 0,
+            id: project.id,
+            workspace: project.workspace,
+            name: project.name,
+            source: video.as_ref().map(|video| video.source.clone()),
+            thumbnail: video
+                .as_ref()
+                .filter(|video| video.source == "youtube")
+                .map(|video| format!("https://i.ytimg.com/vi/{}/mqdefault.jpg", video.identifier)),
+            duration: video.as_ref().and_then(|video| video.duration).unwrap_or(0),
+            video: video.map(VideoInfo::new),
+        }
+    }
 }
 
-#[get("/workspace/list")]
-async fn list_workspaces(db: DbConn, user: User) -> Result<Json<Vec<WorkspaceInfo>>> {
-    let workspaces: Vec<Workspace> = db
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+/// Number of items on a page, from a `limit` query parameter. Lists are paginated with an
+/// `after` cursor: pass the ID of the last item to get the next page. A page with fewer
+/// items than the limit is the last one.
+fn page_size(limit: Option<i64>) -> i64 {
+    limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+}
+
+/// A page of the projects of a workspace in order of ID, after the `after` cursor
+fn load_project_infos(
+    conn: &DbConnection,
+    workspace_id: i32,
+    after: Option<i32>,
+    limit: i64,
+) -> Result<Vec<ProjectInfo>, diesel::result::Error> {
+    Ok(project::table
+        .left_join(video::table)
+        .filter(project::workspace.eq(workspace_id))
+        .filter(project::id.gt(after.unwrap_or(0)))
+        .order(project::id)
+        .limit(limit)
+        .select((project::all_columns, VIDEO_SUMMARY_COLUMNS.nullable()))
+        .load::<(Project, Option<VideoSummary>)>(conn)?
+        .into_iter()
+        .map(|(project, video)| ProjectInfo::new(project, video))
+        .collect())
+}
+
+/// The user's workspaces with their members and how many projects they have
+#[get("/workspace/list?<after>&<limit>")]
+async fn list_workspaces(
+    after: Option<i32>,
+    limit: Option<i64>,
+    db: DbConn,
+    user: User,
+    _scope: ReadScope,
+) -> Result<Json<Vec<WorkspaceInfo>>> {
+    let workspace_infos = db
         .run(move |conn| {
-            workspace::table
+            let workspaces: Vec<Workspace> = workspace::table
                 .inner_join(workspace_member::table)
                 .filter(workspace_member::user.eq(user.id))
+                .filter(workspace::id.gt(after.unwrap_or(0)))
+                .order(workspace::id)
+                .limit(page_size(limit))
                 .select(workspace::all_columns)
-                .load::<Workspace>(conn)
+                .load::<Workspace>(conn)?;
+            let workspace_ids: Vec<i32> = workspaces.iter().map(|w| w.id).collect();
+
+            let mut members: HashMap<i32, Vec<WorkspaceMemberInfo>> = HashMap::new();
+            for (workspace_id, username, display_name, role) in workspace_member::table
+                .inner_join(user::table)
+                .filter(workspace_member::workspace.eq_any(&workspace_ids))
+                .select((
+                    workspace_member::workspace,
+                    user::username,
+                    user::display_name,
+                    workspace_member::role,
+                ))
+                .load::<(i32, String, Option<String>, i32)>(conn)?
+            {
+                members
+                    .entry(workspace_id)
+                    .or_default()
+                    .push(WorkspaceMemberInfo {
+                        name: display_name.unwrap_or(username),
+                        role,
+                    });
+            }
+
+            // Members without two-factor authentication can see the workspace but not its
+            // projects, so they know to enable it
+            let visible_ids: Vec<i32> = workspaces
+                .iter()
+                .filter(|w| w.require_2fa <= user.totp_enabled)
+                .map(|w| w.id)
+                .collect();
+            let projects: HashMap<i32, i64> = project::table
+                .filter(project::workspace.eq_any(&visible_ids))
+                .group_by(project::workspace)
+                // The query builder doesn't allow aggregates next to other columns
+                .select((
+                    project::workspace,
+                    diesel::dsl::sql::<diesel::sql_types::BigInt>("COUNT(*)"),
+                ))
+                .load::<(i32, i64)>(conn)?
+                .into_iter()
+                .collect();
+
+            Ok(workspaces
+                .into_iter()
+                .map(|workspace| WorkspaceInfo {
+                    id: workspace.id,
+                    name: workspace.name,
+                    shared: workspace.shared,
+                    require_2fa: workspace.require_2fa != 0,
+                    members: members.remove(&workspace.id).unwrap_or_default(),
+                    projects: projects.get(&workspace.id).copied().unwrap_or(0),
+                })
+                .collect::<Vec<WorkspaceInfo>>())
         })
-        .await?;
+        .await
+        .map_err(|e: diesel::result::Error| Debug(e))?;
 
-    let mut workspace_infos: Vec<WorkspaceInfo> = Vec::new();
-    for workspace in workspaces {
-        let workspace_clone = workspace.clone();
-        let members: Vec<(WorkspaceMember, User)> = db
-            .run(move |conn| {
-                WorkspaceMember::belonging_to(&workspace_clone)
-                    .inner_join(user::table)
-                    .load::<(WorkspaceMember, User)>(conn)
-            })
-            .await?;
-        let member_infos: Vec<WorkspaceMemberInfo> = members
-            .iter()
-            .map(|(member, user)| WorkspaceMemberInfo {
-                name: user
-                    .display_name
-                    .as_ref()
-                    .unwrap_or(&user.username)
-                    .to_string(),
-                role: member.role,
-            })
-            .collect();
-        let workspace_clone2 = workspace.clone(); // is there a better way to do this?
-        let projects: Vec<(Project, Video)> = db
-            .run(move |conn| {
-                Project::belonging_to(&workspace_clone2)
-                    .inner_join(schema::video::table)
-                    .load::<(Project, Video)>(conn)
-            })
-            .await?;
-
-        let project_infos: Vec<ProjectInfo> = projects
-            .iter()
-            .map(|(project, video)| ProjectInfo {
-                id: project.id,
-                workspace: project.workspace,
-                name: project.name.clone(),
-                source: video.source.clone(),
-                video: Some(VideoInfo {
-                    id: video.identifier.clone(),
-                    duration: video.duration.unwrap_or(0),
-                }),
-                thumbnail: format!("https://i.ytimg.com/vi/{}/mqdefault.jpg", video.identifier),
-                duration: video.duration.unwrap_or(0),
-            })
-            .collect();
+    Ok(Json(workspace_infos))
+}
 
-        workspace_infos.push(WorkspaceInfo {
-            id: workspace.id,
-            name: workspace.name,
-            shared: workspace.shared,
-            members: member_infos,
-            projects: project_infos,
-        });
+/// A page of the projects in a workspace
+#[get("/workspace/<workspace_id>/projects?<after>&<limit>")]
+async fn list_workspace_projects(
+    workspace_id: i32,
+    after: Option<i32>,
+    limit: Option<i64>,
+    user: User,
+    _scope: ReadScope,
+    db: DbConn,
+) -> Result<Json<Vec<ProjectInfo>>, Status> {
+    let (workspace, _) = get_workspace_as_member(&db, workspace_id, &user).await?;
+
+    let projects = db
+        .run(move |conn| load_project_infos(conn, workspace.id, after, page_size(limit)))
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(projects))
+}
+
+/// Get a workspace along with the user's role in it, like `get_project_as_member`.
+async fn get_workspace_as_member(
+    db: &DbConn,
+    workspace_id: i32,
+    user: &User,
+) -> Result<(Workspace, i32), Status> {
+    let user_id = user.id;
+    let totp_enabled = user.totp_enabled;
+    let (workspace, member_role): (Workspace, Option<i32>) = db
+        .run(move |conn| {
+            workspace::table
+                .left_join(
+                    workspace_member::table.on(workspace_member::workspace
+                        .eq(workspace::id)
+                        .and(workspace_member::user.eq(user_id))),
+                )
+                .filter(workspace::id.eq(workspace_id))
+                .filter(workspace::require_2fa.le(totp_enabled))
+                .select((workspace::all_columns, workspace_member::role.nullable()))
+                .first::<(Workspace, Option<i32>)>(conn)
+        })
+        .await
+        .map_err(|_| Status::NotFound)?;
+
+    if workspace.owner == user_id {
+        Ok((workspace, role::ADMIN))
+    } else {
+        member_role
+            .map(|role| (workspace, role))
+            .ok_or(Status::NotFound)
     }
+}
 
-    Ok(Json(workspace_infos))
+/// Lift a login lockout from a member of the workspace. Only workspace admins can do this.
+/// Lockouts of the addresses they logged in from expire on their own.
+#[post("/workspace/<workspace_id>/member/<username>/unlock")]
+async fn unlock_member(
+    workspace_id: i32,
+    username: String,
+    user: User,
+    _scope: AdminScope,
+    db: DbConn,
+) -> Result<(), Status> {
+    let (workspace, member_role) = get_workspace_as_member(&db, workspace_id, &user).await?;
+    if member_role != role::ADMIN {
+        return Err(Status::Forbidden);
+    }
+
+    let username_clone = username.clone();
+    let member_id = db
+        .run(move |conn| {
+            workspace_member::table
+                .inner_join(user::table)
+                .filter(workspace_member::workspace.eq(workspace.id))
+                .filter(user::username.eq(username_clone))
+                .select(user::id)
+                .first::<i32>(conn)
+        })
+        .await
+        .map_err(|_| Status::NotFound)?;
+
+    db.run(move |conn| clear_login_failures(conn, member_id, &username))
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct TwoFactorRequirementInfo {
+    required: bool,
+}
+
+/// Require all members of the workspace to use two-factor authentication.
+/// Members without it can no longer see the workspace until they enable it.
+#[post("/workspace/<workspace_id>/require_2fa", data = "<info>")]
+async fn set_workspace_require_2fa(
+    workspace_id: i32,
+    info: Json<TwoFactorRequirementInfo>,
+    user: User,
+    _scope: AdminScope,
+    db: DbConn,
+) -> Result<(), (Status, &'static str)> {
+    let (workspace, member_role) = get_workspace_as_member(&db, workspace_id, &user)
+        .await
+        .map_err(|status| (status, "Workspace not found"))?;
+    if member_role != role::ADMIN {
+        return Err((Status::Forbidden, "Only workspace admins can change this"));
+    }
+    // Don't let admins lock themselves out
+    if info.required && user.totp_enabled == 0 {
+        return Err((
+            Status::BadRequest,
+            "Enable two-factor authentication on your own account first",
+        ));
+    }
+
+    let required = info.required as i32;
+    db.run(move |conn| {
+        diesel::update(workspace::table.find(workspace.id))
+            .set(workspace::require_2fa.eq(required))
+            .execute(conn)
+    })
+    .await
+    .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+
+    Ok(())
 }
 
 #[get("/project/<id>")]
-async fn get_project(id: i32, user: User, db: DbConn) -> Result<Json<ProjectInfo>, Status> {
-    let (project, video): (Project, Video) = db
+async fn get_project(
+    id: i32,
+    user: User,
+    _scope: ReadScope,
+    db: DbConn,
+) -> Result<Json<ProjectInfo>, Status> {
+    let (project, _) = get_project_as_member(&db, id, &user).await?;
+    let video_id = project.video;
+    let video = db
         .run(move |conn| {
-            project::table
-                .inner_join(workspace::table.left_join(workspace_member::table))
-                .filter(workspace_member::user.eq(user.id))
-                .filter(project::id.eq(id))
-                .inner_join(schema::video::table)
-                .select((project::all_columns, video::all_columns))
-                .first::<(Project, Video)>(conn)
+            video::table
+                .filter(video::id.nullable().eq(video_id))
+                .select(VIDEO_SUMMARY_COLUMNS)
+                .first::<VideoSummary>(conn)
+                .optional()
         })
         .await
-        .map_err(|_| Status::NotFound)?;
+        .map_err(|_| Status::InternalServerError)?;
 
-    Ok(Json(ProjectInfo {
-        id: project.id,
-        workspace: project.workspace,
-        name: project.name,
-        source: video.source.clone(),
-        video: Some(VideoInfo {
-            id: video.identifier.clone(),
-            duration: video.duration.unwrap_or(0),
-        }),
-        thumbnail: format!("https://i.ytimg.com/vi/{}/mqdefault.jpg", video.identifier),
-        duration: video.duration.unwrap_or(0),
-    }))
+    Ok(Json(ProjectInfo::new(project, video)))
 }
 
 #[derive(Debug, Deserialize)]
@@ -185,90 +495,334 @@ async fn get_project(id: i32, user: User, db: DbConn) -> Result<Json<ProjectInfo
 struct ProjectCreationInfo {
     name: String,
     workspace: i32,
-    video: String,
+    /// YouTube ID. Can be left out and attached later with `set_project_video`.
+    video: Option<String>,
 }
 
 #[post("/project/create", data = "<project>")]
 async fn create_project(
     project: Json<ProjectCreationInfo>,
     user: User,
+    _scope: WriteScope,
     db: DbConn,
     queue: &State<Sender<SubtitleEvent>>,
 ) -> Result<String, Status> {
-    let workspace_id = project.workspace;
-    let user_is_in_workspace = db
-        .run(move |conn| {
-            workspace_member::table
-                .filter(workspace_member::user.eq(user.id))
-                .filter(workspace_member::workspace.eq(workspace_id))
-                .first::<WorkspaceMember>(conn)
-        })
+    get_workspace_as_member(&db, project.workspace, &user)
         .await
-        .is_ok();
-
-    if !user_is_in_workspace {
-        return Err(Status::Forbidden);
-    }
+        .map_err(|_| Status::Forbidden)?;
 
     let project = project.into_inner();
 
-    match youtube_video_exists(&project.video).await {
-        Ok(false) | Err(_) => {
-            return Err(Status::NotFound);
-        }
-        _ => {}
-    }
+    let new_video = match &project.video {
+        Some(youtube_id) => {
+            let language = match find_youtube_video(youtube_id).await {
+                Ok(Some(video)) => video.language(),
+                Ok(None) | Err(_) => {
+                    return Err(Status::NotFound);
+                }
+            };
 
-    let new_video = NewVideo {
-        identifier: project.video.clone(),
-        source: "youtube".to_string(),
-        duration: None,
-        waveform: None,
+            Some(NewVideo {
+                identifier: youtube_id.clone(),
+                source: "youtube".to_string(),
+                duration: None,
+                waveform: None,
+                language,
+            })
+        }
+        None => None,
     };
-    let video_id = db
-        .run(move |conn| {
-            let result = diesel::insert_into(video::table)
-                .values(new_video)
-                .execute(conn);
 
-            result?;
-
-            diesel::select(last_insert_rowid).get_result::<i32>(conn)
+    let (name, workspace) = (project.name.clone(), project.workspace);
+    let project_id = db
+        .run(move |conn| {
+            conn.transaction(|| {
+                let video_id = match new_video {
+                    Some(new_video) => Some(insert_returning_id!(
+                        conn,
+                        diesel::insert_into(video::table).values(new_video),
+                        video::id
+                    )?),
+                    None => None,
+                };
+                insert_returning_id!(
+                    conn,
+                    diesel::insert_into(project::table).values(NewProject {
+                        name,
+                        workspace,
+                        video: video_id,
+                    }),
+                    project::id
+                )
+            })
         })
         .await
-        .map_err(|_| Status::InternalServerError)?;
+        .map_err(|_: diesel::result::Error| Status::InternalServerError)?;
 
-    let new_project = NewProject {
-        name: project.name.clone(),
-        workspace: project.workspace,
-        video: Some(video_id),
-    };
+    if let Some(youtube_id) = project.video {
+        spawn_waveform_generation(db, queue.inner().to_owned(), project_id, youtube_id);
+    }
 
-    let project_id = db
-        .run(move |conn| {
-            let result = diesel::insert_into(project::table)
-                .values(new_project)
-                .execute(conn);
+    Ok(project_id.to_string())
+}
 
-            result?;
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct ProjectTimingInfo {
+    /// Like "25", "29.97" or "30000/1001". Without one, times are only in milliseconds.
+    frame_rate: Option<String>,
+    #[serde(default)]
+    drop_frame: bool,
+    #[serde(default)]
+    snap_to_frames: bool,
+}
 
-            diesel::select(last_insert_rowid).get_result::<i32>(conn)
-        })
+/// Set the frame rate and timecode format of a project. Existing cues keep their times, use
+/// `uptitle-server requantise` to snap them to a new frame rate.
+#[put("/project/<project_id>/timing", data = "<info>")]
+async fn set_project_timing(
+    project_id: i32,
+    info: Json<ProjectTimingInfo>,
+    user: User,
+    _scope: WriteScope,
+    db: DbConn,
+) -> Result<(), (Status, &'static str)> {
+    let (project, _) = get_project_as_member(&db, project_id, &user)
         .await
-        .map_err(|_| Status::InternalServerError)?;
+        .map_err(|_| (Status::NotFound, "Project not found"))?;
 
-    let sender = queue.inner().to_owned();
-    let youtube_id = project.video.clone();
+    let frame_rate = match &info.frame_rate {
+        Some(rate) => {
+            Some(FrameRate::parse(rate).map_err(|message| (Status::BadRequest, message))?)
+        }
+        None => None,
+    };
+    if info.drop_frame && !frame_rate.is_some_and(|rate| rate.supports_drop_frame()) {
+        return Err((
+            Status::BadRequest,
+            "Drop-frame timecode only exists for 29.97 and 59.94",
+        ));
+    }
+    if info.snap_to_frames && frame_rate.is_none() {
+        return Err((Status::BadRequest, "Snapping to frames needs a frame rate"));
+    }
+
+    let (drop_frame, snap_to_frames) = (info.drop_frame, info.snap_to_frames);
+    db.run(move |conn| {
+        diesel::update(project::table.find(project.id))
+            .set((
+                project::frame_rate_num.eq(frame_rate.map(|rate| rate.num)),
+                project::frame_rate_den.eq(frame_rate.map(|rate| rate.den)),
+                project::drop_frame.eq(drop_frame as i32),
+                project::snap_to_frames.eq(snap_to_frames as i32),
+            ))
+            .execute(conn)
+    })
+    .await
+    .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+
+    Ok(())
+}
+
+/// Generate the waveform of a project's video in the background, and let the project's
+/// clients know when it's ready
+fn spawn_waveform_generation(
+    db: DbConn,
+    sender: Sender<SubtitleEvent>,
+    project_id: i32,
+    youtube_id: String,
+) {
     task::spawn(async move {
-        if let Ok(()) = download_youtube_audio(db, youtube_id.as_str()).await {
+        if let Ok(()) = download_youtube_audio(&db, youtube_id.as_str()).await {
             let _ = sender.send(SubtitleEvent {
                 info: SubtitleEventType::WaveformReady,
                 project: project_id,
             });
         }
+
+        let video_id = db
+            .run(move |conn| {
+                project::table
+                    .find(project_id)
+                    .select(project::video)
+                    .first::<Option<i32>>(conn)
+            })
+            .await;
+        if let Ok(Some(video_id)) = video_id {
+            let _ = db
+                .run(move |conn| detect_speech_regions(conn, video_id, &SpeechSettings::default()))
+                .await;
+            run_shot_change_detection(&db, &sender, project_id, video_id, DEFAULT_SCENE_THRESHOLD)
+                .await;
+        }
     });
+}
 
-    Ok(project_id.to_string())
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct ProjectVideoInfo {
+    /// YouTube ID
+    video: String,
+    /// Multiply all subtitle timings by this, like for a video at a different speed
+    scale: Option<f64>,
+    /// Milliseconds to move all subtitles by after scaling, like for a cut with a longer intro
+    offset: Option<i32>,
+}
+
+/// Scale and then move a subtitle time, clamped to the times that can be stored
+fn remap_time(time: i32, scale: f64, offset: i32) -> i32 {
+    // Float to integer casts saturate, so this can't overflow
+    let scaled = (time as f64 * scale).round() as i64;
+    scaled
+        .saturating_add(offset as i64)
+        .clamp(0, i32::MAX as i64) as i32
+}
+
+/// Attach a video to a project, or replace its video. The waveform is generated again, and the
+/// subtitles can be remapped to the new video's timing in a single undoable operation. Remaps
+/// that squash a subtitle to nothing at either end of the video are rejected. The old video is
+/// left for `admin_delete_orphaned_videos`.
+#[put("/project/<project_id>/video", data = "<info>")]
+async fn set_project_video(
+    project_id: i32,
+    info: Json<ProjectVideoInfo>,
+    user: User,
+    _scope: WriteScope,
+    db: DbConn,
+    queue: &State<Sender<SubtitleEvent>>,
+) -> Result<Json<ProjectInfo>, (Status, &'static str)> {
+    let (project, _) = get_project_as_member(&db, project_id, &user)
+        .await
+        .map_err(|_| (Status::NotFound, "Project not found"))?;
+    let info = info.into_inner();
+
+    let scale = info.scale.unwrap_or(1.0);
+    if !scale.is_finite() || scale <= 0.0 {
+        return Err((Status::BadRequest, "Scale must be a positive number"));
+    }
+    let offset = info.offset.unwrap_or(0);
+    let remap = scale != 1.0 || offset != 0;
+
+    let language = match find_youtube_video(&info.video).await {
+        Ok(Some(video)) => video.language(),
+        Ok(None) | Err(_) => return Err((Status::NotFound, "Video not found")),
+    };
+
+    let youtube_id = info.video.clone();
+    let language_clone = language.clone();
+    let changed: Option<(i32, Vec<Subtitle>)> = db
+        .run(move |conn| {
+            conn.transaction(|| {
+                if remap && !other_users_locks(conn, project.id, user.id)?.is_empty() {
+                    return Ok(None);
+                }
+
+                let video_id = insert_returning_id!(
+                    conn,
+                    diesel::insert_into(video::table).values(NewVideo {
+                        identifier: youtube_id,
+                        source: "youtube".to_string(),
+                        duration: None,
+                        waveform: None,
+                        language: language_clone,
+                    }),
+                    video::id
+                )?;
+                diesel::update(project::table.find(project.id))
+                    .set(project::video.eq(Some(video_id)))
+                    .execute(conn)?;
+                if !remap {
+                    return Ok(Some((video_id, Vec::new())));
+                }
+
+                let originals = subtitle::table
+                    .filter(subtitle::project.eq(project.id))
+                    .load::<Subtitle>(conn)?;
+                let timestamp = unix_timestamp();
+                let mut revisions = Vec::new();
+                let mut remapped = Vec::new();
+                for original in originals {
+                    let subtitle = Subtitle {
+                        start: remap_time(original.start, scale, offset),
+                        end: remap_time(original.end, scale, offset),
+                        ..original.clone()
+                    };
+                    // Moved entirely before the start or past the end, which undo couldn't fix
+                    if subtitle.end <= subtitle.start && original.end > original.start {
+                        return Err(diesel::result::Error::RollbackTransaction);
+                    }
+                    diesel::update(subtitle::table.find(subtitle.id))
+                        .set((
+                            subtitle::start.eq(subtitle.start),
+                            subtitle::end.eq(subtitle.end),
+                        ))
+                        .execute(conn)?;
+                    revisions.push(NewSubtitleRevision::new(
+                        "edit",
+                        user.id,
+                        timestamp,
+                        Some(&original),
+                        Some(&subtitle),
+                    ));
+                    remapped.push(subtitle);
+                }
+                if !revisions.is_empty() {
+                    record_operation(conn, project.id, user.id, &revisions)?;
+                }
+                Ok(Some((video_id, remapped)))
+            })
+        })
+        .await
+        .map_err(|e| match e {
+            diesel::result::Error::RollbackTransaction => (
+                Status::BadRequest,
+                "Scale and offset would move subtitles outside of the video",
+            ),
+            _ => (Status::InternalServerError, "An internal error occured"),
+        })?;
+    let (video_id, remapped) =
+        changed.ok_or((Status::Locked, "Subtitles are locked by another user"))?;
+
+    for subtitle in remapped {
+        let _ = queue.send(SubtitleEvent {
+            info: SubtitleEventType::SubtitleEdit(EditEventData {
+                subtitle: subtitle.id,
+                start: Some(subtitle.start),
+                end: Some(subtitle.end),
+                text: None,
+                status: subtitle.status,
+                origin: None,
+            }),
+            project: project.id,
+        });
+    }
+    let _ = queue.send(SubtitleEvent {
+        info: SubtitleEventType::VideoChange(VideoInfo {
+            id: info.video.clone(),
+            language: language.clone(),
+            ..Default::default()
+        }),
+        project: project.id,
+    });
+    spawn_waveform_generation(db, queue.inner().to_owned(), project.id, info.video.clone());
+
+    let project_info = ProjectInfo::new(
+        Project {
+            video: Some(video_id),
+            ..project
+        },
+        Some(VideoSummary {
+            id: video_id,
+            source: "youtube".to_string(),
+            identifier: info.video,
+            duration: None,
+            metadata: VideoMetadata {
+                language,
+                ..Default::default()
+            },
+        }),
+    );
+    Ok(Json(project_info))
 }
 
 #[derive(Deserialize, Debug)]
@@ -276,6 +830,30 @@ async fn create_project(
 #[serde(rename_all = "camelCase")]
 struct YoutubeResponseTest {
     page_info: PageInfo,
+    #[serde(default)]
+    items: Vec<YoutubeVideo>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+struct YoutubeVideo {
+    snippet: Option<YoutubeSnippet>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+struct YoutubeSnippet {
+    default_audio_language: Option<String>,
+    default_language: Option<String>,
+}
+
+impl YoutubeVideo {
+    /// Language of the original audio, if the uploader set it
+    fn language(self) -> Option<String> {
+        self.snippet
+            .and_then(|snippet| snippet.default_audio_language.or(snippet.default_language))
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -287,9 +865,12 @@ struct PageInfo {
     results_per_page: u32,
 }
 
-async fn youtube_video_exists(youtube_id: &str) -> core::result::Result<bool, reqwest::Error> {
+/// Look a video up with the YouTube Data API. `None` if it doesn't exist.
+async fn find_youtube_video(
+    youtube_id: &str,
+) -> core::result::Result<Option<YoutubeVideo>, reqwest::Error> {
     let url = format!(
-        "https://www.googleapis.com/youtube/v3/videos?part=id&id={}&key={}",
+        "https://www.googleapis.com/youtube/v3/videos?part=snippet&id={}&key={}",
         youtube_id,
         env::var("YOUTUBE_API_KEY").unwrap()
     );
@@ -299,24 +880,64 @@ async fn youtube_video_exists(youtube_id: &str) -> core::result::Result<bool, re
         .json::<YoutubeResponseTest>()
         .await?;
 
-    Ok(response.page_info.total_results > 0)
+    if response.page_info.total_results == 0 {
+        return Ok(None);
+    }
+    Ok(response.items.into_iter().next())
 }
 
 // Status as the error type is pretty much useless, I'll probably change it later
-async fn download_youtube_audio(db: DbConn, youtube_id: &str) -> Result<(), Status> {
+async fn download_youtube_audio(db: &DbConn, youtube_id: &str) -> Result<(), Status> {
     let start = Instant::now();
 
     let client = ytextract::Client::new();
 
-    // Find available streams
-    let stream = client
-        .streams(
+    let video = client
+        .video(
             youtube_id
                 .parse()
                 .map_err(|_| Status::InternalServerError)?,
         )
         .await
+        .map_err(|_| Status::InternalServerError)?;
+    // Find available streams
+    let streams: Vec<ytextract::Stream> = video
+        .streams()
+        .await
         .map_err(|_| Status::InternalServerError)?
+        .collect();
+
+    // The language comes from the YouTube Data API when the video is registered
+    let mut metadata = media::youtube_metadata(&video, &streams);
+    // YouTube only knows the rounded frame rate, the stream has the exact one. If it can't be
+    // probed, the frame rate stays unknown rather than stored rounded.
+    if let Some(url) = media::youtube_video_url(&streams) {
+        let frame_rate = task::spawn_blocking(move || media::probe_frame_rate(&url))
+            .await
+            .ok()
+            .and_then(Result::ok);
+        metadata.frame_rate_num = frame_rate.map(|(num, _)| num);
+        metadata.frame_rate_den = frame_rate.map(|(_, den)| den);
+    }
+    let identifier = youtube_id.to_owned();
+    db.run(move |conn| {
+        let language = video::table
+            .filter(video::identifier.eq(&identifier))
+            .select(video::language)
+            .first::<Option<String>>(conn)
+            .optional()?
+            .flatten();
+        metadata.language = language;
+        diesel::update(video::table)
+            .filter(video::identifier.eq(identifier))
+            .set(&metadata)
+            .execute(conn)
+    })
+    .await
+    .map_err(|_| Status::InternalServerError)?;
+
+    let stream = streams
+        .into_iter()
         // Filter to audio-only and find one with sample rate 48000...
         // 44100 results in waveform alignment issues (makes for 400.9 px per second)
         .filter(|stream| match stream {
@@ -332,23 +953,44 @@ async fn download_youtube_audio(db: DbConn, youtube_id: &str) -> Result<(), Stat
         .expect("Stream has no duration")
         .as_millis() as i32;
 
-    let waveform_filename = format!("/tmp/uptitle-{}.dat", youtube_id);
-
     // Download and convert audio in background
-    let stream_url = stream.url().to_string();
-    let waveform_filename2 = waveform_filename.to_owned();
+    let waveform = generate_waveform(stream.url().to_string()).await?;
 
-    // doesn't work for some longer (>30 min) videos, unclear why
-    let waveform = task::spawn_blocking(move || {
-        let mut waveformer = Command::new("audiowaveform")
-            .args([
-                "--input-format",
-                "wav",
-                "-o",
-                &waveform_filename2,
-                "-b",
-                "8",
-                "--pixels-per-second",
+    // Update database
+    let youtube_id = youtube_id.to_owned();
+    db.run(move |conn| {
+        diesel::update(video::table)
+            .filter(video::identifier.eq(youtube_id))
+            .set((
+                video::waveform.eq(Some(waveform)),
+                video::duration.eq(Some(duration_ms)),
+            ))
+            .execute(conn)
+    })
+    .await
+    .map_err(|_| Status::InternalServerError)?;
+
+    println!("done in {:?}", start.elapsed());
+
+    Ok(())
+}
+
+/// Generate a waveform of the audio of a URL or file that ffmpeg can read
+async fn generate_waveform(input: String) -> Result<Vec<u8>, Status> {
+    let waveform_filename = format!("/tmp/uptitle-{}.dat", generate_token());
+    let waveform_filename2 = waveform_filename.to_owned();
+
+    // doesn't work for some longer (>30 min) videos, unclear why
+    let waveform = task::spawn_blocking(move || {
+        let mut waveformer = Command::new("audiowaveform")
+            .args([
+                "--input-format",
+                "wav",
+                "-o",
+                &waveform_filename2,
+                "-b",
+                "8",
+                "--pixels-per-second",
                 "400",
             ])
             .stdin(Stdio::piped())
@@ -356,7 +998,7 @@ async fn download_youtube_audio(db: DbConn, youtube_id: &str) -> Result<(), Stat
             .expect("audiowaveform failed");
 
         Command::new("ffmpeg")
-            .args(["-i", &stream_url, "-f", "wav", "-"])
+            .args(["-i", &input, "-f", "wav", "-"])
             .stdout(waveformer.stdin.take().unwrap())
             .output()
             .expect("ffmpeg failed");
@@ -369,26 +1011,102 @@ async fn download_youtube_audio(db: DbConn, youtube_id: &str) -> Result<(), Stat
     .map_err(|_| Status::InternalServerError)?
     .map_err(|_| Status::InternalServerError)?;
 
-    // Update database
-    let youtube_id = youtube_id.to_owned();
+    // Clean up
+    std::fs::remove_file(&waveform_filename).expect("could not delete file");
+
+    Ok(waveform)
+}
+
+/// Something ffmpeg can read the picture of: the smallest video stream of YouTube videos, or the
+/// path of files
+async fn video_input(source: String, identifier: String) -> Result<String, Status> {
+    if source != "youtube" {
+        return Ok(identifier);
+    }
+
+    let client = ytextract::Client::new();
+    let streams = client
+        .streams(
+            identifier
+                .parse()
+                .map_err(|_| Status::InternalServerError)?,
+        )
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    streams
+        .filter_map(|stream| match stream {
+            ytextract::Stream::Video(video) => Some(video),
+            _ => None,
+        })
+        .min_by_key(|video| video.height())
+        .map(|video| video.url().to_string())
+        .ok_or(Status::NotFound)
+}
+
+/// Detect the shot changes of a video and replace the ones stored for it. Returns how many
+/// were found.
+async fn detect_shot_changes(db: &DbConn, video_id: i32, threshold: f64) -> Result<usize, Status> {
+    let (source, identifier) = db
+        .run(move |conn| {
+            video::table
+                .find(video_id)
+                .select((video::source, video::identifier))
+                .first::<(String, String)>(conn)
+        })
+        .await
+        .map_err(|_| Status::NotFound)?;
+    let input = video_input(source, identifier).await?;
+
+    let times = task::spawn_blocking(move || media::detect_shot_changes(&input, threshold))
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .map_err(|e| {
+            println!("shot change detection of video {} failed: {}", video_id, e);
+            Status::InternalServerError
+        })?;
+
+    let count = times.len();
+    let shot_changes: Vec<ShotChange> = times
+        .into_iter()
+        .map(|time| ShotChange {
+            video: video_id,
+            time,
+        })
+        .collect();
     db.run(move |conn| {
-        diesel::update(video::table)
-            .filter(video::identifier.eq(youtube_id))
-            .set((
-                video::waveform.eq(Some(waveform)),
-                video::duration.eq(Some(duration_ms)),
-            ))
-            .execute(conn)
+        conn.transaction(|| {
+            diesel::delete(shot_change::table)
+                .filter(shot_change::video.eq(video_id))
+                .execute(conn)?;
+            diesel::insert_into(shot_change::table)
+                .values(&shot_changes)
+                .execute(conn)?;
+            diesel::update(video::table.find(video_id))
+                .set(video::shot_changes_detected.eq(Some(unix_timestamp())))
+                .execute(conn)
+        })
     })
     .await
-    .map_err(|_| Status::InternalServerError)?;
-
-    // Clean up
-    std::fs::remove_file(&waveform_filename).expect("could not delete file");
+    .map_err(|_: diesel::result::Error| Status::InternalServerError)?;
 
-    println!("done in {:?}", start.elapsed());
+    Ok(count)
+}
 
-    Ok(())
+/// Detect the shot changes of a project's video, and let the project's clients know when
+/// they're ready
+async fn run_shot_change_detection(
+    db: &DbConn,
+    sender: &Sender<SubtitleEvent>,
+    project_id: i32,
+    video_id: i32,
+    threshold: f64,
+) {
+    if let Ok(count) = detect_shot_changes(db, video_id, threshold).await {
+        let _ = sender.send(SubtitleEvent {
+            info: SubtitleEventType::ShotChangesReady(ShotChangesReadyEventData { count }),
+            project: project_id,
+        });
+    }
 }
 
 #[get("/waveform/<youtube_id>")]
@@ -409,677 +1127,6265 @@ async fn get_waveform(youtube_id: String, db: DbConn) -> Result<Vec<u8>, Status>
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(crate = "rocket::serde")]
-struct CreateEventData {
-    pub subtitle: i32,
-    pub text: String,
-    pub start: i32,
-    pub end: i32,
-}
+/// How close to a shot change, in frames, a cue boundary can be before it's flagged or snapped
+const SHOT_CHANGE_FRAMES: i64 = 6;
 
-#[derive(Debug, Clone, Serialize)]
-#[serde(crate = "rocket::serde")]
-struct EditEventData {
-    pub subtitle: i32,
-    pub text: Option<String>,
-    pub start: Option<i32>,
-    pub end: Option<i32>,
+/// For counting frames to shot changes when neither the project nor its video has a frame rate
+const DEFAULT_FRAME_RATE: FrameRate = FrameRate { num: 25, den: 1 };
+
+/// Shot changes of a project's video, or `None` if it has no video or they haven't been
+/// detected yet. Distances are counted in frames of the project, or else of the video.
+fn load_shot_changes(conn: &DbConnection, project: &Project) -> QueryResult<Option<ShotChanges>> {
+    let video_id = match project.video {
+        Some(video_id) => video_id,
+        None => return Ok(None),
+    };
+    let (detected, frame_rate_num, frame_rate_den) = video::table
+        .find(video_id)
+        .select((
+            video::shot_changes_detected,
+            video::frame_rate_num,
+            video::frame_rate_den,
+        ))
+        .first::<(Option<i64>, Option<i32>, Option<i32>)>(conn)?;
+    if detected.is_none() {
+        return Ok(None);
+    }
+
+    let times = shot_change::table
+        .filter(shot_change::video.eq(video_id))
+        .select(shot_change::time)
+        .order(shot_change::time.asc())
+        .load::<i32>(conn)?;
+    let rate = project
+        .timecode()
+        .map(|timecode| timecode.rate)
+        .or_else(|| FrameRate::new(frame_rate_num?, frame_rate_den?).ok())
+        .unwrap_or(DEFAULT_FRAME_RATE);
+    Ok(Some(ShotChanges { times, rate }))
 }
 
-#[derive(Debug, Clone, Serialize)]
-#[serde(crate = "rocket::serde")]
-struct DeleteEventData {
-    pub subtitle: i32,
+/// Times of the shot changes in a project's video, in milliseconds
+#[get("/project/<project_id>/shot_changes")]
+async fn get_shot_changes(
+    project_id: i32,
+    user: User,
+    _scope: ReadScope,
+    db: DbConn,
+) -> Result<Json<Vec<i32>>, (Status, &'static str)> {
+    let (project, _) = get_project_as_member(&db, project_id, &user)
+        .await
+        .map_err(|_| (Status::NotFound, "Project not found"))?;
+    if project.video.is_none() {
+        return Err((Status::NotFound, "Project has no video"));
+    }
+
+    let shot_changes = db
+        .run(move |conn| load_shot_changes(conn, &project))
+        .await
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?
+        .ok_or((Status::NotFound, "Shot changes haven't been detected yet"))?;
+    Ok(Json(shot_changes.times))
 }
 
-#[derive(Debug, Clone, Serialize)]
-#[serde(crate = "rocket::serde")]
-enum SubtitleEventType {
-    WaveformReady,
-    SubtitleCreate(CreateEventData),
-    SubtitleEdit(EditEventData),
-    SubtitleDelete(DeleteEventData),
+/// Detect the shot changes of a project's video again, like with a different threshold.
+/// Clients get a `shot_changes_ready` event when it's done.
+#[post("/project/<project_id>/shot_changes/detect?<threshold>")]
+async fn redetect_shot_changes(
+    project_id: i32,
+    threshold: Option<f64>,
+    user: User,
+    _scope: WriteScope,
+    db: DbConn,
+    queue: &State<Sender<SubtitleEvent>>,
+) -> Result<Status, (Status, &'static str)> {
+    let (project, _) = get_project_as_member(&db, project_id, &user)
+        .await
+        .map_err(|_| (Status::NotFound, "Project not found"))?;
+    let video_id = project
+        .video
+        .ok_or((Status::NotFound, "Project has no video"))?;
+    let threshold = threshold.unwrap_or(DEFAULT_SCENE_THRESHOLD);
+    if !(threshold > 0.0 && threshold < 1.0) {
+        return Err((Status::BadRequest, "Threshold must be between 0 and 1"));
+    }
+
+    let sender = queue.inner().to_owned();
+    task::spawn(async move {
+        run_shot_change_detection(&db, &sender, project.id, video_id, threshold).await;
+    });
+    Ok(Status::Accepted)
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Serialize)]
 #[serde(crate = "rocket::serde")]
-struct SubtitleEvent {
-    info: SubtitleEventType,
-    project: i32,
+struct ShotChangeWarning {
+    subtitle: i32,
+    /// "start" or "end"
+    boundary: &'static str,
+    time: i32,
+    shot_change: i32,
+    /// How many frames the boundary is away from the shot change
+    frames: i64,
 }
 
-#[get("/project/<project_id>/events")]
-async fn events(
+/// Cues that start or end a few frames before or after a shot change, instead of on it or
+/// clearly away from it
+#[get("/project/<project_id>/shot_changes/check?<frames>")]
+async fn check_shot_changes(
     project_id: i32,
-    queue: &State<Sender<SubtitleEvent>>,
-    mut end: Shutdown,
-) -> EventStream![] {
-    let mut rx = queue.subscribe();
-    EventStream! {
-        loop {
-            let msg = select! {
-                msg = rx.recv() => match msg {
-                    Ok(msg) => msg,
-                    Err(RecvError::Closed) => break,
-                    Err(RecvError::Lagged(_)) => continue,
-                },
-                _ = &mut end => break,
-            };
+    frames: Option<i64>,
+    user: User,
+    _scope: ReadScope,
+    db: DbConn,
+) -> Result<Json<Vec<ShotChangeWarning>>, (Status, &'static str)> {
+    let (project, _) = get_project_as_member(&db, project_id, &user)
+        .await
+        .map_err(|_| (Status::NotFound, "Project not found"))?;
+    let frames = frames.unwrap_or(SHOT_CHANGE_FRAMES);
+    if frames < 1 {
+        return Err((Status::BadRequest, "Frames must be at least 1"));
+    }
 
-            if msg.project != project_id {
-                continue;
+    let (shot_changes, subtitles) = db
+        .run(move |conn| {
+            let subtitles = Subtitle::belonging_to(&project)
+                .order(subtitle::start.asc())
+                .load::<Subtitle>(conn)?;
+            Ok((load_shot_changes(conn, &project)?, subtitles))
+        })
+        .await
+        .map_err(|_: diesel::result::Error| {
+            (Status::InternalServerError, "An internal error occured")
+        })?;
+    let shot_changes =
+        shot_changes.ok_or((Status::NotFound, "Shot changes haven't been detected yet"))?;
+
+    let mut warnings = Vec::new();
+    for subtitle in subtitles {
+        for (boundary, time) in [("start", subtitle.start), ("end", subtitle.end)] {
+            if let Some((shot_change, distance)) = shot_changes.straddles(time, frames) {
+                warnings.push(ShotChangeWarning {
+                    subtitle: subtitle.id,
+                    boundary,
+                    time,
+                    shot_change,
+                    frames: distance,
+                });
             }
-
-            yield Event::json(&msg).event(match msg.info {
-                SubtitleEventType::WaveformReady => "waveform_ready",
-                SubtitleEventType::SubtitleEdit(_) => "subtitle_edit",
-                SubtitleEventType::SubtitleCreate(_) => "subtitle_create",
-                SubtitleEventType::SubtitleDelete(_) => "subtitle_delete",
-            });
         }
     }
+    Ok(Json(warnings))
 }
 
-#[get("/project/<id>/subtitle/list")]
-async fn get_subtitle_list(id: i32, user: User, db: DbConn) -> Result<Json<Vec<Subtitle>>, Status> {
-    let project: Project = db
-        .run(move |conn| {
-            project::table
-                .inner_join(workspace::table.left_join(workspace_member::table))
-                .filter(workspace_member::user.eq(user.id))
-                .filter(project::id.eq(id))
-                .select(project::all_columns)
-                .first::<Project>(conn)
+/// Find the speech in a video's waveform and replace the speech regions stored for it
+fn detect_speech_regions(
+    conn: &DbConnection,
+    video_id: i32,
+    settings: &SpeechSettings,
+) -> Result<Vec<SpeechRegion>, (Status, &'static str)> {
+    let internal_error =
+        |_: diesel::result::Error| (Status::InternalServerError, "An internal error occured");
+    let waveform = video::table
+        .find(video_id)
+        .select(video::waveform)
+        .first::<Option<Vec<u8>>>(conn)
+        .map_err(internal_error)?
+        .ok_or((Status::NotFound, "The waveform hasn't been generated yet"))?;
+    let peaks =
+        Peaks::parse(&waveform).map_err(|message| (Status::InternalServerError, message))?;
+    let regions: Vec<SpeechRegion> = speech::detect_speech(&peaks, settings)
+        .into_iter()
+        .map(|(start, end)| SpeechRegion {
+            video: video_id,
+            start,
+            end,
         })
-        .await
-        .map_err(|_| Status::NotFound)?;
+        .collect();
 
-    let subtitles: Vec<Subtitle> = db
-        .run(move |conn| {
-            Subtitle::belonging_to(&project)
-                .order(subtitle::start.asc())
-                .load::<Subtitle>(conn)
-        })
+    conn.transaction(|| {
+        diesel::delete(speech_region::table)
+            .filter(speech_region::video.eq(video_id))
+            .execute(conn)?;
+        diesel::insert_into(speech_region::table)
+            .values(&regions)
+            .execute(conn)?;
+        diesel::update(video::table.find(video_id))
+            .set(video::speech_detected.eq(Some(unix_timestamp())))
+            .execute(conn)
+    })
+    .map_err(internal_error)?;
+    Ok(regions)
+}
+
+/// Speech regions of a video, or `None` if they haven't been detected yet
+fn load_speech_regions(
+    conn: &DbConnection,
+    video_id: i32,
+) -> QueryResult<Option<Vec<SpeechRegion>>> {
+    let detected = video::table
+        .find(video_id)
+        .select(video::speech_detected)
+        .first::<Option<i64>>(conn)?;
+    if detected.is_none() {
+        return Ok(None);
+    }
+    speech_region::table
+        .filter(speech_region::video.eq(video_id))
+        .order(speech_region::start.asc())
+        .load::<SpeechRegion>(conn)
+        .map(Some)
+}
+
+#[get("/project/<project_id>/speech")]
+async fn get_speech_regions(
+    project_id: i32,
+    user: User,
+    _scope: ReadScope,
+    db: DbConn,
+) -> Result<Json<Vec<SpeechRegion>>, (Status, &'static str)> {
+    let (project, _) = get_project_as_member(&db, project_id, &user)
         .await
-        .map_err(|_| Status::InternalServerError)?;
+        .map_err(|_| (Status::NotFound, "Project not found"))?;
+    let video_id = project
+        .video
+        .ok_or((Status::NotFound, "Project has no video"))?;
 
-    Ok(Json(subtitles))
+    let regions = db
+        .run(move |conn| load_speech_regions(conn, video_id))
+        .await
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?
+        .ok_or((Status::NotFound, "Speech hasn't been detected yet"))?;
+    Ok(Json(regions))
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(crate = "rocket::serde")]
-struct SubtitleCreationInfo {
-    start: i32,
-    end: i32,
-    text: String,
+struct SpeechDetectionInfo {
+    /// Audio quieter than this, in dBFS, is silence
+    threshold_db: Option<f64>,
+    /// Milliseconds a pause has to last to split speech
+    min_silence: Option<i32>,
+    /// Milliseconds a sound has to last to be speech
+    min_speech: Option<i32>,
 }
 
-#[post("/project/<id>/subtitle/create", data = "<info>")]
-async fn create_subtitle(
-    id: i32,
+/// Detect speech in a project's video again, with different thresholds than the defaults that
+/// are used when the waveform is generated
+#[post("/project/<project_id>/speech/detect", data = "<info>")]
+async fn detect_speech(
+    project_id: i32,
+    info: Json<SpeechDetectionInfo>,
     user: User,
-    info: Json<SubtitleCreationInfo>,
+    _scope: WriteScope,
     db: DbConn,
-    queue: &State<Sender<SubtitleEvent>>,
-) -> Result<String, Status> {
-    let project: Project = db
-        .run(move |conn| {
-            project::table
-                .inner_join(workspace::table.left_join(workspace_member::table))
-                .filter(workspace_member::user.eq(user.id))
-                .filter(project::id.eq(id))
-                .select(project::all_columns)
-                .first::<Project>(conn)
-        })
+) -> Result<Json<Vec<SpeechRegion>>, (Status, &'static str)> {
+    let (project, _) = get_project_as_member(&db, project_id, &user)
         .await
-        .map_err(|_| Status::NotFound)?;
-
-    let subtitle = NewSubtitle {
-        project: project.id,
-        start: info.start,
-        end: info.end,
-        text: info.text.clone(),
+        .map_err(|_| (Status::NotFound, "Project not found"))?;
+    let video_id = project
+        .video
+        .ok_or((Status::NotFound, "Project has no video"))?;
+
+    let defaults = SpeechSettings::default();
+    let settings = SpeechSettings {
+        threshold_db: info.threshold_db.unwrap_or(defaults.threshold_db),
+        min_silence: info.min_silence.unwrap_or(defaults.min_silence),
+        min_speech: info.min_speech.unwrap_or(defaults.min_speech),
     };
-    let subtitle_clone = subtitle.clone();
-
-    let new_id: i32 = db
-        .run(move |conn| {
-            let result = diesel::insert_into(schema::subtitle::table)
-                .values(&subtitle_clone)
-                .execute(conn);
-
-            result?;
+    if !(-90.0..0.0).contains(&settings.threshold_db) {
+        return Err((
+            Status::BadRequest,
+            "The threshold must be between -90 and 0 dB",
+        ));
+    }
+    if settings.min_silence < 0 || settings.min_speech < 0 {
+        return Err((Status::BadRequest, "Durations can't be negative"));
+    }
 
-            diesel::select(last_insert_rowid).get_result::<i32>(conn)
-        })
-        .await
-        .map_err(|_| Status::InternalServerError)?;
+    let regions = db
+        .run(move |conn| detect_speech_regions(conn, video_id, &settings))
+        .await?;
+    Ok(Json(regions))
+}
 
-    // Broadcast SSE
-    let _ = queue.send(SubtitleEvent {
-        info: SubtitleEventType::SubtitleCreate(CreateEventData {
-            subtitle: new_id,
-            start: subtitle.start,
-            end: subtitle.end,
-            text: subtitle.text,
-        }),
-        project: project.id,
-    });
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct CueLimitsInfo {
+    /// Milliseconds
+    min_duration: Option<i32>,
+    max_duration: Option<i32>,
+    min_gap: Option<i32>,
+}
 
-    Ok(new_id.to_string())
+impl CueLimitsInfo {
+    fn settings(&self) -> Result<CueSettings, (Status, &'static str)> {
+        let defaults = CueSettings::default();
+        let settings = CueSettings {
+            min_duration: self.min_duration.unwrap_or(defaults.min_duration),
+            max_duration: self.max_duration.unwrap_or(defaults.max_duration),
+            min_gap: self.min_gap.unwrap_or(defaults.min_gap),
+        };
+        if settings.min_duration <= 0 || settings.min_gap < 0 {
+            return Err((
+                Status::BadRequest,
+                "The minimum duration must be positive and the gap can't be negative",
+            ));
+        }
+        if [
+            settings.min_duration,
+            settings.max_duration,
+            settings.min_gap,
+        ]
+        .iter()
+        .any(|&limit| limit > CueSettings::MAX)
+        {
+            return Err((
+                Status::BadRequest,
+                "Durations and gaps can't be longer than an hour",
+            ));
+        }
+        // Otherwise a region just over the maximum can't be split into two cues that are long
+        // enough
+        if settings.max_duration < settings.min_duration * 2 {
+            return Err((
+                Status::BadRequest,
+                "The maximum duration must be at least twice the minimum duration",
+            ));
+        }
+        Ok(settings)
+    }
 }
 
-#[delete("/project/<project_id>/subtitle/<subtitle_id>")]
-async fn delete_subtitle(
+/// Create empty cues covering the speech in a project's video, leaving out the parts that
+/// already have cues. They're created in a single undoable operation.
+#[post("/project/<project_id>/speech/cues", data = "<info>")]
+async fn create_speech_cues(
     project_id: i32,
-    subtitle_id: i32,
+    info: Json<CueLimitsInfo>,
     user: User,
+    _scope: WriteScope,
     db: DbConn,
     queue: &State<Sender<SubtitleEvent>>,
-) -> Result<(), Status> {
-    let project: Project = db
-        .run(move |conn| {
-            project::table
-                .inner_join(workspace::table.left_join(workspace_member::table))
-                .filter(workspace_member::user.eq(user.id))
-                .filter(project::id.eq(project_id))
-                .select(project::all_columns)
-                .first::<Project>(conn)
-        })
+) -> Result<Json<Vec<i32>>, (Status, &'static str)> {
+    let (project, _) = get_project_as_member(&db, project_id, &user)
         .await
-        .map_err(|_| Status::NotFound)?;
+        .map_err(|_| (Status::NotFound, "Project not found"))?;
+    let video_id = project
+        .video
+        .ok_or((Status::NotFound, "Project has no video"))?;
+
+    let settings = info.settings()?;
 
-    let deleted_count: usize = db
+    let project_clone = project.clone();
+    let created: Option<Vec<Subtitle>> = db
         .run(move |conn| {
-            diesel::delete(subtitle::table)
-                .filter(subtitle::id.eq(subtitle_id))
-                .filter(subtitle::project.eq(project.id))
-                .execute(conn)
+            conn.transaction(|| {
+                let regions = match load_speech_regions(conn, video_id)? {
+                    Some(regions) => regions,
+                    None => return Ok(None),
+                };
+                let regions: Vec<(i32, i32)> = regions
+                    .into_iter()
+                    .map(|region| (region.start, region.end))
+                    .collect();
+                let taken: Vec<(i32, i32)> = subtitle::table
+                    .filter(subtitle::project.eq(project.id))
+                    .select((subtitle::start, subtitle::end))
+                    .load(conn)?;
+
+                let timestamp = unix_timestamp();
+                let mut created = Vec::new();
+                let mut revisions = Vec::new();
+                for (start, end) in speech::cues_from_regions(&regions, &taken, &settings) {
+                    let (start, end) = cue_times(&project_clone, start, end);
+                    let new_id = insert_returning_id!(
+                        conn,
+                        diesel::insert_into(subtitle::table).values(&NewSubtitle {
+                            project: project.id,
+                            start,
+                            end,
+                            text: String::new(),
+                            origin: None,
+                            confidence: None,
+                        }),
+                        subtitle::id
+                    )?;
+                    let subtitle = subtitle::table.find(new_id).first::<Subtitle>(conn)?;
+                    revisions.push(NewSubtitleRevision::new(
+                        "create",
+                        user.id,
+                        timestamp,
+                        None,
+                        Some(&subtitle),
+                    ));
+                    created.push(subtitle);
+                }
+                if !revisions.is_empty() {
+                    record_operation(conn, project.id, user.id, &revisions)?;
+                }
+                Ok(Some(created))
+            })
         })
         .await
-        .map_err(|_| Status::InternalServerError)?;
-
-    if deleted_count == 0 {
-        return Err(Status::NotFound);
+        .map_err(|_: diesel::result::Error| {
+            (Status::InternalServerError, "An internal error occured")
+        })?;
+    let created = created.ok_or((Status::NotFound, "Speech hasn't been detected yet"))?;
+
+    for subtitle in &created {
+        let _ = queue.send(SubtitleEvent {
+            info: SubtitleEventType::SubtitleCreate(CreateEventData::new(subtitle)),
+            project: project_id,
+        });
     }
-
-    // Broadcast SSE
-    let _ = queue.send(SubtitleEvent {
-        info: SubtitleEventType::SubtitleDelete(DeleteEventData {
-            subtitle: subtitle_id,
-        }),
-        project: project.id,
-    });
-
-    Ok(())
+    Ok(Json(
+        created.into_iter().map(|subtitle| subtitle.id).collect(),
+    ))
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(crate = "rocket::serde")]
-struct SubtitleEditInfo {
-    start: Option<i32>,
-    end: Option<i32>,
-    text: Option<String>,
+struct TranscriptAlignmentInfo {
+    text: String,
+    /// Make a cue of every sentence instead of every line
+    #[serde(default)]
+    sentences: bool,
+    /// Delete the project's subtitles first. Without this, the project has to be empty.
+    #[serde(default)]
+    replace: bool,
+    /// Characters per second viewers can read
+    max_cps: Option<f64>,
+    #[serde(flatten)]
+    limits: CueLimitsInfo,
 }
 
-#[patch("/project/<project_id>/subtitle/<subtitle_id>", data = "<info>")]
-async fn edit_subtitle(
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct AlignedCueInfo {
+    subtitle: i32,
+    start: i32,
+    end: i32,
+    /// From 0 to 1. Cues that don't start or end at a pause, or are too short to read, are
+    /// worth checking.
+    confidence: f64,
+}
+
+/// Create timed cues from a plain transcript, spread over the speech in the project's video in
+/// proportion to the length of their text. The cues are created in a single undoable operation.
+#[post("/project/<project_id>/transcript/align", data = "<info>")]
+async fn align_transcript(
     project_id: i32,
-    subtitle_id: i32,
-    info: Json<SubtitleEditInfo>,
+    info: Json<TranscriptAlignmentInfo>,
     user: User,
+    _scope: WriteScope,
     db: DbConn,
     queue: &State<Sender<SubtitleEvent>>,
-) -> Result<(), Status> {
-    let project: Project = db
-        .run(move |conn| {
-            project::table
-                .inner_join(workspace::table.left_join(workspace_member::table))
-                .filter(workspace_member::user.eq(user.id))
-                .filter(project::id.eq(project_id))
-                .select(project::all_columns)
-                .first::<Project>(conn)
-        })
+) -> Result<Json<Vec<AlignedCueInfo>>, (Status, &'static str)> {
+    let (project, _) = get_project_as_member(&db, project_id, &user)
         .await
-        .map_err(|_| Status::NotFound)?;
+        .map_err(|_| (Status::NotFound, "Project not found"))?;
+    let video_id = project
+        .video
+        .ok_or((Status::NotFound, "Project has no video"))?;
+    let info = info.into_inner();
+
+    let texts = speech::split_transcript(&info.text, info.sentences);
+    if texts.is_empty() {
+        return Err((Status::BadRequest, "The transcript is empty"));
+    }
+    let settings = AlignmentSettings {
+        max_cps: info.max_cps.unwrap_or(17.0),
+        cue: info.limits.settings()?,
+    };
+    if settings.max_cps <= 0.0 {
+        return Err((Status::BadRequest, "Reading speed must be positive"));
+    }
 
-    let mut subtitle: Subtitle = db
+    let existing = db
         .run(move |conn| {
             subtitle::table
-                .filter(subtitle::id.eq(subtitle_id))
-                .filter(subtitle::project.eq(project.id))
-                .first::<Subtitle>(conn)
+                .filter(subtitle::project.eq(project_id))
+                .count()
+                .get_result::<i64>(conn)
         })
         .await
-        .map_err(|_| Status::NotFound)?;
-
-    if let Some(start) = info.start {
-        subtitle.start = start;
-    }
-    if let Some(end) = info.end {
-        subtitle.end = end;
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+    if existing > 0 && !info.replace {
+        return Err((Status::Conflict, "The project already has subtitles"));
     }
-    if let Some(text) = info.text.clone() {
-        subtitle.text = text;
+
+    let regions = db
+        .run(move |conn| match load_speech_regions(conn, video_id) {
+            Ok(Some(regions)) => Ok(regions),
+            Ok(None) => detect_speech_regions(conn, video_id, &SpeechSettings::default()),
+            Err(_) => Err((Status::InternalServerError, "An internal error occured")),
+        })
+        .await?;
+    let regions: Vec<(i32, i32)> = regions
+        .into_iter()
+        .map(|region| (region.start, region.end))
+        .collect();
+    let lengths: Vec<usize> = texts.iter().map(|text| text.chars().count()).collect();
+    let aligned = speech::align_transcript(&lengths, &regions, &settings);
+    if aligned.is_empty() {
+        return Err((Status::BadRequest, "No speech was found in the video"));
     }
 
-    let updated_count: usize = db
+    let replace = info.replace;
+    let project_clone = project.clone();
+    let aligned_clone = aligned.clone();
+    let changed: Option<(Vec<Subtitle>, Vec<Subtitle>)> = db
         .run(move |conn| {
-            diesel::update(subtitle::table)
-                .filter(subtitle::id.eq(subtitle_id))
-                .filter(subtitle::project.eq(project.id))
-                .set((
-                    subtitle::start.eq(subtitle.start),
-                    subtitle::end.eq(subtitle.end),
-                    subtitle::text.eq(subtitle.text),
-                ))
-                .execute(conn)
+            conn.transaction(|| {
+                if replace && !other_users_locks(conn, project.id, user.id)?.is_empty() {
+                    return Ok(None);
+                }
+
+                let timestamp = unix_timestamp();
+                let mut revisions = Vec::new();
+                let mut deleted = Vec::new();
+                if replace {
+                    deleted = subtitle::table
+                        .filter(subtitle::project.eq(project.id))
+                        .load::<Subtitle>(conn)?;
+                    diesel::delete(subtitle::table)
+                        .filter(subtitle::project.eq(project.id))
+                        .execute(conn)?;
+                    for subtitle in &deleted {
+                        revisions.push(NewSubtitleRevision::new(
+                            "delete",
+                            user.id,
+                            timestamp,
+                            Some(subtitle),
+                            None,
+                        ));
+                    }
+                }
+
+                let mut created = Vec::new();
+                for (cue, text) in aligned_clone.iter().zip(texts) {
+                    let (start, end) = cue_times(&project_clone, cue.start, cue.end);
+                    let new_id = insert_returning_id!(
+                        conn,
+                        diesel::insert_into(subtitle::table).values(&NewSubtitle {
+                            project: project.id,
+                            start,
+                            end,
+                            text,
+                            origin: None,
+                            confidence: Some(cue.confidence),
+                        }),
+                        subtitle::id
+                    )?;
+                    let subtitle = subtitle::table.find(new_id).first::<Subtitle>(conn)?;
+                    revisions.push(NewSubtitleRevision::new(
+                        "create",
+                        user.id,
+                        timestamp,
+                        None,
+                        Some(&subtitle),
+                    ));
+                    created.push(subtitle);
+                }
+                record_operation(conn, project.id, user.id, &revisions)?;
+                Ok(Some((deleted, created)))
+            })
         })
         .await
-        .map_err(|_| Status::InternalServerError)?;
+        .map_err(|_: diesel::result::Error| {
+            (Status::InternalServerError, "An internal error occured")
+        })?;
+    let (deleted, created) =
+        changed.ok_or((Status::Locked, "Subtitles are locked by another user"))?;
+
+    for subtitle in deleted {
+        let _ = queue.send(SubtitleEvent {
+            info: SubtitleEventType::SubtitleDelete(DeleteEventData {
+                subtitle: subtitle.id,
+            }),
+            project: project_id,
+        });
+    }
+    let mut cues = Vec::new();
+    for (subtitle, cue) in created.into_iter().zip(aligned) {
+        let _ = queue.send(SubtitleEvent {
+            info: SubtitleEventType::SubtitleCreate(CreateEventData::new(&subtitle)),
+            project: project_id,
+        });
+        cues.push(AlignedCueInfo {
+            subtitle: subtitle.id,
+            start: subtitle.start,
+            end: subtitle.end,
+            confidence: cue.confidence,
+        });
+    }
+    Ok(Json(cues))
+}
 
-    if updated_count == 0 {
-        // This probably shouldn't happen because we're already fetching the subtitle earlier
-        return Err(Status::NotFound);
+/// Something ffmpeg can read the audio of: an audio stream of YouTube videos, or the path of
+/// files
+async fn audio_input(source: String, identifier: String) -> Result<String, Status> {
+    if source != "youtube" {
+        return Ok(identifier);
     }
 
-    // Broadcast SSE
-    let _ = queue.send(SubtitleEvent {
-        info: SubtitleEventType::SubtitleEdit(EditEventData {
-            subtitle: subtitle_id,
-            start: info.start,
-            end: info.end,
-            text: info.text.clone(),
-        }),
-        project: project.id,
-    });
+    let client = ytextract::Client::new();
+    let streams = client
+        .streams(
+            identifier
+                .parse()
+                .map_err(|_| Status::InternalServerError)?,
+        )
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    streams
+        .filter(|stream| matches!(stream, ytextract::Stream::Audio(_)))
+        .min_by_key(|stream| stream.bitrate())
+        .map(|stream| stream.url().to_string())
+        .ok_or(Status::NotFound)
+}
 
-    Ok(())
+/// The configured transcriber, and the projects it's working on
+#[derive(Clone)]
+struct Transcription {
+    transcriber: Arc<dyn Transcriber>,
+    running: Arc<Mutex<HashSet<i32>>>,
 }
 
-#[post("/project/<project_id>/snapshot/create")]
-async fn create_snapshot(project_id: i32, user: User, db: DbConn) -> Result<String, Status> {
-    let project: Project = db
-        .run(move |conn| {
-            project::table
-                .inner_join(workspace::table.left_join(workspace_member::table))
-                .filter(workspace_member::user.eq(user.id))
-                .filter(project::id.eq(project_id))
-                .select(project::all_columns)
-                .first::<Project>(conn)
+impl Transcription {
+    /// Mark a project as being transcribed, unless it already is. It's unmarked when the
+    /// returned value is dropped, so a transcription that panics doesn't block the project.
+    fn start(&self, project_id: i32) -> Option<RunningTranscription> {
+        if !self.running.lock().unwrap().insert(project_id) {
+            return None;
+        }
+        Some(RunningTranscription {
+            running: self.running.clone(),
+            project_id,
         })
-        .await
-        .map_err(|_| Status::NotFound)?;
+    }
+}
 
-    // Get all subtitles for this project
-    let subtitles: Vec<Subtitle> = db
-        .run(move |conn| {
-            subtitle::table
-                .filter(subtitle::project.eq(project.id))
-                .order(subtitle::start)
-                .load::<Subtitle>(conn)
-        })
-        .await
-        .map_err(|_| Status::InternalServerError)?;
+struct RunningTranscription {
+    running: Arc<Mutex<HashSet<i32>>>,
+    project_id: i32,
+}
 
-    // Put them into a big json array
-    let subtitles_json = rocket::serde::json::serde_json::to_string(&subtitles)
-        .map_err(|_| Status::InternalServerError)?;
+impl Drop for RunningTranscription {
+    fn drop(&mut self) {
+        let mut running = match self.running.lock() {
+            Ok(running) => running,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        running.remove(&self.project_id);
+    }
+}
 
-    let _affected_rows: usize = db
-        .run(move |conn| {
-            diesel::insert_into(schema::snapshot::table)
-                .values(&Snapshot {
-                    project: project.id,
-                    name: None,
-                    timestamp: SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .expect("we're in the past")
-                        .as_secs() as i64,
-                    subtitles: subtitles_json,
-                })
-                .execute(conn)
-        })
-        .await
-        .map_err(|_| Status::InternalServerError)?;
-    Ok("done".to_string())
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct TranscriptionEventData {
+    /// "running", "done" or "failed"
+    pub state: &'static str,
+    /// Percentage
+    pub progress: u8,
+    /// Number of cues created, when done
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cues: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+fn transcription_event(project_id: i32, data: TranscriptionEventData) -> SubtitleEvent {
+    SubtitleEvent {
+        info: SubtitleEventType::Transcription(data),
+        project: project_id,
+    }
+}
+
+#[derive(Debug, Deserialize)]
 #[serde(crate = "rocket::serde")]
-struct SnapshotInfo {
-    project: i32,
-    timestamp: i64,
-    name: Option<String>,
+struct TranscriptionStartInfo {
+    /// Language code of the speech, like `en`. Defaults to the language of the video.
+    language: Option<String>,
+    /// Delete the project's subtitles when the draft is ready. Without this, the project has
+    /// to be empty.
+    #[serde(default)]
+    replace: bool,
 }
 
-#[get("/project/<project_id>/snapshot/list", rank = 1)]
-async fn list_snapshots(
+/// Start making draft subtitles from the speech in a project's video. Progress and the result
+/// are sent as `transcription` events, and the cues are created in a single undoable operation.
+#[post("/project/<project_id>/transcribe", data = "<info>")]
+async fn transcribe_project(
     project_id: i32,
+    info: Json<TranscriptionStartInfo>,
     user: User,
+    _scope: WriteScope,
     db: DbConn,
-) -> Result<Json<Vec<SnapshotInfo>>, Status> {
-    let project: Project = db
-        .run(move |conn| {
-            project::table
-                .inner_join(workspace::table.left_join(workspace_member::table))
-                .filter(workspace_member::user.eq(user.id))
-                .filter(project::id.eq(project_id))
-                .select(project::all_columns)
-                .first::<Project>(conn)
-        })
+    queue: &State<Sender<SubtitleEvent>>,
+    transcription: &State<Option<Transcription>>,
+) -> Result<Status, (Status, &'static str)> {
+    let transcription = transcription.inner().clone().ok_or((
+        Status::NotImplemented,
+        "Transcription isn't set up on this server",
+    ))?;
+    let (project, _) = get_project_as_member(&db, project_id, &user)
         .await
-        .map_err(|_| Status::NotFound)?;
+        .map_err(|_| (Status::NotFound, "Project not found"))?;
+    let video_id = project
+        .video
+        .ok_or((Status::NotFound, "Project has no video"))?;
+    let info = info.into_inner();
 
-    let snapshots: Vec<SnapshotInfo> = db
+    let (video, existing) = db
         .run(move |conn| {
-            schema::snapshot::table
-                .filter(schema::snapshot::project.eq(project.id))
-                .order(schema::snapshot::timestamp.desc())
-                .select((
-                    schema::snapshot::project,
-                    schema::snapshot::timestamp,
-                    schema::snapshot::name,
-                ))
-                .load::<(i32, i64, Option<String>)>(conn)
+            let video = video::table
+                .find(video_id)
+                .select(VIDEO_SUMMARY_COLUMNS)
+                .first::<VideoSummary>(conn)?;
+            let existing = subtitle::table
+                .filter(subtitle::project.eq(project_id))
+                .count()
+                .get_result::<i64>(conn)?;
+            Ok((video, existing))
         })
         .await
-        .map_err(|_| Status::InternalServerError)?
-        .into_iter()
-        .map(|(project, timestamp, name)| SnapshotInfo {
-            project,
-            timestamp,
-            name,
+        .map_err(|_: diesel::result::Error| {
+            (Status::InternalServerError, "An internal error occured")
+        })?;
+    if existing > 0 && !info.replace {
+        return Err((Status::Conflict, "The project already has subtitles"));
+    }
+    let running = transcription
+        .start(project_id)
+        .ok_or((Status::Conflict, "The project is already being transcribed"))?;
+
+    let sender = queue.inner().to_owned();
+    let language = info.language.or_else(|| video.metadata.language.clone());
+    task::spawn(async move {
+        send_transcription_progress(&sender, project_id, 0);
+        let result = match convert_transcription_audio(video.source, video.identifier).await {
+            Ok(audio) => {
+                let request = TranscriptionRequest {
+                    audio: &audio,
+                    language: language.as_deref(),
+                    duration: video.duration,
+                };
+                let result = run_transcription(
+                    &db,
+                    &sender,
+                    transcription.transcriber.as_ref(),
+                    &project,
+                    user.id,
+                    &request,
+                    info.replace,
+                )
+                .await;
+                let _ = std::fs::remove_file(&audio);
+                result
+            }
+            Err(error) => Err(error),
+        };
+        drop(running);
+
+        let data = match result {
+            Ok(cues) => TranscriptionEventData {
+                state: "done",
+                progress: 100,
+                cues: Some(cues),
+                error: None,
+            },
+            Err(error) => TranscriptionEventData {
+                state: "failed",
+                progress: 0,
+                cues: None,
+                error: Some(error),
+            },
+        };
+        let _ = sender.send(transcription_event(project_id, data));
+    });
+    Ok(Status::Accepted)
+}
+
+fn send_transcription_progress(sender: &Sender<SubtitleEvent>, project_id: i32, progress: u8) {
+    let _ = sender.send(transcription_event(
+        project_id,
+        TranscriptionEventData {
+            state: "running",
+            progress,
+            cues: None,
+            error: None,
+        },
+    ));
+}
+
+/// Convert a video's audio to the 16 kHz mono WAV file transcribers take. The caller removes
+/// the file.
+async fn convert_transcription_audio(
+    source: String,
+    identifier: String,
+) -> Result<std::path::PathBuf, String> {
+    let input = audio_input(source, identifier)
+        .await
+        .map_err(|_| "Could not read the audio".to_string())?;
+    let audio = std::env::temp_dir().join(format!("uptitle-{}.wav", generate_token()));
+    let audio_clone = audio.clone();
+    let converted = task::spawn_blocking(move || {
+        Command::new("ffmpeg")
+            .args([
+                "-y", "-v", "error", "-i", &input, "-ar", "16000", "-ac", "1",
+            ])
+            .args(["-c:a", "pcm_s16le"])
+            .arg(&audio_clone)
+            .output()
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+    if !converted.map_err(|e| e.to_string())?.status.success() {
+        let _ = std::fs::remove_file(&audio);
+        return Err("Could not convert the audio".to_string());
+    }
+    Ok(audio)
+}
+
+/// Transcribe the converted audio of a project's video and create cues from it, returning how
+/// many were created
+async fn run_transcription(
+    db: &DbConn,
+    sender: &Sender<SubtitleEvent>,
+    transcriber: &dyn Transcriber,
+    project: &Project,
+    user_id: i32,
+    request: &TranscriptionRequest<'_>,
+    replace: bool,
+) -> Result<usize, String> {
+    let project_id = project.id;
+    let cues = task::block_in_place(|| {
+        let mut last_progress = 0;
+        transcriber.transcribe(request, &mut |progress| {
+            if progress != last_progress {
+                last_progress = progress;
+                send_transcription_progress(sender, project_id, progress);
+            }
         })
-        .collect();
+    })?;
 
-    Ok(Json(snapshots))
+    let project = project.clone();
+    let changed: Option<(Vec<Subtitle>, Vec<Subtitle>)> = db
+        .run(move |conn| {
+            conn.transaction(|| {
+                let existing = subtitle::table
+                    .filter(subtitle::project.eq(project.id))
+                    .load::<Subtitle>(conn)?;
+                let locked = !other_users_locks(conn, project.id, user_id)?.is_empty();
+                if !existing.is_empty() && (!replace || locked) {
+                    return Ok(None);
+                }
+
+                let timestamp = unix_timestamp();
+                let mut revisions = Vec::new();
+                diesel::delete(subtitle::table)
+                    .filter(subtitle::project.eq(project.id))
+                    .execute(conn)?;
+                for subtitle in &existing {
+                    revisions.push(NewSubtitleRevision::new(
+                        "delete",
+                        user_id,
+                        timestamp,
+                        Some(subtitle),
+                        None,
+                    ));
+                }
+
+                let mut created = Vec::new();
+                for cue in cues {
+                    let (start, end) = cue_times(&project, cue.start, cue.end);
+                    let new_id = insert_returning_id!(
+                        conn,
+                        diesel::insert_into(subtitle::table).values(&NewSubtitle {
+                            project: project.id,
+                            start,
+                            end,
+                            text: cue.text,
+                            origin: Some(origin::TRANSCRIPTION.to_string()),
+                            confidence: cue.confidence,
+                        }),
+                        subtitle::id
+                    )?;
+                    let subtitle = subtitle::table.find(new_id).first::<Subtitle>(conn)?;
+                    revisions.push(NewSubtitleRevision::new(
+                        "create",
+                        user_id,
+                        timestamp,
+                        None,
+                        Some(&subtitle),
+                    ));
+                    created.push(subtitle);
+                }
+                if !revisions.is_empty() {
+                    record_operation(conn, project.id, user_id, &revisions)?;
+                }
+                Ok(Some((existing, created)))
+            })
+        })
+        .await
+        .map_err(|_: diesel::result::Error| "An internal error occured".to_string())?;
+    let (deleted, created) = changed.ok_or_else(|| {
+        "The project's subtitles were changed or locked during the transcription".to_string()
+    })?;
+
+    for subtitle in deleted {
+        let _ = sender.send(SubtitleEvent {
+            info: SubtitleEventType::SubtitleDelete(DeleteEventData {
+                subtitle: subtitle.id,
+            }),
+            project: project_id,
+        });
+    }
+    let count = created.len();
+    for subtitle in created {
+        let _ = sender.send(SubtitleEvent {
+            info: SubtitleEventType::SubtitleCreate(CreateEventData::new(&subtitle)),
+            project: project_id,
+        });
+    }
+    Ok(count)
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Deserialize)]
 #[serde(crate = "rocket::serde")]
-struct SnapshotResponse {
-    name: Option<String>,
-    subtitles: Vec<Subtitle>,
+struct TranslationInfo {
+    /// Language code to translate to, like `nl`
+    target: String,
+    /// Language code of the subtitles. Defaults to the language of the video, or lets the
+    /// translator detect it.
+    source: Option<String>,
+    /// Only translate these subtitles, instead of the whole project
+    subtitles: Option<Vec<i32>>,
 }
 
-#[get("/project/<project_id>/snapshot/<timestamp>", rank = 2)]
-async fn get_snapshot(
+fn is_language_code(code: &str) -> bool {
+    !code.is_empty()
+        && code.len() <= 16
+        && code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// Machine translate a project's subtitles, keeping their timing. The translations replace the
+/// text in a single undoable operation, and are marked as translations until someone edits them.
+/// Subtitles that are edited while the translator is working are left alone.
+#[post("/project/<project_id>/translate", data = "<info>")]
+async fn translate_project(
     project_id: i32,
-    timestamp: i64,
+    info: Json<TranslationInfo>,
     user: User,
+    _scope: WriteScope,
     db: DbConn,
-) -> Result<Json<SnapshotResponse>, Status> {
-    let project: Project = db
-        .run(move |conn| {
-            project::table
-                .inner_join(workspace::table.left_join(workspace_member::table))
-                .filter(workspace_member::user.eq(user.id))
-                .filter(project::id.eq(project_id))
-                .select(project::all_columns)
-                .first::<Project>(conn)
-        })
+    queue: &State<Sender<SubtitleEvent>>,
+    translator: &State<Option<Arc<dyn Translator>>>,
+) -> Result<Json<Vec<Subtitle>>, (Status, &'static str)> {
+    let translator = translator.inner().clone().ok_or((
+        Status::NotImplemented,
+        "Translation isn't set up on this server",
+    ))?;
+    let (project, _) = get_project_as_member(&db, project_id, &user)
         .await
-        .map_err(|_| Status::NotFound)?;
+        .map_err(|_| (Status::NotFound, "Project not found"))?;
+    let info = info.into_inner();
+    if !is_language_code(&info.target) || !info.source.as_deref().is_none_or(is_language_code) {
+        return Err((Status::BadRequest, "Invalid language code"));
+    }
 
-    let snapshot: Snapshot = db
+    let selection = info.subtitles.clone();
+    let video_id = project.video;
+    let (subtitles, video_language) = db
         .run(move |conn| {
-            schema::snapshot::table
-                .filter(schema::snapshot::project.eq(project.id))
-                .filter(schema::snapshot::timestamp.eq(timestamp))
-                .first::<Snapshot>(conn)
+            let mut query = subtitle::table
+                .filter(subtitle::project.eq(project_id))
+                .into_boxed();
+            if let Some(ids) = selection {
+                query = query.filter(subtitle::id.eq_any(ids));
+            }
+            let subtitles = query.order(subtitle::start.asc()).load::<Subtitle>(conn)?;
+            let language = match video_id {
+                Some(video_id) => video::table
+                    .find(video_id)
+                    .select(video::language)
+                    .first::<Option<String>>(conn)?,
+                None => None,
+            };
+            Ok((subtitles, language))
         })
         .await
-        .map_err(|_| Status::NotFound)?;
-
-    // This is kinda unnecessary because it first deserializes the json and then re-serializes it.
-    // But this way the return type of this function is the clearest.
-    let subtitles: Vec<Subtitle> = rocket::serde::json::serde_json::from_str(&snapshot.subtitles)
-        .map_err(|_| Status::InternalServerError)?;
+        .map_err(|_: diesel::result::Error| {
+            (Status::InternalServerError, "An internal error occured")
+        })?;
+    if let Some(ids) = &info.subtitles {
+        if ids.iter().any(|id| !subtitles.iter().any(|s| s.id == *id)) {
+            return Err((Status::NotFound, "Subtitle not found"));
+        }
+    }
+    let subtitles: Vec<Subtitle> = subtitles
+        .into_iter()
+        .filter(|subtitle| !subtitle.text.trim().is_empty())
+        .collect();
+    if subtitles.is_empty() {
+        return Ok(Json(Vec::new()));
+    }
 
-    let response: SnapshotResponse = SnapshotResponse {
-        name: snapshot.name,
-        subtitles,
-    };
+    let source = info.source.or(video_language);
+    let texts: Vec<String> = subtitles.iter().map(|s| s.text.clone()).collect();
+    let translations = translator
+        .translate(&texts, source.as_deref(), &info.target)
+        .await
+        .map_err(|e| {
+            error!("Translation failed: {}", e);
+            (Status::BadGateway, "The translator failed")
+        })?;
+    if translations.len() != texts.len() {
+        error!(
+            "Translation failed: got {} texts for {}",
+            translations.len(),
+            texts.len()
+        );
+        return Err((Status::BadGateway, "The translator failed"));
+    }
 
-    Ok(Json(response))
+    let user_id = user.id;
+    let translated: Option<Vec<Subtitle>> = db
+        .run(move |conn| {
+            conn.transaction(|| {
+                let timestamp = unix_timestamp();
+                let mut revisions = Vec::new();
+                let mut translated = Vec::new();
+                for (original, text) in subtitles.into_iter().zip(translations) {
+                    let current = subtitle::table
+                        .find(original.id)
+                        .first::<Subtitle>(conn)
+                        .optional()?;
+                    let current = match current {
+                        Some(current) if current.text == original.text => current,
+                        _ => continue,
+                    };
+                    let range = [(current.start, current.end)];
+                    if find_blocking_lock(conn, project_id, user_id, current.id, &range)?.is_some()
+                    {
+                        return Ok(None);
+                    }
+
+                    let updated = Subtitle {
+                        text: text.trim().to_string(),
+                        origin: Some(origin::TRANSLATION.to_string()),
+                        confidence: None,
+                        ..current.clone()
+                    };
+                    diesel::update(subtitle::table.find(current.id))
+                        .set((
+                            subtitle::text.eq(&updated.text),
+                            subtitle::origin.eq(&updated.origin),
+                            subtitle::confidence.eq(updated.confidence),
+                        ))
+                        .execute(conn)?;
+                    revisions.push(NewSubtitleRevision::new(
+                        "edit",
+                        user_id,
+                        timestamp,
+                        Some(&current),
+                        Some(&updated),
+                    ));
+                    translated.push(updated);
+                }
+                if !revisions.is_empty() {
+                    record_operation(conn, project_id, user_id, &revisions)?;
+                }
+                Ok(Some(translated))
+            })
+        })
+        .await
+        .map_err(|_: diesel::result::Error| {
+            (Status::InternalServerError, "An internal error occured")
+        })?;
+    let translated = translated.ok_or((Status::Locked, "Subtitles are locked by another user"))?;
+
+    for subtitle in &translated {
+        let _ = queue.send(SubtitleEvent {
+            info: SubtitleEventType::SubtitleEdit(EditEventData {
+                subtitle: subtitle.id,
+                start: None,
+                end: None,
+                text: Some(subtitle.text.clone()),
+                status: subtitle.status.clone(),
+                origin: subtitle.origin.clone(),
+            }),
+            project: project_id,
+        });
+    }
+    Ok(Json(translated))
 }
 
-#[derive(Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct CreateEventData {
+    pub subtitle: i32,
+    pub text: String,
+    pub start: i32,
+    pub end: i32,
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f64>,
+}
+
+impl CreateEventData {
+    fn new(subtitle: &Subtitle) -> CreateEventData {
+        CreateEventData {
+            subtitle: subtitle.id,
+            text: subtitle.text.clone(),
+            start: subtitle.start,
+            end: subtitle.end,
+            status: subtitle.status.clone(),
+            origin: subtitle.origin.clone(),
+            confidence: subtitle.confidence,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct EditEventData {
+    pub subtitle: i32,
+    pub text: Option<String>,
+    pub start: Option<i32>,
+    pub end: Option<i32>,
+    /// The current status, whether or not it changed
+    pub status: String,
+    /// Set when the new text came from a machine, like a translation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct DeleteEventData {
+    pub subtitle: i32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct ShotChangesReadyEventData {
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+enum SubtitleEventType {
+    WaveformReady,
+    ShotChangesReady(ShotChangesReadyEventData),
+    Transcription(TranscriptionEventData),
+    VideoChange(VideoInfo),
+    SubtitleCreate(CreateEventData),
+    SubtitleEdit(EditEventData),
+    SubtitleDelete(DeleteEventData),
+    SubtitleLock(LockInfo),
+    SubtitleUnlock(UnlockEventData),
+    CommentCreate(CommentInfo),
+    CommentEdit(CommentInfo),
+    CommentDelete(CommentDeleteEventData),
+    CommentResolve(CommentResolveEventData),
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct SubtitleEvent {
+    info: SubtitleEventType,
+    project: i32,
+}
+
+/// Live changes to a project, for members only since they include comments and video details
+#[get("/project/<project_id>/events")]
+async fn events(
+    project_id: i32,
+    user: User,
+    _scope: ReadScope,
+    db: DbConn,
+    queue: &State<Sender<SubtitleEvent>>,
+    mut end: Shutdown,
+) -> Result<EventStream![], Status> {
+    let (project, _) = get_project_as_member(&db, project_id, &user).await?;
+    let project_id = project.id;
+
+    let mut rx = queue.subscribe();
+    Ok(EventStream! {
+        loop {
+            let msg = select! {
+                msg = rx.recv() => match msg {
+                    Ok(msg) => msg,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => continue,
+                },
+                _ = &mut end => break,
+            };
+
+            if msg.project != project_id {
+                continue;
+            }
+
+            yield Event::json(&msg).event(match msg.info {
+                SubtitleEventType::WaveformReady => "waveform_ready",
+                SubtitleEventType::ShotChangesReady(_) => "shot_changes_ready",
+                SubtitleEventType::Transcription(_) => "transcription",
+                SubtitleEventType::VideoChange(_) => "video_change",
+                SubtitleEventType::SubtitleEdit(_) => "subtitle_edit",
+                SubtitleEventType::SubtitleCreate(_) => "subtitle_create",
+                SubtitleEventType::SubtitleDelete(_) => "subtitle_delete",
+                SubtitleEventType::SubtitleLock(_) => "subtitle_lock",
+                SubtitleEventType::SubtitleUnlock(_) => "subtitle_unlock",
+                SubtitleEventType::CommentCreate(_) => "comment_create",
+                SubtitleEventType::CommentEdit(_) => "comment_edit",
+                SubtitleEventType::CommentDelete(_) => "comment_delete",
+                SubtitleEventType::CommentResolve(_) => "comment_resolve",
+            });
+        }
+    })
+}
+
+/// A subtitle, with its times as SMPTE timecodes if the project has a frame rate
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct SubtitleInfo {
+    #[serde(flatten)]
+    subtitle: Subtitle,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    start_timecode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    end_timecode: Option<String>,
+}
+
+impl SubtitleInfo {
+    fn new(subtitle: Subtitle, timecode: Option<Timecode>) -> SubtitleInfo {
+        SubtitleInfo {
+            start_timecode: timecode.map(|timecode| timecode.format(subtitle.start)),
+            end_timecode: timecode.map(|timecode| timecode.format(subtitle.end)),
+            subtitle,
+        }
+    }
+}
+
+/// A time in a request: milliseconds, or a timecode like "00:01:02:03" in projects with a
+/// frame rate
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", untagged)]
+enum TimeInput {
+    Milliseconds(i32),
+    Timecode(String),
+}
+
+impl TimeInput {
+    fn to_milliseconds(&self, project: &Project) -> Result<i32, (Status, &'static str)> {
+        match self {
+            TimeInput::Milliseconds(ms) => Ok(*ms),
+            TimeInput::Timecode(timecode) => project
+                .timecode()
+                .ok_or((
+                    Status::BadRequest,
+                    "Set a frame rate for the project to use timecodes",
+                ))?
+                .parse(timecode)
+                .map_err(|message| (Status::BadRequest, message)),
+        }
+    }
+}
+
+/// Cue times as stored, snapped to frames if the project wants that
+fn cue_times(project: &Project, start: i32, end: i32) -> (i32, i32) {
+    match project.timecode() {
+        Some(timecode) if project.snap_to_frames != 0 => timecode.rate.snap_cue(start, end),
+        _ => (start, end),
+    }
+}
+
+#[get("/project/<id>/subtitle/list")]
+async fn get_subtitle_list(
+    id: i32,
+    user: User,
+    _scope: ReadScope,
+    db: DbConn,
+) -> Result<Json<Vec<SubtitleInfo>>, Status> {
+    let (project, _) = get_project_as_member(&db, id, &user)
+        .await
+        .map_err(|_| Status::NotFound)?;
+
+    let timecode = project.timecode();
+    let subtitles: Vec<Subtitle> = db
+        .run(move |conn| {
+            Subtitle::belonging_to(&project)
+                .order(subtitle::start.asc())
+                .load::<Subtitle>(conn)
+        })
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(
+        subtitles
+            .into_iter()
+            .map(|subtitle| SubtitleInfo::new(subtitle, timecode))
+            .collect(),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct SubtitleCreationInfo {
+    start: TimeInput,
+    end: TimeInput,
+    text: String,
+}
+
+#[post("/project/<id>/subtitle/create", data = "<info>")]
+async fn create_subtitle(
+    id: i32,
+    user: User,
+    _scope: WriteScope,
+    info: Json<SubtitleCreationInfo>,
+    db: DbConn,
+    queue: &State<Sender<SubtitleEvent>>,
+) -> Result<String, (Status, &'static str)> {
+    let (project, _) = get_project_as_member(&db, id, &user)
+        .await
+        .map_err(|_| (Status::NotFound, "Project not found"))?;
+
+    let (start, end) = cue_times(
+        &project,
+        info.start.to_milliseconds(&project)?,
+        info.end.to_milliseconds(&project)?,
+    );
+    let subtitle = NewSubtitle {
+        project: project.id,
+        start,
+        end,
+        text: info.text.clone(),
+        origin: None,
+        confidence: None,
+    };
+
+    let created: Subtitle = db
+        .run(move |conn| {
+            conn.transaction(|| {
+                let new_id = insert_returning_id!(
+                    conn,
+                    diesel::insert_into(schema::subtitle::table).values(&subtitle),
+                    schema::subtitle::id
+                )?;
+                let created = subtitle::table.find(new_id).first::<Subtitle>(conn)?;
+
+                record_operation(
+                    conn,
+                    created.project,
+                    user.id,
+                    &[NewSubtitleRevision::new(
+                        "create",
+                        user.id,
+                        unix_timestamp(),
+                        None,
+                        Some(&created),
+                    )],
+                )?;
+
+                Ok(created)
+            })
+        })
+        .await
+        .map_err(|_: diesel::result::Error| {
+            (Status::InternalServerError, "An internal error occured")
+        })?;
+
+    // Broadcast SSE
+    let _ = queue.send(SubtitleEvent {
+        info: SubtitleEventType::SubtitleCreate(CreateEventData::new(&created)),
+        project: project.id,
+    });
+
+    Ok(created.id.to_string())
+}
+
+#[delete("/project/<project_id>/subtitle/<subtitle_id>")]
+async fn delete_subtitle(
+    project_id: i32,
+    subtitle_id: i32,
+    user: User,
+    _scope: WriteScope,
+    db: DbConn,
+    queue: &State<Sender<SubtitleEvent>>,
+) -> Result<(), (Status, &'static str)> {
+    let (project, _) = get_project_as_member(&db, project_id, &user)
+        .await
+        .map_err(|_| (Status::NotFound, "Project not found"))?;
+
+    db.run(move |conn| {
+        conn.transaction(|| {
+            let subtitle: Subtitle = subtitle::table
+                .filter(subtitle::id.eq(subtitle_id))
+                .filter(subtitle::project.eq(project.id))
+                .first::<Subtitle>(conn)?;
+
+            let range = [(subtitle.start, subtitle.end)];
+            if find_blocking_lock(conn, project.id, user.id, subtitle.id, &range)?.is_some() {
+                return Err(SubtitleChangeError::Locked);
+            }
+
+            diesel::delete(subtitle::table.find(subtitle.id)).execute(conn)?;
+            record_operation(
+                conn,
+                project.id,
+                user.id,
+                &[NewSubtitleRevision::new(
+                    "delete",
+                    user.id,
+                    unix_timestamp(),
+                    Some(&subtitle),
+                    None,
+                )],
+            )?;
+
+            Ok(())
+        })
+    })
+    .await?;
+
+    // Broadcast SSE
+    let _ = queue.send(SubtitleEvent {
+        info: SubtitleEventType::SubtitleDelete(DeleteEventData {
+            subtitle: subtitle_id,
+        }),
+        project: project.id,
+    });
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct SubtitleEditInfo {
+    start: Option<TimeInput>,
+    end: Option<TimeInput>,
+    text: Option<String>,
+    /// Move the new start and end onto shot changes that are a few frames away
+    #[serde(default)]
+    snap_to_shot_changes: bool,
+}
+
+#[patch("/project/<project_id>/subtitle/<subtitle_id>", data = "<info>")]
+async fn edit_subtitle(
+    project_id: i32,
+    subtitle_id: i32,
+    info: Json<SubtitleEditInfo>,
+    user: User,
+    _scope: WriteScope,
+    db: DbConn,
+    queue: &State<Sender<SubtitleEvent>>,
+) -> Result<(), (Status, &'static str)> {
+    let (project, _) = get_project_as_member(&db, project_id, &user)
+        .await
+        .map_err(|_| (Status::NotFound, "Project not found"))?;
+
+    let start = info
+        .start
+        .as_ref()
+        .map(|start| start.to_milliseconds(&project))
+        .transpose()?;
+    let end = info
+        .end
+        .as_ref()
+        .map(|end| end.to_milliseconds(&project))
+        .transpose()?;
+    let shot_changes = if info.snap_to_shot_changes && (start.is_some() || end.is_some()) {
+        let project = project.clone();
+        let shot_changes = db
+            .run(move |conn| load_shot_changes(conn, &project))
+            .await
+            .map_err(|_| (Status::InternalServerError, "An internal error occured"))?
+            .ok_or((Status::BadRequest, "Shot changes haven't been detected yet"))?;
+        Some(shot_changes)
+    } else {
+        None
+    };
+    let text = info.text.clone();
+
+    let subtitle: Subtitle = db
+        .run(move |conn| {
+            conn.transaction(|| {
+                let original: Subtitle = subtitle::table
+                    .filter(subtitle::id.eq(subtitle_id))
+                    .filter(subtitle::project.eq(project.id))
+                    .first::<Subtitle>(conn)?;
+
+                let mut subtitle = original.clone();
+                if let Some(start) = start {
+                    subtitle.start = start;
+                }
+                if let Some(end) = end {
+                    subtitle.end = end;
+                }
+                if let Some(shot_changes) = &shot_changes {
+                    let snap = |time: i32, edited: bool| {
+                        if edited {
+                            shot_changes.snap(time, SHOT_CHANGE_FRAMES)
+                        } else {
+                            time
+                        }
+                    };
+                    let snapped_start = snap(subtitle.start, start.is_some());
+                    let snapped_end = snap(subtitle.end, end.is_some());
+                    // Both ends can be near the same shot change in very short cues
+                    if snapped_start < snapped_end {
+                        (subtitle.start, subtitle.end) = (snapped_start, snapped_end);
+                    }
+                }
+                if start.is_some() || end.is_some() {
+                    (subtitle.start, subtitle.end) =
+                        cue_times(&project, subtitle.start, subtitle.end);
+                }
+                if let Some(text) = text {
+                    subtitle.text = text;
+                    subtitle.origin = None;
+                    subtitle.confidence = None;
+                }
+                // An approved subtitle needs another review once its text or timing changes
+                let content = |s: &Subtitle| (s.start, s.end, s.text.clone());
+                if original.status == status::APPROVED && content(&original) != content(&subtitle) {
+                    subtitle.status = status::NEEDS_REVIEW.to_string();
+                }
+
+                // Check both the old and new timing, so a cue can't be moved into or out of a
+                // locked range
+                let ranges = [
+                    (original.start, original.end),
+                    (subtitle.start, subtitle.end),
+                ];
+                if find_blocking_lock(conn, project.id, user.id, subtitle_id, &ranges)?.is_some() {
+                    return Err(SubtitleChangeError::Locked);
+                }
+
+                diesel::update(subtitle::table.find(subtitle_id))
+                    .set((
+                        subtitle::start.eq(subtitle.start),
+                        subtitle::end.eq(subtitle.end),
+                        subtitle::text.eq(&subtitle.text),
+                        subtitle::origin.eq(&subtitle.origin),
+                        subtitle::confidence.eq(subtitle.confidence),
+                        subtitle::status.eq(&subtitle.status),
+                    ))
+                    .execute(conn)?;
+                if subtitle.status != original.status {
+                    diesel::insert_into(subtitle_status_change::table)
+                        .values(&NewSubtitleStatusChange {
+                            project: project.id,
+                            subtitle: subtitle_id,
+                            user: Some(user.id),
+                            timestamp: unix_timestamp(),
+                            from: original.status.clone(),
+                            to: subtitle.status.clone(),
+                        })
+                        .execute(conn)?;
+                }
+                record_operation(
+                    conn,
+                    project.id,
+                    user.id,
+                    &[NewSubtitleRevision::new(
+                        "edit",
+                        user.id,
+                        unix_timestamp(),
+                        Some(&original),
+                        Some(&subtitle),
+                    )],
+                )?;
+
+                Ok(subtitle)
+            })
+        })
+        .await?;
+
+    // Broadcast SSE
+    let _ = queue.send(SubtitleEvent {
+        info: SubtitleEventType::SubtitleEdit(EditEventData {
+            subtitle: subtitle_id,
+            start: start.map(|_| subtitle.start),
+            end: end.map(|_| subtitle.end),
+            text: info.text.clone(),
+            status: subtitle.status,
+            origin: None,
+        }),
+        project: project_id,
+    });
+
+    Ok(())
+}
+
+// History
+
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct RevisionInfo {
+    id: i32,
+    user: Option<i32>,
+    user_name: Option<String>,
+    timestamp: i64,
+    action: String,
+    before: Option<SubtitleState>,
+    after: Option<SubtitleState>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct SubtitleState {
+    start: i32,
+    end: i32,
+    text: String,
+}
+
+impl SubtitleState {
+    fn from_parts(start: Option<i32>, end: Option<i32>, text: Option<String>) -> Option<Self> {
+        Some(SubtitleState {
+            start: start?,
+            end: end?,
+            text: text?,
+        })
+    }
+}
+
+#[get("/project/<project_id>/subtitle/<subtitle_id>/history")]
+async fn get_subtitle_history(
+    project_id: i32,
+    subtitle_id: i32,
+    user: User,
+    _scope: ReadScope,
+    db: DbConn,
+) -> Result<Json<Vec<RevisionInfo>>, Status> {
+    let (project, _) = get_project_as_member(&db, project_id, &user).await?;
+
+    let revisions: Vec<(SubtitleRevision, Option<User>)> = db
+        .run(move |conn| {
+            subtitle_revision::table
+                .left_join(user::table)
+                .filter(subtitle_revision::project.eq(project.id))
+                .filter(subtitle_revision::subtitle.eq(subtitle_id))
+                .order(subtitle_revision::id.asc())
+                .load::<(SubtitleRevision, Option<User>)>(conn)
+        })
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if revisions.is_empty() {
+        return Err(Status::NotFound);
+    }
+
+    Ok(Json(
+        revisions
+            .into_iter()
+            .map(|(revision, author)| RevisionInfo {
+                id: revision.id,
+                user: revision.user,
+                user_name: author.map(|author| author.display_name.unwrap_or(author.username)),
+                timestamp: revision.timestamp,
+                action: revision.action,
+                before: SubtitleState::from_parts(
+                    revision.before_start,
+                    revision.before_end,
+                    revision.before_text,
+                ),
+                after: SubtitleState::from_parts(
+                    revision.after_start,
+                    revision.after_end,
+                    revision.after_text,
+                ),
+            })
+            .collect(),
+    ))
+}
+
+/// Put a subtitle back into the state it had right after the given revision.
+/// Subtitles that have since been deleted are recreated with their old ID.
+#[post("/project/<project_id>/subtitle/<subtitle_id>/history/<revision_id>/revert")]
+async fn revert_subtitle(
+    project_id: i32,
+    subtitle_id: i32,
+    revision_id: i32,
+    user: User,
+    _scope: WriteScope,
+    db: DbConn,
+    queue: &State<Sender<SubtitleEvent>>,
+) -> Result<(), (Status, &'static str)> {
+    let (project, _) = get_project_as_member(&db, project_id, &user)
+        .await
+        .map_err(|status| (status, "Project not found"))?;
+
+    let revision: SubtitleRevision = db
+        .run(move |conn| {
+            subtitle_revision::table
+                .filter(subtitle_revision::id.eq(revision_id))
+                .filter(subtitle_revision::project.eq(project.id))
+                .filter(subtitle_revision::subtitle.eq(subtitle_id))
+                .first::<SubtitleRevision>(conn)
+        })
+        .await
+        .map_err(|_| (Status::NotFound, "Revision not found"))?;
+
+    let target = revision
+        .after()
+        .ok_or((Status::BadRequest, "Cannot revert to a deleted subtitle"))?;
+
+    let (existed, reverted) = db
+        .run(move |conn| {
+            conn.transaction(|| {
+                let current: Option<Subtitle> = subtitle::table
+                    .filter(subtitle::id.eq(subtitle_id))
+                    .filter(subtitle::project.eq(project.id))
+                    .first::<Subtitle>(conn)
+                    .optional()?;
+
+                let mut ranges = vec![(target.start, target.end)];
+                if let Some(current) = &current {
+                    ranges.push((current.start, current.end));
+                }
+                if find_blocking_lock(conn, project.id, user.id, subtitle_id, &ranges)?.is_some() {
+                    return Err(SubtitleChangeError::Locked);
+                }
+
+                let reverted = set_subtitle_state(conn, current.as_ref(), Some(&target))?;
+                record_operation(
+                    conn,
+                    project.id,
+                    user.id,
+                    &[NewSubtitleRevision::new(
+                        "revert",
+                        user.id,
+                        unix_timestamp(),
+                        current.as_ref(),
+                        reverted.as_ref(),
+                    )],
+                )?;
+                Ok((current.is_some(), reverted))
+            })
+        })
+        .await?;
+
+    // Broadcast SSE
+    let _ = queue.send(subtitle_change_event(
+        project.id,
+        subtitle_id,
+        existed,
+        reverted.as_ref(),
+    ));
+
+    Ok(())
+}
+
+// Undo
+
+/// Start a new undoable operation for a user and record its revisions under it.
+/// Anything the user undid before is dropped from their redo stack.
+fn record_operation(
+    conn: &DbConnection,
+    project_id: i32,
+    user_id: i32,
+    revisions: &[NewSubtitleRevision],
+) -> QueryResult<i32> {
+    let redo_stack: Vec<i32> = operation::table
+        .filter(operation::project.eq(project_id))
+        .filter(operation::user.eq(user_id))
+        .filter(operation::undone.eq(1))
+        .select(operation::id)
+        .load::<i32>(conn)?;
+    if !redo_stack.is_empty() {
+        diesel::update(subtitle_revision::table)
+            .filter(subtitle_revision::operation.eq_any(redo_stack.clone()))
+            .set(subtitle_revision::operation.eq(None::<i32>))
+            .execute(conn)?;
+        diesel::delete(operation::table)
+            .filter(operation::id.eq_any(redo_stack))
+            .execute(conn)?;
+    }
+
+    let operation_id = insert_returning_id!(
+        conn,
+        diesel::insert_into(operation::table).values(&NewOperation {
+            project: project_id,
+            user: user_id,
+            timestamp: unix_timestamp(),
+        }),
+        operation::id
+    )?;
+
+    let revisions: Vec<NewSubtitleRevision> = revisions
+        .iter()
+        .cloned()
+        .map(|revision| NewSubtitleRevision {
+            operation: Some(operation_id),
+            ..revision
+        })
+        .collect();
+    diesel::insert_into(subtitle_revision::table)
+        .values(&revisions)
+        .execute(conn)?;
+
+    Ok(operation_id)
+}
+
+/// Bring a subtitle from its current state to the target state, recreating or deleting it as
+/// needed. Returns the subtitle as it is now.
+fn set_subtitle_state(
+    conn: &DbConnection,
+    current: Option<&Subtitle>,
+    target: Option<&Subtitle>,
+) -> QueryResult<Option<Subtitle>> {
+    match (current, target) {
+        (Some(current), None) => {
+            diesel::delete(subtitle::table.find(current.id)).execute(conn)?;
+            Ok(None)
+        }
+        (None, Some(target)) => {
+            diesel::insert_into(subtitle::table)
+                .values((
+                    subtitle::id.eq(target.id),
+                    subtitle::project.eq(target.project),
+                    subtitle::start.eq(target.start),
+                    subtitle::end.eq(target.end),
+                    subtitle::text.eq(&target.text),
+                    subtitle::status.eq(&target.status),
+                    subtitle::origin.eq(&target.origin),
+                    subtitle::confidence.eq(target.confidence),
+                ))
+                .execute(conn)?;
+            Ok(Some(target.clone()))
+        }
+        // The status is left alone, so undoing an edit doesn't approve a subtitle again. The
+        // origin goes with the text, so redoing a translation marks it again and undoing it
+        // takes the mark away.
+        (Some(current), Some(target)) => {
+            diesel::update(subtitle::table.find(current.id))
+                .set((
+                    subtitle::start.eq(target.start),
+                    subtitle::end.eq(target.end),
+                    subtitle::text.eq(&target.text),
+                    subtitle::origin.eq(&target.origin),
+                    subtitle::confidence.eq(target.confidence),
+                ))
+                .execute(conn)?;
+            Ok(Some(Subtitle {
+                status: current.status.clone(),
+                ..target.clone()
+            }))
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+/// The event to broadcast after `set_subtitle_state`
+fn subtitle_change_event(
+    project_id: i32,
+    subtitle_id: i32,
+    existed: bool,
+    target: Option<&Subtitle>,
+) -> SubtitleEvent {
+    let info = match target {
+        None => SubtitleEventType::SubtitleDelete(DeleteEventData {
+            subtitle: subtitle_id,
+        }),
+        Some(target) if !existed => SubtitleEventType::SubtitleCreate(CreateEventData::new(target)),
+        Some(target) => SubtitleEventType::SubtitleEdit(EditEventData {
+            subtitle: subtitle_id,
+            start: Some(target.start),
+            end: Some(target.end),
+            text: Some(target.text.clone()),
+            status: target.status.clone(),
+            origin: target.origin.clone(),
+        }),
+    };
+    SubtitleEvent {
+        info,
+        project: project_id,
+    }
+}
+
+enum UndoError {
+    Database,
+    NothingToDo,
+    Conflict,
+    Locked,
+}
+
+impl From<diesel::result::Error> for UndoError {
+    fn from(_: diesel::result::Error) -> Self {
+        UndoError::Database
+    }
+}
+
+/// Undo the user's latest operation in a project, or redo the one they undid last.
+/// Fails with a conflict if any affected subtitle was changed since, so nobody's work is lost.
+fn undo_or_redo(
+    conn: &DbConnection,
+    project_id: i32,
+    user_id: i32,
+    undo: bool,
+) -> Result<Vec<SubtitleEvent>, UndoError> {
+    conn.transaction(|| {
+        let query = operation::table
+            .filter(operation::project.eq(project_id))
+            .filter(operation::user.eq(user_id))
+            .into_boxed();
+        let operation: Operation = if undo {
+            query
+                .filter(operation::undone.eq(0))
+                .order(operation::id.desc())
+        } else {
+            query
+                .filter(operation::undone.eq(1))
+                .order(operation::id.asc())
+        }
+        .first::<Operation>(conn)
+        .optional()?
+        .ok_or(UndoError::NothingToDo)?;
+
+        let mut revisions: Vec<SubtitleRevision> = subtitle_revision::table
+            .filter(subtitle_revision::operation.eq(operation.id))
+            .order(subtitle_revision::id.asc())
+            .load::<SubtitleRevision>(conn)?;
+        if undo {
+            revisions.reverse();
+        }
+
+        let mut events = Vec::new();
+        for revision in revisions {
+            let (expected, target) = if undo {
+                (revision.after(), revision.before())
+            } else {
+                (revision.before(), revision.after())
+            };
+
+            let current: Option<Subtitle> = subtitle::table
+                .filter(subtitle::id.eq(revision.subtitle))
+                .filter(subtitle::project.eq(project_id))
+                .first::<Subtitle>(conn)
+                .optional()?;
+
+            let content = |s: &Subtitle| (s.start, s.end, s.text.clone());
+            if current.as_ref().map(content) != expected.as_ref().map(content) {
+                return Err(UndoError::Conflict);
+            }
+
+            let ranges: Vec<(i32, i32)> = current
+                .iter()
+                .chain(target.iter())
+                .map(|s| (s.start, s.end))
+                .collect();
+            if find_blocking_lock(conn, project_id, user_id, revision.subtitle, &ranges)?.is_some()
+            {
+                return Err(UndoError::Locked);
+            }
+
+            let changed = set_subtitle_state(conn, current.as_ref(), target.as_ref())?;
+            diesel::insert_into(subtitle_revision::table)
+                .values(&NewSubtitleRevision::new(
+                    if undo { "undo" } else { "redo" },
+                    user_id,
+                    unix_timestamp(),
+                    current.as_ref(),
+                    changed.as_ref(),
+                ))
+                .execute(conn)?;
+
+            events.push(subtitle_change_event(
+                project_id,
+                revision.subtitle,
+                current.is_some(),
+                changed.as_ref(),
+            ));
+        }
+
+        diesel::update(operation::table.find(operation.id))
+            .set(operation::undone.eq(if undo { 1 } else { 0 }))
+            .execute(conn)?;
+
+        Ok(events)
+    })
+}
+
+#[post("/project/<project_id>/undo")]
+async fn undo(
+    project_id: i32,
+    user: User,
+    _scope: WriteScope,
+    db: DbConn,
+    queue: &State<Sender<SubtitleEvent>>,
+) -> Result<(), (Status, &'static str)> {
+    let (project, _) = get_project_as_member(&db, project_id, &user)
+        .await
+        .map_err(|status| (status, "Project not found"))?;
+
+    let events = db
+        .run(move |conn| undo_or_redo(conn, project.id, user.id, true))
+        .await
+        .map_err(|error| match error {
+            UndoError::NothingToDo => (Status::NotFound, "Nothing to undo"),
+            UndoError::Conflict => (Status::Conflict, "Subtitle was changed by someone else"),
+            UndoError::Locked => (Status::Locked, "Subtitle is locked by another user"),
+            UndoError::Database => (Status::InternalServerError, "An internal error occured"),
+        })?;
+
+    // Broadcast SSE
+    for event in events {
+        let _ = queue.send(event);
+    }
+
+    Ok(())
+}
+
+#[post("/project/<project_id>/redo")]
+async fn redo(
+    project_id: i32,
+    user: User,
+    _scope: WriteScope,
+    db: DbConn,
+    queue: &State<Sender<SubtitleEvent>>,
+) -> Result<(), (Status, &'static str)> {
+    let (project, _) = get_project_as_member(&db, project_id, &user)
+        .await
+        .map_err(|status| (status, "Project not found"))?;
+
+    let events = db
+        .run(move |conn| undo_or_redo(conn, project.id, user.id, false))
+        .await
+        .map_err(|error| match error {
+            UndoError::NothingToDo => (Status::NotFound, "Nothing to redo"),
+            UndoError::Conflict => (Status::Conflict, "Subtitle was changed by someone else"),
+            UndoError::Locked => (Status::Locked, "Subtitle is locked by another user"),
+            UndoError::Database => (Status::InternalServerError, "An internal error occured"),
+        })?;
+
+    // Broadcast SSE
+    for event in events {
+        let _ = queue.send(event);
+    }
+
+    Ok(())
+}
+
+// Locks
+
+/// Lock duration in seconds when the client doesn't ask for one
+const DEFAULT_LOCK_DURATION: i64 = 10 * 60;
+/// Longest a lock can be held without renewing it, so forgotten locks don't linger
+const MAX_LOCK_DURATION: i64 = 2 * 60 * 60;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct LockInfo {
+    id: i32,
+    owner: i32,
+    owner_name: String,
+    subtitle: Option<i32>,
+    start: Option<i32>,
+    end: Option<i32>,
+    expires: i64,
+}
+
+impl LockInfo {
+    fn new(lock: SubtitleLock, owner: &User) -> LockInfo {
+        LockInfo {
+            id: lock.id,
+            owner: lock.owner,
+            owner_name: owner
+                .display_name
+                .as_ref()
+                .unwrap_or(&owner.username)
+                .to_string(),
+            subtitle: lock.subtitle,
+            start: lock.start,
+            end: lock.end,
+            expires: lock.expires,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct UnlockEventData {
+    lock: i32,
+}
+
+/// Active locks in a project that are held by anyone but `user_id`
+fn other_users_locks(
+    conn: &DbConnection,
+    project_id: i32,
+    user_id: i32,
+) -> QueryResult<Vec<SubtitleLock>> {
+    subtitle_lock::table
+        .filter(subtitle_lock::project.eq(project_id))
+        .filter(subtitle_lock::owner.ne(user_id))
+        .filter(subtitle_lock::expires.gt(unix_timestamp()))
+        .load::<SubtitleLock>(conn)
+}
+
+/// Find a lock held by someone other than `user_id` that covers the subtitle at any of the given
+/// time ranges. Edits pass both the old and the new timing, so a cue can't be moved in or out of
+/// a locked range.
+fn find_blocking_lock(
+    conn: &DbConnection,
+    project_id: i32,
+    user_id: i32,
+    subtitle_id: i32,
+    ranges: &[(i32, i32)],
+) -> QueryResult<Option<SubtitleLock>> {
+    Ok(other_users_locks(conn, project_id, user_id)?
+        .into_iter()
+        .find(|lock| {
+            ranges
+                .iter()
+                .any(|&(start, end)| lock.covers(subtitle_id, start, end))
+        }))
+}
+
+/// Why a change to a single subtitle was refused
+enum SubtitleChangeError {
+    Database,
+    NotFound,
+    Locked,
+}
+
+impl From<diesel::result::Error> for SubtitleChangeError {
+    fn from(error: diesel::result::Error) -> Self {
+        match error {
+            diesel::result::Error::NotFound => SubtitleChangeError::NotFound,
+            _ => SubtitleChangeError::Database,
+        }
+    }
+}
+
+impl From<SubtitleChangeError> for (Status, &'static str) {
+    fn from(error: SubtitleChangeError) -> Self {
+        match error {
+            SubtitleChangeError::Database => {
+                (Status::InternalServerError, "An internal error occured")
+            }
+            SubtitleChangeError::NotFound => (Status::NotFound, "Subtitle not found"),
+            SubtitleChangeError::Locked => (Status::Locked, "Subtitle is locked by another user"),
+        }
+    }
+}
+
+#[get("/project/<project_id>/lock/list")]
+async fn list_locks(
+    project_id: i32,
+    user: User,
+    _scope: ReadScope,
+    db: DbConn,
+) -> Result<Json<Vec<LockInfo>>, Status> {
+    let (project, _) = get_project_as_member(&db, project_id, &user).await?;
+
+    let locks: Vec<(SubtitleLock, User)> = db
+        .run(move |conn| {
+            subtitle_lock::table
+                .inner_join(user::table)
+                .filter(subtitle_lock::project.eq(project.id))
+                .filter(subtitle_lock::expires.gt(unix_timestamp()))
+                .order(subtitle_lock::id.asc())
+                .load::<(SubtitleLock, User)>(conn)
+        })
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(
+        locks
+            .into_iter()
+            .map(|(lock, owner)| LockInfo::new(lock, &owner))
+            .collect(),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct LockCreationInfo {
+    subtitle: Option<i32>,
+    start: Option<i32>,
+    end: Option<i32>,
+    /// Seconds until the lock expires
+    duration: Option<i64>,
+}
+
+#[post("/project/<project_id>/lock/create", data = "<info>")]
+async fn create_lock(
+    project_id: i32,
+    info: Json<LockCreationInfo>,
+    user: User,
+    _scope: WriteScope,
+    db: DbConn,
+    queue: &State<Sender<SubtitleEvent>>,
+) -> Result<String, (Status, &'static str)> {
+    let (project, _) = get_project_as_member(&db, project_id, &user)
+        .await
+        .map_err(|status| (status, "Project not found"))?;
+    let info = info.into_inner();
+
+    // Work out which time range the lock will cover right now, to compare against existing locks
+    let (subtitle_id, range) = match (info.subtitle, info.start, info.end) {
+        (Some(subtitle_id), None, None) => {
+            let subtitle: Subtitle = db
+                .run(move |conn| {
+                    subtitle::table
+                        .filter(subtitle::id.eq(subtitle_id))
+                        .filter(subtitle::project.eq(project.id))
+                        .first::<Subtitle>(conn)
+                })
+                .await
+                .map_err(|_| (Status::NotFound, "Subtitle not found"))?;
+            (Some(subtitle.id), (subtitle.start, subtitle.end))
+        }
+        (None, Some(start), Some(end)) if start < end => (None, (start, end)),
+        _ => {
+            return Err((
+                Status::BadRequest,
+                "Specify either a subtitle or a start and end time",
+            ))
+        }
+    };
+
+    let duration = info.duration.unwrap_or(DEFAULT_LOCK_DURATION);
+    if duration <= 0 || duration > MAX_LOCK_DURATION {
+        return Err((Status::BadRequest, "Invalid lock duration"));
+    }
+
+    let new_lock = NewSubtitleLock {
+        project: project.id,
+        owner: user.id,
+        subtitle: subtitle_id,
+        start: info.start,
+        end: info.end,
+        expires: unix_timestamp() + duration,
+    };
+    let user_id = user.id;
+
+    let lock: Option<SubtitleLock> = db
+        .run(move |conn| {
+            conn.transaction(|| {
+                diesel::delete(subtitle_lock::table)
+                    .filter(subtitle_lock::expires.le(unix_timestamp()))
+                    .execute(conn)?;
+
+                let others = other_users_locks(conn, project.id, user_id)?;
+
+                // Subtitle locks only know their subtitle, so look up where those currently are
+                let locked_ids: Vec<i32> = others.iter().filter_map(|lock| lock.subtitle).collect();
+                let locked_subtitles: Vec<Subtitle> = subtitle::table
+                    .filter(subtitle::id.eq_any(locked_ids))
+                    .load::<Subtitle>(conn)?;
+
+                let conflict = others
+                    .iter()
+                    .any(|lock| match (subtitle_id, lock.subtitle) {
+                        (Some(id), _) => lock.covers(id, range.0, range.1),
+                        (None, Some(locked_id)) => locked_subtitles
+                            .iter()
+                            .any(|s| s.id == locked_id && s.start < range.1 && range.0 < s.end),
+                        (None, None) => match (lock.start, lock.end) {
+                            (Some(start), Some(end)) => start < range.1 && range.0 < end,
+                            _ => false,
+                        },
+                    });
+                if conflict {
+                    return Ok(None);
+                }
+
+                let id = insert_returning_id!(
+                    conn,
+                    diesel::insert_into(subtitle_lock::table).values(&new_lock),
+                    subtitle_lock::id
+                )?;
+
+                subtitle_lock::table
+                    .find(id)
+                    .first::<SubtitleLock>(conn)
+                    .map(Some)
+            })
+        })
+        .await
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+
+    let lock = lock.ok_or((Status::Locked, "Already locked by another user"))?;
+    let lock_id = lock.id;
+
+    // Broadcast SSE
+    let _ = queue.send(SubtitleEvent {
+        info: SubtitleEventType::SubtitleLock(LockInfo::new(lock, &user)),
+        project: project.id,
+    });
+
+    Ok(lock_id.to_string())
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct LockRenewInfo {
+    duration: Option<i64>,
+}
+
+/// Extend a lock held by the current user
+#[post("/project/<project_id>/lock/<lock_id>/renew", data = "<info>")]
+async fn renew_lock(
+    project_id: i32,
+    lock_id: i32,
+    info: Json<LockRenewInfo>,
+    user: User,
+    _scope: WriteScope,
+    db: DbConn,
+    queue: &State<Sender<SubtitleEvent>>,
+) -> Result<(), (Status, &'static str)> {
+    let (project, _) = get_project_as_member(&db, project_id, &user)
+        .await
+        .map_err(|status| (status, "Project not found"))?;
+
+    let duration = info.duration.unwrap_or(DEFAULT_LOCK_DURATION);
+    if duration <= 0 || duration > MAX_LOCK_DURATION {
+        return Err((Status::BadRequest, "Invalid lock duration"));
+    }
+
+    let user_id = user.id;
+    let lock: SubtitleLock = db
+        .run(move |conn| {
+            let now = unix_timestamp();
+            diesel::update(subtitle_lock::table)
+                .filter(subtitle_lock::id.eq(lock_id))
+                .filter(subtitle_lock::project.eq(project.id))
+                .filter(subtitle_lock::owner.eq(user_id))
+                .filter(subtitle_lock::expires.gt(now))
+                .set(subtitle_lock::expires.eq(now + duration))
+                .execute(conn)?;
+            subtitle_lock::table
+                .filter(subtitle_lock::id.eq(lock_id))
+                .filter(subtitle_lock::owner.eq(user_id))
+                .filter(subtitle_lock::expires.gt(now))
+                .first::<SubtitleLock>(conn)
+        })
+        .await
+        .map_err(|_| (Status::NotFound, "Lock not found or expired"))?;
+
+    // Broadcast SSE
+    let _ = queue.send(SubtitleEvent {
+        info: SubtitleEventType::SubtitleLock(LockInfo::new(lock, &user)),
+        project: project.id,
+    });
+
+    Ok(())
+}
+
+/// Release a lock. Workspace admins can also break locks held by other users.
+#[delete("/project/<project_id>/lock/<lock_id>")]
+async fn delete_lock(
+    project_id: i32,
+    lock_id: i32,
+    user: User,
+    _scope: WriteScope,
+    admin_scope: Option<AdminScope>,
+    db: DbConn,
+    queue: &State<Sender<SubtitleEvent>>,
+) -> Result<(), (Status, &'static str)> {
+    let (project, member_role) = get_project_as_member(&db, project_id, &user)
+        .await
+        .map_err(|status| (status, "Project not found"))?;
+
+    let lock: SubtitleLock = db
+        .run(move |conn| {
+            subtitle_lock::table
+                .filter(subtitle_lock::id.eq(lock_id))
+                .filter(subtitle_lock::project.eq(project.id))
+                .first::<SubtitleLock>(conn)
+        })
+        .await
+        .map_err(|_| (Status::NotFound, "Lock not found"))?;
+
+    if lock.owner != user.id {
+        if member_role != role::ADMIN {
+            return Err((Status::Forbidden, "Only workspace admins can break locks"));
+        }
+        if admin_scope.is_none() {
+            return Err((Status::Forbidden, "Access token lacks the admin scope"));
+        }
+    }
+
+    db.run(move |conn| diesel::delete(subtitle_lock::table.find(lock_id)).execute(conn))
+        .await
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+
+    // Broadcast SSE
+    let _ = queue.send(SubtitleEvent {
+        info: SubtitleEventType::SubtitleUnlock(UnlockEventData { lock: lock_id }),
+        project: project.id,
+    });
+
+    Ok(())
+}
+
+// Comments
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct CommentInfo {
+    id: i32,
+    parent: Option<i32>,
+    author: i32,
+    author_name: String,
+    subtitle: Option<i32>,
+    start: Option<i32>,
+    end: Option<i32>,
+    text: String,
+    created: i64,
+    edited: Option<i64>,
+    resolved: bool,
+    resolved_by: Option<i32>,
+    mentions: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct CommentDeleteEventData {
+    comment: i32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct CommentResolveEventData {
+    comment: i32,
+    resolved: bool,
+    resolved_by: Option<i32>,
+}
+
+/// Usernames mentioned as `@name` in a comment
+fn parse_mentions(text: &str) -> Vec<String> {
+    let mut names: Vec<String> = text
+        .split_whitespace()
+        .filter_map(|word| word.strip_prefix('@'))
+        .map(|name| name.trim_end_matches(|c: char| ",.!?:;)".contains(c)))
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_string())
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Replace the mentions of a comment with the workspace members named in its text
+fn save_mentions(
+    conn: &DbConnection,
+    comment_id: i32,
+    workspace_id: i32,
+    text: &str,
+) -> QueryResult<()> {
+    diesel::delete(comment_mention::table)
+        .filter(comment_mention::comment.eq(comment_id))
+        .execute(conn)?;
+
+    let mentioned: Vec<CommentMention> = workspace_member::table
+        .inner_join(user::table)
+        .filter(workspace_member::workspace.eq(workspace_id))
+        .filter(user::username.eq_any(parse_mentions(text)))
+        .select(user::id)
+        .load::<i32>(conn)?
+        .into_iter()
+        .map(|user_id| CommentMention {
+            comment: comment_id,
+            user: user_id,
+        })
+        .collect();
+
+    diesel::insert_into(comment_mention::table)
+        .values(&mentioned)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Load comments of a project along with their authors and mentions.
+/// With `comment_id`, only that comment is loaded.
+fn load_comment_infos(
+    conn: &DbConnection,
+    project_id: i32,
+    comment_id: Option<i32>,
+) -> QueryResult<Vec<CommentInfo>> {
+    let mut query = comment::table
+        .inner_join(user::table.on(user::id.eq(comment::author)))
+        .filter(comment::project.eq(project_id))
+        .into_boxed();
+    if let Some(comment_id) = comment_id {
+        query = query.filter(comment::id.eq(comment_id));
+    }
+    let comments: Vec<(Comment, User)> = query
+        .order(comment::id.asc())
+        .load::<(Comment, User)>(conn)?;
+
+    let ids: Vec<i32> = comments.iter().map(|(comment, _)| comment.id).collect();
+    let mentions: Vec<(i32, String)> = comment_mention::table
+        .inner_join(user::table)
+        .filter(comment_mention::comment.eq_any(ids))
+        .select((comment_mention::comment, user::username))
+        .load::<(i32, String)>(conn)?;
+
+    Ok(comments
+        .into_iter()
+        .map(|(comment, author)| CommentInfo {
+            id: comment.id,
+            parent: comment.parent,
+            author: comment.author,
+            author_name: author.display_name.unwrap_or(author.username),
+            subtitle: comment.subtitle,
+            start: comment.start,
+            end: comment.end,
+            text: comment.text,
+            created: comment.created,
+            edited: comment.edited,
+            resolved: comment.resolved != 0,
+            resolved_by: comment.resolved_by,
+            mentions: mentions
+                .iter()
+                .filter(|(id, _)| *id == comment.id)
+                .map(|(_, name)| name.clone())
+                .collect(),
+        })
+        .collect())
+}
+
+#[get("/project/<project_id>/comment/list")]
+async fn list_comments(
+    project_id: i32,
+    user: User,
+    _scope: ReadScope,
+    db: DbConn,
+) -> Result<Json<Vec<CommentInfo>>, Status> {
+    let (project, _) = get_project_as_member(&db, project_id, &user).await?;
+
+    let comments = db
+        .run(move |conn| load_comment_infos(conn, project.id, None))
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(comments))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct CommentCreationInfo {
+    text: String,
+    parent: Option<i32>,
+    subtitle: Option<i32>,
+    start: Option<i32>,
+    end: Option<i32>,
+}
+
+#[post("/project/<project_id>/comment/create", data = "<info>")]
+async fn create_comment(
+    project_id: i32,
+    info: Json<CommentCreationInfo>,
+    user: User,
+    _scope: WriteScope,
+    db: DbConn,
+    queue: &State<Sender<SubtitleEvent>>,
+) -> Result<String, (Status, &'static str)> {
+    let (project, _) = get_project_as_member(&db, project_id, &user)
+        .await
+        .map_err(|status| (status, "Project not found"))?;
+    let info = info.into_inner();
+
+    if info.text.trim().is_empty() {
+        return Err((Status::BadRequest, "Comment cannot be empty"));
+    }
+
+    let new_comment = match info.parent {
+        // Replies belong to the thread, so they can't be attached to anything themselves
+        Some(parent_id) => {
+            if info.subtitle.is_some() || info.start.is_some() || info.end.is_some() {
+                return Err((
+                    Status::BadRequest,
+                    "Replies cannot be attached to subtitles",
+                ));
+            }
+            let parent: Comment = db
+                .run(move |conn| {
+                    comment::table
+                        .filter(comment::id.eq(parent_id))
+                        .filter(comment::project.eq(project.id))
+                        .first::<Comment>(conn)
+                })
+                .await
+                .map_err(|_| (Status::NotFound, "Comment not found"))?;
+            if parent.parent.is_some() {
+                return Err((Status::BadRequest, "Cannot reply to a reply"));
+            }
+            NewComment {
+                project: project.id,
+                parent: Some(parent.id),
+                author: user.id,
+                subtitle: None,
+                start: None,
+                end: None,
+                text: info.text,
+                created: unix_timestamp(),
+            }
+        }
+        None => {
+            match (info.start, info.end) {
+                (None, None) => {}
+                (Some(start), Some(end)) if start <= end => {}
+                _ => return Err((Status::BadRequest, "Invalid time range")),
+            }
+            if let Some(subtitle_id) = info.subtitle {
+                db.run(move |conn| {
+                    subtitle::table
+                        .filter(subtitle::id.eq(subtitle_id))
+                        .filter(subtitle::project.eq(project.id))
+                        .select(subtitle::id)
+                        .first::<i32>(conn)
+                })
+                .await
+                .map_err(|_| (Status::NotFound, "Subtitle not found"))?;
+            }
+            NewComment {
+                project: project.id,
+                parent: None,
+                author: user.id,
+                subtitle: info.subtitle,
+                start: info.start,
+                end: info.end,
+                text: info.text,
+                created: unix_timestamp(),
+            }
+        }
+    };
+
+    let comment: CommentInfo = db
+        .run(move |conn| {
+            conn.transaction(|| {
+                let id = insert_returning_id!(
+                    conn,
+                    diesel::insert_into(comment::table).values(&new_comment),
+                    comment::id
+                )?;
+                save_mentions(conn, id, project.workspace, &new_comment.text)?;
+
+                load_comment_infos(conn, project.id, Some(id))?
+                    .pop()
+                    .ok_or(diesel::result::Error::NotFound)
+            })
+        })
+        .await
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+    let comment_id = comment.id;
+
+    // Broadcast SSE
+    let _ = queue.send(SubtitleEvent {
+        info: SubtitleEventType::CommentCreate(comment),
+        project: project.id,
+    });
+
+    Ok(comment_id.to_string())
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct CommentEditInfo {
+    text: String,
+}
+
+/// Change the text of a comment. Only its author can do this.
+#[patch("/project/<project_id>/comment/<comment_id>", data = "<info>")]
+async fn edit_comment(
+    project_id: i32,
+    comment_id: i32,
+    info: Json<CommentEditInfo>,
+    user: User,
+    _scope: WriteScope,
+    db: DbConn,
+    queue: &State<Sender<SubtitleEvent>>,
+) -> Result<(), (Status, &'static str)> {
+    let (project, _) = get_project_as_member(&db, project_id, &user)
+        .await
+        .map_err(|status| (status, "Project not found"))?;
+    let text = info.into_inner().text;
+
+    if text.trim().is_empty() {
+        return Err((Status::BadRequest, "Comment cannot be empty"));
+    }
+
+    let author: i32 = db
+        .run(move |conn| {
+            comment::table
+                .filter(comment::id.eq(comment_id))
+                .filter(comment::project.eq(project.id))
+                .select(comment::author)
+                .first::<i32>(conn)
+        })
+        .await
+        .map_err(|_| (Status::NotFound, "Comment not found"))?;
+
+    if author != user.id {
+        return Err((Status::Forbidden, "Only the author can edit a comment"));
+    }
+
+    let comment: CommentInfo = db
+        .run(move |conn| {
+            conn.transaction(|| {
+                diesel::update(comment::table.find(comment_id))
+                    .set((
+                        comment::text.eq(&text),
+                        comment::edited.eq(Some(unix_timestamp())),
+                    ))
+                    .execute(conn)?;
+                save_mentions(conn, comment_id, project.workspace, &text)?;
+
+                load_comment_infos(conn, project.id, Some(comment_id))?
+                    .pop()
+                    .ok_or(diesel::result::Error::NotFound)
+            })
+        })
+        .await
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+
+    // Broadcast SSE
+    let _ = queue.send(SubtitleEvent {
+        info: SubtitleEventType::CommentEdit(comment),
+        project: project.id,
+    });
+
+    Ok(())
+}
+
+/// Delete a comment and its replies. Workspace admins can delete anyone's comments.
+#[delete("/project/<project_id>/comment/<comment_id>")]
+async fn delete_comment(
+    project_id: i32,
+    comment_id: i32,
+    user: User,
+    _scope: WriteScope,
+    admin_scope: Option<AdminScope>,
+    db: DbConn,
+    queue: &State<Sender<SubtitleEvent>>,
+) -> Result<(), (Status, &'static str)> {
+    let (project, member_role) = get_project_as_member(&db, project_id, &user)
+        .await
+        .map_err(|status| (status, "Project not found"))?;
+
+    let author: i32 = db
+        .run(move |conn| {
+            comment::table
+                .filter(comment::id.eq(comment_id))
+                .filter(comment::project.eq(project.id))
+                .select(comment::author)
+                .first::<i32>(conn)
+        })
+        .await
+        .map_err(|_| (Status::NotFound, "Comment not found"))?;
+
+    if author != user.id {
+        if member_role != role::ADMIN {
+            return Err((Status::Forbidden, "Only the author can delete a comment"));
+        }
+        if admin_scope.is_none() {
+            return Err((Status::Forbidden, "Access token lacks the admin scope"));
+        }
+    }
+
+    db.run(move |conn| {
+        conn.transaction(|| {
+            let thread: Vec<i32> = comment::table
+                .filter(
+                    comment::id
+                        .eq(comment_id)
+                        .or(comment::parent.eq(comment_id)),
+                )
+                .select(comment::id)
+                .load::<i32>(conn)?;
+            diesel::delete(comment_mention::table)
+                .filter(comment_mention::comment.eq_any(thread.clone()))
+                .execute(conn)?;
+            diesel::delete(comment::table)
+                .filter(comment::id.eq_any(thread))
+                .execute(conn)
+        })
+    })
+    .await
+    .map_err(|_: diesel::result::Error| {
+        (Status::InternalServerError, "An internal error occured")
+    })?;
+
+    // Broadcast SSE
+    let _ = queue.send(SubtitleEvent {
+        info: SubtitleEventType::CommentDelete(CommentDeleteEventData {
+            comment: comment_id,
+        }),
+        project: project.id,
+    });
+
+    Ok(())
+}
+
+async fn set_comment_resolved(
+    project_id: i32,
+    comment_id: i32,
+    resolved: bool,
+    user: User,
+    db: DbConn,
+    queue: &State<Sender<SubtitleEvent>>,
+) -> Result<(), (Status, &'static str)> {
+    let (project, _) = get_project_as_member(&db, project_id, &user)
+        .await
+        .map_err(|status| (status, "Project not found"))?;
+
+    let comment: Comment = db
+        .run(move |conn| {
+            comment::table
+                .filter(comment::id.eq(comment_id))
+                .filter(comment::project.eq(project.id))
+                .first::<Comment>(conn)
+        })
+        .await
+        .map_err(|_| (Status::NotFound, "Comment not found"))?;
+
+    if comment.parent.is_some() {
+        return Err((Status::BadRequest, "Only whole threads can be resolved"));
+    }
+
+    let resolved_by = if resolved { Some(user.id) } else { None };
+    db.run(move |conn| {
+        diesel::update(comment::table.find(comment_id))
+            .set((
+                comment::resolved.eq(resolved as i32),
+                comment::resolved_by.eq(resolved_by),
+            ))
+            .execute(conn)
+    })
+    .await
+    .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+
+    // Broadcast SSE
+    let _ = queue.send(SubtitleEvent {
+        info: SubtitleEventType::CommentResolve(CommentResolveEventData {
+            comment: comment_id,
+            resolved,
+            resolved_by,
+        }),
+        project: project.id,
+    });
+
+    Ok(())
+}
+
+#[post("/project/<project_id>/comment/<comment_id>/resolve")]
+async fn resolve_comment(
+    project_id: i32,
+    comment_id: i32,
+    user: User,
+    _scope: WriteScope,
+    db: DbConn,
+    queue: &State<Sender<SubtitleEvent>>,
+) -> Result<(), (Status, &'static str)> {
+    set_comment_resolved(project_id, comment_id, true, user, db, queue).await
+}
+
+#[post("/project/<project_id>/comment/<comment_id>/unresolve")]
+async fn unresolve_comment(
+    project_id: i32,
+    comment_id: i32,
+    user: User,
+    _scope: WriteScope,
+    db: DbConn,
+    queue: &State<Sender<SubtitleEvent>>,
+) -> Result<(), (Status, &'static str)> {
+    set_comment_resolved(project_id, comment_id, false, user, db, queue).await
+}
+
+// Review status
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct SubtitleStatusInfo {
+    status: String,
+}
+
+#[patch("/project/<project_id>/subtitle/<subtitle_id>/status", data = "<info>")]
+async fn set_subtitle_status(
+    project_id: i32,
+    subtitle_id: i32,
+    info: Json<SubtitleStatusInfo>,
+    user: User,
+    _scope: WriteScope,
+    db: DbConn,
+    queue: &State<Sender<SubtitleEvent>>,
+) -> Result<(), (Status, &'static str)> {
+    let (project, member_role) = get_project_as_member(&db, project_id, &user)
+        .await
+        .map_err(|status| (status, "Project not found"))?;
+    let new_status = info.into_inner().status;
+
+    if !status::ALL.contains(&new_status.as_str()) {
+        return Err((Status::BadRequest, "Unknown status"));
+    }
+
+    let subtitle: Subtitle = db
+        .run(move |conn| {
+            subtitle::table
+                .filter(subtitle::id.eq(subtitle_id))
+                .filter(subtitle::project.eq(project.id))
+                .first::<Subtitle>(conn)
+        })
+        .await
+        .map_err(|_| (Status::NotFound, "Subtitle not found"))?;
+
+    if subtitle.status == new_status {
+        return Ok(());
+    }
+
+    if !status::can_transition(member_role, &subtitle.status, &new_status) {
+        return Err((
+            Status::Forbidden,
+            "Only reviewers can approve, reject or reopen subtitles",
+        ));
+    }
+
+    let range = [(subtitle.start, subtitle.end)];
+    let blocking_lock = db
+        .run(move |conn| find_blocking_lock(conn, project.id, user.id, subtitle_id, &range))
+        .await
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+
+    if blocking_lock.is_some() {
+        return Err((Status::Locked, "Subtitle is locked by another user"));
+    }
+
+    let status_clone = new_status.clone();
+    db.run(move |conn| {
+        conn.transaction(|| {
+            diesel::update(subtitle::table.find(subtitle_id))
+                .set(subtitle::status.eq(&status_clone))
+                .execute(conn)?;
+            diesel::insert_into(subtitle_status_change::table)
+                .values(&NewSubtitleStatusChange {
+                    project: project.id,
+                    subtitle: subtitle_id,
+                    user: Some(user.id),
+                    timestamp: unix_timestamp(),
+                    from: subtitle.status,
+                    to: status_clone,
+                })
+                .execute(conn)
+        })
+    })
+    .await
+    .map_err(|_: diesel::result::Error| {
+        (Status::InternalServerError, "An internal error occured")
+    })?;
+
+    // Broadcast SSE
+    let _ = queue.send(SubtitleEvent {
+        info: SubtitleEventType::SubtitleEdit(EditEventData {
+            subtitle: subtitle_id,
+            start: None,
+            end: None,
+            text: None,
+            status: new_status,
+            origin: None,
+        }),
+        project: project.id,
+    });
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct StatusChangeInfo {
+    user: Option<i32>,
+    user_name: Option<String>,
+    timestamp: i64,
+    from: String,
+    to: String,
+}
+
+#[get("/project/<project_id>/subtitle/<subtitle_id>/status/history")]
+async fn get_subtitle_status_history(
+    project_id: i32,
+    subtitle_id: i32,
+    user: User,
+    _scope: ReadScope,
+    db: DbConn,
+) -> Result<Json<Vec<StatusChangeInfo>>, Status> {
+    let (project, _) = get_project_as_member(&db, project_id, &user).await?;
+
+    let changes: Vec<(SubtitleStatusChange, Option<User>)> = db
+        .run(move |conn| {
+            subtitle_status_change::table
+                .left_join(user::table)
+                .filter(subtitle_status_change::project.eq(project.id))
+                .filter(subtitle_status_change::subtitle.eq(subtitle_id))
+                .order(subtitle_status_change::id.asc())
+                .load::<(SubtitleStatusChange, Option<User>)>(conn)
+        })
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(
+        changes
+            .into_iter()
+            .map(|(change, author)| StatusChangeInfo {
+                user: change.user,
+                user_name: author.map(|author| author.display_name.unwrap_or(author.username)),
+                timestamp: change.timestamp,
+                from: change.from,
+                to: change.to,
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct ProjectProgress {
+    total: i64,
+    statuses: BTreeMap<String, i64>,
+}
+
+/// Count the subtitles of a project per review status
+#[get("/project/<project_id>/progress")]
+async fn get_project_progress(
+    project_id: i32,
+    user: User,
+    _scope: ReadScope,
+    db: DbConn,
+) -> Result<Json<ProjectProgress>, Status> {
+    let (project, _) = get_project_as_member(&db, project_id, &user).await?;
+
+    let subtitle_statuses: Vec<String> = db
+        .run(move |conn| {
+            subtitle::table
+                .filter(subtitle::project.eq(project.id))
+                .select(subtitle::status)
+                .load::<String>(conn)
+        })
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let mut statuses: BTreeMap<String, i64> = status::ALL
+        .iter()
+        .map(|status| (status.to_string(), 0))
+        .collect();
+    for status in &subtitle_statuses {
+        *statuses.entry(status.clone()).or_insert(0) += 1;
+    }
+
+    Ok(Json(ProjectProgress {
+        total: subtitle_statuses.len() as i64,
+        statuses,
+    }))
+}
+
+#[post("/project/<project_id>/snapshot/create")]
+async fn create_snapshot(
+    project_id: i32,
+    user: User,
+    _scope: WriteScope,
+    db: DbConn,
+) -> Result<String, Status> {
+    let (project, _) = get_project_as_member(&db, project_id, &user)
+        .await
+        .map_err(|_| Status::NotFound)?;
+
+    // Get all subtitles for this project
+    let subtitles: Vec<Subtitle> = db
+        .run(move |conn| {
+            subtitle::table
+                .filter(subtitle::project.eq(project.id))
+                .order(subtitle::start)
+                .load::<Subtitle>(conn)
+        })
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // Put them into a big json array
+    let subtitles_json = rocket::serde::json::serde_json::to_string(&subtitles)
+        .map_err(|_| Status::InternalServerError)?;
+
+    let _affected_rows: usize = db
+        .run(move |conn| {
+            diesel::insert_into(schema::snapshot::table)
+                .values(&Snapshot {
+                    project: project.id,
+                    name: None,
+                    timestamp: unix_timestamp(),
+                    subtitles: subtitles_json,
+                })
+                .execute(conn)
+        })
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    Ok("done".to_string())
+}
+
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct SnapshotInfo {
+    project: i32,
+    timestamp: i64,
+    name: Option<String>,
+}
+
+#[get("/project/<project_id>/snapshot/list", rank = 1)]
+async fn list_snapshots(
+    project_id: i32,
+    user: User,
+    _scope: ReadScope,
+    db: DbConn,
+) -> Result<Json<Vec<SnapshotInfo>>, Status> {
+    let (project, _) = get_project_as_member(&db, project_id, &user)
+        .await
+        .map_err(|_| Status::NotFound)?;
+
+    let snapshots: Vec<SnapshotInfo> = db
+        .run(move |conn| {
+            schema::snapshot::table
+                .filter(schema::snapshot::project.eq(project.id))
+                .order(schema::snapshot::timestamp.desc())
+                .select((
+                    schema::snapshot::project,
+                    schema::snapshot::timestamp,
+                    schema::snapshot::name,
+                ))
+                .load::<(i32, i64, Option<String>)>(conn)
+        })
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .into_iter()
+        .map(|(project, timestamp, name)| SnapshotInfo {
+            project,
+            timestamp,
+            name,
+        })
+        .collect();
+
+    Ok(Json(snapshots))
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct SnapshotResponse {
+    name: Option<String>,
+    subtitles: Vec<Subtitle>,
+}
+
+#[get("/project/<project_id>/snapshot/<timestamp>", rank = 2)]
+async fn get_snapshot(
+    project_id: i32,
+    timestamp: i64,
+    user: User,
+    _scope: ReadScope,
+    db: DbConn,
+) -> Result<Json<SnapshotResponse>, Status> {
+    let (project, _) = get_project_as_member(&db, project_id, &user)
+        .await
+        .map_err(|_| Status::NotFound)?;
+
+    let snapshot: Snapshot = db
+        .run(move |conn| {
+            schema::snapshot::table
+                .filter(schema::snapshot::project.eq(project.id))
+                .filter(schema::snapshot::timestamp.eq(timestamp))
+                .first::<Snapshot>(conn)
+        })
+        .await
+        .map_err(|_| Status::NotFound)?;
+
+    // This is kinda unnecessary because it first deserializes the json and then re-serializes it.
+    // But this way the return type of this function is the clearest.
+    let subtitles: Vec<Subtitle> = rocket::serde::json::serde_json::from_str(&snapshot.subtitles)
+        .map_err(|_| Status::InternalServerError)?;
+
+    let response: SnapshotResponse = SnapshotResponse {
+        name: snapshot.name,
+        subtitles,
+    };
+
+    Ok(Json(response))
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct SnapshotPatchInfo {
+    name: String,
+}
+
+#[patch("/project/<project_id>/snapshot/<timestamp>", data = "<info>")]
+async fn edit_snapshot(
+    project_id: i32,
+    timestamp: i64,
+    info: Json<SnapshotPatchInfo>,
+    user: User,
+    _scope: WriteScope,
+    db: DbConn,
+) -> Result<(), Status> {
+    let (project, _) = get_project_as_member(&db, project_id, &user)
+        .await
+        .map_err(|_| Status::NotFound)?;
+
+    let _affected_rows: usize = db
+        .run(move |conn| {
+            diesel::update(schema::snapshot::table)
+                .filter(schema::snapshot::project.eq(project.id))
+                .filter(schema::snapshot::timestamp.eq(timestamp))
+                .set(schema::snapshot::name.eq(info.name.clone()))
+                .execute(conn)
+        })
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    Ok(())
+}
+
+// Authentication
+
+/// How long a session stays valid without being used, in seconds
+const SESSION_DURATION: i64 = 30 * 24 * 60 * 60;
+/// Only bump `last_seen` this often, so not every request writes to the database
+const SESSION_TOUCH_INTERVAL: i64 = 5 * 60;
+/// How long a user has to enter their two-factor code after entering their password
+const PENDING_SESSION_DURATION: i64 = 5 * 60;
+
+fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Tokens are only stored hashed, so a leaked database can't be used to log in
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Hash of the session token in the auth cookie, if there is one
+fn current_session_token(cookies: &CookieJar<'_>) -> Option<String> {
+    cookies
+        .get_private("auth")
+        .map(|cookie| hash_token(cookie.value()))
+}
+
+/// Create a new session for the user and put its token in the auth cookie.
+/// Pending sessions go in the pending_auth cookie instead and only allow entering a two-factor code.
+async fn start_session(
+    db: &DbConn,
+    cookies: &CookieJar<'_>,
+    user_id: i32,
+    user_agent: Option<String>,
+    pending: bool,
+) -> QueryResult<()> {
+    let token = generate_token();
+    let now = unix_timestamp();
+    let new_session = NewSession {
+        token: hash_token(&token),
+        user: user_id,
+        created: now,
+        last_seen: now,
+        user_agent,
+        expires: now
+            + if pending {
+                PENDING_SESSION_DURATION
+            } else {
+                SESSION_DURATION
+            },
+        pending: pending as i32,
+    };
+
+    db.run(move |conn| {
+        diesel::delete(session::table)
+            .filter(session::user.eq(user_id))
+            .filter(session::expires.le(now))
+            .execute(conn)?;
+        diesel::insert_into(session::table)
+            .values(&new_session)
+            .execute(conn)
+    })
+    .await?;
+
+    let cookie_name = if pending { "pending_auth" } else { "auth" };
+    cookies.add_private(Cookie::new(cookie_name, token));
+    Ok(())
+}
+
+/// Log a user out everywhere
+fn revoke_all_sessions(conn: &DbConnection, user_id: i32) -> QueryResult<usize> {
+    diesel::delete(session::table)
+        .filter(session::user.eq(user_id))
+        .execute(conn)
+}
+
+fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+/// Replace a user's password on their behalf, logging them out and lifting any lockout
+fn reset_user_password(conn: &DbConnection, user: &User, password_hash: &str) -> QueryResult<()> {
+    diesel::update(user::table.find(user.id))
+        .set(user::password.eq(password_hash))
+        .execute(conn)?;
+    revoke_all_sessions(conn, user.id)?;
+    clear_login_failures(conn, user.id, &user.username)?;
+    Ok(())
+}
+
+/// The User-Agent header of a request, stored with new sessions so users can tell them apart
+struct UserAgent(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for UserAgent {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<UserAgent, Self::Error> {
+        Outcome::Success(UserAgent(
+            request.headers().get_one("User-Agent").map(String::from),
+        ))
+    }
+}
+
+/// The `ip_header` setting: the header a reverse proxy puts the client's address in
+struct IpHeader(Option<String>);
+
+/// Address of the client, used to throttle failed logins. Clients can send any header they like,
+/// so this is only known when `ip_header` is set, and the proxy in front of the server has to
+/// overwrite that header. Without it, logins are only throttled per username.
+struct ClientAddress(Option<IpAddr>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientAddress {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<ClientAddress, ()> {
+        let address = match request.rocket().state::<IpHeader>() {
+            Some(IpHeader(Some(header))) => request
+                .headers()
+                .get_one(header)
+                .and_then(|address| address.trim().parse().ok()),
+            _ => None,
+        };
+        Outcome::Success(ClientAddress(address))
+    }
+}
+
+/// How the current request was authenticated. Cached in the request by `authenticate`.
+enum Authentication {
+    Session,
+    AccessToken(Vec<String>),
+}
+
+/// Authenticate a request that carries an access token. Its scopes are checked by the
+/// `ReadScope`, `WriteScope` and `AdminScope` guards of each route.
+async fn authenticate_access_token(
+    request: &Request<'_>,
+    db: DbConn,
+    token: String,
+) -> Authenticated {
+    let result = db
+        .run(move |conn| {
+            let now = unix_timestamp();
+            let (access_token, user): (AccessToken, User) = access_token::table
+                .inner_join(user::table)
+                .filter(access_token::token.eq(token))
+                .filter(
+                    access_token::expires
+                        .is_null()
+                        .or(access_token::expires.gt(now)),
+                )
+                .filter(user::disabled.eq(0))
+                .first(conn)?;
+
+            diesel::update(access_token::table.find(access_token.id))
+                .set(access_token::last_used.eq(Some(now)))
+                .execute(conn)?;
+
+            Ok((access_token, user))
+        })
+        .await;
+
+    let (access_token, user) = match result {
+        Ok(result) => result,
+        Err(diesel::result::Error::NotFound) => {
+            return Err((Status::Unauthorized, "Invalid or expired access token"))
+        }
+        Err(_) => return Err((Status::InternalServerError, "An internal error occured.")),
+    };
+
+    let scopes = access_token.scope_list();
+    request.local_cache(|| Authentication::AccessToken(scopes));
+    Ok(user)
+}
+
+/// The user a request is made by, or why it isn't allowed
+type Authenticated = Result<User, (Status, &'static str)>;
+
+/// Ensure user is logged in and get their info from the DB
+async fn authenticate(request: &Request<'_>) -> Authenticated {
+    let db = match request.guard::<DbConn>().await {
+        Outcome::Success(c) => c,
+        _ => return Err((Status::ServiceUnavailable, "An internal error occured.")),
+    };
+
+    let bearer_token = request
+        .headers()
+        .get_one("Authorization")
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .map(|token| hash_token(token.trim()));
+    if let Some(token) = bearer_token {
+        return authenticate_access_token(request, db, token).await;
+    }
+
+    let token = match current_session_token(request.cookies()) {
+        Some(token) => token,
+        None => return Err((Status::Unauthorized, "No cookies?")),
+    };
+
+    let user = db
+        .run(move |conn| {
+            let now = unix_timestamp();
+            let (session, user): (Session, User) = session::table
+                .inner_join(user::table)
+                .filter(session::token.eq(token))
+                .filter(session::expires.gt(now))
+                .filter(session::pending.eq(0))
+                .filter(user::disabled.eq(0))
+                .first(conn)?;
+
+            if now - session.last_seen > SESSION_TOUCH_INTERVAL {
+                diesel::update(session::table.find(session.id))
+                    .set((
+                        session::last_seen.eq(now),
+                        session::expires.eq(now + SESSION_DURATION),
+                    ))
+                    .execute(conn)?;
+            }
+
+            Ok(user)
+        })
+        .await;
+    match user {
+        Ok(user) => {
+            request.local_cache(|| Authentication::Session);
+            Ok(user)
+        }
+        Err(diesel::result::Error::NotFound) => {
+            Err((Status::Unauthorized, "Session expired or revoked"))
+        }
+        Err(_) => Err((Status::InternalServerError, "An internal error occured.")),
+    }
+}
+
+/// Authenticate the request once and cache the result in it, so the `User` guard and the scope
+/// guards of a route share a single lookup
+async fn authenticated_user<'a>(request: &'a Request<'_>) -> &'a Authenticated {
+    request.local_cache_async(authenticate(request)).await
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for User {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<User, Self::Error> {
+        match authenticated_user(request).await {
+            Ok(user) => Outcome::Success(user.clone()),
+            Err(failure) => Outcome::Failure(*failure),
+        }
+    }
+}
+
+/// Check that the request is logged in, and that its access token has the `required` scope if
+/// it was made with one. Every route with a `User` has one of the guards below that do this.
+async fn require_scope<T>(
+    request: &Request<'_>,
+    required: &str,
+    error: &'static str,
+    guard: T,
+) -> request::Outcome<T, &'static str> {
+    if let Err(failure) = authenticated_user(request).await {
+        return Outcome::Failure(*failure);
+    }
+
+    match request.local_cache(|| Authentication::Session) {
+        Authentication::AccessToken(scopes) if !scope::allows(scopes, required) => {
+            Outcome::Failure((Status::Forbidden, error))
+        }
+        _ => Outcome::Success(guard),
+    }
+}
+
+/// Guard for routes that only read. Logged in users and all access tokens pass.
+struct ReadScope;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ReadScope {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<ReadScope, Self::Error> {
+        require_scope(
+            request,
+            scope::READ,
+            "Access token lacks the read scope",
+            ReadScope,
+        )
+        .await
+    }
+}
+
+/// Guard for routes that change projects, subtitles, comments and snapshots.
+/// Logged in users always pass, access tokens need the subtitles:write scope.
+struct WriteScope;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WriteScope {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<WriteScope, Self::Error> {
+        require_scope(
+            request,
+            scope::SUBTITLES_WRITE,
+            "Access token lacks the subtitles:write scope",
+            WriteScope,
+        )
+        .await
+    }
+}
+
+/// Guard for routes that manage the account itself, such as sessions and access tokens, and
+/// for what only workspace admins can do. Logged in users always pass, access tokens need the
+/// admin scope.
+struct AdminScope;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminScope {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<AdminScope, Self::Error> {
+        require_scope(
+            request,
+            scope::ADMIN,
+            "Access token lacks the admin scope",
+            AdminScope,
+        )
+        .await
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct LoginInfo {
+    user: String,
+    password: String,
+}
+
+/// Failed logins that are let through without any delay
+const FREE_LOGIN_ATTEMPTS: i32 = 3;
+/// Failed logins after which the username or address is locked out for a while
+const LOGIN_LOCKOUT_THRESHOLD: i32 = 10;
+const LOGIN_LOCKOUT_DURATION: i64 = 15 * 60;
+const MAX_LOGIN_DELAY: i64 = 5 * 60;
+/// Failures older than this are forgotten
+const LOGIN_FAILURE_WINDOW: i64 = 60 * 60;
+
+fn login_failure_keys(username: &str, client_ip: Option<IpAddr>) -> Vec<String> {
+    let mut keys = vec![format!("user:{}", username)];
+    if let Some(ip) = client_ip {
+        keys.push(format!("ip:{}", ip));
+    }
+    keys
+}
+
+/// Whether another login attempt is allowed right now. After a few failures, attempts have to be
+/// spaced out exponentially further apart, and after even more the key is locked out entirely.
+fn login_allowed(failure: &LoginFailure, now: i64) -> bool {
+    if now - failure.last_failure > LOGIN_FAILURE_WINDOW {
+        return true;
+    }
+    if matches!(failure.locked_until, Some(locked_until) if locked_until > now) {
+        return false;
+    }
+    if failure.failures < FREE_LOGIN_ATTEMPTS {
+        return true;
+    }
+    let delay = (1i64 << (failure.failures - FREE_LOGIN_ATTEMPTS).min(16)).min(MAX_LOGIN_DELAY);
+    now >= failure.last_failure + delay
+}
+
+/// Count a failed login against each key. The count is incremented by the database, so
+/// concurrent failures can't overwrite each other.
+fn record_login_failure(conn: &DbConnection, keys: &[String]) -> QueryResult<()> {
+    let now = unix_timestamp();
+    conn.transaction(|| {
+        for key in keys {
+            let updated = diesel::update(
+                login_failure::table
+                    .filter(login_failure::key.eq(key))
+                    .filter(login_failure::last_failure.ge(now - LOGIN_FAILURE_WINDOW)),
+            )
+            .set((
+                login_failure::failures.eq(login_failure::failures + 1),
+                login_failure::last_failure.eq(now),
+            ))
+            .execute(conn)?;
+            // The first failure, or the first one since the others were forgotten
+            if updated == 0 {
+                upsert!(
+                    conn,
+                    login_failure::table,
+                    login_failure::key,
+                    &LoginFailure {
+                        key: key.clone(),
+                        failures: 1,
+                        last_failure: now,
+                        locked_until: None,
+                    }
+                )?;
+            }
+
+            diesel::update(
+                login_failure::table
+                    .filter(login_failure::key.eq(key))
+                    .filter(login_failure::failures.ge(LOGIN_LOCKOUT_THRESHOLD)),
+            )
+            .set(login_failure::locked_until.eq(Some(now + LOGIN_LOCKOUT_DURATION)))
+            .execute(conn)?;
+        }
+        Ok(())
+    })
+}
+
+/// Forget a user's failed logins, lifting lockouts of both their password and their two-factor
+/// codes. Lockouts of an address are left alone, they expire after `LOGIN_LOCKOUT_DURATION`.
+fn clear_login_failures(conn: &DbConnection, user_id: i32, username: &str) -> QueryResult<usize> {
+    diesel::delete(login_failure::table)
+        .filter(
+            login_failure::key.eq_any([format!("user:{}", username), format!("2fa:{}", user_id)]),
+        )
+        .execute(conn)
+}
+
+/// Checked against when the username doesn't exist, so that takes as long as a wrong password
+static DUMMY_PASSWORD_HASH: OnceLock<String> = OnceLock::new();
+
+fn dummy_password_hash() -> &'static str {
+    DUMMY_PASSWORD_HASH.get_or_init(|| {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(b"not a real password", &salt)
+            .expect("could not hash dummy password")
+            .to_string()
+    })
+}
+
+#[post("/login", data = "<info>")]
+async fn login(
+    cookies: &CookieJar<'_>,
+    info: Json<LoginInfo>,
+    user_agent: UserAgent,
+    client_address: ClientAddress,
+    oidc: &State<Option<Oidc>>,
+    db: DbConn,
+) -> Result<Json<LoginResponse>, (Status, &'static str)> {
+    if !password_login_enabled(oidc) {
+        return Err((
+            Status::Forbidden,
+            "Password login is disabled, use single sign-on",
+        ));
+    }
+    let supplied_info = info.into_inner();
+
+    let keys = login_failure_keys(&supplied_info.user, client_address.0);
+    let keys_clone = keys.clone();
+    let failures: Vec<LoginFailure> = db
+        .run(move |conn| {
+            login_failure::table
+                .filter(login_failure::key.eq_any(keys_clone))
+                .load::<LoginFailure>(conn)
+        })
+        .await
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+
+    let now = unix_timestamp();
+    if !failures.iter().all(|failure| login_allowed(failure, now)) {
+        return Err((
+            Status::TooManyRequests,
+            "Too many failed attempts, try again later",
+        ));
+    }
+
+    let username = supplied_info.user.clone();
+    let user: Option<User> = db
+        .run(move |conn| {
+            user::table
+                .filter(user::username.eq(username))
+                .first(conn)
+                .optional()
+        })
+        .await
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+
+    let password_hash = match &user {
+        Some(user) => user.password.as_str(),
+        None => dummy_password_hash(),
+    };
+    let parsed_hash = PasswordHash::new(password_hash)
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+    let password_correct = Argon2::default()
+        .verify_password(supplied_info.password.as_bytes(), &parsed_hash)
+        .is_ok();
+
+    // Don't tell apart unknown users and wrong passwords
+    let user = match user {
+        Some(user) if password_correct => user,
+        _ => {
+            db.run(move |conn| record_login_failure(conn, &keys))
+                .await
+                .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+            return Err((Status::Unauthorized, "Username or password incorrect"));
+        }
+    };
+    if user.disabled != 0 {
+        return Err((Status::Forbidden, "This account has been disabled"));
+    }
+
+    // Failures from the same address are kept, someone else could be guessing from there
+    let user_key = format!("user:{}", user.username);
+    db.run(move |conn| diesel::delete(login_failure::table.find(user_key)).execute(conn))
+        .await
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+
+    // With two-factor authentication the password only gets a pending session,
+    // which is upgraded by `login_two_factor`
+    let two_factor = user.totp_enabled != 0;
+    start_session(&db, cookies, user.id, user_agent.0, two_factor)
+        .await
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+    Ok(Json(LoginResponse {
+        error: false,
+        message: Some(if two_factor {
+            "Two-factor code required"
+        } else {
+            "Logged in"
+        }),
+        two_factor_required: two_factor,
+    }))
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct GenericResponse {
+    error: bool,
+    message: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct LoginResponse {
+    error: bool,
+    message: Option<&'static str>,
+    /// The password was correct, but a code still has to be sent to /login/2fa
+    two_factor_required: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct RegistrationInfo {
+    user: String,
+    password: String,
+    /// Needed while registration is invite-only
+    invite: Option<String>,
+}
+
+#[post("/register", data = "<info>")]
+async fn register(
+    cookies: &CookieJar<'_>,
+    info: Json<RegistrationInfo>,
+    user_agent: UserAgent,
+    oidc: &State<Option<Oidc>>,
+    db: DbConn,
+) -> Result<Json<GenericResponse>, (Status, &'static str)> {
+    if !password_login_enabled(oidc) {
+        return Err((
+            Status::Forbidden,
+            "Password login is disabled, use single sign-on",
+        ));
+    }
+    let supplied_info = info.into_inner();
+    // clone variable to move it into the closure, maybe this can be done in a nicer way?
+    let cloned_name = supplied_info.user.clone();
+
+    let (existing_user, mode) = db
+        .run(move |conn| {
+            let existing_user = user::table
+                .filter(user::username.eq(cloned_name.as_str()))
+                .count()
+                .get_result::<i64>(conn)?;
+            Ok((existing_user, registration_mode(conn)?))
+        })
+        .await
+        .map_err(|_: diesel::result::Error| {
+            (Status::InternalServerError, "An internal error occured")
+        })?;
+
+    let invite = match (mode == registration::INVITE_ONLY, &supplied_info.invite) {
+        (false, _) => None,
+        (true, Some(invite)) => Some(hash_token(invite)),
+        (true, None) => return Err((Status::Forbidden, "Registration is invite-only")),
+    };
+
+    if existing_user > 0 {
+        return Err((Status::BadRequest, "Username not available"));
+    }
+
+    if supplied_info.password.len() < 8 {
+        return Err((Status::BadRequest, "Password must be at least 8 characters"));
+    }
+
+    let salt = SaltString::generate(&mut OsRng);
+    let argon2 = Argon2::default();
+    let password_hash = match argon2.hash_password(supplied_info.password.as_bytes(), &salt) {
+        Ok(hash) => hash,
+        Err(_) => return Err((Status::InternalServerError, "An internal error occured")),
+    };
+    let new_user = NewUser {
+        username: supplied_info.user,
+        password: password_hash.to_string(),
+    };
+
+    let user_id = db
+        .run(move |conn| {
+            conn.transaction(|| {
+                let id = insert_returning_id!(
+                    conn,
+                    diesel::insert_into(user::table).values(&new_user),
+                    user::id
+                )?;
+                // Use up the invitation, unless someone else just did
+                if let Some(invite) = invite {
+                    let used = diesel::update(invitation::table)
+                        .filter(invitation::token.eq(invite))
+                        .filter(invitation::used.is_null())
+                        .filter(invitation::expires.gt(unix_timestamp()))
+                        .set((
+                            invitation::used.eq(Some(unix_timestamp())),
+                            invitation::used_by.eq(Some(id)),
+                        ))
+                        .execute(conn)?;
+                    if used == 0 {
+                        return Err(diesel::result::Error::RollbackTransaction);
+                    }
+                }
+                Ok(id)
+            })
+        })
+        .await;
+
+    match user_id {
+        Ok(id) => {
+            start_session(&db, cookies, id, user_agent.0, false)
+                .await
+                .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+            Ok(Json(GenericResponse {
+                error: false,
+                message: None,
+            }))
+        }
+        Err(diesel::result::Error::RollbackTransaction) => {
+            Err((Status::Forbidden, "Invitation is invalid or has expired"))
+        }
+        Err(_) => Err((Status::InternalServerError, "An internal error occured")),
+    }
+}
+
+/// Publicly visible stuff
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct UserInfo {
+    name: String,
+    display_name: Option<String>,
+}
+
+/// Get user ID
+#[get("/auth")]
+fn auth(user: User, _scope: ReadScope) -> Json<UserInfo> {
+    let info = UserInfo {
+        name: user.username,
+        display_name: user.display_name,
+    };
+    Json(info)
+}
+
+/// Test thing
+#[get("/secure")]
+fn secure(user: User, _scope: ReadScope) -> Json<User> {
+    Json(user)
+}
+
+/// End the current session and remove the auth cookie.
+#[post("/logout")]
+async fn logout(cookies: &CookieJar<'_>, db: DbConn) -> String {
+    if let Some(token) = current_session_token(cookies) {
+        let _ = db
+            .run(move |conn| {
+                diesel::delete(session::table)
+                    .filter(session::token.eq(token))
+                    .execute(conn)
+            })
+            .await;
+    }
+    cookies.remove_private(Cookie::named("auth"));
+    cookies.remove_private(Cookie::named("pending_auth"));
+    "Goodbye".into()
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct SessionInfo {
+    id: i32,
+    created: i64,
+    last_seen: i64,
+    user_agent: Option<String>,
+    expires: i64,
+    current: bool,
+}
+
+#[get("/session/list")]
+async fn list_sessions(
+    user: User,
+    _scope: AdminScope,
+    cookies: &CookieJar<'_>,
+    db: DbConn,
+) -> Result<Json<Vec<SessionInfo>>, Status> {
+    let current_token = current_session_token(cookies);
+    let sessions: Vec<Session> = db
+        .run(move |conn| {
+            Session::belonging_to(&user)
+                .filter(session::expires.gt(unix_timestamp()))
+                .filter(session::pending.eq(0))
+                .order(session::last_seen.desc())
+                .load::<Session>(conn)
+        })
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(
+        sessions
+            .into_iter()
+            .map(|session| SessionInfo {
+                id: session.id,
+                created: session.created,
+                last_seen: session.last_seen,
+                user_agent: session.user_agent,
+                expires: session.expires,
+                current: current_token.as_ref() == Some(&session.token),
+            })
+            .collect(),
+    ))
+}
+
+/// Log out everywhere, including this client
+#[delete("/session/all", rank = 1)]
+async fn revoke_sessions(
+    user: User,
+    _scope: AdminScope,
+    cookies: &CookieJar<'_>,
+    db: DbConn,
+) -> Result<(), Status> {
+    db.run(move |conn| revoke_all_sessions(conn, user.id))
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    cookies.remove_private(Cookie::named("auth"));
+    Ok(())
+}
+
+#[delete("/session/<session_id>", rank = 2)]
+async fn revoke_session(
+    session_id: i32,
+    user: User,
+    _scope: AdminScope,
+    cookies: &CookieJar<'_>,
+    db: DbConn,
+) -> Result<(), Status> {
+    let session: Session = db
+        .run(move |conn| {
+            session::table
+                .filter(session::id.eq(session_id))
+                .filter(session::user.eq(user.id))
+                .first::<Session>(conn)
+        })
+        .await
+        .map_err(|_| Status::NotFound)?;
+
+    db.run(move |conn| diesel::delete(session::table.find(session_id)).execute(conn))
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if current_session_token(cookies) == Some(session.token) {
+        cookies.remove_private(Cookie::named("auth"));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct PasswordChangeInfo {
+    current: String,
+    new: String,
+}
+
+/// Change the password of the logged in user. This logs out all other sessions.
+#[post("/user/password", data = "<info>")]
+async fn change_password(
+    user: User,
+    _scope: AdminScope,
+    info: Json<PasswordChangeInfo>,
+    cookies: &CookieJar<'_>,
+    user_agent: UserAgent,
+    db: DbConn,
+) -> Result<Json<GenericResponse>, (Status, &'static str)> {
+    let info = info.into_inner();
+    verify_password(&user, &info.current)?;
+
+    if info.new.len() < 8 {
+        return Err((Status::BadRequest, "Password must be at least 8 characters"));
+    }
+
+    let salt = SaltString::generate(&mut OsRng);
+    let password_hash = Argon2::default()
+        .hash_password(info.new.as_bytes(), &salt)
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?
+        .to_string();
+
+    db.run(move |conn| {
+        conn.transaction(|| {
+            diesel::update(user::table.find(user.id))
+                .set(user::password.eq(password_hash))
+                .execute(conn)?;
+            revoke_all_sessions(conn, user.id)
+        })
+    })
+    .await
+    .map_err(|_: diesel::result::Error| {
+        (Status::InternalServerError, "An internal error occured")
+    })?;
+
+    start_session(&db, cookies, user.id, user_agent.0, false)
+        .await
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+
+    Ok(Json(GenericResponse {
+        error: false,
+        message: Some("Password changed"),
+    }))
+}
+
+// Account
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct ProfileInfo {
+    username: String,
+    display_name: Option<String>,
+    email: Option<String>,
+    email_verified: bool,
+    two_factor: bool,
+    single_sign_on: bool,
+    instance_admin: bool,
+}
+
+impl From<User> for ProfileInfo {
+    fn from(user: User) -> Self {
+        ProfileInfo {
+            username: user.username,
+            display_name: user.display_name,
+            email: user.email,
+            email_verified: user.email_verified != 0,
+            two_factor: user.totp_enabled != 0,
+            single_sign_on: user.oidc_subject.is_some(),
+            instance_admin: user.instance_admin != 0,
+        }
+    }
+}
+
+#[get("/user/profile")]
+fn get_profile(user: User, _scope: ReadScope) -> Json<ProfileInfo> {
+    Json(user.into())
+}
+
+/// Fields that are left out stay the same, empty strings clear them
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct ProfileUpdateInfo {
+    display_name: Option<String>,
+    email: Option<String>,
+}
+
+const MAX_DISPLAY_NAME_LENGTH: usize = 64;
+const MAX_EMAIL_LENGTH: usize = 254;
+
+/// Changing the email address sends a confirmation email to the new address
+#[patch("/user/profile", data = "<info>")]
+async fn update_profile(
+    user: User,
+    _scope: AdminScope,
+    info: Json<ProfileUpdateInfo>,
+    mailer: &State<Option<Arc<Mailer>>>,
+    db: DbConn,
+) -> Result<Json<ProfileInfo>, (Status, &'static str)> {
+    let info = info.into_inner();
+
+    let display_name = match info.display_name.as_deref().map(str::trim) {
+        None => user.display_name.clone(),
+        Some("") => None,
+        Some(name) if name.chars().count() > MAX_DISPLAY_NAME_LENGTH => {
+            return Err((Status::BadRequest, "Display name is too long"))
+        }
+        Some(name) => Some(name.to_string()),
+    };
+
+    let email = match info.email.as_deref().map(str::trim) {
+        None => user.email.clone(),
+        Some("") => None,
+        Some(email) => {
+            if email.len() > MAX_EMAIL_LENGTH || !email.contains('@') {
+                return Err((Status::BadRequest, "Invalid email address"));
+            }
+            Some(email.to_lowercase())
+        }
+    };
+
+    if email.is_some() && email != user.email {
+        let email_clone = email.clone();
+        let taken = db
+            .run(move |conn| {
+                user::table
+                    .filter(user::email.eq(email_clone))
+                    .filter(user::id.ne(user.id))
+                    .count()
+                    .get_result::<i64>(conn)
+            })
+            .await
+            .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+        if taken > 0 {
+            return Err((Status::BadRequest, "Email address is already in use"));
+        }
+    }
+
+    let email_changed = email != user.email;
+    let mailer = mailer.inner().clone();
+    let mailer_clone = mailer.clone();
+    let updated: User = db
+        .run(move |conn| {
+            conn.transaction(|| {
+                diesel::update(user::table.find(user.id))
+                    .set((user::display_name.eq(display_name), user::email.eq(&email)))
+                    .execute(conn)?;
+                if email_changed {
+                    diesel::update(user::table.find(user.id))
+                        .set(user::email_verified.eq(0))
+                        .execute(conn)?;
+                    if let (Some(mailer), Some(email)) = (&mailer_clone, &email) {
+                        queue_email_verification(conn, mailer, &user, email)?;
+                    }
+                }
+                user::table.find(user.id).first::<User>(conn)
+            })
+        })
+        .await
+        .map_err(|_: diesel::result::Error| {
+            (Status::InternalServerError, "An internal error occured")
+        })?;
+    if let Some(mailer) = mailer {
+        mailer.wake();
+    }
+
+    Ok(Json(updated.into()))
+}
+
+/// Delete a workspace with all of its projects.
+/// Foreign keys are enforced, so rows are deleted before the rows they refer to. Most of them
+/// would cascade anyway, but not all, and this doesn't depend on how each table was declared.
+fn delete_workspace_data(conn: &DbConnection, workspace_id: i32) -> QueryResult<()> {
+    let project_ids: Vec<i32> = project::table
+        .filter(project::workspace.eq(workspace_id))
+        .select(project::id)
+        .load(conn)?;
+    let comment_ids: Vec<i32> = comment::table
+        .filter(comment::project.eq_any(&project_ids))
+        .select(comment::id)
+        .load(conn)?;
+
+    diesel::delete(comment_mention::table)
+        .filter(comment_mention::comment.eq_any(&comment_ids))
+        .execute(conn)?;
+    diesel::delete(comment::table)
+        .filter(comment::project.eq_any(&project_ids))
+        .execute(conn)?;
+    diesel::delete(subtitle_lock::table)
+        .filter(subtitle_lock::project.eq_any(&project_ids))
+        .execute(conn)?;
+    diesel::delete(subtitle_revision::table)
+        .filter(subtitle_revision::project.eq_any(&project_ids))
+        .execute(conn)?;
+    diesel::delete(operation::table)
+        .filter(operation::project.eq_any(&project_ids))
+        .execute(conn)?;
+    diesel::delete(subtitle_status_change::table)
+        .filter(subtitle_status_change::project.eq_any(&project_ids))
+        .execute(conn)?;
+    diesel::delete(snapshot::table)
+        .filter(snapshot::project.eq_any(&project_ids))
+        .execute(conn)?;
+    diesel::delete(subtitle::table)
+        .filter(subtitle::project.eq_any(&project_ids))
+        .execute(conn)?;
+    diesel::delete(project::table)
+        .filter(project::id.eq_any(&project_ids))
+        .execute(conn)?;
+    diesel::delete(workspace_member::table)
+        .filter(workspace_member::workspace.eq(workspace_id))
+        .execute(conn)?;
+    diesel::delete(workspace::table.find(workspace_id)).execute(conn)?;
+    Ok(())
+}
+
+/// Delete a user and everything that only belongs to them. Their edits stay, without an author.
+fn delete_user_data(conn: &DbConnection, user: &User) -> QueryResult<()> {
+    let authored: Vec<i32> = comment::table
+        .filter(comment::author.eq(user.id))
+        .select(comment::id)
+        .load(conn)?;
+    // Replies to deleted comments go too
+    let comment_ids: Vec<i32> = comment::table
+        .filter(
+            comment::id
+                .eq_any(&authored)
+                .or(comment::parent.eq_any(&authored)),
+        )
+        .select(comment::id)
+        .load(conn)?;
+    diesel::delete(comment_mention::table)
+        .filter(
+            comment_mention::comment
+                .eq_any(&comment_ids)
+                .or(comment_mention::user.eq(user.id)),
+        )
+        .execute(conn)?;
+    diesel::delete(comment::table)
+        .filter(comment::id.eq_any(&comment_ids))
+        .execute(conn)?;
+    diesel::update(comment::table)
+        .filter(comment::resolved_by.eq(user.id))
+        .set(comment::resolved_by.eq(None::<i32>))
+        .execute(conn)?;
+
+    let operation_ids: Vec<i32> = operation::table
+        .filter(operation::user.eq(user.id))
+        .select(operation::id)
+        .load(conn)?;
+    diesel::update(subtitle_revision::table)
+        .filter(subtitle_revision::operation.eq_any(&operation_ids))
+        .set(subtitle_revision::operation.eq(None::<i32>))
+        .execute(conn)?;
+    diesel::delete(operation::table)
+        .filter(operation::id.eq_any(&operation_ids))
+        .execute(conn)?;
+    diesel::update(subtitle_revision::table)
+        .filter(subtitle_revision::user.eq(user.id))
+        .set(subtitle_revision::user.eq(None::<i32>))
+        .execute(conn)?;
+    diesel::update(subtitle_status_change::table)
+        .filter(subtitle_status_change::user.eq(user.id))
+        .set(subtitle_status_change::user.eq(None::<i32>))
+        .execute(conn)?;
+
+    diesel::delete(subtitle_lock::table)
+        .filter(subtitle_lock::owner.eq(user.id))
+        .execute(conn)?;
+    diesel::delete(workspace_member::table)
+        .filter(workspace_member::user.eq(user.id))
+        .execute(conn)?;
+    diesel::delete(recovery_code::table)
+        .filter(recovery_code::user.eq(user.id))
+        .execute(conn)?;
+    diesel::delete(email_token::table)
+        .filter(email_token::user.eq(user.id))
+        .execute(conn)?;
+    diesel::update(invitation::table)
+        .filter(invitation::created_by.eq(user.id))
+        .set(invitation::created_by.eq(None::<i32>))
+        .execute(conn)?;
+    diesel::update(invitation::table)
+        .filter(invitation::used_by.eq(user.id))
+        .set(invitation::used_by.eq(None::<i32>))
+        .execute(conn)?;
+    diesel::delete(access_token::table)
+        .filter(access_token::user.eq(user.id))
+        .execute(conn)?;
+    revoke_all_sessions(conn, user.id)?;
+    clear_login_failures(conn, user.id, &user.username)?;
+    diesel::delete(user::table.find(user.id)).execute(conn)?;
+    Ok(())
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+enum OwnedWorkspaceAction {
+    /// Hand workspaces over to another member, preferring admins.
+    /// Workspaces without other members are deleted.
+    Transfer,
+    Delete,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct AccountDeletionInfo {
+    password: Option<String>,
+    /// Accounts that only log in through single sign-on confirm with their username instead
+    confirm: Option<String>,
+    owned_workspaces: OwnedWorkspaceAction,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct AccountDeletionResult {
+    transferred_workspaces: Vec<i32>,
+    deleted_workspaces: Vec<i32>,
+}
+
+#[delete("/user", data = "<info>")]
+async fn delete_account(
+    user: User,
+    _scope: AdminScope,
+    info: Json<AccountDeletionInfo>,
+    cookies: &CookieJar<'_>,
+    db: DbConn,
+) -> Result<Json<AccountDeletionResult>, (Status, &'static str)> {
+    let info = info.into_inner();
+    match (&info.password, &info.confirm) {
+        (Some(password), _) => verify_password(&user, password)?,
+        (None, Some(confirm)) if user.oidc_subject.is_some() && *confirm == user.username => {}
+        _ => return Err((Status::Unauthorized, "Password incorrect")),
+    }
+
+    let result = db
+        .run(move |conn| {
+            conn.transaction(|| {
+                let owned: Vec<Workspace> = workspace::table
+                    .filter(workspace::owner.eq(user.id))
+                    .load(conn)?;
+
+                let mut result = AccountDeletionResult {
+                    transferred_workspaces: Vec::new(),
+                    deleted_workspaces: Vec::new(),
+                };
+                for workspace in owned {
+                    let members: Vec<WorkspaceMember> = WorkspaceMember::belonging_to(&workspace)
+                        .filter(workspace_member::user.ne(user.id))
+                        .load(conn)?;
+                    let successor = members
+                        .iter()
+                        .find(|member| member.role == role::ADMIN)
+                        .or_else(|| members.first());
+
+                    match successor {
+                        Some(successor)
+                            if info.owned_workspaces == OwnedWorkspaceAction::Transfer =>
+                        {
+                            diesel::update(workspace::table.find(workspace.id))
+                                .set(workspace::owner.eq(successor.user))
+                                .execute(conn)?;
+                            diesel::update(workspace_member::table)
+                                .filter(workspace_member::workspace.eq(workspace.id))
+                                .filter(workspace_member::user.eq(successor.user))
+                                .set(workspace_member::role.eq(role::ADMIN))
+                                .execute(conn)?;
+                            result.transferred_workspaces.push(workspace.id);
+                        }
+                        _ => {
+                            delete_workspace_data(conn, workspace.id)?;
+                            result.deleted_workspaces.push(workspace.id);
+                        }
+                    }
+                }
+
+                delete_user_data(conn, &user)?;
+                Ok(result)
+            })
+        })
+        .await
+        .map_err(|_: diesel::result::Error| {
+            (Status::InternalServerError, "An internal error occured")
+        })?;
+
+    cookies.remove_private(Cookie::named("auth"));
+    Ok(Json(result))
+}
+
+// Email
+
+/// The worker checks for emails that are due for a retry this often, in seconds
+const EMAIL_POLL_INTERVAL: u64 = 60;
+/// Emails that failed this many times stay in the outbox and aren't tried again
+const EMAIL_MAX_ATTEMPTS: i32 = 8;
+/// Wait after the first failure, doubled after every failure after that
+const EMAIL_RETRY_DELAY: i64 = 60;
+const PASSWORD_RESET_DURATION: i64 = 60 * 60;
+const EMAIL_VERIFICATION_DURATION: i64 = 2 * 24 * 60 * 60;
+/// Don't send another reset email to the same user within this many seconds
+const PASSWORD_RESET_INTERVAL: i64 = 60;
+
+/// Put an email in the outbox. Call `Mailer::wake` once the transaction is done.
+fn queue_email(
+    conn: &DbConnection,
+    mailer: &Mailer,
+    recipient: &str,
+    template: &email::Template,
+) -> QueryResult<usize> {
+    let (subject, body) = mailer.render(template);
+    let now = unix_timestamp();
+    diesel::insert_into(email_outbox::table)
+        .values(&NewOutboxEmail {
+            recipient: recipient.to_string(),
+            subject,
+            body,
+            created: now,
+            next_attempt: now,
+        })
+        .execute(conn)
+}
+
+/// Create a token to send by email. Only the newest token of each kind works.
+fn create_email_token(
+    conn: &DbConnection,
+    user_id: i32,
+    purpose: &str,
+    email: Option<String>,
+    duration: i64,
+) -> QueryResult<String> {
+    let token = generate_token();
+    let now = unix_timestamp();
+    diesel::delete(email_token::table)
+        .filter(email_token::user.eq(user_id))
+        .filter(email_token::purpose.eq(purpose))
+        .execute(conn)?;
+    diesel::insert_into(email_token::table)
+        .values(&NewEmailToken {
+            user: user_id,
+            purpose: purpose.to_string(),
+            token: hash_token(&token),
+            email,
+            created: now,
+            expires: now + duration,
+        })
+        .execute(conn)?;
+    Ok(token)
+}
+
+/// Send the user a link to confirm their email address
+fn queue_email_verification(
+    conn: &DbConnection,
+    mailer: &Mailer,
+    user: &User,
+    email: &str,
+) -> QueryResult<()> {
+    let token = create_email_token(
+        conn,
+        user.id,
+        email_purpose::VERIFY_EMAIL,
+        Some(email.to_string()),
+        EMAIL_VERIFICATION_DURATION,
+    )?;
+    queue_email(
+        conn,
+        mailer,
+        email,
+        &email::Template::VerifyEmail {
+            username: &user.username,
+            token: &token,
+        },
+    )?;
+    Ok(())
+}
+
+/// Send everything in the outbox that is due, then wait until something new is queued
+async fn run_email_worker(db: DbConn, mailer: Arc<Mailer>, mut shutdown: Shutdown) {
+    loop {
+        let now = unix_timestamp();
+        let due = db
+            .run(move |conn| {
+                email_outbox::table
+                    .filter(email_outbox::attempts.lt(EMAIL_MAX_ATTEMPTS))
+                    .filter(email_outbox::next_attempt.le(now))
+                    .order(email_outbox::id.asc())
+                    .load::<OutboxEmail>(conn)
+            })
+            .await
+            .unwrap_or_else(|e| {
+                println!("could not load email outbox: {}", e);
+                Vec::new()
+            });
+
+        for email in due {
+            let sender = mailer.clone();
+            let (recipient, subject, body) = (email.recipient, email.subject, email.body);
+            let result = task::spawn_blocking(move || sender.send(&recipient, &subject, &body))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()));
+
+            if let Err(error) = &result {
+                println!("could not send email {}: {}", email.id, error);
+            }
+            let _ = db
+                .run(move |conn| match result {
+                    // Sent emails can contain tokens, so they aren't kept around
+                    Ok(()) => diesel::delete(email_outbox::table.find(email.id)).execute(conn),
+                    Err(error) => diesel::update(email_outbox::table.find(email.id))
+                        .set((
+                            email_outbox::attempts.eq(email.attempts + 1),
+                            email_outbox::next_attempt
+                                .eq(unix_timestamp()
+                                    + (EMAIL_RETRY_DELAY << email.attempts.min(16))),
+                            email_outbox::last_error.eq(Some(error)),
+                        ))
+                        .execute(conn),
+                })
+                .await;
+        }
+
+        select! {
+            _ = mailer.queued() => {},
+            _ = sleep(Duration::from_secs(EMAIL_POLL_INTERVAL)) => {},
+            _ = &mut shutdown => break,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct PasswordResetRequestInfo {
+    /// Username or email address
+    user: String,
+}
+
+/// Email a password reset link to the user's confirmed email address.
+/// The response is the same whether or not the account exists.
+#[post("/password/reset/request", data = "<info>")]
+async fn request_password_reset(
+    info: Json<PasswordResetRequestInfo>,
+    oidc: &State<Option<Oidc>>,
+    mailer: &State<Option<Arc<Mailer>>>,
+    db: DbConn,
+) -> Result<Json<GenericResponse>, (Status, &'static str)> {
+    if !password_login_enabled(oidc) {
+        return Err((
+            Status::Forbidden,
+            "Password login is disabled, use single sign-on",
+        ));
+    }
+    let mailer = mailer
+        .as_ref()
+        .cloned()
+        .ok_or((Status::ServiceUnavailable, "Email is not configured"))?;
+
+    let name = info.into_inner().user.trim().to_string();
+    let mailer_clone = mailer.clone();
+    db.run(move |conn| {
+        conn.transaction(|| {
+            let user = user::table
+                .filter(
+                    user::username
+                        .eq(&name)
+                        .or(user::email.eq(name.to_lowercase())),
+                )
+                .filter(user::email_verified.eq(1))
+                .first::<User>(conn)
+                .optional()?;
+            let (user, email) = match user {
+                Some(user) => match user.email.clone() {
+                    Some(email) => (user, email),
+                    None => return Ok(()),
+                },
+                None => return Ok(()),
+            };
+
+            let recent = email_token::table
+                .filter(email_token::user.eq(user.id))
+                .filter(email_token::purpose.eq(email_purpose::PASSWORD_RESET))
+                .filter(email_token::created.gt(unix_timestamp() - PASSWORD_RESET_INTERVAL))
+                .count()
+                .get_result::<i64>(conn)?;
+            if recent > 0 {
+                return Ok(());
+            }
+
+            let token = create_email_token(
+                conn,
+                user.id,
+                email_purpose::PASSWORD_RESET,
+                None,
+                PASSWORD_RESET_DURATION,
+            )?;
+            queue_email(
+                conn,
+                &mailer_clone,
+                &email,
+                &email::Template::PasswordReset {
+                    username: &user.username,
+                    token: &token,
+                },
+            )?;
+            Ok(())
+        })
+    })
+    .await
+    .map_err(|_: diesel::result::Error| {
+        (Status::InternalServerError, "An internal error occured")
+    })?;
+    mailer.wake();
+
+    Ok(Json(GenericResponse {
+        error: false,
+        message: Some("If the account has a confirmed email address, a reset link is on its way"),
+    }))
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct PasswordResetInfo {
+    token: String,
+    password: String,
+}
+
+/// Set a new password with the token from a reset email. This logs out all sessions.
+#[post("/password/reset", data = "<info>")]
+async fn reset_password(
+    info: Json<PasswordResetInfo>,
+    oidc: &State<Option<Oidc>>,
+    db: DbConn,
+) -> Result<Json<GenericResponse>, (Status, &'static str)> {
+    if !password_login_enabled(oidc) {
+        return Err((
+            Status::Forbidden,
+            "Password login is disabled, use single sign-on",
+        ));
+    }
+    let info = info.into_inner();
+    if info.password.len() < 8 {
+        return Err((Status::BadRequest, "Password must be at least 8 characters"));
+    }
+
+    let salt = SaltString::generate(&mut OsRng);
+    let password_hash = Argon2::default()
+        .hash_password(info.password.as_bytes(), &salt)
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?
+        .to_string();
+
+    let token = hash_token(&info.token);
+    let reset = db
+        .run(move |conn| {
+            conn.transaction(|| {
+                let user = email_token::table
+                    .inner_join(user::table)
+                    .filter(email_token::token.eq(token))
+                    .filter(email_token::purpose.eq(email_purpose::PASSWORD_RESET))
+                    .filter(email_token::expires.gt(unix_timestamp()))
+                    .select(user::all_columns)
+                    .first::<User>(conn)
+                    .optional()?;
+                let user = match user {
+                    Some(user) => user,
+                    None => return Ok(false),
+                };
+
+                diesel::delete(email_token::table)
+                    .filter(email_token::user.eq(user.id))
+                    .filter(email_token::purpose.eq(email_purpose::PASSWORD_RESET))
+                    .execute(conn)?;
+                reset_user_password(conn, &user, &password_hash)?;
+                Ok(true)
+            })
+        })
+        .await
+        .map_err(|_: diesel::result::Error| {
+            (Status::InternalServerError, "An internal error occured")
+        })?;
+
+    if !reset {
+        return Err((Status::BadRequest, "Reset link is invalid or has expired"));
+    }
+    Ok(Json(GenericResponse {
+        error: false,
+        message: Some("Password changed"),
+    }))
+}
+
+/// Send another confirmation email for the user's email address
+#[post("/user/email/verify/send")]
+async fn send_email_verification(
+    user: User,
+    _scope: AdminScope,
+    mailer: &State<Option<Arc<Mailer>>>,
+    db: DbConn,
+) -> Result<(), (Status, &'static str)> {
+    let mailer = mailer
+        .as_ref()
+        .cloned()
+        .ok_or((Status::ServiceUnavailable, "Email is not configured"))?;
+    let email = user
+        .email
+        .clone()
+        .ok_or((Status::BadRequest, "No email address set"))?;
+    if user.email_verified != 0 {
+        return Err((Status::BadRequest, "Email address is already confirmed"));
+    }
+
+    let mailer_clone = mailer.clone();
+    db.run(move |conn| queue_email_verification(conn, &mailer_clone, &user, &email))
+        .await
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+    mailer.wake();
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct EmailTokenInfo {
+    token: String,
+}
+
+/// Confirm an email address with the token from the confirmation email.
+/// Doesn't need a login, since the link may be opened in another browser.
+#[post("/user/email/verify", data = "<info>")]
+async fn verify_email(
+    info: Json<EmailTokenInfo>,
+    db: DbConn,
+) -> Result<Json<GenericResponse>, (Status, &'static str)> {
+    let token = hash_token(&info.token);
+    let verified = db
+        .run(move |conn| {
+            conn.transaction(|| {
+                let found = email_token::table
+                    .inner_join(user::table)
+                    .filter(email_token::token.eq(token))
+                    .filter(email_token::purpose.eq(email_purpose::VERIFY_EMAIL))
+                    .filter(email_token::expires.gt(unix_timestamp()))
+                    .first::<(EmailToken, User)>(conn)
+                    .optional()?;
+                let (email_token, user) = match found {
+                    // The address may have changed since the email was sent
+                    Some((email_token, user)) if email_token.email == user.email => {
+                        (email_token, user)
+                    }
+                    _ => return Ok(false),
+                };
+
+                diesel::delete(email_token::table.find(email_token.id)).execute(conn)?;
+                diesel::update(user::table.find(user.id))
+                    .set(user::email_verified.eq(1))
+                    .execute(conn)?;
+                Ok(true)
+            })
+        })
+        .await
+        .map_err(|_: diesel::result::Error| {
+            (Status::InternalServerError, "An internal error occured")
+        })?;
+
+    if !verified {
+        return Err((
+            Status::BadRequest,
+            "Confirmation link is invalid or has expired",
+        ));
+    }
+    Ok(Json(GenericResponse {
+        error: false,
+        message: Some("Email address confirmed"),
+    }))
+}
+
+// Instance administration
+
+/// How long an invitation can be used, in seconds
+const INVITATION_DURATION: i64 = 7 * 24 * 60 * 60;
+
+/// Guard for the /admin routes, which only the operators of the server can use.
+/// Access tokens of instance admins need the admin scope.
+struct InstanceAdmin(User);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for InstanceAdmin {
+    type Error = &'static str;
+
+    async fn from_request(
+        request: &'r Request<'_>,
+    ) -> request::Outcome<InstanceAdmin, Self::Error> {
+        let user = match authenticated_user(request).await {
+            Ok(user) => user.clone(),
+            Err(failure) => return Outcome::Failure(*failure),
+        };
+        match request.guard::<AdminScope>().await {
+            Outcome::Success(_) => {}
+            Outcome::Failure(failure) => return Outcome::Failure(failure),
+            Outcome::Forward(forward) => return Outcome::Forward(forward),
+        }
+
+        if user.instance_admin == 0 {
+            return Outcome::Failure((Status::Forbidden, "Only instance admins can do this"));
+        }
+        Outcome::Success(InstanceAdmin(user))
+    }
+}
+
+/// Current value of the registration setting, open unless changed
+fn registration_mode(conn: &DbConnection) -> QueryResult<String> {
+    Ok(setting::table
+        .find(registration::KEY)
+        .select(setting::value)
+        .first::<String>(conn)
+        .optional()?
+        .unwrap_or_else(|| registration::OPEN.to_string()))
+}
+
+#[derive(QueryableByName)]
+struct KeyCount {
+    #[sql_type = "diesel::sql_types::Integer"]
+    key: i32,
+    #[sql_type = "diesel::sql_types::BigInt"]
+    count: i64,
+}
+
+/// Run a query selecting `key` and `count` columns, usually with a GROUP BY,
+/// which the query builder can't do together with aggregates
+fn load_counts(conn: &DbConnection, query: &'static str) -> QueryResult<BTreeMap<i32, i64>> {
+    Ok(diesel::sql_query(query)
+        .load::<KeyCount>(conn)?
+        .into_iter()
+        .map(|row| (row.key, row.count))
+        .collect())
+}
+
+#[derive(Serialize)]
 #[serde(crate = "rocket::serde")]
-struct SnapshotPatchInfo {
+struct AdminUserInfo {
+    id: i32,
+    username: String,
+    display_name: Option<String>,
+    email: Option<String>,
+    email_verified: bool,
+    two_factor: bool,
+    single_sign_on: bool,
+    instance_admin: bool,
+    disabled: bool,
+    owned_workspaces: i64,
+    memberships: i64,
+    sessions: i64,
+}
+
+/// All users with how much they own and use, for the admin API and the command line
+fn load_admin_users(conn: &DbConnection) -> QueryResult<Vec<AdminUserInfo>> {
+    let users = user::table.order(user::id.asc()).load::<User>(conn)?;
+    let owned = load_counts(
+        conn,
+        r#"SELECT "owner" AS "key", COUNT(*) AS "count" FROM "workspace" GROUP BY "owner""#,
+    )?;
+    let memberships = load_counts(
+        conn,
+        r#"SELECT "user" AS "key", COUNT(*) AS "count" FROM "workspace_member" GROUP BY "user""#,
+    )?;
+    let sessions = load_counts(
+        conn,
+        r#"SELECT "user" AS "key", COUNT(*) AS "count" FROM "session" WHERE "pending" = 0 GROUP BY "user""#,
+    )?;
+
+    Ok(users
+        .into_iter()
+        .map(|user| AdminUserInfo {
+            owned_workspaces: owned.get(&user.id).copied().unwrap_or(0),
+            memberships: memberships.get(&user.id).copied().unwrap_or(0),
+            sessions: sessions.get(&user.id).copied().unwrap_or(0),
+            id: user.id,
+            username: user.username,
+            display_name: user.display_name,
+            email: user.email,
+            email_verified: user.email_verified != 0,
+            two_factor: user.totp_enabled != 0,
+            single_sign_on: user.oidc_subject.is_some(),
+            instance_admin: user.instance_admin != 0,
+            disabled: user.disabled != 0,
+        })
+        .collect())
+}
+
+#[get("/admin/user/list")]
+async fn admin_list_users(
+    _admin: InstanceAdmin,
+    db: DbConn,
+) -> Result<Json<Vec<AdminUserInfo>>, (Status, &'static str)> {
+    db.run(|conn| load_admin_users(conn))
+        .await
+        .map(Json)
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))
+}
+
+/// Fields that are left out stay the same
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct AdminUserUpdateInfo {
+    disabled: Option<bool>,
+    instance_admin: Option<bool>,
+}
+
+/// Disable or enable an account, or make it an instance admin.
+/// Disabling logs the user out everywhere.
+#[patch("/admin/user/<user_id>", data = "<info>")]
+async fn admin_update_user(
+    user_id: i32,
+    info: Json<AdminUserUpdateInfo>,
+    admin: InstanceAdmin,
+    db: DbConn,
+) -> Result<(), (Status, &'static str)> {
+    let info = info.into_inner();
+    // Don't let the last admin lock everyone out
+    if user_id == admin.0.id && (info.disabled == Some(true) || info.instance_admin == Some(false))
+    {
+        return Err((Status::BadRequest, "You can't disable or demote yourself"));
+    }
+
+    let found = db
+        .run(move |conn| {
+            conn.transaction(|| {
+                if user::table.find(user_id).count().get_result::<i64>(conn)? == 0 {
+                    return Ok(false);
+                }
+                if let Some(disabled) = info.disabled {
+                    diesel::update(user::table.find(user_id))
+                        .set(user::disabled.eq(disabled as i32))
+                        .execute(conn)?;
+                    if disabled {
+                        revoke_all_sessions(conn, user_id)?;
+                    }
+                }
+                if let Some(instance_admin) = info.instance_admin {
+                    diesel::update(user::table.find(user_id))
+                        .set(user::instance_admin.eq(instance_admin as i32))
+                        .execute(conn)?;
+                }
+                Ok(true)
+            })
+        })
+        .await
+        .map_err(|_: diesel::result::Error| {
+            (Status::InternalServerError, "An internal error occured")
+        })?;
+
+    if !found {
+        return Err((Status::NotFound, "User not found"));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct AdminPasswordResetInfo {
+    /// A random password is generated when this is left out
+    password: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct AdminPasswordResetResult {
+    password: String,
+}
+
+/// Set a new password for a user and log them out everywhere
+#[post("/admin/user/<user_id>/password", data = "<info>")]
+async fn admin_reset_password(
+    user_id: i32,
+    info: Json<AdminPasswordResetInfo>,
+    _admin: InstanceAdmin,
+    db: DbConn,
+) -> Result<Json<AdminPasswordResetResult>, (Status, &'static str)> {
+    let password = match info.into_inner().password {
+        Some(password) if password.len() < 8 => {
+            return Err((Status::BadRequest, "Password must be at least 8 characters"))
+        }
+        Some(password) => password,
+        None => generate_token()[..16].to_string(),
+    };
+
+    let password_hash = hash_password(&password)
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+
+    let found = db
+        .run(move |conn| {
+            conn.transaction(|| {
+                let user = match user::table.find(user_id).first::<User>(conn).optional()? {
+                    Some(user) => user,
+                    None => return Ok(false),
+                };
+                reset_user_password(conn, &user, &password_hash)?;
+                Ok(true)
+            })
+        })
+        .await
+        .map_err(|_: diesel::result::Error| {
+            (Status::InternalServerError, "An internal error occured")
+        })?;
+
+    if !found {
+        return Err((Status::NotFound, "User not found"));
+    }
+    Ok(Json(AdminPasswordResetResult { password }))
+}
+
+/// Log a user out everywhere. Their access tokens keep working.
+#[delete("/admin/user/<user_id>/sessions")]
+async fn admin_revoke_sessions(
+    user_id: i32,
+    _admin: InstanceAdmin,
+    db: DbConn,
+) -> Result<(), (Status, &'static str)> {
+    db.run(move |conn| revoke_all_sessions(conn, user_id))
+        .await
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+    Ok(())
+}
+
+/// Lift a login lockout from any user. Lockouts of addresses expire on their own.
+#[post("/admin/user/<user_id>/unlock")]
+async fn admin_unlock_user(
+    user_id: i32,
+    _admin: InstanceAdmin,
+    db: DbConn,
+) -> Result<(), (Status, &'static str)> {
+    let found = db
+        .run(move |conn| {
+            let username = match user::table
+                .find(user_id)
+                .select(user::username)
+                .first::<String>(conn)
+                .optional()?
+            {
+                Some(username) => username,
+                None => return Ok(false),
+            };
+            clear_login_failures(conn, user_id, &username)?;
+            Ok(true)
+        })
+        .await
+        .map_err(|_: diesel::result::Error| {
+            (Status::InternalServerError, "An internal error occured")
+        })?;
+
+    if !found {
+        return Err((Status::NotFound, "User not found"));
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct AdminWorkspaceInfo {
+    id: i32,
+    name: String,
+    owner: String,
+    shared: bool,
+    require_2fa: bool,
+    members: i64,
+    projects: i64,
+}
+
+fn load_admin_workspaces(conn: &DbConnection) -> QueryResult<Vec<AdminWorkspaceInfo>> {
+    let workspaces = workspace::table
+        .inner_join(user::table)
+        .select((workspace::all_columns, user::username))
+        .order(workspace::id.asc())
+        .load::<(Workspace, String)>(conn)?;
+    let members = load_counts(
+        conn,
+        r#"SELECT "workspace" AS "key", COUNT(*) AS "count" FROM "workspace_member" GROUP BY "workspace""#,
+    )?;
+    let projects = load_counts(
+        conn,
+        r#"SELECT "workspace" AS "key", COUNT(*) AS "count" FROM "project" GROUP BY "workspace""#,
+    )?;
+
+    Ok(workspaces
+        .into_iter()
+        .map(|(workspace, owner)| AdminWorkspaceInfo {
+            members: members.get(&workspace.id).copied().unwrap_or(0),
+            projects: projects.get(&workspace.id).copied().unwrap_or(0),
+            id: workspace.id,
+            name: workspace.name,
+            owner,
+            shared: workspace.shared != 0,
+            require_2fa: workspace.require_2fa != 0,
+        })
+        .collect())
+}
+
+#[get("/admin/workspace/list")]
+async fn admin_list_workspaces(
+    _admin: InstanceAdmin,
+    db: DbConn,
+) -> Result<Json<Vec<AdminWorkspaceInfo>>, (Status, &'static str)> {
+    db.run(|conn| load_admin_workspaces(conn))
+        .await
+        .map(Json)
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct AdminProjectInfo {
+    id: i32,
     name: String,
+    workspace: i32,
+    video: Option<i32>,
+    subtitles: i64,
+    revisions: i64,
+    comments: i64,
+    snapshots: i64,
+    /// Size of the subtitles stored in snapshots
+    snapshot_bytes: i64,
+    waveform_bytes: i64,
+}
+
+fn load_admin_projects(conn: &DbConnection) -> QueryResult<Vec<AdminProjectInfo>> {
+    let projects = project::table
+        .order(project::id.asc())
+        .load::<Project>(conn)?;
+    let subtitles = load_counts(
+        conn,
+        r#"SELECT "project" AS "key", COUNT(*) AS "count" FROM "subtitle" GROUP BY "project""#,
+    )?;
+    let revisions = load_counts(
+        conn,
+        r#"SELECT "project" AS "key", COUNT(*) AS "count" FROM "subtitle_revision" GROUP BY "project""#,
+    )?;
+    let comments = load_counts(
+        conn,
+        r#"SELECT "project" AS "key", COUNT(*) AS "count" FROM "comment" GROUP BY "project""#,
+    )?;
+    let snapshots = load_counts(
+        conn,
+        r#"SELECT "project" AS "key", COUNT(*) AS "count" FROM "snapshot" GROUP BY "project""#,
+    )?;
+    let snapshot_bytes = load_counts(
+        conn,
+        r#"SELECT "project" AS "key", SUM(LENGTH("subtitles")) AS "count" FROM "snapshot" GROUP BY "project""#,
+    )?;
+    let waveform_bytes = load_counts(
+        conn,
+        r#"SELECT "id" AS "key", CAST(COALESCE(LENGTH("waveform"), 0) AS BIGINT) AS "count" FROM "video""#,
+    )?;
+
+    Ok(projects
+        .into_iter()
+        .map(|project| AdminProjectInfo {
+            subtitles: subtitles.get(&project.id).copied().unwrap_or(0),
+            revisions: revisions.get(&project.id).copied().unwrap_or(0),
+            comments: comments.get(&project.id).copied().unwrap_or(0),
+            snapshots: snapshots.get(&project.id).copied().unwrap_or(0),
+            snapshot_bytes: snapshot_bytes.get(&project.id).copied().unwrap_or(0),
+            waveform_bytes: project
+                .video
+                .and_then(|video| waveform_bytes.get(&video).copied())
+                .unwrap_or(0),
+            id: project.id,
+            name: project.name,
+            workspace: project.workspace,
+            video: project.video,
+        })
+        .collect())
+}
+
+#[get("/admin/project/list")]
+async fn admin_list_projects(
+    _admin: InstanceAdmin,
+    db: DbConn,
+) -> Result<Json<Vec<AdminProjectInfo>>, (Status, &'static str)> {
+    db.run(|conn| load_admin_projects(conn))
+        .await
+        .map(Json)
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct OrphanedVideoCleanup {
+    deleted_videos: usize,
+}
+
+/// Delete videos that no project uses anymore, along with their waveforms
+#[delete("/admin/video/orphaned")]
+async fn admin_delete_orphaned_videos(
+    _admin: InstanceAdmin,
+    db: DbConn,
+) -> Result<Json<OrphanedVideoCleanup>, (Status, &'static str)> {
+    let deleted_videos = db
+        .run(|conn| {
+            conn.transaction(|| {
+                let used: Vec<i32> = project::table
+                    .select(project::video)
+                    .load::<Option<i32>>(conn)?
+                    .into_iter()
+                    .flatten()
+                    .collect();
+                diesel::delete(video::table)
+                    .filter(video::id.ne_all(used))
+                    .execute(conn)
+            })
+        })
+        .await
+        .map_err(|_: diesel::result::Error| {
+            (Status::InternalServerError, "An internal error occured")
+        })?;
+
+    Ok(Json(OrphanedVideoCleanup { deleted_videos }))
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct InstanceSettings {
+    /// "open" or "invite_only"
+    registration: String,
+}
+
+#[get("/admin/settings")]
+async fn admin_get_settings(
+    _admin: InstanceAdmin,
+    db: DbConn,
+) -> Result<Json<InstanceSettings>, (Status, &'static str)> {
+    let registration = db
+        .run(|conn| registration_mode(conn))
+        .await
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+    Ok(Json(InstanceSettings { registration }))
+}
+
+#[patch("/admin/settings", data = "<info>")]
+async fn admin_update_settings(
+    info: Json<InstanceSettings>,
+    _admin: InstanceAdmin,
+    db: DbConn,
+) -> Result<Json<InstanceSettings>, (Status, &'static str)> {
+    let info = info.into_inner();
+    if info.registration != registration::OPEN && info.registration != registration::INVITE_ONLY {
+        return Err((
+            Status::BadRequest,
+            "Registration must be \"open\" or \"invite_only\"",
+        ));
+    }
+
+    let registration = info.registration.clone();
+    db.run(move |conn| {
+        upsert!(
+            conn,
+            setting::table,
+            setting::key,
+            &Setting {
+                key: registration::KEY.to_string(),
+                value: registration,
+            }
+        )
+    })
+    .await
+    .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+
+    Ok(Json(info))
+}
+
+#[get("/admin/invitation/list")]
+async fn admin_list_invitations(
+    _admin: InstanceAdmin,
+    db: DbConn,
+) -> Result<Json<Vec<Invitation>>, (Status, &'static str)> {
+    let invitations = db
+        .run(|conn| {
+            invitation::table
+                .order(invitation::id.desc())
+                .load::<Invitation>(conn)
+        })
+        .await
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+    Ok(Json(invitations))
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct InvitationCreationInfo {
+    /// If set and email is configured, the invitation is emailed there
+    email: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct InvitationCreated {
+    id: i32,
+    /// Shown only once, the server only keeps its hash
+    token: String,
+    emailed: bool,
+}
+
+/// Create a single-use invitation for registering while registration is invite-only
+#[post("/admin/invitation/create", data = "<info>")]
+async fn admin_create_invitation(
+    info: Json<InvitationCreationInfo>,
+    admin: InstanceAdmin,
+    mailer: &State<Option<Arc<Mailer>>>,
+    db: DbConn,
+) -> Result<Json<InvitationCreated>, (Status, &'static str)> {
+    let email = match info.into_inner().email.as_deref().map(str::trim) {
+        None | Some("") => None,
+        Some(email) if email.len() > MAX_EMAIL_LENGTH || !email.contains('@') => {
+            return Err((Status::BadRequest, "Invalid email address"))
+        }
+        Some(email) => Some(email.to_lowercase()),
+    };
+
+    let token = generate_token();
+    let now = unix_timestamp();
+    let new_invitation = NewInvitation {
+        token: hash_token(&token),
+        email: email.clone(),
+        created_by: Some(admin.0.id),
+        created: now,
+        expires: now + INVITATION_DURATION,
+    };
+    let mailer = match &email {
+        Some(_) => mailer.inner().clone(),
+        None => None,
+    };
+    let mailer_clone = mailer.clone();
+    let token_clone = token.clone();
+    let id = db
+        .run(move |conn| {
+            conn.transaction(|| {
+                let id = insert_returning_id!(
+                    conn,
+                    diesel::insert_into(invitation::table).values(&new_invitation),
+                    invitation::id
+                )?;
+                if let (Some(mailer), Some(email)) = (&mailer_clone, &email) {
+                    queue_email(
+                        conn,
+                        mailer,
+                        email,
+                        &email::Template::Invitation {
+                            token: &token_clone,
+                        },
+                    )?;
+                }
+                Ok(id)
+            })
+        })
+        .await
+        .map_err(|_: diesel::result::Error| {
+            (Status::InternalServerError, "An internal error occured")
+        })?;
+    if let Some(mailer) = &mailer {
+        mailer.wake();
+    }
+
+    Ok(Json(InvitationCreated {
+        id,
+        token,
+        emailed: mailer.is_some(),
+    }))
+}
+
+#[delete("/admin/invitation/<invitation_id>")]
+async fn admin_delete_invitation(
+    invitation_id: i32,
+    _admin: InstanceAdmin,
+    db: DbConn,
+) -> Result<(), (Status, &'static str)> {
+    let deleted = db
+        .run(move |conn| diesel::delete(invitation::table.find(invitation_id)).execute(conn))
+        .await
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+    if deleted == 0 {
+        return Err((Status::NotFound, "Invitation not found"));
+    }
+    Ok(())
+}
+
+// Two-factor authentication
+
+/// Name shown in authenticator apps
+const TOTP_ISSUER: &str = "Uptitle";
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Recovery codes are shown as `xxxxx-xxxxx`, but accepted without the dash and in any case
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hash_token(&normalized)
+}
+
+/// Replace the user's recovery codes with new ones, and return them in plain text
+fn generate_recovery_codes(conn: &DbConnection, user_id: i32) -> QueryResult<Vec<String>> {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; 5];
+            OsRng.fill_bytes(&mut bytes);
+            let code = hex::encode(bytes);
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect();
+    let new_codes: Vec<NewRecoveryCode> = codes
+        .iter()
+        .map(|code| NewRecoveryCode {
+            user: user_id,
+            code: hash_recovery_code(code),
+        })
+        .collect();
+
+    diesel::delete(recovery_code::table)
+        .filter(recovery_code::user.eq(user_id))
+        .execute(conn)?;
+    diesel::insert_into(recovery_code::table)
+        .values(&new_codes)
+        .execute(conn)?;
+    Ok(codes)
+}
+
+fn verify_password(user: &User, password: &str) -> Result<(), (Status, &'static str)> {
+    let parsed_hash = PasswordHash::new(&user.password)
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+    Argon2::default()
+        .verify_password(password.as_bytes(), &parsed_hash)
+        .map_err(|_| (Status::Unauthorized, "Password incorrect"))
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct TwoFactorEnrollment {
+    secret: String,
+    uri: String,
+}
+
+/// Start enrolling in two-factor authentication. It isn't enabled until a code from the
+/// authenticator app is sent to /user/2fa/confirm.
+#[post("/user/2fa/enroll")]
+async fn enroll_two_factor(
+    user: User,
+    _scope: AdminScope,
+    db: DbConn,
+) -> Result<Json<TwoFactorEnrollment>, (Status, &'static str)> {
+    if user.totp_enabled != 0 {
+        return Err((
+            Status::BadRequest,
+            "Two-factor authentication is already enabled",
+        ));
+    }
+
+    let secret = totp::generate_secret();
+    let secret_clone = secret.clone();
+    db.run(move |conn| {
+        diesel::update(user::table.find(user.id))
+            .set((
+                user::totp_secret.eq(Some(secret_clone)),
+                user::totp_last_step.eq(None::<i64>),
+            ))
+            .execute(conn)
+    })
+    .await
+    .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+
+    Ok(Json(TwoFactorEnrollment {
+        uri: totp::otpauth_uri(TOTP_ISSUER, &user.username, &secret),
+        secret,
+    }))
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct TwoFactorCodeInfo {
+    code: String,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct RecoveryCodes {
+    recovery_codes: Vec<String>,
+}
+
+/// Finish enrolling by checking the first code. Returns the recovery codes, which are only shown once.
+#[post("/user/2fa/confirm", data = "<info>")]
+async fn confirm_two_factor(
+    user: User,
+    _scope: AdminScope,
+    info: Json<TwoFactorCodeInfo>,
+    db: DbConn,
+) -> Result<Json<RecoveryCodes>, (Status, &'static str)> {
+    if user.totp_enabled != 0 {
+        return Err((
+            Status::BadRequest,
+            "Two-factor authentication is already enabled",
+        ));
+    }
+    let secret = user
+        .totp_secret
+        .as_ref()
+        .ok_or((Status::BadRequest, "Start enrolling first"))?;
+    let step = totp::verify(secret, &info.code, unix_timestamp(), None)
+        .ok_or((Status::BadRequest, "Code incorrect"))?;
+
+    let recovery_codes = db
+        .run(move |conn| {
+            conn.transaction(|| {
+                diesel::update(user::table.find(user.id))
+                    .set((
+                        user::totp_enabled.eq(1),
+                        user::totp_last_step.eq(Some(step)),
+                    ))
+                    .execute(conn)?;
+                generate_recovery_codes(conn, user.id)
+            })
+        })
+        .await
+        .map_err(|_: diesel::result::Error| {
+            (Status::InternalServerError, "An internal error occured")
+        })?;
+
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct PasswordConfirmationInfo {
+    password: String,
+}
+
+/// Replace the recovery codes, for when they've run out or were lost
+#[post("/user/2fa/recovery_codes", data = "<info>")]
+async fn regenerate_recovery_codes(
+    user: User,
+    _scope: AdminScope,
+    info: Json<PasswordConfirmationInfo>,
+    db: DbConn,
+) -> Result<Json<RecoveryCodes>, (Status, &'static str)> {
+    verify_password(&user, &info.password)?;
+    if user.totp_enabled == 0 {
+        return Err((
+            Status::BadRequest,
+            "Two-factor authentication is not enabled",
+        ));
+    }
+
+    let recovery_codes = db
+        .run(move |conn| generate_recovery_codes(conn, user.id))
+        .await
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+#[post("/user/2fa/disable", data = "<info>")]
+async fn disable_two_factor(
+    user: User,
+    _scope: AdminScope,
+    info: Json<PasswordConfirmationInfo>,
+    db: DbConn,
+) -> Result<(), (Status, &'static str)> {
+    verify_password(&user, &info.password)?;
+
+    db.run(move |conn| {
+        conn.transaction(|| {
+            diesel::update(user::table.find(user.id))
+                .set((
+                    user::totp_secret.eq(None::<String>),
+                    user::totp_enabled.eq(0),
+                    user::totp_last_step.eq(None::<i64>),
+                ))
+                .execute(conn)?;
+            diesel::delete(recovery_code::table)
+                .filter(recovery_code::user.eq(user.id))
+                .execute(conn)
+        })
+    })
+    .await
+    .map_err(|_: diesel::result::Error| {
+        (Status::InternalServerError, "An internal error occured")
+    })?;
+
+    Ok(())
 }
 
-#[patch("/project/<project_id>/snapshot/<timestamp>", data = "<info>")]
-async fn edit_snapshot(
-    project_id: i32,
-    timestamp: i64,
-    info: Json<SnapshotPatchInfo>,
-    user: User,
+/// Second step of logging in: check the TOTP code or a recovery code for the pending session
+/// started by `login`, and replace it with a real one.
+#[post("/login/2fa", data = "<info>")]
+async fn login_two_factor(
+    cookies: &CookieJar<'_>,
+    info: Json<TwoFactorCodeInfo>,
+    user_agent: UserAgent,
     db: DbConn,
-) -> Result<(), Status> {
-    let project: Project = db
+) -> Result<Json<LoginResponse>, (Status, &'static str)> {
+    let token = cookies
+        .get_private("pending_auth")
+        .map(|cookie| hash_token(cookie.value()))
+        .ok_or((Status::Unauthorized, "Log in with your password first"))?;
+
+    let now = unix_timestamp();
+    let (pending_session, user): (Session, User) = db
         .run(move |conn| {
-            project::table
-                .inner_join(workspace::table.left_join(workspace_member::table))
-                .filter(workspace_member::user.eq(user.id))
-                .filter(project::id.eq(project_id))
-                .select(project::all_columns)
-                .first::<Project>(conn)
+            session::table
+                .inner_join(user::table)
+                .filter(session::token.eq(token))
+                .filter(session::pending.eq(1))
+                .filter(session::expires.gt(now))
+                .first(conn)
         })
         .await
-        .map_err(|_| Status::NotFound)?;
+        .map_err(|_| {
+            (
+                Status::Unauthorized,
+                "Login expired, log in with your password again",
+            )
+        })?;
+
+    // Codes are short, so guessing them is throttled just like passwords
+    let keys = vec![format!("2fa:{}", user.id)];
+    let keys_clone = keys.clone();
+    let failures: Vec<LoginFailure> = db
+        .run(move |conn| {
+            login_failure::table
+                .filter(login_failure::key.eq_any(keys_clone))
+                .load::<LoginFailure>(conn)
+        })
+        .await
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+    if !failures.iter().all(|failure| login_allowed(failure, now)) {
+        return Err((
+            Status::TooManyRequests,
+            "Too many failed attempts, try again later",
+        ));
+    }
 
-    let _affected_rows: usize = db
+    let code = info.into_inner().code;
+    let step = user
+        .totp_secret
+        .as_ref()
+        .and_then(|secret| totp::verify(secret, &code, now, user.totp_last_step));
+    let user_id = user.id;
+    let accepted = db
         .run(move |conn| {
-            diesel::update(schema::snapshot::table)
-                .filter(schema::snapshot::project.eq(project.id))
-                .filter(schema::snapshot::timestamp.eq(timestamp))
-                .set(schema::snapshot::name.eq(info.name.clone()))
-                .execute(conn)
+            if let Some(step) = step {
+                // Only succeeds once per step, even with concurrent requests
+                let updated = diesel::update(user::table.find(user_id))
+                    .filter(
+                        user::totp_last_step
+                            .is_null()
+                            .or(user::totp_last_step.lt(step)),
+                    )
+                    .set(user::totp_last_step.eq(Some(step)))
+                    .execute(conn)?;
+                return Ok(updated > 0);
+            }
+
+            // Recovery codes can only be used once
+            let used = diesel::delete(recovery_code::table)
+                .filter(recovery_code::user.eq(user_id))
+                .filter(recovery_code::code.eq(hash_recovery_code(&code)))
+                .execute(conn)?;
+            Ok(used > 0)
         })
         .await
-        .map_err(|_| Status::InternalServerError)?;
-    Ok(())
+        .map_err(|_: diesel::result::Error| {
+            (Status::InternalServerError, "An internal error occured")
+        })?;
+
+    if !accepted {
+        db.run(move |conn| record_login_failure(conn, &keys))
+            .await
+            .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+        return Err((Status::Unauthorized, "Code incorrect"));
+    }
+
+    db.run(move |conn| {
+        diesel::delete(login_failure::table)
+            .filter(login_failure::key.eq_any(keys))
+            .execute(conn)?;
+        diesel::delete(session::table.find(pending_session.id)).execute(conn)
+    })
+    .await
+    .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+
+    cookies.remove_private(Cookie::named("pending_auth"));
+    start_session(&db, cookies, user.id, user_agent.0, false)
+        .await
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+    Ok(Json(LoginResponse {
+        error: false,
+        message: Some("Logged in"),
+        two_factor_required: false,
+    }))
 }
 
-// Authentication
+// Single sign-on
 
-// Ensure user is logged in and get their info from the DB
-#[rocket::async_trait]
-impl<'r> FromRequest<'r> for User {
-    type Error = &'static str;
+/// Password login can be turned off when single sign-on is configured
+fn password_login_enabled(oidc: &Option<Oidc>) -> bool {
+    !matches!(oidc, Some(oidc) if !oidc.config.password_login)
+}
 
-    async fn from_request(request: &'r Request<'_>) -> request::Outcome<User, Self::Error> {
-        let db = match request.guard::<DbConn>().await {
-            Outcome::Success(c) => c,
-            _ => {
-                return Outcome::Failure((Status::ServiceUnavailable, "An internal error occured."))
-            }
-        };
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct LoginMethods {
+    password: bool,
+    oidc: bool,
+}
 
-        let auth_cookie = request.cookies().get_private("auth");
-        if let Some(cookie) = auth_cookie {
-            let user = db
-                .run(move |conn| {
-                    user::table
-                        .filter(
-                            user::id.eq(cookie
-                                .value()
-                                .parse::<i32>()
-                                .expect("Auth cookie was not an int")),
-                        )
-                        .first(conn)
-                })
-                .await;
-            match user {
-                Ok(user) => Outcome::Success(user),
-                Err(_) => Outcome::Failure((Status::Unauthorized, "User not found")),
-            }
-        } else {
-            Outcome::Failure((Status::Unauthorized, "No cookies?"))
+/// Lets the login page know which options to show
+#[get("/auth/methods")]
+fn login_methods(oidc: &State<Option<Oidc>>) -> Json<LoginMethods> {
+    Json(LoginMethods {
+        password: password_login_enabled(oidc),
+        oidc: oidc.is_some(),
+    })
+}
+
+/// Send the user to the identity provider
+#[get("/oidc/login")]
+async fn oidc_login(
+    cookies: &CookieJar<'_>,
+    oidc: &State<Option<Oidc>>,
+) -> Result<Redirect, (Status, &'static str)> {
+    let oidc = oidc
+        .as_ref()
+        .ok_or((Status::NotFound, "Single sign-on is not configured"))?;
+    let (url, flow) = oidc
+        .authorization_url()
+        .await
+        .map_err(|message| (Status::BadGateway, message))?;
+
+    let flow = serde_json::to_string(&flow)
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+    // Lax, because the callback is a cross-site navigation from the identity provider
+    cookies.add_private(
+        Cookie::build("oidc_flow", flow)
+            .same_site(SameSite::Lax)
+            .finish(),
+    );
+    Ok(Redirect::to(url))
+}
+
+/// Find the user linked to the identity provider account. Otherwise link the user with the same
+/// email address, or create a new user.
+fn find_or_create_oidc_user(
+    conn: &DbConnection,
+    claims: &oidc::Claims,
+    unusable_password: String,
+) -> QueryResult<User> {
+    if let Some(user) = user::table
+        .filter(user::oidc_subject.eq(&claims.sub))
+        .first::<User>(conn)
+        .optional()?
+    {
+        return Ok(user);
+    }
+
+    // Only link by email if the identity provider says it's verified,
+    // and the user confirmed it on our side too
+    let email_verified = claims.email_verified == Some(true);
+    if let (Some(email), true) = (&claims.email, email_verified) {
+        if let Some(user) = user::table
+            .filter(user::email.eq(email.to_lowercase()))
+            .filter(user::email_verified.eq(1))
+            .filter(user::oidc_subject.is_null())
+            .first::<User>(conn)
+            .optional()?
+        {
+            diesel::update(user::table.find(user.id))
+                .set(user::oidc_subject.eq(Some(&claims.sub)))
+                .execute(conn)?;
+            return Ok(user);
         }
     }
-}
 
-#[derive(Deserialize, Serialize)]
-#[serde(crate = "rocket::serde")]
-struct LoginInfo {
-    user: String,
-    password: String,
+    let base_name = claims
+        .preferred_username
+        .clone()
+        .or_else(|| {
+            claims
+                .email
+                .as_ref()
+                .and_then(|email| email.split('@').next())
+                .map(String::from)
+        })
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "user".to_string());
+    let mut username = base_name.clone();
+    let mut suffix = 1;
+    while user::table
+        .filter(user::username.eq(&username))
+        .count()
+        .get_result::<i64>(conn)?
+        > 0
+    {
+        suffix += 1;
+        username = format!("{}{}", base_name, suffix);
+    }
+
+    let id = insert_returning_id!(
+        conn,
+        diesel::insert_into(user::table).values((
+            user::username.eq(username),
+            user::password.eq(unusable_password),
+            user::email.eq(claims.email.as_ref().map(|email| email.to_lowercase())),
+            user::email_verified.eq(email_verified as i32),
+            user::display_name.eq(&claims.name),
+            user::oidc_subject.eq(Some(&claims.sub)),
+        )),
+        user::id
+    )?;
+    user::table.find(id).first::<User>(conn)
 }
 
-#[post("/login", data = "<info>")]
-async fn login(
+/// The identity provider sends the user back here
+#[get("/oidc/callback?<code>&<state>")]
+async fn oidc_callback(
+    code: Option<String>,
+    state: Option<String>,
     cookies: &CookieJar<'_>,
-    info: Json<LoginInfo>,
+    user_agent: UserAgent,
+    oidc: &State<Option<Oidc>>,
     db: DbConn,
-) -> Result<Json<GenericResponse>, (Status, &'static str)> {
-    let supplied_info = info.into_inner();
-    let user: User = db
+) -> Result<Redirect, (Status, &'static str)> {
+    let oidc = oidc
+        .as_ref()
+        .ok_or((Status::NotFound, "Single sign-on is not configured"))?;
+
+    let flow: oidc::Flow = cookies
+        .get_private("oidc_flow")
+        .and_then(|cookie| serde_json::from_str(cookie.value()).ok())
+        .ok_or((Status::BadRequest, "Login expired, please try again"))?;
+    cookies.remove_private(Cookie::named("oidc_flow"));
+
+    // Login was cancelled or refused at the identity provider
+    let code = code.ok_or((Status::Unauthorized, "Login was not completed"))?;
+    if state.as_ref() != Some(&flow.state) {
+        return Err((Status::BadRequest, "Login state mismatch, please try again"));
+    }
+
+    let claims = oidc
+        .exchange(&flow, &code, unix_timestamp())
+        .await
+        .map_err(|message| (Status::Unauthorized, message))?;
+
+    // Users created through single sign-on get a random password nobody knows
+    let salt = SaltString::generate(&mut OsRng);
+    let unusable_password = Argon2::default()
+        .hash_password(generate_token().as_bytes(), &salt)
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?
+        .to_string();
+    let user = db
         .run(move |conn| {
-            user::table
-                .filter(user::username.eq(supplied_info.user))
-                .first(conn)
+            conn.transaction(|| find_or_create_oidc_user(conn, &claims, unusable_password))
         })
         .await
-        .map_err(|_| (Status::Unauthorized, "Username incorrect"))?;
-
-    let parsed_hash = PasswordHash::new(&user.password)
         .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
-    Argon2::default()
-        .verify_password(supplied_info.password.as_bytes(), &parsed_hash)
-        .map_err(|_| (Status::Unauthorized, "Password incorrect"))?;
+    if user.disabled != 0 {
+        return Err((Status::Forbidden, "This account has been disabled"));
+    }
 
-    cookies.add_private(Cookie::new("auth", user.id.to_string()));
-    Ok(Json(GenericResponse {
-        error: false,
-        message: Some("Logged in"),
-    }))
+    let two_factor = user.totp_enabled != 0;
+    start_session(&db, cookies, user.id, user_agent.0, two_factor)
+        .await
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+    Ok(Redirect::to(oidc.config.post_login_redirect.clone()))
 }
 
-// TODO use the returning thing https://github.com/diesel-rs/diesel/discussions/2684
-// might need a newer diesel version but that caused errors in the model
-no_arg_sql_function!(
-    last_insert_rowid,
-    diesel::sql_types::Integer,
-    "Represents the SQL last_insert_row() function"
-);
-
 #[derive(Serialize)]
 #[serde(crate = "rocket::serde")]
-struct GenericResponse {
-    error: bool,
-    message: Option<&'static str>,
+struct AccessTokenInfo {
+    id: i32,
+    name: String,
+    scopes: Vec<String>,
+    created: i64,
+    expires: Option<i64>,
+    last_used: Option<i64>,
 }
 
-#[post("/register", data = "<info>")]
-async fn register(
-    cookies: &CookieJar<'_>,
-    info: Json<LoginInfo>,
+#[get("/token/list")]
+async fn list_access_tokens(
+    user: User,
+    _scope: AdminScope,
     db: DbConn,
-) -> Result<Json<GenericResponse>, (Status, &'static str)> {
-    let supplied_info = info.into_inner();
-    // clone variable to move it into the closure, maybe this can be done in a nicer way?
-    let cloned_name = supplied_info.user.clone();
-
-    let existing_user = db
+) -> Result<Json<Vec<AccessTokenInfo>>, Status> {
+    let tokens: Vec<AccessToken> = db
         .run(move |conn| {
-            user::table
-                .filter(user::username.eq(cloned_name.as_str()))
-                .count()
-                .get_result::<i64>(conn)
+            AccessToken::belonging_to(&user)
+                .order(access_token::id.asc())
+                .load::<AccessToken>(conn)
         })
         .await
-        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
+        .map_err(|_| Status::InternalServerError)?;
 
-    if existing_user > 0 {
-        return Err((Status::BadRequest, "Username not available"));
-    }
+    Ok(Json(
+        tokens
+            .into_iter()
+            .map(|token| AccessTokenInfo {
+                id: token.id,
+                scopes: token.scope_list(),
+                name: token.name,
+                created: token.created,
+                expires: token.expires,
+                last_used: token.last_used,
+            })
+            .collect(),
+    ))
+}
 
-    if supplied_info.password.len() < 8 {
-        return Err((Status::BadRequest, "Password must be at least 8 characters"));
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct AccessTokenCreationInfo {
+    name: String,
+    scopes: Vec<String>,
+    /// Unix timestamp after which the token stops working
+    expires: Option<i64>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct AccessTokenCreated {
+    id: i32,
+    /// Only ever shown here, the server just keeps a hash
+    token: String,
+}
+
+#[post("/token/create", data = "<info>")]
+async fn create_access_token(
+    user: User,
+    _scope: AdminScope,
+    info: Json<AccessTokenCreationInfo>,
+    db: DbConn,
+) -> Result<Json<AccessTokenCreated>, (Status, &'static str)> {
+    let info = info.into_inner();
+
+    if info.name.trim().is_empty() {
+        return Err((Status::BadRequest, "Token name cannot be empty"));
+    }
+    if info.scopes.is_empty() || !info.scopes.iter().all(|s| scope::ALL.contains(&s.as_str())) {
+        return Err((Status::BadRequest, "Unknown scope"));
+    }
+    if matches!(info.expires, Some(expires) if expires <= unix_timestamp()) {
+        return Err((Status::BadRequest, "Expiry must be in the future"));
     }
 
-    let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
-    let password_hash = match argon2.hash_password(supplied_info.password.as_bytes(), &salt) {
-        Ok(hash) => hash,
-        Err(_) => return Err((Status::InternalServerError, "An internal error occured")),
-    };
-    let new_user = NewUser {
-        username: supplied_info.user,
-        password: password_hash.to_string(),
+    let token = format!("upt_{}", generate_token());
+    let new_token = NewAccessToken {
+        user: user.id,
+        name: info.name,
+        token: hash_token(&token),
+        scopes: info.scopes.join(","),
+        created: unix_timestamp(),
+        expires: info.expires,
     };
 
-    let user_id = db
+    let id = db
         .run(move |conn| {
-            let result = diesel::insert_into(user::table)
-                .values(&new_user)
-                .execute(conn);
+            insert_returning_id!(
+                conn,
+                diesel::insert_into(access_token::table).values(&new_token),
+                access_token::id
+            )
+        })
+        .await
+        .map_err(|_| (Status::InternalServerError, "An internal error occured"))?;
 
-            result?;
+    Ok(Json(AccessTokenCreated { id, token }))
+}
 
-            diesel::select(last_insert_rowid).get_result::<i32>(conn)
+#[delete("/token/<token_id>")]
+async fn revoke_access_token(
+    token_id: i32,
+    user: User,
+    _scope: AdminScope,
+    db: DbConn,
+) -> Result<(), Status> {
+    let deleted_count = db
+        .run(move |conn| {
+            diesel::delete(access_token::table)
+                .filter(access_token::id.eq(token_id))
+                .filter(access_token::user.eq(user.id))
+                .execute(conn)
         })
-        .await;
+        .await
+        .map_err(|_| Status::InternalServerError)?;
 
-    match user_id {
-        Ok(id) => {
-            cookies.add_private(Cookie::new("auth", id.to_string()));
-            Ok(Json(GenericResponse {
-                error: false,
-                message: None,
-            }))
-        }
-        Err(_) => Err((Status::InternalServerError, "An internal error occured")),
+    if deleted_count == 0 {
+        return Err(Status::NotFound);
     }
+    Ok(())
 }
 
-/// Publicly visible stuff
-#[derive(Serialize)]
-#[serde(crate = "rocket::serde")]
-struct UserInfo {
-    name: String,
+// Migrations
+
+#[cfg(feature = "sqlite")]
+#[derive(QueryableByName)]
+struct ForeignKeysPragma {
+    #[sql_type = "diesel::sql_types::Integer"]
+    foreign_keys: i32,
 }
 
-/// Get user ID
-#[get("/auth")]
-fn auth(user: User) -> Json<UserInfo> {
-    let info = UserInfo {
-        name: user.username,
-    };
-    Json(info)
+/// rocket_sync_db_pools turns foreign keys on for every pooled SQLite connection, make sure
+/// that stays the case since deletions rely on the cascades
+#[cfg(feature = "sqlite")]
+fn check_foreign_keys(conn: &DbConnection) -> Result<(), String> {
+    let pragma = diesel::sql_query("PRAGMA foreign_keys")
+        .get_result::<ForeignKeysPragma>(conn)
+        .map_err(|e| e.to_string())?;
+    if pragma.foreign_keys != 1 {
+        return Err("foreign keys are not enforced on database connections".to_string());
+    }
+    Ok(())
 }
 
-/// Test thing
-#[get("/secure")]
-fn secure(user: User) -> Json<User> {
-    Json(user)
+/// Migrations that haven't been run on the database yet, and ones the database has that this
+/// binary doesn't know about, like after downgrading
+fn migration_drift(conn: &DbConnection) -> Result<(Vec<String>, Vec<String>), String> {
+    let embedded: HashSet<String> = MIGRATION_VERSIONS.iter().map(|v| v.to_string()).collect();
+    // Fails on a new database, which doesn't have the migrations table yet
+    let applied = conn.previously_run_migration_versions().unwrap_or_default();
+
+    let mut pending: Vec<String> = embedded.difference(&applied).cloned().collect();
+    let mut unknown: Vec<String> = applied.difference(&embedded).cloned().collect();
+    pending.sort();
+    unknown.sort();
+    Ok((pending, unknown))
 }
 
-/// Remove the auth cookie.
-#[post("/logout")]
-fn logout(cookies: &CookieJar<'_>) -> String {
-    cookies.remove_private(Cookie::named("auth"));
-    "Goodbye".into()
+/// Run pending migrations before the server starts. With `check_only`, refuse to start instead
+/// if the database doesn't match the migrations in this binary.
+fn migrations_fairing(check_only: bool) -> AdHoc {
+    AdHoc::try_on_ignite("Database migrations", move |rocket| async move {
+        let db = match DbConn::get_one(&rocket).await {
+            Some(db) => db,
+            None => return Err(rocket),
+        };
+        let result = db
+            .run(move |conn| {
+                #[cfg(feature = "sqlite")]
+                check_foreign_keys(conn)?;
+
+                let (pending, unknown) = migration_drift(conn)?;
+                if !unknown.is_empty() {
+                    let message = format!(
+                        "the database has migrations this version doesn't know about: {}",
+                        unknown.join(", ")
+                    );
+                    if check_only {
+                        return Err(message);
+                    }
+                    println!("warning: {}", message);
+                }
+                if pending.is_empty() {
+                    return Ok(());
+                }
+                if check_only {
+                    return Err(format!(
+                        "the database has pending migrations: {}. Run `uptitle-server migrate` first.",
+                        pending.join(", ")
+                    ));
+                }
+                embedded_migrations::run_with_output(conn, &mut std::io::stdout())
+                    .map_err(|e| e.to_string())
+            })
+            .await;
+
+        match result {
+            Ok(()) => Ok(rocket),
+            Err(message) => {
+                eprintln!("error: {}", message);
+                Err(rocket)
+            }
+        }
+    })
 }
 
-#[launch]
-fn rocket() -> _ {
+#[rocket::main]
+async fn main() {
+    let cli = cli::Cli::parse();
+    match cli.command {
+        None | Some(cli::Command::Serve) => {
+            // Errors are reported when dropped
+            let _ = rocket()
+                .attach(migrations_fairing(cli.check_migrations))
+                .launch()
+                .await;
+        }
+        Some(command) => {
+            if let Err(message) = cli::run(command, rocket()).await {
+                eprintln!("error: {}", message);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn rocket() -> Rocket<Build> {
     dotenv::dotenv().ok();
+    configure(rocket::build())
+}
 
-    rocket::build()
+/// Add the configured services, the database and the routes. Tests call this with their own
+/// configuration instead of Rocket.toml.
+fn configure(rocket: Rocket<Build>) -> Rocket<Build> {
+    let oidc = rocket
+        .figment()
+        .find_value("oidc")
+        .is_ok()
+        .then(|| {
+            rocket
+                .figment()
+                .extract_inner::<OidcConfig>("oidc")
+                .expect("invalid oidc configuration")
+        })
+        .map(Oidc::new);
+    let transcription = rocket
+        .figment()
+        .find_value("transcriber")
+        .is_ok()
+        .then(|| Transcription {
+            transcriber: rocket
+                .figment()
+                .extract_inner::<TranscriberConfig>("transcriber")
+                .expect("invalid transcriber configuration")
+                .build(),
+            running: Arc::default(),
+        });
+    let translator = rocket.figment().find_value("translator").is_ok().then(|| {
+        rocket
+            .figment()
+            .extract_inner::<TranslatorConfig>("translator")
+            .expect("invalid translator configuration")
+            .build()
+    });
+    let ip_header = rocket.figment().extract_inner::<String>("ip_header").ok();
+    let mailer = rocket.figment().find_value("email").is_ok().then(|| {
+        let config = rocket
+            .figment()
+            .extract_inner::<EmailConfig>("email")
+            .expect("invalid email configuration");
+        Arc::new(Mailer::new(config).expect("invalid email configuration"))
+    });
+
+    rocket
         .attach(DbConn::fairing())
+        .attach(AdHoc::on_liftoff("Email worker", |rocket| {
+            Box::pin(async move {
+                let mailer = match rocket.state::<Option<Arc<Mailer>>>() {
+                    Some(Some(mailer)) => mailer.clone(),
+                    _ => return,
+                };
+                let db = DbConn::get_one(rocket)
+                    .await
+                    .expect("no database connection for the email worker");
+                task::spawn(run_email_worker(db, mailer, rocket.shutdown()));
+            })
+        }))
+        .manage(oidc)
+        .manage(mailer)
+        .manage(transcription)
+        .manage(translator)
+        .manage(IpHeader(ip_header))
         .manage(channel::<SubtitleEvent>(1024).0)
         .mount("/api", routes![secure]) // Temp
         .mount("/api", routes![login, auth, logout, register]) // Auth
-        .mount("/api", routes![list_workspaces]) // Workspaces
         .mount(
             "/api",
-            routes![get_project, create_project, events, get_waveform],
+            routes![
+                list_sessions,
+                revoke_sessions,
+                revoke_session,
+                change_password
+            ],
+        ) // Sessions
+        .mount(
+            "/api",
+            routes![list_access_tokens, create_access_token, revoke_access_token],
+        ) // Access tokens
+        .mount(
+            "/api",
+            routes![
+                login_two_factor,
+                enroll_two_factor,
+                confirm_two_factor,
+                regenerate_recovery_codes,
+                disable_two_factor
+            ],
+        ) // Two-factor authentication
+        .mount("/api", routes![get_profile, update_profile, delete_account]) // Account
+        .mount(
+            "/api",
+            routes![
+                request_password_reset,
+                reset_password,
+                send_email_verification,
+                verify_email
+            ],
+        ) // Email
+        .mount("/api", routes![login_methods, oidc_login, oidc_callback]) // Single sign-on
+        .mount(
+            "/api",
+            routes![
+                admin_list_users,
+                admin_update_user,
+                admin_reset_password,
+                admin_revoke_sessions,
+                admin_unlock_user,
+                admin_list_workspaces,
+                admin_list_projects,
+                admin_delete_orphaned_videos,
+                admin_get_settings,
+                admin_update_settings,
+                admin_list_invitations,
+                admin_create_invitation,
+                admin_delete_invitation
+            ],
+        ) // Instance administration
+        .mount(
+            "/api",
+            routes![
+                list_workspaces,
+                list_workspace_projects,
+                unlock_member,
+                set_workspace_require_2fa
+            ],
+        ) // Workspaces
+        .mount(
+            "/api",
+            routes![
+                get_project,
+                create_project,
+                set_project_video,
+                set_project_timing,
+                events,
+                get_waveform,
+                get_shot_changes,
+                redetect_shot_changes,
+                check_shot_changes,
+                get_speech_regions,
+                detect_speech,
+                create_speech_cues,
+                align_transcript,
+                transcribe_project,
+                translate_project
+            ],
         ) // Projects
         .mount(
             "/api",
@@ -1087,9 +7393,36 @@ fn rocket() -> _ {
                 get_subtitle_list,
                 create_subtitle,
                 edit_subtitle,
-                delete_subtitle
+                delete_subtitle,
+                get_subtitle_history,
+                revert_subtitle,
+                undo,
+                redo
             ],
         ) // Subtitles
+        .mount(
+            "/api",
+            routes![
+                set_subtitle_status,
+                get_subtitle_status_history,
+                get_project_progress
+            ],
+        ) // Review status
+        .mount(
+            "/api",
+            routes![list_locks, create_lock, renew_lock, delete_lock],
+        ) // Locks
+        .mount(
+            "/api",
+            routes![
+                list_comments,
+                create_comment,
+                edit_comment,
+                delete_comment,
+                resolve_comment,
+                unresolve_comment
+            ],
+        ) // Comments
         .mount(
             "/api",
             routes![list_snapshots, create_snapshot, get_snapshot, edit_snapshot],
@@ -0,0 +1,266 @@
+//! Metadata of videos: from YouTube with ytextract, or from media files with ffprobe. Shot
+//! changes are detected with ffmpeg's scene filter.
+
+use std::path::Path;
+use std::process::Command;
+
+use rocket::serde::Deserialize;
+use ytextract::Stream;
+
+use crate::models::VideoMetadata;
+use crate::timecode::FrameRate;
+
+/// The video stream with the highest resolution, and then the highest frame rate
+fn best_video_stream(streams: &[Stream]) -> Option<&ytextract::stream::Video> {
+    streams
+        .iter()
+        .filter_map(|stream| match stream {
+            Stream::Video(video) => Some(video),
+            _ => None,
+        })
+        .max_by_key(|video| (video.height(), video.fps()))
+}
+
+/// Metadata of a YouTube video, except for the language which ytextract doesn't know. The
+/// resolution is the one of the best video stream. YouTube rounds frame rates, so 29.97 is
+/// reported as 30. The frame rate is left out, `probe_frame_rate` on `youtube_video_url` gets
+/// the exact one.
+pub fn youtube_metadata(video: &ytextract::Video, streams: &[Stream]) -> VideoMetadata {
+    let best_video = best_video_stream(streams);
+    let audio_sample_rate = streams.iter().find_map(|stream| match stream {
+        Stream::Audio(audio) => Some(audio.sample_rate()),
+        _ => None,
+    });
+
+    VideoMetadata {
+        title: Some(video.title().to_string()),
+        channel: Some(video.channel().name().to_string()),
+        published: Some(video.date().and_hms(0, 0, 0).timestamp()),
+        language: None,
+        frame_rate_num: None,
+        frame_rate_den: None,
+        width: best_video.map(|video| video.width() as i32),
+        height: best_video.map(|video| video.height() as i32),
+        sample_rate: audio_sample_rate.map(|rate| rate as i32),
+    }
+}
+
+/// Where to download the best video stream of a YouTube video
+pub fn youtube_video_url(streams: &[Stream]) -> Option<String> {
+    best_video_stream(streams).map(|video| video.url().to_string())
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct FfprobeOutput {
+    #[serde(default)]
+    streams: Vec<FfprobeStream>,
+    format: Option<FfprobeFormat>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct FfprobeStream {
+    codec_type: Option<String>,
+    width: Option<i32>,
+    height: Option<i32>,
+    /// Like "30000/1001"
+    r_frame_rate: Option<String>,
+    /// A number in a string, like "48000"
+    sample_rate: Option<String>,
+    #[serde(default)]
+    tags: FfprobeTags,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct FfprobeFormat {
+    /// Seconds, like "12.345000"
+    duration: Option<String>,
+    #[serde(default)]
+    tags: FfprobeTags,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(crate = "rocket::serde")]
+struct FfprobeTags {
+    title: Option<String>,
+    artist: Option<String>,
+    language: Option<String>,
+}
+
+/// Metadata and duration in milliseconds of a media file. This blocks, so call it from
+/// `spawn_blocking`.
+pub fn probe_file(path: &Path) -> Result<(VideoMetadata, Option<i32>), String> {
+    let output = Command::new("ffprobe")
+        .args([
+            "-v",
+            "error",
+            "-print_format",
+            "json",
+            "-show_format",
+            "-show_streams",
+        ])
+        .arg(path)
+        .output()
+        .map_err(|e| format!("could not run ffprobe: {}", e))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    let probe: FfprobeOutput = rocket::serde::json::from_slice(&output.stdout)
+        .map_err(|e| format!("unexpected ffprobe output: {}", e))?;
+
+    let video = probe
+        .streams
+        .iter()
+        .find(|stream| stream.codec_type.as_deref() == Some("video"));
+    let audio = probe
+        .streams
+        .iter()
+        .find(|stream| stream.codec_type.as_deref() == Some("audio"));
+    let frame_rate = video
+        .and_then(|video| video.r_frame_rate.as_deref())
+        .and_then(parse_fraction);
+    let format_tags = probe.format.as_ref().map(|format| &format.tags);
+
+    let metadata = VideoMetadata {
+        title: format_tags.and_then(|tags| tags.title.clone()),
+        channel: format_tags.and_then(|tags| tags.artist.clone()),
+        published: None,
+        language: audio.and_then(|audio| audio.tags.language.clone()),
+        frame_rate_num: frame_rate.map(|(num, _)| num),
+        frame_rate_den: frame_rate.map(|(_, den)| den),
+        width: video.and_then(|video| video.width),
+        height: video.and_then(|video| video.height),
+        sample_rate: audio
+            .and_then(|audio| audio.sample_rate.as_deref())
+            .and_then(|rate| rate.parse().ok()),
+    };
+    let duration = probe
+        .format
+        .and_then(|format| format.duration)
+        .and_then(|duration| duration.parse::<f64>().ok())
+        .map(|seconds| (seconds * 1000.0).round() as i32);
+
+    Ok((metadata, duration))
+}
+
+/// The exact frame rate of the first video stream of a file or URL, as a fraction. This blocks,
+/// so call it from `spawn_blocking`.
+pub fn probe_frame_rate(input: &str) -> Result<(i32, i32), String> {
+    let output = Command::new("ffprobe")
+        .args([
+            "-v",
+            "error",
+            "-print_format",
+            "json",
+            "-select_streams",
+            "v:0",
+            "-show_streams",
+            input,
+        ])
+        .output()
+        .map_err(|e| format!("could not run ffprobe: {}", e))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    let probe: FfprobeOutput = rocket::serde::json::from_slice(&output.stdout)
+        .map_err(|e| format!("unexpected ffprobe output: {}", e))?;
+
+    probe
+        .streams
+        .first()
+        .and_then(|video| video.r_frame_rate.as_deref())
+        .and_then(parse_fraction)
+        .ok_or_else(|| "no frame rate in the video".to_string())
+}
+
+/// Parse a fraction like "30000/1001". ffprobe reports "0/0" when it doesn't know.
+fn parse_fraction(fraction: &str) -> Option<(i32, i32)> {
+    let (num, den) = fraction.split_once('/')?;
+    let (num, den) = (num.parse().ok()?, den.parse().ok()?);
+    if num <= 0 || den <= 0 {
+        return None;
+    }
+    Some((num, den))
+}
+
+/// How different a frame has to be from the one before it to count as a shot change, between 0
+/// and 1
+pub const DEFAULT_SCENE_THRESHOLD: f64 = 0.4;
+
+/// Times of shot changes in a video or file that ffmpeg can read, in milliseconds. This blocks
+/// for about as long as it takes to decode the video, so call it from `spawn_blocking`.
+pub fn detect_shot_changes(input: &str, threshold: f64) -> Result<Vec<i32>, String> {
+    // Scaling the frames down first makes this a lot faster, and doesn't change which cuts are found
+    let filter = format!("scale=320:-2,select='gt(scene,{})',showinfo", threshold);
+    let output = Command::new("ffmpeg")
+        .args(["-hide_banner", "-nostats", "-i", input, "-an", "-sn", "-vf"])
+        .arg(&filter)
+        .args(["-f", "null", "-"])
+        .output()
+        .map_err(|e| format!("could not run ffmpeg: {}", e))?;
+    let log = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        return Err(log.lines().last().unwrap_or_default().trim().to_string());
+    }
+
+    Ok(parse_showinfo(&log))
+}
+
+/// Times in milliseconds of the frames in the log of ffmpeg's showinfo filter, in order
+pub fn parse_showinfo(log: &str) -> Vec<i32> {
+    // showinfo logs a line like "[Parsed_showinfo_2 @ 0x...] n: 0 pts: 12012 pts_time:12.012 ..."
+    // for every frame that got through the select filter
+    let mut times: Vec<i32> = log
+        .lines()
+        .filter(|line| line.contains("Parsed_showinfo"))
+        .filter_map(|line| line.split_once("pts_time:"))
+        .filter_map(|(_, rest)| rest.split_whitespace().next()?.parse::<f64>().ok())
+        .map(|seconds| (seconds * 1000.0).round() as i32)
+        .collect();
+    times.sort_unstable();
+    times.dedup();
+    times
+}
+
+/// The shot changes of a video, for checking and snapping cue boundaries
+pub struct ShotChanges {
+    /// Sorted times in milliseconds
+    pub times: Vec<i32>,
+    /// Frame rate to count distances in
+    pub rate: FrameRate,
+}
+
+impl ShotChanges {
+    /// The closest shot change to a time, and how many frames away it is
+    pub fn nearest(&self, time: i32) -> Option<(i32, i64)> {
+        let index = self.times.partition_point(|&cut| cut < time);
+        let before = index.checked_sub(1).map(|index| self.times[index]);
+        let after = self.times.get(index).copied();
+        [before, after]
+            .into_iter()
+            .flatten()
+            .map(|cut| {
+                (
+                    cut,
+                    (self.rate.frame_at(time) - self.rate.frame_at(cut)).abs(),
+                )
+            })
+            .min_by_key(|&(_, frames)| frames)
+    }
+
+    /// Whether a cue boundary is close to a shot change without being on it
+    pub fn straddles(&self, time: i32, frames: i64) -> Option<(i32, i64)> {
+        self.nearest(time)
+            .filter(|&(_, distance)| distance > 0 && distance <= frames)
+    }
+
+    /// Move a time onto a shot change that is at most the given number of frames away
+    pub fn snap(&self, time: i32, frames: i64) -> i32 {
+        match self.nearest(time) {
+            Some((cut, distance)) if distance <= frames => cut,
+            _ => time,
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use crate::schema::*;
+use crate::timecode::{FrameRate, Timecode};
 use rocket::serde::Deserialize;
 use rocket::serde::Serialize;
 
@@ -11,6 +12,21 @@ pub struct User {
     pub password: String,
     pub email: Option<String>,
     pub display_name: Option<String>,
+    /// Base32 TOTP secret. Set during enrollment, but only used once `totp_enabled` is set.
+    #[serde(skip_serializing)]
+    pub totp_secret: Option<String>,
+    pub totp_enabled: i32,
+    /// Last TOTP time step that was accepted, so a code can't be used twice
+    #[serde(skip_serializing)]
+    pub totp_last_step: Option<i64>,
+    /// `sub` claim of the linked single sign-on account
+    #[serde(skip_serializing)]
+    pub oidc_subject: Option<String>,
+    pub email_verified: i32,
+    /// Operator of the whole server, can use the /api/admin routes
+    pub instance_admin: i32,
+    /// Disabled users can't log in, and their sessions and access tokens stop working
+    pub disabled: i32,
 }
 
 #[derive(Insertable)]
@@ -20,6 +36,222 @@ pub struct NewUser {
     pub password: String,
 }
 
+/// A logged in client. `token` is the SHA-256 hash of the token stored in the auth cookie.
+#[derive(Debug, Clone, Queryable, Identifiable, Associations)]
+#[belongs_to(User, foreign_key = "user")]
+#[table_name = "session"]
+pub struct Session {
+    pub id: i32,
+    pub token: String,
+    pub user: i32,
+    pub created: i64,
+    pub last_seen: i64,
+    pub user_agent: Option<String>,
+    pub expires: i64,
+    /// Set while the user still has to enter their two-factor code
+    pub pending: i32,
+}
+
+#[derive(Insertable)]
+#[table_name = "session"]
+pub struct NewSession {
+    pub token: String,
+    pub user: i32,
+    pub created: i64,
+    pub last_seen: i64,
+    pub user_agent: Option<String>,
+    pub expires: i64,
+    pub pending: i32,
+}
+
+/// One-time code that can be used instead of a TOTP code. Stored hashed like session tokens.
+#[derive(Debug, Clone, Queryable, Identifiable, Associations)]
+#[belongs_to(User, foreign_key = "user")]
+#[table_name = "recovery_code"]
+pub struct RecoveryCode {
+    pub id: i32,
+    pub user: i32,
+    pub code: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "recovery_code"]
+pub struct NewRecoveryCode {
+    pub user: i32,
+    pub code: String,
+}
+
+/// An email waiting to be sent. Rows are deleted once the email is sent.
+#[derive(Debug, Clone, Queryable, Identifiable)]
+#[table_name = "email_outbox"]
+pub struct OutboxEmail {
+    pub id: i32,
+    pub recipient: String,
+    pub subject: String,
+    pub body: String,
+    pub created: i64,
+    pub attempts: i32,
+    pub next_attempt: i64,
+    pub last_error: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name = "email_outbox"]
+pub struct NewOutboxEmail {
+    pub recipient: String,
+    pub subject: String,
+    pub body: String,
+    pub created: i64,
+    pub next_attempt: i64,
+}
+
+/// Single-use token sent by email, stored hashed. For email verification, `email` is the
+/// address it was sent to, so it stops working if the user changes their address in between.
+#[derive(Debug, Clone, Queryable, Identifiable, Associations)]
+#[belongs_to(User, foreign_key = "user")]
+#[table_name = "email_token"]
+pub struct EmailToken {
+    pub id: i32,
+    pub user: i32,
+    pub purpose: String,
+    pub token: String,
+    pub email: Option<String>,
+    pub created: i64,
+    pub expires: i64,
+}
+
+#[derive(Insertable)]
+#[table_name = "email_token"]
+pub struct NewEmailToken {
+    pub user: i32,
+    pub purpose: String,
+    pub token: String,
+    pub email: Option<String>,
+    pub created: i64,
+    pub expires: i64,
+}
+
+/// Values of `email_token.purpose`
+pub mod email_purpose {
+    pub const PASSWORD_RESET: &str = "password_reset";
+    pub const VERIFY_EMAIL: &str = "verify_email";
+}
+
+/// Server-wide settings that can be changed at runtime, like `registration`
+#[derive(Debug, Clone, Queryable, Insertable, AsChangeset)]
+#[table_name = "setting"]
+#[primary_key(key)]
+pub struct Setting {
+    pub key: String,
+    pub value: String,
+}
+
+/// The `registration` setting: whether anyone can register, or only people with an invitation
+pub mod registration {
+    pub const KEY: &str = "registration";
+    pub const OPEN: &str = "open";
+    pub const INVITE_ONLY: &str = "invite_only";
+}
+
+/// Lets someone register while registration is invite-only. Stored hashed, and only usable once.
+#[derive(Debug, Clone, Serialize, Queryable, Identifiable)]
+#[serde(crate = "rocket::serde")]
+#[table_name = "invitation"]
+pub struct Invitation {
+    pub id: i32,
+    #[serde(skip_serializing)]
+    pub token: String,
+    pub email: Option<String>,
+    pub created_by: Option<i32>,
+    pub created: i64,
+    pub expires: i64,
+    pub used: Option<i64>,
+    /// Cleared when that user is deleted, `used` stays set
+    pub used_by: Option<i32>,
+}
+
+#[derive(Insertable)]
+#[table_name = "invitation"]
+pub struct NewInvitation {
+    pub token: String,
+    pub email: Option<String>,
+    pub created_by: Option<i32>,
+    pub created: i64,
+    pub expires: i64,
+}
+
+/// A personal access token for scripts, sent as `Authorization: Bearer <token>`.
+/// Like sessions, only the SHA-256 hash of the token is stored.
+#[derive(Debug, Clone, Queryable, Identifiable, Associations)]
+#[belongs_to(User, foreign_key = "user")]
+#[table_name = "access_token"]
+pub struct AccessToken {
+    pub id: i32,
+    pub user: i32,
+    pub name: String,
+    pub token: String,
+    /// Comma separated, see `scope`
+    pub scopes: String,
+    pub created: i64,
+    pub expires: Option<i64>,
+    pub last_used: Option<i64>,
+}
+
+impl AccessToken {
+    pub fn scope_list(&self) -> Vec<String> {
+        self.scopes
+            .split(',')
+            .filter(|scope| !scope.is_empty())
+            .map(String::from)
+            .collect()
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "access_token"]
+pub struct NewAccessToken {
+    pub user: i32,
+    pub name: String,
+    pub token: String,
+    pub scopes: String,
+    pub created: i64,
+    pub expires: Option<i64>,
+}
+
+/// Failed logins for a username (`user:<name>`) or a client address (`ip:<address>`)
+#[derive(Debug, Clone, Queryable, Insertable, AsChangeset)]
+#[table_name = "login_failure"]
+#[primary_key(key)]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct LoginFailure {
+    pub key: String,
+    pub failures: i32,
+    pub last_failure: i64,
+    pub locked_until: Option<i64>,
+}
+
+/// Scopes an access token can have
+pub mod scope {
+    /// Read anything the user can see
+    pub const READ: &str = "read";
+    /// Change projects, subtitles, comments and snapshots
+    pub const SUBTITLES_WRITE: &str = "subtitles:write";
+    /// Everything the user can do when logged in, including account management
+    pub const ADMIN: &str = "admin";
+
+    pub const ALL: [&str; 3] = [READ, SUBTITLES_WRITE, ADMIN];
+
+    /// Whether a token with the given scopes may do something that needs `required`.
+    /// Each scope includes the ones listed before it.
+    pub fn allows(scopes: &[String], required: &str) -> bool {
+        let level = |scope: &str| ALL.iter().position(|s| *s == scope);
+        let required = level(required);
+        scopes
+            .iter()
+            .any(|scope| level(scope).is_some() && level(scope) >= required)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Queryable, Identifiable)]
 #[serde(crate = "rocket::serde")]
 #[table_name = "workspace"]
@@ -28,6 +260,15 @@ pub struct Workspace {
     pub name: String,
     pub owner: i32,
     pub shared: i32,
+    /// Members need two-factor authentication to access the workspace
+    pub require_2fa: i32,
+}
+
+/// Values of `workspace_member.role`
+pub mod role {
+    pub const MEMBER: i32 = 0;
+    pub const ADMIN: i32 = 1;
+    pub const REVIEWER: i32 = 2;
 }
 
 #[derive(Debug, Clone, Serialize, Queryable, Identifiable, Associations)]
@@ -50,8 +291,91 @@ pub struct Video {
     pub identifier: String,
     pub duration: Option<i32>,
     pub waveform: Option<Vec<u8>>,
+    pub title: Option<String>,
+    pub channel: Option<String>,
+    pub published: Option<i64>,
+    pub language: Option<String>,
+    pub frame_rate_num: Option<i32>,
+    pub frame_rate_den: Option<i32>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub sample_rate: Option<i32>,
+    /// When shot changes were last detected
+    pub shot_changes_detected: Option<i64>,
+    /// When speech regions were last detected
+    pub speech_detected: Option<i64>,
+}
+
+/// A video without its waveform, which can be several megabytes. Select it with
+/// `VIDEO_SUMMARY_COLUMNS`.
+#[derive(Debug, Queryable)]
+pub struct VideoSummary {
+    pub id: i32,
+    pub source: String,
+    pub identifier: String,
+    pub duration: Option<i32>,
+    pub metadata: VideoMetadata,
 }
 
+#[allow(clippy::type_complexity)]
+pub const VIDEO_SUMMARY_COLUMNS: (
+    video::id,
+    video::source,
+    video::identifier,
+    video::duration,
+    VideoMetadataColumns,
+) = (
+    video::id,
+    video::source,
+    video::identifier,
+    video::duration,
+    VIDEO_METADATA_COLUMNS,
+);
+
+/// What we know about a video besides its duration. Everything is optional since it depends on
+/// the source, and videos registered before these were recorded don't have any of it.
+#[derive(Debug, Clone, Default, Queryable, AsChangeset)]
+#[table_name = "video"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct VideoMetadata {
+    pub title: Option<String>,
+    pub channel: Option<String>,
+    /// Unix timestamp of the publish date
+    pub published: Option<i64>,
+    /// Language code of the original audio, like `en`
+    pub language: Option<String>,
+    /// Frames per second as a fraction, like 30000/1001 for 29.97
+    pub frame_rate_num: Option<i32>,
+    pub frame_rate_den: Option<i32>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub sample_rate: Option<i32>,
+}
+
+pub type VideoMetadataColumns = (
+    video::title,
+    video::channel,
+    video::published,
+    video::language,
+    video::frame_rate_num,
+    video::frame_rate_den,
+    video::width,
+    video::height,
+    video::sample_rate,
+);
+
+pub const VIDEO_METADATA_COLUMNS: VideoMetadataColumns = (
+    video::title,
+    video::channel,
+    video::published,
+    video::language,
+    video::frame_rate_num,
+    video::frame_rate_den,
+    video::width,
+    video::height,
+    video::sample_rate,
+);
+
 #[derive(Debug, Insertable, Serialize)]
 #[serde(crate = "rocket::serde")]
 #[table_name = "video"]
@@ -60,6 +384,7 @@ pub struct NewVideo {
     pub identifier: String,
     pub duration: Option<i32>,
     pub waveform: Option<Vec<u8>>,
+    pub language: Option<String>,
 }
 
 #[derive(Debug, Clone, Queryable, Serialize, Identifiable, Associations)]
@@ -72,6 +397,22 @@ pub struct Project {
     pub workspace: i32,
     pub name: String,
     pub video: Option<i32>,
+    /// Frames per second as a fraction, for timecodes and snapping
+    pub frame_rate_num: Option<i32>,
+    pub frame_rate_den: Option<i32>,
+    pub drop_frame: i32,
+    /// Move cue boundaries to the nearest frame when they're created or edited
+    pub snap_to_frames: i32,
+}
+
+impl Project {
+    pub fn timecode(&self) -> Option<Timecode> {
+        let rate = FrameRate::new(self.frame_rate_num?, self.frame_rate_den?).ok()?;
+        Some(Timecode {
+            rate,
+            drop_frame: self.drop_frame != 0 && rate.supports_drop_frame(),
+        })
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Insertable)]
@@ -94,6 +435,48 @@ pub struct Subtitle {
     pub start: i32,
     pub end: i32,
     pub text: String,
+    // Older snapshots were taken before subtitles had a status
+    #[serde(default = "status::default")]
+    pub status: String,
+    /// How a machine made the text, one of `origin::ALL`, until someone edits it
+    #[serde(default)]
+    pub origin: Option<String>,
+    /// From 0 to 1, how sure the machine was about the text
+    #[serde(default)]
+    pub confidence: Option<f64>,
+}
+
+/// Values of `subtitle.origin`
+pub mod origin {
+    pub const TRANSCRIPTION: &str = "transcription";
+    pub const TRANSLATION: &str = "translation";
+
+    pub const ALL: [&str; 2] = [TRANSCRIPTION, TRANSLATION];
+}
+
+/// Values of `subtitle.status`, in workflow order
+pub mod status {
+    pub const DRAFT: &str = "draft";
+    pub const TRANSLATED: &str = "translated";
+    pub const NEEDS_REVIEW: &str = "needs-review";
+    pub const APPROVED: &str = "approved";
+    pub const REJECTED: &str = "rejected";
+
+    pub const ALL: [&str; 5] = [DRAFT, TRANSLATED, NEEDS_REVIEW, APPROVED, REJECTED];
+
+    pub fn default() -> String {
+        DRAFT.to_string()
+    }
+
+    /// Whether a workspace member with the given role may move a subtitle between these statuses.
+    /// Approving and rejecting, and reopening approved subtitles, is left to reviewers and admins.
+    pub fn can_transition(role: i32, from: &str, to: &str) -> bool {
+        if !ALL.contains(&to) {
+            return false;
+        }
+        let is_reviewer = role == super::role::REVIEWER || role == super::role::ADMIN;
+        is_reviewer || !(to == APPROVED || to == REJECTED || from == APPROVED)
+    }
 }
 
 #[derive(Debug, Clone, Insertable)]
@@ -103,6 +486,254 @@ pub struct NewSubtitle {
     pub start: i32,
     pub end: i32,
     pub text: String,
+    pub origin: Option<String>,
+    pub confidence: Option<f64>,
+}
+
+/// A soft lock on either a single subtitle or a time range within a project
+#[derive(Debug, Clone, Queryable, Serialize, Identifiable, Associations)]
+#[serde(crate = "rocket::serde")]
+#[belongs_to(Project, foreign_key = "project")]
+#[table_name = "subtitle_lock"]
+#[primary_key(id)]
+pub struct SubtitleLock {
+    pub id: i32,
+    pub project: i32,
+    pub owner: i32,
+    pub subtitle: Option<i32>,
+    pub start: Option<i32>,
+    pub end: Option<i32>,
+    pub expires: i64,
+}
+
+impl SubtitleLock {
+    /// Whether this lock applies to a subtitle occupying the given time range
+    pub fn covers(&self, subtitle_id: i32, start: i32, end: i32) -> bool {
+        match (self.subtitle, self.start, self.end) {
+            (Some(id), _, _) => id == subtitle_id,
+            (None, Some(lock_start), Some(lock_end)) => lock_start < end && start < lock_end,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "subtitle_lock"]
+pub struct NewSubtitleLock {
+    pub project: i32,
+    pub owner: i32,
+    pub subtitle: Option<i32>,
+    pub start: Option<i32>,
+    pub end: Option<i32>,
+    pub expires: i64,
+}
+
+/// A group of subtitle revisions made by one user in one request, which can be undone as a whole
+#[derive(Debug, Clone, Queryable, Serialize, Identifiable, Associations)]
+#[serde(crate = "rocket::serde")]
+#[belongs_to(Project, foreign_key = "project")]
+#[table_name = "operation"]
+#[primary_key(id)]
+pub struct Operation {
+    pub id: i32,
+    pub project: i32,
+    pub user: i32,
+    pub timestamp: i64,
+    pub undone: i32,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "operation"]
+pub struct NewOperation {
+    pub project: i32,
+    pub user: i32,
+    pub timestamp: i64,
+}
+
+/// One change to a subtitle. `before_*` is empty for creations, `after_*` is empty for deletions.
+/// Status changes themselves are tracked separately in `subtitle_status_change`, the status here
+/// is only kept so recreated subtitles get theirs back.
+#[derive(Debug, Clone, Queryable, Serialize, Identifiable, Associations)]
+#[serde(crate = "rocket::serde")]
+#[belongs_to(Project, foreign_key = "project")]
+#[table_name = "subtitle_revision"]
+#[primary_key(id)]
+pub struct SubtitleRevision {
+    pub id: i32,
+    pub project: i32,
+    pub subtitle: i32,
+    pub user: Option<i32>,
+    pub timestamp: i64,
+    pub action: String,
+    pub before_start: Option<i32>,
+    pub before_end: Option<i32>,
+    pub before_text: Option<String>,
+    pub after_start: Option<i32>,
+    pub after_end: Option<i32>,
+    pub after_text: Option<String>,
+    pub operation: Option<i32>,
+    /// Empty in revisions from before statuses were recorded, which count as drafts
+    pub before_status: Option<String>,
+    pub after_status: Option<String>,
+    /// What `subtitle.origin` and `subtitle.confidence` were, so undoing and redoing keeps a
+    /// machine's mark. Empty in revisions from before they were recorded.
+    pub before_origin: Option<String>,
+    pub before_confidence: Option<f64>,
+    pub after_origin: Option<String>,
+    pub after_confidence: Option<f64>,
+}
+
+impl SubtitleRevision {
+    /// The subtitle as it was before this revision, if it existed
+    pub fn before(&self) -> Option<Subtitle> {
+        Some(Subtitle {
+            id: self.subtitle,
+            project: self.project,
+            start: self.before_start?,
+            end: self.before_end?,
+            text: self.before_text.clone()?,
+            status: self.before_status.clone().unwrap_or_else(status::default),
+            origin: self.before_origin.clone(),
+            confidence: self.before_confidence,
+        })
+    }
+
+    /// The subtitle as it was after this revision, if it still existed
+    pub fn after(&self) -> Option<Subtitle> {
+        Some(Subtitle {
+            id: self.subtitle,
+            project: self.project,
+            start: self.after_start?,
+            end: self.after_end?,
+            text: self.after_text.clone()?,
+            status: self.after_status.clone().unwrap_or_else(status::default),
+            origin: self.after_origin.clone(),
+            confidence: self.after_confidence,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "subtitle_revision"]
+pub struct NewSubtitleRevision {
+    pub project: i32,
+    pub subtitle: i32,
+    pub user: Option<i32>,
+    pub timestamp: i64,
+    pub action: String,
+    pub before_start: Option<i32>,
+    pub before_end: Option<i32>,
+    pub before_text: Option<String>,
+    pub after_start: Option<i32>,
+    pub after_end: Option<i32>,
+    pub after_text: Option<String>,
+    pub operation: Option<i32>,
+    pub before_status: Option<String>,
+    pub after_status: Option<String>,
+    pub before_origin: Option<String>,
+    pub before_confidence: Option<f64>,
+    pub after_origin: Option<String>,
+    pub after_confidence: Option<f64>,
+}
+
+impl NewSubtitleRevision {
+    pub fn new(
+        action: &str,
+        user: i32,
+        timestamp: i64,
+        before: Option<&Subtitle>,
+        after: Option<&Subtitle>,
+    ) -> NewSubtitleRevision {
+        let subtitle = before.or(after).expect("revision needs a subtitle");
+        NewSubtitleRevision {
+            project: subtitle.project,
+            subtitle: subtitle.id,
+            user: Some(user),
+            timestamp,
+            action: action.to_string(),
+            before_start: before.map(|s| s.start),
+            before_end: before.map(|s| s.end),
+            before_text: before.map(|s| s.text.clone()),
+            after_start: after.map(|s| s.start),
+            after_end: after.map(|s| s.end),
+            after_text: after.map(|s| s.text.clone()),
+            operation: None,
+            before_status: before.map(|s| s.status.clone()),
+            after_status: after.map(|s| s.status.clone()),
+            before_origin: before.and_then(|s| s.origin.clone()),
+            before_confidence: before.and_then(|s| s.confidence),
+            after_origin: after.and_then(|s| s.origin.clone()),
+            after_confidence: after.and_then(|s| s.confidence),
+        }
+    }
+}
+
+/// A review comment on a project, optionally attached to a subtitle or a time range.
+/// Replies have a `parent` and inherit what their thread is attached to.
+#[derive(Debug, Clone, Queryable, Serialize, Identifiable, Associations)]
+#[serde(crate = "rocket::serde")]
+#[belongs_to(Project, foreign_key = "project")]
+#[table_name = "comment"]
+#[primary_key(id)]
+pub struct Comment {
+    pub id: i32,
+    pub project: i32,
+    pub parent: Option<i32>,
+    pub author: i32,
+    pub subtitle: Option<i32>,
+    pub start: Option<i32>,
+    pub end: Option<i32>,
+    pub text: String,
+    pub created: i64,
+    pub edited: Option<i64>,
+    pub resolved: i32,
+    pub resolved_by: Option<i32>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "comment"]
+pub struct NewComment {
+    pub project: i32,
+    pub parent: Option<i32>,
+    pub author: i32,
+    pub subtitle: Option<i32>,
+    pub start: Option<i32>,
+    pub end: Option<i32>,
+    pub text: String,
+    pub created: i64,
+}
+
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[table_name = "comment_mention"]
+pub struct CommentMention {
+    pub comment: i32,
+    pub user: i32,
+}
+
+#[derive(Debug, Clone, Queryable, Serialize, Identifiable, Associations)]
+#[serde(crate = "rocket::serde")]
+#[belongs_to(Project, foreign_key = "project")]
+#[table_name = "subtitle_status_change"]
+#[primary_key(id)]
+pub struct SubtitleStatusChange {
+    pub id: i32,
+    pub project: i32,
+    pub subtitle: i32,
+    pub user: Option<i32>,
+    pub timestamp: i64,
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "subtitle_status_change"]
+pub struct NewSubtitleStatusChange {
+    pub project: i32,
+    pub subtitle: i32,
+    pub user: Option<i32>,
+    pub timestamp: i64,
+    pub from: String,
+    pub to: String,
 }
 
 #[derive(Debug, Clone, Queryable, Serialize, Identifiable, Associations, Insertable)]
@@ -116,3 +747,22 @@ pub struct Snapshot {
     pub name: Option<String>,
     pub subtitles: String,
 }
+
+/// A cut in a video, in milliseconds
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[table_name = "shot_change"]
+pub struct ShotChange {
+    pub video: i32,
+    pub time: i32,
+}
+
+/// A part of a video with speech in it, in milliseconds
+#[derive(Debug, Clone, Queryable, Insertable, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[table_name = "speech_region"]
+pub struct SpeechRegion {
+    #[serde(skip_serializing)]
+    pub video: i32,
+    pub start: i32,
+    pub end: i32,
+}
@@ -1,11 +1,144 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    access_token (id) {
+        id -> Integer,
+        user -> Integer,
+        name -> Text,
+        token -> Text,
+        scopes -> Text,
+        created -> BigInt,
+        expires -> Nullable<BigInt>,
+        last_used -> Nullable<BigInt>,
+    }
+}
+
+diesel::table! {
+    comment (id) {
+        id -> Integer,
+        project -> Integer,
+        parent -> Nullable<Integer>,
+        author -> Integer,
+        subtitle -> Nullable<Integer>,
+        start -> Nullable<Integer>,
+        end -> Nullable<Integer>,
+        text -> Text,
+        created -> BigInt,
+        edited -> Nullable<BigInt>,
+        resolved -> Integer,
+        resolved_by -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    comment_mention (comment, user) {
+        comment -> Integer,
+        user -> Integer,
+    }
+}
+
+diesel::table! {
+    email_outbox (id) {
+        id -> Integer,
+        recipient -> Text,
+        subject -> Text,
+        body -> Text,
+        created -> BigInt,
+        attempts -> Integer,
+        next_attempt -> BigInt,
+        last_error -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    email_token (id) {
+        id -> Integer,
+        user -> Integer,
+        purpose -> Text,
+        token -> Text,
+        email -> Nullable<Text>,
+        created -> BigInt,
+        expires -> BigInt,
+    }
+}
+
+diesel::table! {
+    invitation (id) {
+        id -> Integer,
+        token -> Text,
+        email -> Nullable<Text>,
+        created_by -> Nullable<Integer>,
+        created -> BigInt,
+        expires -> BigInt,
+        used -> Nullable<BigInt>,
+        used_by -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    login_failure (key) {
+        key -> Text,
+        failures -> Integer,
+        last_failure -> BigInt,
+        locked_until -> Nullable<BigInt>,
+    }
+}
+
+diesel::table! {
+    operation (id) {
+        id -> Integer,
+        project -> Integer,
+        user -> Integer,
+        timestamp -> BigInt,
+        undone -> Integer,
+    }
+}
+
 diesel::table! {
     project (id) {
         id -> Integer,
         workspace -> Integer,
         name -> Text,
         video -> Nullable<Integer>,
+        frame_rate_num -> Nullable<Integer>,
+        frame_rate_den -> Nullable<Integer>,
+        drop_frame -> Integer,
+        snap_to_frames -> Integer,
+    }
+}
+
+diesel::table! {
+    recovery_code (id) {
+        id -> Integer,
+        user -> Integer,
+        code -> Text,
+    }
+}
+
+diesel::table! {
+    session (id) {
+        id -> Integer,
+        token -> Text,
+        user -> Integer,
+        created -> BigInt,
+        last_seen -> BigInt,
+        user_agent -> Nullable<Text>,
+        expires -> BigInt,
+        pending -> Integer,
+    }
+}
+
+diesel::table! {
+    setting (key) {
+        key -> Text,
+        value -> Text,
+    }
+}
+
+diesel::table! {
+    shot_change (video, time) {
+        video -> Integer,
+        time -> Integer,
     }
 }
 
@@ -18,6 +151,14 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    speech_region (video, start) {
+        video -> Integer,
+        start -> Integer,
+        end -> Integer,
+    }
+}
+
 diesel::table! {
     subtitle (id) {
         id -> Integer,
@@ -25,6 +166,57 @@ diesel::table! {
         start -> Integer,
         end -> Integer,
         text -> Text,
+        status -> Text,
+        origin -> Nullable<Text>,
+        confidence -> Nullable<Double>,
+    }
+}
+
+diesel::table! {
+    subtitle_lock (id) {
+        id -> Integer,
+        project -> Integer,
+        owner -> Integer,
+        subtitle -> Nullable<Integer>,
+        start -> Nullable<Integer>,
+        end -> Nullable<Integer>,
+        expires -> BigInt,
+    }
+}
+
+diesel::table! {
+    subtitle_revision (id) {
+        id -> Integer,
+        project -> Integer,
+        subtitle -> Integer,
+        user -> Nullable<Integer>,
+        timestamp -> BigInt,
+        action -> Text,
+        before_start -> Nullable<Integer>,
+        before_end -> Nullable<Integer>,
+        before_text -> Nullable<Text>,
+        after_start -> Nullable<Integer>,
+        after_end -> Nullable<Integer>,
+        after_text -> Nullable<Text>,
+        operation -> Nullable<Integer>,
+        before_status -> Nullable<Text>,
+        after_status -> Nullable<Text>,
+        before_origin -> Nullable<Text>,
+        before_confidence -> Nullable<Double>,
+        after_origin -> Nullable<Text>,
+        after_confidence -> Nullable<Double>,
+    }
+}
+
+diesel::table! {
+    subtitle_status_change (id) {
+        id -> Integer,
+        project -> Integer,
+        subtitle -> Integer,
+        user -> Nullable<Integer>,
+        timestamp -> BigInt,
+        from -> Text,
+        to -> Text,
     }
 }
 
@@ -35,6 +227,13 @@ diesel::table! {
         password -> Text,
         email -> Nullable<Text>,
         display_name -> Nullable<Text>,
+        totp_secret -> Nullable<Text>,
+        totp_enabled -> Integer,
+        totp_last_step -> Nullable<BigInt>,
+        oidc_subject -> Nullable<Text>,
+        email_verified -> Integer,
+        instance_admin -> Integer,
+        disabled -> Integer,
     }
 }
 
@@ -45,6 +244,17 @@ diesel::table! {
         identifier -> Text,
         duration -> Nullable<Integer>,
         waveform -> Nullable<Binary>,
+        title -> Nullable<Text>,
+        channel -> Nullable<Text>,
+        published -> Nullable<BigInt>,
+        language -> Nullable<Text>,
+        frame_rate_num -> Nullable<Integer>,
+        frame_rate_den -> Nullable<Integer>,
+        width -> Nullable<Integer>,
+        height -> Nullable<Integer>,
+        sample_rate -> Nullable<Integer>,
+        shot_changes_detected -> Nullable<BigInt>,
+        speech_detected -> Nullable<BigInt>,
     }
 }
 
@@ -54,6 +264,7 @@ diesel::table! {
         name -> Text,
         owner -> Integer,
         shared -> Integer,
+        require_2fa -> Integer,
     }
 }
 
@@ -65,18 +276,53 @@ diesel::table! {
     }
 }
 
+diesel::joinable!(access_token -> user (user));
+diesel::joinable!(comment -> project (project));
+diesel::joinable!(comment_mention -> comment (comment));
+diesel::joinable!(comment_mention -> user (user));
+diesel::joinable!(email_token -> user (user));
+diesel::joinable!(operation -> project (project));
+diesel::joinable!(operation -> user (user));
 diesel::joinable!(project -> video (video));
 diesel::joinable!(project -> workspace (workspace));
+diesel::joinable!(recovery_code -> user (user));
+diesel::joinable!(session -> user (user));
+diesel::joinable!(shot_change -> video (video));
 diesel::joinable!(snapshot -> project (project));
+diesel::joinable!(speech_region -> video (video));
 diesel::joinable!(subtitle -> project (project));
+diesel::joinable!(subtitle_lock -> project (project));
+diesel::joinable!(subtitle_lock -> subtitle (subtitle));
+diesel::joinable!(subtitle_lock -> user (owner));
+diesel::joinable!(subtitle_revision -> operation (operation));
+diesel::joinable!(subtitle_revision -> project (project));
+diesel::joinable!(subtitle_revision -> user (user));
+diesel::joinable!(subtitle_status_change -> project (project));
+diesel::joinable!(subtitle_status_change -> user (user));
 diesel::joinable!(workspace -> user (owner));
 diesel::joinable!(workspace_member -> user (user));
 diesel::joinable!(workspace_member -> workspace (workspace));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    access_token,
+    comment,
+    comment_mention,
+    email_outbox,
+    email_token,
+    invitation,
+    login_failure,
+    operation,
     project,
+    recovery_code,
+    session,
+    setting,
+    shot_change,
     snapshot,
+    speech_region,
     subtitle,
+    subtitle_lock,
+    subtitle_revision,
+    subtitle_status_change,
     user,
     video,
     workspace,
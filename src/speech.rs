@@ -0,0 +1,355 @@
+//! Speech and silence regions, found in the waveform peaks that audiowaveform generates, and
+//! turning them into cue timings.
+
+/// Peaks of a waveform in the audiowaveform `.dat` format
+pub struct Peaks {
+    /// Loudest sample of each pixel, from 0 to 1
+    pub amplitudes: Vec<f64>,
+    pub pixels_per_second: f64,
+}
+
+impl Peaks {
+    /// Read an audiowaveform `.dat` file, version 1 or 2, with 8 or 16 bit peaks. Channels are
+    /// mixed by taking the loudest one.
+    pub fn parse(data: &[u8]) -> Result<Peaks, &'static str> {
+        let invalid = "Invalid waveform data";
+        let word = |index: usize| -> Result<u32, &'static str> {
+            let bytes = data.get(index * 4..index * 4 + 4).ok_or(invalid)?;
+            Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+        };
+
+        let version = word(0)?;
+        let eight_bit = word(1)? & 1 == 1;
+        let sample_rate = word(2)?;
+        let samples_per_pixel = word(3)?;
+        let length = word(4)? as usize;
+        let (channels, header) = match version {
+            1 => (1, 20),
+            2 => (word(5)? as usize, 24),
+            _ => return Err(invalid),
+        };
+        if sample_rate == 0 || samples_per_pixel == 0 || channels == 0 {
+            return Err(invalid);
+        }
+
+        let sample_size = if eight_bit { 1 } else { 2 };
+        let pixel_size = channels * 2 * sample_size;
+        let body = &data[header..];
+        let length = length.min(body.len() / pixel_size);
+        let amplitudes = body
+            .chunks_exact(pixel_size)
+            .take(length)
+            .map(|pixel| {
+                pixel
+                    .chunks_exact(sample_size)
+                    .map(|sample| match sample {
+                        [byte] => (*byte as i8 as f64 / 128.0).abs(),
+                        _ => (i16::from_le_bytes([sample[0], sample[1]]) as f64 / 32768.0).abs(),
+                    })
+                    .fold(0.0, f64::max)
+            })
+            .collect();
+
+        Ok(Peaks {
+            amplitudes,
+            pixels_per_second: sample_rate as f64 / samples_per_pixel as f64,
+        })
+    }
+
+    fn pixel_ms(&self, pixel: usize) -> i32 {
+        (pixel as f64 * 1000.0 / self.pixels_per_second).round() as i32
+    }
+
+    fn ms_pixels(&self, ms: i32) -> usize {
+        (ms.max(0) as f64 * self.pixels_per_second / 1000.0).round() as usize
+    }
+}
+
+/// When audio counts as speech
+#[derive(Debug, Clone, Copy)]
+pub struct SpeechSettings {
+    /// Audio quieter than this, in dBFS, is silence
+    pub threshold_db: f64,
+    /// Pauses shorter than this many milliseconds don't split speech
+    pub min_silence: i32,
+    /// Sounds shorter than this many milliseconds aren't speech, like clicks
+    pub min_speech: i32,
+}
+
+impl Default for SpeechSettings {
+    fn default() -> Self {
+        SpeechSettings {
+            threshold_db: -35.0,
+            min_silence: 300,
+            min_speech: 200,
+        }
+    }
+}
+
+/// Start and end in milliseconds of the parts of the audio with speech in them
+pub fn detect_speech(peaks: &Peaks, settings: &SpeechSettings) -> Vec<(i32, i32)> {
+    let threshold = 10f64.powf(settings.threshold_db / 20.0);
+
+    // Take the loudest pixel of every 20 ms, so the gaps between syllables don't count as silence
+    let window = peaks.ms_pixels(20).max(1);
+    let loud: Vec<bool> = (0..peaks.amplitudes.len())
+        .map(|pixel| {
+            let from = pixel.saturating_sub(window / 2);
+            let to = (pixel + window / 2 + 1).min(peaks.amplitudes.len());
+            peaks.amplitudes[from..to]
+                .iter()
+                .any(|&peak| peak >= threshold)
+        })
+        .collect();
+
+    let mut regions: Vec<(usize, usize)> = Vec::new();
+    let mut start = None;
+    for (pixel, &loud) in loud.iter().chain([&false]).enumerate() {
+        match (loud, start) {
+            (true, None) => start = Some(pixel),
+            (false, Some(from)) => {
+                regions.push((from, pixel));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+
+    let min_silence = peaks.ms_pixels(settings.min_silence);
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in regions {
+        match merged.last_mut() {
+            Some(last) if start - last.1 < min_silence => last.1 = end,
+            _ => merged.push((start, end)),
+        }
+    }
+
+    let min_speech = peaks.ms_pixels(settings.min_speech);
+    merged
+        .into_iter()
+        .filter(|(start, end)| end - start >= min_speech)
+        .map(|(start, end)| (peaks.pixel_ms(start), peaks.pixel_ms(end)))
+        .collect()
+}
+
+/// Limits for cues made from speech regions, in milliseconds
+#[derive(Debug, Clone, Copy)]
+pub struct CueSettings {
+    pub min_duration: i32,
+    pub max_duration: i32,
+    /// Time between the end of a cue and the start of the next
+    pub min_gap: i32,
+}
+
+impl CueSettings {
+    /// Longest duration or gap that can be asked for, an hour
+    pub const MAX: i32 = 3_600_000;
+}
+
+impl Default for CueSettings {
+    fn default() -> Self {
+        CueSettings {
+            min_duration: 1000,
+            max_duration: 7000,
+            min_gap: 80,
+        }
+    }
+}
+
+/// Cue timings covering the speech regions. Long regions are split into equal parts, short
+/// ones are lengthened, and cues that would overlap one of the `taken` ranges are left out.
+pub fn cues_from_regions(
+    regions: &[(i32, i32)],
+    taken: &[(i32, i32)],
+    settings: &CueSettings,
+) -> Vec<(i32, i32)> {
+    let mut cues: Vec<(i32, i32)> = Vec::new();
+    for &(start, end) in regions {
+        // In i64, and clamped back to the times that can be stored, so huge limits or regions
+        // near the end of the range can't overflow
+        let (start, end) = (start as i64, end as i64);
+        let min_duration = settings.min_duration as i64;
+        let max_duration = settings.max_duration as i64;
+        let min_gap = settings.min_gap as i64;
+        let to_time = |time: i64| time.clamp(0, i32::MAX as i64) as i32;
+
+        let end = end.max(start + min_duration);
+        let length = end - start;
+        let parts = ((length + min_gap + max_duration - 1) / (max_duration + min_gap)).max(1);
+        let part_length = (length - min_gap * (parts - 1)) / parts;
+
+        for part in 0..parts {
+            let mut cue_start = start + part * (part_length + min_gap);
+            if let Some(&(_, previous_end)) = cues.last() {
+                cue_start = cue_start.max(previous_end as i64 + min_gap);
+            }
+            let cue_end = (start + part * (part_length + min_gap) + part_length)
+                .max(cue_start + min_duration);
+            cues.push((to_time(cue_start), to_time(cue_end)));
+        }
+    }
+
+    cues.retain(|&(start, end)| {
+        !taken.iter().any(|&(taken_start, taken_end)| {
+            start < taken_end.saturating_add(settings.min_gap)
+                && taken_start < end.saturating_add(settings.min_gap)
+        })
+    });
+    cues
+}
+
+/// Split a transcript into cue texts, either one per line or one per sentence
+pub fn split_transcript(text: &str, sentences: bool) -> Vec<String> {
+    if !sentences {
+        return text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect();
+    }
+
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        current.push(c);
+        if matches!(c, '.' | '!' | '?' | '…') && chars.peek().is_none_or(|next| *next == ' ') {
+            parts.push(current.trim().to_string());
+            current.clear();
+        }
+    }
+    if !current.trim().is_empty() {
+        parts.push(current.trim().to_string());
+    }
+    parts
+}
+
+/// Limits for aligning a transcript
+#[derive(Debug, Clone, Copy)]
+pub struct AlignmentSettings {
+    /// Characters per second viewers can read
+    pub max_cps: f64,
+    pub cue: CueSettings,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AlignedCue {
+    pub start: i32,
+    pub end: i32,
+    /// From 0 to 1. Lower when the cue doesn't start or end at a pause, or is too short to read.
+    pub confidence: f64,
+}
+
+/// Spread cue texts of the given lengths in characters over the speech regions, proportionally
+/// to their length. Boundaries that fall close to a pause are moved onto it.
+pub fn align_transcript(
+    lengths: &[usize],
+    regions: &[(i32, i32)],
+    settings: &AlignmentSettings,
+) -> Vec<AlignedCue> {
+    let (first, last) = match (regions.first(), regions.last()) {
+        (Some(first), Some(last)) if !lengths.is_empty() => (first, last),
+        _ => return Vec::new(),
+    };
+
+    // Positions on a timeline of just the speech, where the pauses between regions are left out
+    let mut junctions = Vec::new();
+    let mut speech_length = 0i64;
+    for (index, &(start, end)) in regions.iter().enumerate() {
+        if index > 0 {
+            junctions.push((speech_length, index));
+        }
+        speech_length += (end - start) as i64;
+    }
+    let to_time = |position: i64| -> i32 {
+        let mut before = 0i64;
+        for &(start, end) in regions {
+            let length = (end - start) as i64;
+            if position <= before + length {
+                return start + (position - before) as i32;
+            }
+            before += length;
+        }
+        last.1
+    };
+
+    let total_chars: i64 = lengths.iter().map(|&length| length.max(1) as i64).sum();
+    let mut chars_before = 0i64;
+    let positions: Vec<i64> = lengths
+        .iter()
+        .map(|&length| {
+            chars_before += length.max(1) as i64;
+            speech_length * chars_before / total_chars
+        })
+        .collect();
+
+    // Boundaries as (end of the cue before, start of the cue after, whether it's at a pause)
+    let mut boundaries = vec![(first.0, first.0, true)];
+    let mut used_junction = 0;
+    for (index, &position) in positions[..positions.len() - 1].iter().enumerate() {
+        let previous = if index == 0 { 0 } else { positions[index - 1] };
+        let next = positions[index + 1];
+        let tolerance = (position - previous).min(next - position) / 4;
+        let junction = junctions
+            .iter()
+            .filter(|&&(_, region)| region > used_junction)
+            .filter(|&&(junction, _)| (junction - position).abs() <= tolerance)
+            .min_by_key(|&&(junction, _)| (junction - position).abs());
+        match junction {
+            Some(&(_, region)) => {
+                used_junction = region;
+                boundaries.push((regions[region - 1].1, regions[region].0, true));
+            }
+            None => {
+                let time = to_time(position);
+                let half_gap = settings.cue.min_gap / 2;
+                boundaries.push((
+                    time.saturating_sub(half_gap),
+                    time.saturating_add(settings.cue.min_gap - half_gap),
+                    false,
+                ));
+            }
+        }
+    }
+    boundaries.push((last.1, last.1, true));
+
+    let mut cues: Vec<AlignedCue> = Vec::new();
+    for (index, &length) in lengths.iter().enumerate() {
+        let (_, aligned_start, start_at_pause) = boundaries[index];
+        let (aligned_end, next_start, end_at_pause) = boundaries[index + 1];
+        // A cue can run into the pause after it without covering anyone else's speech
+        let free_end = match boundaries.get(index + 2) {
+            Some(_) if end_at_pause => next_start.saturating_sub(settings.cue.min_gap),
+            Some(_) => aligned_end,
+            None => i32::MAX,
+        };
+
+        // Cues that are too short to read are lengthened, which pushes back the cues after them
+        let needed = (settings.cue.min_duration as f64)
+            .max(length as f64 * 1000.0 / settings.max_cps)
+            .ceil()
+            .min(settings.cue.max_duration as f64) as i32;
+        let start = match cues.last() {
+            Some(previous) => aligned_start.max(previous.end.saturating_add(settings.cue.min_gap)),
+            None => aligned_start,
+        };
+        let end = aligned_end
+            .max(start.saturating_add(needed))
+            .min(start.saturating_add(settings.cue.max_duration));
+
+        // How much of the cue is where the text would be going by its length
+        let overlap = end.min(free_end.max(aligned_end)) - start.max(aligned_start);
+        let span = end.max(aligned_end) - start.min(aligned_start);
+        let fit = (overlap.max(0) as f64 / span.max(1) as f64).min(1.0);
+        let at_pause = |at_pause: bool| if at_pause { 1.0 } else { 0.5 };
+        let confidence = (at_pause(start_at_pause) + at_pause(end_at_pause)) / 2.0 * fit;
+        cues.push(AlignedCue {
+            start,
+            end,
+            confidence: (confidence * 100.0).round() / 100.0,
+        });
+    }
+    cues
+}
@@ -0,0 +1,274 @@
+//! Sessions, access tokens and throttled logins
+
+use rocket::http::{Cookie, Header, Method, Status};
+use rocket::serde::json::json;
+
+use super::TestServer;
+use crate::models::*;
+use crate::schema::*;
+use crate::{
+    hash_token, record_login_failure, unix_timestamp, FREE_LOGIN_ATTEMPTS, LOGIN_LOCKOUT_DURATION,
+    LOGIN_LOCKOUT_THRESHOLD,
+};
+use diesel::prelude::*;
+
+async fn profile_status(server: &TestServer, cookie: Option<Cookie<'static>>) -> Status {
+    let mut request = server.client.get("/api/user/profile");
+    if let Some(cookie) = cookie {
+        request = request.private_cookie(cookie);
+    }
+    request.dispatch().await.status()
+}
+
+#[rocket::async_test]
+async fn sessions_can_be_revoked() {
+    let server = TestServer::new().await;
+    let alice = server.register("alice").await;
+    let first = server.client.cookies().get_private("auth").unwrap();
+
+    // The cookie holds a random token, the server only keeps its hash
+    assert_ne!(first.value(), alice.to_string());
+    let token = hash_token(first.value());
+    let owner = server
+        .run(move |conn| {
+            session::table
+                .filter(session::token.eq(token))
+                .select(session::user)
+                .first::<i32>(conn)
+                .unwrap()
+        })
+        .await;
+    assert_eq!(owner, alice);
+    let forged = Cookie::new("auth", alice.to_string());
+    assert_eq!(
+        profile_status(&server, Some(forged)).await,
+        Status::Unauthorized
+    );
+
+    server.login("alice").await;
+    let sessions = server.get_json("/api/session/list").await;
+    let sessions = sessions.as_array().unwrap();
+    assert_eq!(sessions.len(), 2);
+    let other = sessions
+        .iter()
+        .find(|session| session["current"] == false)
+        .unwrap();
+    let path = format!("/api/session/{}", other["id"]);
+    assert_eq!(
+        server.client.delete(path).dispatch().await.status(),
+        Status::Ok
+    );
+    assert_eq!(
+        profile_status(&server, Some(first.into_owned())).await,
+        Status::Unauthorized
+    );
+    assert_eq!(profile_status(&server, None).await, Status::Ok);
+
+    // Logging out everywhere includes this client
+    let current = server.client.cookies().get_private("auth").unwrap();
+    let response = server.client.delete("/api/session/all").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        profile_status(&server, Some(current.into_owned())).await,
+        Status::Unauthorized
+    );
+}
+
+/// An access token with the given scopes, for the logged in user. Returns its id and the token.
+async fn create_token(server: &TestServer, scopes: &[&str]) -> (i64, String) {
+    let (status, created) = server
+        .send_json(
+            Method::Post,
+            "/api/token/create",
+            json!({ "name": "script", "scopes": scopes }),
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+    (
+        created["id"].as_i64().unwrap(),
+        created["token"].as_str().unwrap().to_string(),
+    )
+}
+
+/// Send a request with a bearer token instead of the session cookie
+async fn with_token(server: &TestServer, method: Method, path: &str, token: &str) -> Status {
+    server
+        .client
+        .req(method, path.to_string())
+        .header(Header::new("Authorization", format!("Bearer {}", token)))
+        .body(json!({ "start": 0, "end": 1000, "text": "One" }).to_string())
+        .dispatch()
+        .await
+        .status()
+}
+
+#[rocket::async_test]
+async fn tokens_are_limited_to_their_scopes() {
+    let (server, _, project) = TestServer::with_project(|figment| figment).await;
+    let (read_id, read) = create_token(&server, &[scope::READ]).await;
+    let (_, write) = create_token(&server, &[scope::SUBTITLES_WRITE]).await;
+    let subtitles = format!("/api/project/{}/subtitle/list", project);
+    let create = format!("/api/project/{}/subtitle/create", project);
+
+    assert_eq!(
+        with_token(&server, Method::Get, &subtitles, &read).await,
+        Status::Ok
+    );
+    assert_eq!(
+        with_token(&server, Method::Post, &create, &read).await,
+        Status::Forbidden
+    );
+    assert_eq!(
+        with_token(&server, Method::Post, &create, &write).await,
+        Status::Ok
+    );
+    // Neither can manage the account
+    for token in [&read, &write] {
+        assert_eq!(
+            with_token(&server, Method::Get, "/api/token/list", token).await,
+            Status::Forbidden
+        );
+    }
+    assert_eq!(server.subtitles(project).await.len(), 1);
+
+    let path = format!("/api/token/{}", read_id);
+    assert_eq!(
+        server.client.delete(path).dispatch().await.status(),
+        Status::Ok
+    );
+    assert_eq!(
+        with_token(&server, Method::Get, &subtitles, &read).await,
+        Status::Unauthorized
+    );
+}
+
+/// Try to log in, from the given address if there is one. Returns the status and the message.
+async fn attempt_login(
+    server: &TestServer,
+    name: &str,
+    password: &str,
+    address: Option<&str>,
+) -> (Status, String) {
+    let mut request = server
+        .client
+        .post("/api/login")
+        .body(json!({ "user": name, "password": password }).to_string());
+    if let Some(address) = address {
+        request = request.header(Header::new("X-Real-IP", address.to_string()));
+    }
+    let response = request.dispatch().await;
+    (response.status(), response.into_string().await.unwrap())
+}
+
+async fn failure_keys(server: &TestServer) -> Vec<String> {
+    server
+        .run(|conn| {
+            login_failure::table
+                .select(login_failure::key)
+                .order(login_failure::key)
+                .load::<String>(conn)
+                .unwrap()
+        })
+        .await
+}
+
+#[rocket::async_test]
+async fn failed_logins_dont_tell_users_apart() {
+    let server = TestServer::new().await;
+    server.register("alice").await;
+    let wrong_password = attempt_login(&server, "alice", "password2", None).await;
+    assert_eq!(
+        wrong_password,
+        (
+            Status::Unauthorized,
+            "Username or password incorrect".to_string()
+        )
+    );
+    assert_eq!(
+        attempt_login(&server, "nobody", "password2", None).await,
+        wrong_password
+    );
+}
+
+#[rocket::async_test]
+async fn failed_logins_lock_the_account_until_unlocked() {
+    let server = TestServer::new().await;
+    let bob = server.register("bob").await;
+    let alice = server.register("alice").await;
+    let (workspace, _) = server.create_project(alice).await;
+    server.add_member(workspace, bob, role::MEMBER).await;
+
+    for _ in 0..FREE_LOGIN_ATTEMPTS {
+        let (status, _) = attempt_login(&server, "bob", "password2", None).await;
+        assert_eq!(status, Status::Unauthorized);
+    }
+    // Now attempts have to be spaced out, even with the right password
+    let (status, _) = attempt_login(&server, "bob", "password1", None).await;
+    assert_eq!(status, Status::TooManyRequests);
+
+    // Past the threshold, the account is locked for a while, and so are wrong two-factor codes
+    let locked_until = server
+        .run(move |conn| {
+            let keys = ["user:bob".to_string(), format!("2fa:{}", bob)];
+            for _ in FREE_LOGIN_ATTEMPTS..LOGIN_LOCKOUT_THRESHOLD {
+                record_login_failure(conn, &keys).unwrap();
+            }
+            login_failure::table
+                .find("user:bob")
+                .select(login_failure::locked_until)
+                .first::<Option<i64>>(conn)
+                .unwrap()
+        })
+        .await;
+    assert!(locked_until.unwrap() >= unix_timestamp() + LOGIN_LOCKOUT_DURATION - 5);
+
+    // Alice is still logged in, and can lift it as an admin of a workspace Bob is in
+    let path = format!("/api/workspace/{}/member/bob/unlock", workspace);
+    assert_eq!(
+        server.client.post(path).dispatch().await.status(),
+        Status::Ok
+    );
+    assert!(failure_keys(&server).await.is_empty());
+    let (status, _) = attempt_login(&server, "bob", "password1", None).await;
+    assert_eq!(status, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn addresses_come_from_the_configured_header_only() {
+    // Without `ip_header`, anyone could pick the address their failures count against
+    let server = TestServer::new().await;
+    server.register("alice").await;
+    attempt_login(&server, "nobody", "password2", Some("203.0.113.7")).await;
+    assert_eq!(failure_keys(&server).await, ["user:nobody"]);
+
+    let server = TestServer::with_config(|figment| figment.merge(("ip_header", "X-Real-IP"))).await;
+    let alice = server.register("alice").await;
+    for _ in 0..FREE_LOGIN_ATTEMPTS {
+        attempt_login(&server, "nobody", "password2", Some("203.0.113.7")).await;
+    }
+    assert_eq!(
+        failure_keys(&server).await,
+        ["ip:203.0.113.7", "user:nobody"]
+    );
+    let (status, _) = attempt_login(&server, "alice", "password1", Some("203.0.113.7")).await;
+    assert_eq!(status, Status::TooManyRequests);
+
+    // Unlocking a user leaves the address alone, but logging in from elsewhere works
+    server
+        .run(move |conn| {
+            diesel::update(user::table.find(alice))
+                .set(user::instance_admin.eq(1))
+                .execute(conn)
+                .unwrap()
+        })
+        .await;
+    let path = format!("/api/admin/user/{}/unlock", alice);
+    assert_eq!(
+        server.client.post(path).dispatch().await.status(),
+        Status::Ok
+    );
+    let (status, _) = attempt_login(&server, "alice", "password1", Some("203.0.113.7")).await;
+    assert_eq!(status, Status::TooManyRequests);
+    let (status, _) = attempt_login(&server, "alice", "password1", Some("198.51.100.2")).await;
+    assert_eq!(status, Status::Ok);
+}
@@ -0,0 +1,116 @@
+//! Comment threads, mentions, and the live events members get
+
+use std::time::Duration;
+
+use rocket::http::{Method, Status};
+use rocket::local::asynchronous::LocalResponse;
+use rocket::serde::json::{json, serde_json, Value};
+use rocket::tokio::io::AsyncReadExt;
+use rocket::tokio::time::timeout;
+
+use super::TestServer;
+use crate::models::*;
+
+/// The mentions of each comment of a project
+async fn mentions(server: &TestServer, project: i32) -> Vec<Value> {
+    server
+        .get_json(&format!("/api/project/{}/comment/list", project))
+        .await
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|comment| comment["mentions"].clone())
+        .collect()
+}
+
+#[rocket::async_test]
+async fn only_members_are_mentioned() {
+    let server = TestServer::new().await;
+    let bob = server.register("bob").await;
+    server.register("carol").await;
+    let alice = server.register("alice").await;
+    let (workspace, project) = server.create_project(alice).await;
+    server.add_member(workspace, bob, role::MEMBER).await;
+
+    let (status, comment) = server
+        .send_json(
+            Method::Post,
+            &format!("/api/project/{}/comment/create", project),
+            json!({ "text": "Thanks @bob, and @carol. And @bob!" }),
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(mentions(&server, project).await, [json!(["bob"])]);
+
+    // Editing the text replaces the mentions
+    let (status, _) = server
+        .send_json(
+            Method::Patch,
+            &format!("/api/project/{}/comment/{}", project, comment),
+            json!({ "text": "Thanks @alice" }),
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(mentions(&server, project).await, [json!(["alice"])]);
+}
+
+/// The next event sent on a stream, as its name and data. Heartbeats, which are comments, can
+/// come in between the fields.
+async fn next_event(response: &mut LocalResponse<'_>) -> (String, Value) {
+    let mut received = String::new();
+    let mut buffer = [0u8; 4096];
+    let complete = |received: &str| match received.find("data:") {
+        Some(data) => received[data..].contains("\n\n"),
+        None => false,
+    };
+    while !complete(&received) {
+        let read = timeout(Duration::from_secs(5), response.read(&mut buffer))
+            .await
+            .expect("no event was sent")
+            .unwrap();
+        assert!(read > 0, "the stream ended");
+        received.push_str(std::str::from_utf8(&buffer[..read]).unwrap());
+    }
+    let field = |name: &str| {
+        received
+            .lines()
+            .find_map(|line| line.strip_prefix(name))
+            .unwrap()
+            .to_string()
+    };
+    (
+        field("event:"),
+        serde_json::from_str(&field("data:")).unwrap(),
+    )
+}
+
+#[rocket::async_test]
+async fn events_are_for_members_of_the_project() {
+    let server = TestServer::new().await;
+    server.register("carol").await;
+    let alice = server.register("alice").await;
+    let (_, project) = server.create_project(alice).await;
+    let (_, other_project) = server.create_project(alice).await;
+
+    let mut response = server
+        .client
+        .get(format!("/api/project/{}/events", project))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    server.create_subtitle(other_project, 0, "Elsewhere").await;
+    let subtitle = server.create_subtitle(project, 0, "Here").await;
+
+    let (event, data) = next_event(&mut response).await;
+    assert_eq!(event, "subtitle_create");
+    assert_eq!(data["project"], project);
+    assert_eq!(data["info"]["SubtitleCreate"]["subtitle"], subtitle);
+
+    server.login("carol").await;
+    let response = server
+        .client
+        .get(format!("/api/project/{}/events", project))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::NotFound);
+}
@@ -0,0 +1,317 @@
+//! The schema and the hand-written SQL, which have to work the same on both backends
+
+use rocket::error::ErrorKind;
+use rocket::http::{Method, Status};
+use rocket::local::asynchronous::Client;
+use rocket::serde::json::{json, Value};
+
+use super::{TestDatabase, TestServer};
+#[cfg(feature = "sqlite")]
+use crate::check_foreign_keys;
+use crate::models::role;
+use crate::schema::*;
+use crate::{configure, migration_drift, migrations_fairing, MIGRATION_VERSIONS};
+use diesel::prelude::*;
+
+/// Select every column of each table, which fails if the migrations of the backend and
+/// schema.rs disagree about a name
+macro_rules! select_all_columns {
+    ($conn:expr, $($table:ident),+) => {
+        $(
+            $table::table
+                .select($table::all_columns)
+                .limit(0)
+                .execute($conn)
+                .unwrap_or_else(|e| panic!("{}: {}", stringify!($table), e));
+        )+
+    };
+}
+
+#[rocket::async_test]
+async fn migrations_match_the_schema() {
+    let server = TestServer::new().await;
+    server
+        .run(|conn| {
+            let (pending, unknown) = migration_drift(conn).unwrap();
+            assert!(pending.is_empty(), "pending: {:?}", pending);
+            assert!(unknown.is_empty(), "unknown: {:?}", unknown);
+
+            select_all_columns!(
+                conn,
+                access_token,
+                comment,
+                comment_mention,
+                email_outbox,
+                email_token,
+                invitation,
+                login_failure,
+                operation,
+                project,
+                recovery_code,
+                session,
+                setting,
+                shot_change,
+                snapshot,
+                speech_region,
+                subtitle,
+                subtitle_lock,
+                subtitle_revision,
+                subtitle_status_change,
+                user,
+                video,
+                workspace,
+                workspace_member
+            );
+        })
+        .await;
+}
+
+/// Whether a server that checks the migrations before starting starts on the database
+async fn starts_checked(database: &TestDatabase) -> bool {
+    let rocket = configure(rocket::custom(database.config())).attach(migrations_fairing(true));
+    match Client::tracked(rocket).await {
+        Ok(_) => true,
+        Err(error) => {
+            assert!(
+                matches!(error.kind(), ErrorKind::FailedFairings(_)),
+                "{:?}",
+                error
+            );
+            false
+        }
+    }
+}
+
+#[rocket::async_test]
+async fn checking_migrations_refuses_a_different_database() {
+    let database = TestDatabase::new();
+    assert!(starts_checked(&database).await);
+
+    // Forgetting the latest migration ran makes it pending
+    let latest = MIGRATION_VERSIONS.iter().max().unwrap();
+    let conn = database.connect();
+    diesel::sql_query(format!(
+        "DELETE FROM __diesel_schema_migrations WHERE version = '{}'",
+        latest
+    ))
+    .execute(&conn)
+    .unwrap();
+    assert_eq!(migration_drift(&conn).unwrap().0, [latest.to_string()]);
+    assert!(!starts_checked(&database).await);
+
+    // Like after downgrading
+    diesel::sql_query(format!(
+        "INSERT INTO __diesel_schema_migrations (version) VALUES ('{}'), ('29991231235959')",
+        latest
+    ))
+    .execute(&conn)
+    .unwrap();
+    assert_eq!(
+        migration_drift(&conn).unwrap(),
+        (Vec::new(), vec!["29991231235959".to_string()])
+    );
+    assert!(!starts_checked(&database).await);
+}
+
+#[cfg(feature = "sqlite")]
+#[rocket::async_test]
+async fn pooled_connections_enforce_foreign_keys() {
+    let server = TestServer::new().await;
+    server.run(|conn| check_foreign_keys(conn)).await.unwrap();
+
+    // SQLite leaves them off unless asked
+    let database = TestDatabase::new();
+    let conn = database.connect();
+    assert!(check_foreign_keys(&conn).is_err());
+    diesel::sql_query("PRAGMA foreign_keys = ON")
+        .execute(&conn)
+        .unwrap();
+    assert!(check_foreign_keys(&conn).is_ok());
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn snapshot_cascade_migration_drops_orphans() {
+    use diesel_migrations::{migration_from, migration_paths_in_directory, run_migrations};
+
+    const CASCADE: &str = "20261018210000";
+    let directory = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("migrations");
+    let (before, after): (Vec<_>, Vec<_>) = migration_paths_in_directory(&directory)
+        .unwrap()
+        .into_iter()
+        .map(|entry| migration_from(entry.path()).unwrap())
+        .partition(|migration| migration.version() < CASCADE);
+    assert!(after.iter().any(|migration| migration.version() == CASCADE));
+
+    let database = TestDatabase::unmigrated();
+    let conn = database.connect();
+    run_migrations(&conn, before, &mut std::io::sink()).unwrap();
+    // Foreign keys are off on this connection, like they were on every connection before
+    diesel::sql_query(
+        r#"INSERT INTO "project" ("id", "workspace", "name") VALUES (1, 1, 'Project')"#,
+    )
+    .execute(&conn)
+    .unwrap();
+    diesel::sql_query(
+        r#"INSERT INTO "snapshot" ("project", "timestamp", "subtitles")
+           VALUES (1, 100, '[]'), (2, 100, '[]')"#,
+    )
+    .execute(&conn)
+    .unwrap();
+
+    run_migrations(&conn, after, &mut std::io::sink()).unwrap();
+    let snapshots = snapshot::table
+        .select(snapshot::project)
+        .load::<i32>(&conn)
+        .unwrap();
+    assert_eq!(snapshots, [1]);
+
+    diesel::sql_query("PRAGMA foreign_keys = ON")
+        .execute(&conn)
+        .unwrap();
+    diesel::delete(project::table.find(1))
+        .execute(&conn)
+        .unwrap();
+    let snapshots = snapshot::table.count().get_result::<i64>(&conn).unwrap();
+    assert_eq!(snapshots, 0);
+}
+
+/// Alice owns a workspace with a project that has a bit of everything, and Bob is a member.
+/// The client is logged in as Alice. Returns the ids of Alice, Bob, the workspace and the
+/// project.
+async fn populate(server: &TestServer) -> (i32, i32, i32, i32) {
+    let bob = server.register("bob").await;
+    let alice = server.register("alice").await;
+    let (workspace, project) = server.create_project(alice).await;
+    server.add_member(workspace, bob, role::MEMBER).await;
+
+    for (start, text) in [(0, "One"), (1000, "Two")] {
+        let (status, _) = server
+            .send_json(
+                Method::Post,
+                &format!("/api/project/{}/subtitle/create", project),
+                json!({ "start": start, "end": start + 800, "text": text }),
+            )
+            .await;
+        assert_eq!(status, Status::Ok);
+    }
+    let (status, _) = server
+        .send_json(
+            Method::Post,
+            &format!("/api/project/{}/comment/create", project),
+            json!({ "text": "Looks good @bob" }),
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+    let response = server
+        .client
+        .post(format!("/api/project/{}/snapshot/create", project))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    server
+        .run(move |conn| {
+            let video = insert_returning_id!(
+                conn,
+                diesel::insert_into(video::table).values((
+                    video::source.eq("youtube"),
+                    video::identifier.eq("dQw4w9WgXcQ"),
+                    video::waveform.eq(Some(vec![0u8; 300])),
+                )),
+                video::id
+            )
+            .unwrap();
+            diesel::update(project::table.find(project))
+                .set(project::video.eq(Some(video)))
+                .execute(conn)
+                .unwrap();
+        })
+        .await;
+
+    (alice, bob, workspace, project)
+}
+
+fn find<'a>(list: &'a Value, key: &str, value: Value) -> &'a Value {
+    list.as_array()
+        .unwrap()
+        .iter()
+        .find(|item| item[key] == value)
+        .unwrap_or_else(|| panic!("no {} {} in {}", key, value, list))
+}
+
+#[rocket::async_test]
+async fn admin_lists_count_per_row() {
+    let server = TestServer::new().await;
+    let (alice, _, workspace, project) = populate(&server).await;
+    server
+        .run(move |conn| {
+            diesel::update(user::table.find(alice))
+                .set(user::instance_admin.eq(1))
+                .execute(conn)
+                .unwrap()
+        })
+        .await;
+
+    let users = server.get_json("/api/admin/user/list").await;
+    let alice = find(&users, "username", json!("alice"));
+    assert_eq!(alice["owned_workspaces"], 1);
+    assert_eq!(alice["memberships"], 1);
+    assert_eq!(alice["sessions"], 1);
+    let bob = find(&users, "username", json!("bob"));
+    assert_eq!(bob["owned_workspaces"], 0);
+    assert_eq!(bob["memberships"], 1);
+    assert_eq!(bob["sessions"], 1);
+
+    let workspaces = server.get_json("/api/admin/workspace/list").await;
+    let workspace = find(&workspaces, "id", json!(workspace));
+    assert_eq!(workspace["owner"], "alice");
+    assert_eq!(workspace["members"], 2);
+    assert_eq!(workspace["projects"], 1);
+
+    let projects = server.get_json("/api/admin/project/list").await;
+    let project = find(&projects, "id", json!(project));
+    assert_eq!(project["subtitles"], 2);
+    assert_eq!(project["revisions"], 2);
+    assert_eq!(project["comments"], 1);
+    assert_eq!(project["snapshots"], 1);
+    assert!(project["snapshot_bytes"].as_i64().unwrap() > 0);
+    assert_eq!(project["waveform_bytes"], 300);
+}
+
+#[rocket::async_test]
+async fn deleting_an_account_deletes_its_workspaces() {
+    let server = TestServer::new().await;
+    let (_, bob, workspace, project) = populate(&server).await;
+
+    let (status, result) = server
+        .send_json(
+            Method::Delete,
+            "/api/user",
+            json!({ "password": "password1", "owned_workspaces": "delete" }),
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(result["deleted_workspaces"], json!([workspace]));
+
+    let (left, bob_exists) = server
+        .run(move |conn| {
+            let count = |rows: QueryResult<i64>| rows.unwrap();
+            let left = [
+                count(workspace::table.count().get_result(conn)),
+                count(workspace_member::table.count().get_result(conn)),
+                count(project::table.count().get_result(conn)),
+                count(subtitle::table.count().get_result(conn)),
+                count(subtitle_revision::table.count().get_result(conn)),
+                count(operation::table.count().get_result(conn)),
+                count(comment::table.count().get_result(conn)),
+                count(comment_mention::table.count().get_result(conn)),
+                count(snapshot::table.count().get_result(conn)),
+            ];
+            let bob_exists = user::table.find(bob).count().get_result::<i64>(conn);
+            (left, bob_exists.unwrap())
+        })
+        .await;
+    assert_eq!(left, [0; 9], "rows left of project {}", project);
+    assert_eq!(bob_exists, 1);
+}
@@ -0,0 +1,367 @@
+//! The email outbox, delivered to a local SMTP sink
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rocket::http::Status;
+use rocket::serde::json::json;
+use rocket::tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use rocket::tokio::net::TcpListener;
+use rocket::tokio::task;
+use rocket::tokio::time::sleep;
+
+use super::TestServer;
+use crate::email::Mailer;
+use crate::models::*;
+use crate::schema::*;
+use crate::{unix_timestamp, EMAIL_MAX_ATTEMPTS, EMAIL_RETRY_DELAY};
+use diesel::prelude::*;
+
+/// Accepts every email, after turning away the first `failures` delivery attempts with a
+/// temporary error
+struct SmtpSink {
+    port: u16,
+    messages: Arc<Mutex<Vec<String>>>,
+}
+
+impl SmtpSink {
+    async fn start(failures: usize) -> SmtpSink {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let failures = Arc::new(Mutex::new(failures));
+
+        let received = messages.clone();
+        task::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let received = received.clone();
+                let failures = failures.clone();
+                task::spawn(async move {
+                    let (reader, mut writer) = stream.into_split();
+                    let mut lines = BufReader::new(reader).lines();
+                    let _ = writer.write_all(b"220 sink ESMTP\r\n").await;
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let command = line.to_ascii_uppercase();
+                        let reply: &[u8] = if command.starts_with("MAIL FROM") {
+                            let mut failures = failures.lock().unwrap();
+                            if *failures > 0 {
+                                *failures -= 1;
+                                b"451 Try again later\r\n"
+                            } else {
+                                b"250 OK\r\n"
+                            }
+                        } else if command == "DATA" {
+                            let _ = writer.write_all(b"354 Go ahead\r\n").await;
+                            let mut message = String::new();
+                            while let Ok(Some(line)) = lines.next_line().await {
+                                if line == "." {
+                                    break;
+                                }
+                                message.push_str(&line);
+                                message.push('\n');
+                            }
+                            received.lock().unwrap().push(decode_message(&message));
+                            b"250 Queued\r\n"
+                        } else if command == "QUIT" {
+                            let _ = writer.write_all(b"221 Bye\r\n").await;
+                            break;
+                        } else {
+                            b"250 OK\r\n"
+                        };
+                        let _ = writer.write_all(reply).await;
+                    }
+                });
+            }
+        });
+
+        SmtpSink { port, messages }
+    }
+
+    fn messages(&self) -> Vec<String> {
+        self.messages.lock().unwrap().clone()
+    }
+
+    async fn server(&self) -> TestServer {
+        let port = self.port;
+        TestServer::with_config(|figment| {
+            figment
+                .merge(("email.smtp_host", "127.0.0.1"))
+                .merge(("email.smtp_port", port))
+                .merge(("email.smtp_security", "none"))
+                .merge(("email.from", "Uptitle <noreply@uptitle.example>"))
+                .merge(("email.public_url", "https://uptitle.example/"))
+        })
+        .await
+    }
+
+    /// Wait for the worker to deliver the `count`th email, and return it
+    async fn wait_for(&self, count: usize) -> String {
+        for _ in 0..200 {
+            if let Some(message) = self.messages().get(count - 1) {
+                return message.clone();
+            }
+            sleep(Duration::from_millis(25)).await;
+        }
+        panic!("email {} was not delivered", count);
+    }
+}
+
+/// Undo the quoted-printable encoding of the body, like a mail client would
+fn decode_message(message: &str) -> String {
+    let (head, body) = message.split_once("\n\n").unwrap_or((message, ""));
+    if !head.contains("Content-Transfer-Encoding: quoted-printable") {
+        return message.to_string();
+    }
+
+    let body = body.replace("=\n", "");
+    let mut decoded = Vec::new();
+    let mut bytes = body.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte == b'=' {
+            let hex = [bytes.next().unwrap(), bytes.next().unwrap()];
+            decoded.push(u8::from_str_radix(std::str::from_utf8(&hex).unwrap(), 16).unwrap());
+        } else {
+            decoded.push(byte);
+        }
+    }
+    format!("{}\n\n{}", head, String::from_utf8(decoded).unwrap())
+}
+
+/// The token in the link of an email
+fn link_token(message: &str) -> String {
+    let start = message.find("?token=").expect("no link in the email") + "?token=".len();
+    message[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_hexdigit())
+        .collect()
+}
+
+async fn outbox(server: &TestServer) -> Vec<OutboxEmail> {
+    server
+        .run(|conn| email_outbox::table.load::<OutboxEmail>(conn).unwrap())
+        .await
+}
+
+async fn set_verified_email(server: &TestServer, user_id: i32, email: &'static str) {
+    server
+        .run(move |conn| {
+            diesel::update(user::table.find(user_id))
+                .set((user::email.eq(Some(email)), user::email_verified.eq(1)))
+                .execute(conn)
+                .unwrap()
+        })
+        .await;
+}
+
+async fn request_reset(server: &TestServer, user: &str) {
+    let response = server
+        .client
+        .post("/api/password/reset/request")
+        .body(json!({ "user": user }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+}
+
+async fn post(server: &TestServer, path: &str, body: rocket::serde::json::Value) -> Status {
+    server
+        .client
+        .post(path.to_string())
+        .body(body.to_string())
+        .dispatch()
+        .await
+        .status()
+}
+
+#[rocket::async_test]
+async fn password_reset() {
+    let sink = SmtpSink::start(0).await;
+    let server = sink.server().await;
+    let alice = server.register("alice").await;
+    set_verified_email(&server, alice, "alice@example.com").await;
+    server.client.post("/api/logout").dispatch().await;
+
+    request_reset(&server, "Alice@Example.com").await;
+    let message = sink.wait_for(1).await;
+    assert!(message.contains("To: alice@example.com"));
+    assert!(message.contains("Subject: Reset your Uptitle password"));
+    assert!(message.contains("https://uptitle.example/reset-password?token="));
+    let token = link_token(&message);
+    assert_eq!(token.len(), 64);
+
+    // Asking again right away doesn't send another link
+    request_reset(&server, "alice").await;
+    sleep(Duration::from_millis(200)).await;
+    assert_eq!(sink.messages().len(), 1);
+    assert!(outbox(&server).await.is_empty());
+
+    let reset = json!({ "token": token, "password": "new password" });
+    assert_eq!(
+        post(&server, "/api/password/reset", reset.clone()).await,
+        Status::Ok
+    );
+    assert_eq!(
+        post(&server, "/api/password/reset", reset).await,
+        Status::BadRequest
+    );
+    let login = |password| json!({ "user": "alice", "password": password });
+    assert_eq!(
+        post(&server, "/api/login", login("password1")).await,
+        Status::Unauthorized
+    );
+    assert_eq!(
+        post(&server, "/api/login", login("new password")).await,
+        Status::Ok
+    );
+}
+
+#[rocket::async_test]
+async fn password_reset_needs_a_confirmed_address() {
+    let sink = SmtpSink::start(0).await;
+    let server = sink.server().await;
+    let alice = server.register("alice").await;
+    set_verified_email(&server, alice, "alice@example.com").await;
+    server
+        .run(move |conn| {
+            diesel::update(user::table.find(alice))
+                .set(user::email_verified.eq(0))
+                .execute(conn)
+                .unwrap()
+        })
+        .await;
+
+    request_reset(&server, "alice").await;
+    request_reset(&server, "nobody").await;
+    sleep(Duration::from_millis(200)).await;
+    assert!(sink.messages().is_empty());
+    assert!(outbox(&server).await.is_empty());
+}
+
+#[rocket::async_test]
+async fn email_verification() {
+    let sink = SmtpSink::start(0).await;
+    let server = sink.server().await;
+    server.register("bob").await;
+
+    let set_email = |email| {
+        server
+            .client
+            .patch("/api/user/profile")
+            .body(json!({ "email": email }).to_string())
+            .dispatch()
+    };
+    assert_eq!(set_email("old@example.com").await.status(), Status::Ok);
+    let old_token = link_token(&sink.wait_for(1).await);
+    assert_eq!(set_email("Bob@Example.com").await.status(), Status::Ok);
+    let message = sink.wait_for(2).await;
+    assert!(message.contains("To: bob@example.com"));
+    assert!(message.contains("https://uptitle.example/verify-email?token="));
+    let token = link_token(&message);
+
+    // Only the link for the current address works
+    let verify = |token| json!({ "token": token });
+    assert_eq!(
+        post(&server, "/api/user/email/verify", verify(old_token)).await,
+        Status::BadRequest
+    );
+    assert_eq!(
+        post(&server, "/api/user/email/verify", verify(token.clone())).await,
+        Status::Ok
+    );
+    assert_eq!(
+        post(&server, "/api/user/email/verify", verify(token)).await,
+        Status::BadRequest
+    );
+
+    let user = server
+        .run(|conn| {
+            user::table
+                .filter(user::username.eq("bob"))
+                .first::<User>(conn)
+                .unwrap()
+        })
+        .await;
+    assert_eq!(user.email.as_deref(), Some("bob@example.com"));
+    assert_eq!(user.email_verified, 1);
+}
+
+#[rocket::async_test]
+async fn failed_emails_are_retried() {
+    let sink = SmtpSink::start(1).await;
+    let server = sink.server().await;
+    let alice = server.register("alice").await;
+    set_verified_email(&server, alice, "alice@example.com").await;
+
+    let before = unix_timestamp();
+    request_reset(&server, "alice").await;
+    let mut failed = None;
+    for _ in 0..200 {
+        failed = outbox(&server)
+            .await
+            .into_iter()
+            .find(|email| email.attempts == 1);
+        if failed.is_some() {
+            break;
+        }
+        sleep(Duration::from_millis(25)).await;
+    }
+    let failed = failed.expect("the first attempt should fail");
+    assert!(failed.last_error.unwrap().contains("Try again later"));
+    assert!(failed.next_attempt >= before + EMAIL_RETRY_DELAY);
+    assert!(sink.messages().is_empty());
+
+    // Make the retry due instead of waiting for it
+    server
+        .run(move |conn| {
+            diesel::update(email_outbox::table.find(failed.id))
+                .set(email_outbox::next_attempt.eq(0))
+                .execute(conn)
+                .unwrap()
+        })
+        .await;
+    let mailer = server.client.rocket().state::<Option<Arc<Mailer>>>();
+    mailer.unwrap().as_ref().unwrap().wake();
+    assert!(sink.wait_for(1).await.contains("To: alice@example.com"));
+    for _ in 0..200 {
+        if outbox(&server).await.is_empty() {
+            return;
+        }
+        sleep(Duration::from_millis(25)).await;
+    }
+    panic!("sent emails should leave the outbox");
+}
+
+#[rocket::async_test]
+async fn emails_are_given_up_after_too_many_attempts() {
+    let sink = SmtpSink::start(0).await;
+    let server = sink.server().await;
+    server
+        .run(|conn| {
+            for (recipient, attempts) in [
+                ("given-up@example.com", EMAIL_MAX_ATTEMPTS),
+                ("retried@example.com", EMAIL_MAX_ATTEMPTS - 1),
+            ] {
+                diesel::insert_into(email_outbox::table)
+                    .values((
+                        email_outbox::recipient.eq(recipient),
+                        email_outbox::subject.eq("Hello"),
+                        email_outbox::body.eq("Hello"),
+                        email_outbox::created.eq(0),
+                        email_outbox::attempts.eq(attempts),
+                        email_outbox::next_attempt.eq(0),
+                    ))
+                    .execute(conn)
+                    .unwrap();
+            }
+        })
+        .await;
+    let mailer = server.client.rocket().state::<Option<Arc<Mailer>>>();
+    mailer.unwrap().as_ref().unwrap().wake();
+
+    assert!(sink.wait_for(1).await.contains("To: retried@example.com"));
+    sleep(Duration::from_millis(200)).await;
+    assert_eq!(sink.messages().len(), 1);
+    let left = outbox(&server).await;
+    assert_eq!(left.len(), 1);
+    assert_eq!(left[0].recipient, "given-up@example.com");
+}
@@ -0,0 +1,147 @@
+//! The edit history of single subtitles, and reverting to an earlier revision
+
+use rocket::http::{Method, Status};
+use rocket::serde::json::{json, Value};
+
+use super::TestServer;
+use crate::models::*;
+
+async fn edit(server: &TestServer, project: i32, subtitle: i32, text: &str) {
+    let (status, _) = server
+        .send_json(
+            Method::Patch,
+            &format!("/api/project/{}/subtitle/{}", project, subtitle),
+            json!({ "text": text }),
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+}
+
+async fn history(server: &TestServer, project: i32, subtitle: i32) -> Vec<Value> {
+    server
+        .get_json(&format!(
+            "/api/project/{}/subtitle/{}/history",
+            project, subtitle
+        ))
+        .await
+        .as_array()
+        .unwrap()
+        .clone()
+}
+
+async fn revert(server: &TestServer, project: i32, subtitle: i32, revision: &Value) -> Status {
+    let path = format!(
+        "/api/project/{}/subtitle/{}/history/{}/revert",
+        project, subtitle, revision["id"]
+    );
+    server.client.post(path).dispatch().await.status()
+}
+
+async fn text_of(server: &TestServer, project: i32, subtitle: i32) -> Option<String> {
+    server
+        .subtitles(project)
+        .await
+        .into_iter()
+        .find(|s| s.id == subtitle)
+        .map(|s| s.text)
+}
+
+#[rocket::async_test]
+async fn history_shows_who_changed_what() {
+    let server = TestServer::new().await;
+    let bob = server.register("bob").await;
+    let alice = server.register("alice").await;
+    let (workspace, project) = server.create_project(alice).await;
+    server.add_member(workspace, bob, role::MEMBER).await;
+    let subtitle = server.create_subtitle(project, 0, "One").await;
+    edit(&server, project, subtitle, "Two").await;
+    server.login("bob").await;
+    edit(&server, project, subtitle, "Three").await;
+
+    let revisions: Vec<(String, String, Value, Value)> = history(&server, project, subtitle)
+        .await
+        .into_iter()
+        .map(|revision| {
+            (
+                revision["action"].as_str().unwrap().to_string(),
+                revision["user_name"].as_str().unwrap().to_string(),
+                revision["before"]["text"].clone(),
+                revision["after"]["text"].clone(),
+            )
+        })
+        .collect();
+    let revision = |action: &str, user: &str, before: Value, after: Value| {
+        (action.to_string(), user.to_string(), before, after)
+    };
+    assert_eq!(
+        revisions,
+        [
+            revision("create", "alice", Value::Null, json!("One")),
+            revision("edit", "alice", json!("One"), json!("Two")),
+            revision("edit", "bob", json!("Two"), json!("Three")),
+        ]
+    );
+
+    // Members of other workspaces can't see it
+    server.register("carol").await;
+    let path = format!("/api/project/{}/subtitle/{}/history", project, subtitle);
+    let response = server.client.get(path).dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn reverting_brings_back_a_revision() {
+    let server = TestServer::new().await;
+    let bob = server.register("bob").await;
+    let alice = server.register("alice").await;
+    let (workspace, project) = server.create_project(alice).await;
+    server.add_member(workspace, bob, role::MEMBER).await;
+    let subtitle = server.create_subtitle(project, 0, "One").await;
+    edit(&server, project, subtitle, "Two").await;
+
+    let revisions = history(&server, project, subtitle).await;
+    assert_eq!(
+        revert(&server, project, subtitle, &revisions[0]).await,
+        Status::Ok
+    );
+    assert_eq!(text_of(&server, project, subtitle).await.unwrap(), "One");
+    let revisions = history(&server, project, subtitle).await;
+    assert_eq!(revisions.len(), 3);
+    assert_eq!(revisions[2]["action"], "revert");
+
+    // A deleted subtitle comes back with its old id
+    let path = format!("/api/project/{}/subtitle/{}", project, subtitle);
+    assert_eq!(
+        server.client.delete(path).dispatch().await.status(),
+        Status::Ok
+    );
+    assert_eq!(text_of(&server, project, subtitle).await, None);
+    let revisions = history(&server, project, subtitle).await;
+    let deletion = revisions.last().unwrap();
+    assert_eq!(
+        revert(&server, project, subtitle, deletion).await,
+        Status::BadRequest
+    );
+    assert_eq!(
+        revert(&server, project, subtitle, &revisions[1]).await,
+        Status::Ok
+    );
+    assert_eq!(text_of(&server, project, subtitle).await.unwrap(), "Two");
+
+    // Not while someone else has it locked
+    server.login("bob").await;
+    let (status, _) = server
+        .send_json(
+            Method::Post,
+            &format!("/api/project/{}/lock/create", project),
+            json!({ "subtitle": subtitle }),
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+    server.login("alice").await;
+    assert_eq!(
+        revert(&server, project, subtitle, &revisions[0]).await,
+        Status::Locked
+    );
+    assert_eq!(text_of(&server, project, subtitle).await.unwrap(), "Two");
+}
@@ -0,0 +1,140 @@
+//! Locks that keep other members from changing subtitles someone is working on
+
+use rocket::http::{Method, Status};
+use rocket::serde::json::{json, Value};
+
+use super::TestServer;
+use crate::models::*;
+
+async fn lock(server: &TestServer, project: i32, body: Value) -> i32 {
+    let (status, id) = server
+        .send_json(
+            Method::Post,
+            &format!("/api/project/{}/lock/create", project),
+            body,
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+    id.as_i64().unwrap() as i32
+}
+
+async fn edit(server: &TestServer, project: i32, subtitle: i32, body: Value) -> Status {
+    let (status, _) = server
+        .send_json(
+            Method::Patch,
+            &format!("/api/project/{}/subtitle/{}", project, subtitle),
+            body,
+        )
+        .await;
+    status
+}
+
+async fn delete(server: &TestServer, path: String) -> Status {
+    server.client.delete(path).dispatch().await.status()
+}
+
+/// Alice owns a project with two subtitles, and Bob is a member. Alice is logged in. Returns
+/// the project and the subtitles.
+async fn setup() -> (TestServer, i32, i32, i32) {
+    let server = TestServer::new().await;
+    let bob = server.register("bob").await;
+    let alice = server.register("alice").await;
+    let (workspace, project) = server.create_project(alice).await;
+    server.add_member(workspace, bob, role::MEMBER).await;
+    let first = server.create_subtitle(project, 0, "One").await;
+    let second = server.create_subtitle(project, 5000, "Two").await;
+    (server, project, first, second)
+}
+
+#[rocket::async_test]
+async fn locked_subtitles_cant_be_changed_by_others() {
+    let (server, project, first, second) = setup().await;
+    let subtitle_lock = lock(&server, project, json!({ "subtitle": first })).await;
+    let range_lock = lock(&server, project, json!({ "start": 10000, "end": 20000 })).await;
+    // The locks don't get in the way of the one holding them
+    assert_eq!(
+        edit(&server, project, first, json!({ "text": "Uno" })).await,
+        Status::Ok
+    );
+
+    server.login("bob").await;
+    assert_eq!(
+        edit(&server, project, first, json!({ "text": "Eins" })).await,
+        Status::Locked
+    );
+    let path = format!("/api/project/{}/subtitle/{}", project, first);
+    assert_eq!(delete(&server, path).await, Status::Locked);
+    // Nor can a subtitle be moved into a locked range
+    assert_eq!(
+        edit(
+            &server,
+            project,
+            second,
+            json!({ "start": 12000, "end": 13000 })
+        )
+        .await,
+        Status::Locked
+    );
+    assert_eq!(
+        edit(&server, project, second, json!({ "text": "Zwei" })).await,
+        Status::Ok
+    );
+
+    // Only admins can break someone else's lock
+    let path = format!("/api/project/{}/lock/{}", project, subtitle_lock);
+    assert_eq!(delete(&server, path).await, Status::Forbidden);
+    let texts: Vec<String> = server
+        .subtitles(project)
+        .await
+        .into_iter()
+        .map(|subtitle| subtitle.text)
+        .collect();
+    assert_eq!(texts, ["Uno", "Zwei"]);
+
+    server.login("alice").await;
+    let path = format!("/api/project/{}/lock/{}", project, range_lock);
+    assert_eq!(delete(&server, path).await, Status::Ok);
+    server.login("bob").await;
+    assert_eq!(
+        edit(
+            &server,
+            project,
+            second,
+            json!({ "start": 12000, "end": 13000 })
+        )
+        .await,
+        Status::Ok
+    );
+}
+
+#[rocket::async_test]
+async fn admins_can_break_locks() {
+    let (server, project, first, _) = setup().await;
+    server.login("bob").await;
+    let bobs_lock = lock(&server, project, json!({ "subtitle": first })).await;
+
+    server.login("alice").await;
+    let (status, _) = server
+        .send_json(
+            Method::Post,
+            &format!("/api/project/{}/lock/create", project),
+            json!({ "subtitle": first }),
+        )
+        .await;
+    assert_eq!(status, Status::Locked);
+    assert_eq!(
+        edit(&server, project, first, json!({ "text": "Uno" })).await,
+        Status::Locked
+    );
+
+    let path = format!("/api/project/{}/lock/{}", project, bobs_lock);
+    assert_eq!(delete(&server, path).await, Status::Ok);
+    assert_eq!(
+        edit(&server, project, first, json!({ "text": "Uno" })).await,
+        Status::Ok
+    );
+    let locks = server
+        .get_json(&format!("/api/project/{}/lock/list", project))
+        .await;
+    assert_eq!(locks, json!([]));
+}
@@ -0,0 +1,446 @@
+//! Tests that run the server in-process against a fresh database, and stand-ins for the
+//! services it talks to.
+//!
+//! With the `postgres` feature, `UPTITLE_TEST_DATABASE_URL` has to point to a database the
+//! tests can create schemas in. Every test gets its own schema, which is dropped afterwards.
+
+mod auth;
+mod comments;
+mod database;
+mod email;
+mod history;
+mod locks;
+mod oidc;
+mod review;
+mod shot_changes;
+mod speech;
+mod transcription;
+mod translation;
+mod undo;
+mod video;
+mod workspaces;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use rocket::figment::Figment;
+use rocket::http::{Method, Status};
+use rocket::local::asynchronous::{Client, LocalResponse};
+use rocket::serde::json::{json, serde_json, Value};
+use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+use rocket::tokio::net::{TcpListener, TcpStream};
+use rocket::tokio::task;
+use rocket::Config;
+
+use crate::db::DbConnection;
+use crate::models::{role, Subtitle};
+use crate::schema::*;
+use crate::{configure, embedded_migrations, DbConn};
+use diesel::prelude::*;
+
+static DATABASE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn database_name() -> String {
+    format!(
+        "uptitle_test_{}_{}",
+        std::process::id(),
+        DATABASE_COUNTER.fetch_add(1, Ordering::SeqCst)
+    )
+}
+
+/// A database that only lives as long as the test
+#[cfg(feature = "sqlite")]
+pub struct TestDatabase {
+    pub url: String,
+}
+
+#[cfg(feature = "sqlite")]
+impl TestDatabase {
+    pub fn new() -> TestDatabase {
+        let database = TestDatabase::unmigrated();
+        database.migrate();
+        database
+    }
+
+    /// An empty database, for running migrations on
+    pub fn unmigrated() -> TestDatabase {
+        let path = std::env::temp_dir().join(format!("{}.sqlite", database_name()));
+        TestDatabase {
+            url: path.to_string_lossy().into_owned(),
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Drop for TestDatabase {
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", self.url, suffix));
+        }
+    }
+}
+
+/// A database that only lives as long as the test
+#[cfg(feature = "postgres")]
+pub struct TestDatabase {
+    pub url: String,
+    base_url: String,
+    schema: String,
+}
+
+#[cfg(feature = "postgres")]
+impl TestDatabase {
+    pub fn new() -> TestDatabase {
+        let base_url = std::env::var("UPTITLE_TEST_DATABASE_URL")
+            .expect("set UPTITLE_TEST_DATABASE_URL to run the tests against PostgreSQL");
+        let schema = database_name();
+        let conn = diesel::pg::PgConnection::establish(&base_url)
+            .expect("could not connect to the test database");
+        diesel::sql_query(format!("CREATE SCHEMA \"{}\"", schema))
+            .execute(&conn)
+            .expect("could not create the test schema");
+
+        // Every pooled connection starts out in the test schema
+        let separator = if base_url.contains('?') { '&' } else { '?' };
+        let database = TestDatabase {
+            url: format!(
+                "{}{}options=-c%20search_path%3D{}",
+                base_url, separator, schema
+            ),
+            base_url,
+            schema,
+        };
+        database.migrate();
+        database
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl Drop for TestDatabase {
+    fn drop(&mut self) {
+        if let Ok(conn) = diesel::pg::PgConnection::establish(&self.base_url) {
+            let _ = diesel::sql_query(format!("DROP SCHEMA \"{}\" CASCADE", self.schema))
+                .execute(&conn);
+        }
+    }
+}
+
+impl TestDatabase {
+    fn migrate(&self) {
+        embedded_migrations::run(&self.connect()).expect("could not migrate the test database");
+    }
+
+    /// A connection of its own, outside of any server's pool
+    pub fn connect(&self) -> DbConnection {
+        DbConnection::establish(&self.url).expect("could not open the test database")
+    }
+
+    /// Configuration for a server using the database
+    pub fn config(&self) -> Figment {
+        Figment::from(Config::debug_default())
+            .merge(("log_level", "off"))
+            .merge(("databases.diesel.url", &self.url))
+            .merge(("databases.diesel.pool_size", 4))
+    }
+}
+
+/// The server with its own database. `Rocket.toml` and the environment are ignored, tests add
+/// the configuration they need.
+pub struct TestServer {
+    pub client: Client,
+    // Dropped after the client has closed its connections
+    _database: TestDatabase,
+}
+
+impl TestServer {
+    pub async fn new() -> TestServer {
+        TestServer::with_config(|figment| figment).await
+    }
+
+    pub async fn with_config(config: impl FnOnce(Figment) -> Figment) -> TestServer {
+        let database = TestDatabase::new();
+        let rocket = configure(rocket::custom(config(database.config())));
+        TestServer {
+            client: Client::tracked(rocket)
+                .await
+                .expect("the server should start"),
+            _database: database,
+        }
+    }
+
+    /// Run something on a database connection of the server
+    pub async fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut DbConnection) -> T + Send + 'static,
+    ) -> T {
+        DbConn::get_one(self.client.rocket())
+            .await
+            .expect("no database connection")
+            .run(f)
+            .await
+    }
+
+    /// A server with Alice logged in and a project she owns. Returns her id and the project.
+    pub async fn with_project(config: impl FnOnce(Figment) -> Figment) -> (TestServer, i32, i32) {
+        let server = TestServer::with_config(config).await;
+        let alice = server.register("alice").await;
+        let (_, project) = server.create_project(alice).await;
+        (server, alice, project)
+    }
+
+    /// Register a user, which also logs the client in as them. Returns the user's id.
+    pub async fn register(&self, name: &str) -> i32 {
+        let response = self
+            .client
+            .post("/api/register")
+            .body(json!({ "user": name, "password": "password1" }).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status().code, 200, "registering {}", name);
+        let name = name.to_string();
+        self.run(move |conn| {
+            user::table
+                .filter(user::username.eq(name))
+                .select(user::id)
+                .first::<i32>(conn)
+                .unwrap()
+        })
+        .await
+    }
+
+    /// Log the client in as a registered user
+    pub async fn login(&self, name: &str) {
+        let response = self
+            .client
+            .post("/api/login")
+            .body(json!({ "user": name, "password": "password1" }).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok, "logging in as {}", name);
+    }
+
+    /// A workspace owned by the user, with a project in it. There's no route for creating
+    /// workspaces. Returns the workspace and project ids.
+    pub async fn create_project(&self, owner: i32) -> (i32, i32) {
+        self.run(move |conn| {
+            let workspace = insert_returning_id!(
+                conn,
+                diesel::insert_into(workspace::table).values((
+                    workspace::name.eq("Workspace"),
+                    workspace::owner.eq(owner),
+                    workspace::shared.eq(0),
+                )),
+                workspace::id
+            )
+            .unwrap();
+            diesel::insert_into(workspace_member::table)
+                .values((
+                    workspace_member::workspace.eq(workspace),
+                    workspace_member::user.eq(owner),
+                    workspace_member::role.eq(role::ADMIN),
+                ))
+                .execute(conn)
+                .unwrap();
+            let project = insert_returning_id!(
+                conn,
+                diesel::insert_into(project::table).values((
+                    project::workspace.eq(workspace),
+                    project::name.eq("Project"),
+                )),
+                project::id
+            )
+            .unwrap();
+            (workspace, project)
+        })
+        .await
+    }
+
+    /// Give the project an uploaded video of the given length in milliseconds. Returns the
+    /// video's id.
+    pub async fn attach_video(&self, project: i32, duration: i32) -> i32 {
+        // The glob import of the schema is shadowed by the `video` test module
+        use crate::schema::video;
+
+        self.run(move |conn| {
+            let video = insert_returning_id!(
+                conn,
+                diesel::insert_into(video::table).values((
+                    video::source.eq("upload"),
+                    video::identifier.eq("video.mp4"),
+                    video::duration.eq(Some(duration)),
+                )),
+                video::id
+            )
+            .unwrap();
+            diesel::update(project::table.find(project))
+                .set(project::video.eq(Some(video)))
+                .execute(conn)
+                .unwrap();
+            video
+        })
+        .await
+    }
+
+    /// Make the user a member of the workspace
+    pub async fn add_member(&self, workspace: i32, user: i32, role: i32) {
+        self.run(move |conn| {
+            diesel::insert_into(workspace_member::table)
+                .values((
+                    workspace_member::workspace.eq(workspace),
+                    workspace_member::user.eq(user),
+                    workspace_member::role.eq(role),
+                ))
+                .execute(conn)
+                .unwrap()
+        })
+        .await;
+    }
+
+    /// Create a subtitle a second long, as the logged in user. Returns its id.
+    pub async fn create_subtitle(&self, project: i32, start: i32, text: &str) -> i32 {
+        let (status, id) = self
+            .send_json(
+                Method::Post,
+                &format!("/api/project/{}/subtitle/create", project),
+                json!({ "start": start, "end": start + 1000, "text": text }),
+            )
+            .await;
+        assert_eq!(status, Status::Ok, "creating {:?}", text);
+        id.as_i64().unwrap() as i32
+    }
+
+    /// The subtitles of a project, in order
+    pub async fn subtitles(&self, project: i32) -> Vec<Subtitle> {
+        self.run(move |conn| {
+            subtitle::table
+                .filter(subtitle::project.eq(project))
+                .order((subtitle::start, subtitle::id))
+                .load::<Subtitle>(conn)
+                .unwrap()
+        })
+        .await
+    }
+
+    pub async fn get_json(&self, path: &str) -> Value {
+        let response = self.client.get(path.to_string()).dispatch().await;
+        assert_eq!(response.status(), Status::Ok, "GET {}", path);
+        body_json(response).await
+    }
+
+    /// Send a request with a JSON body and return the status and the JSON response, if any
+    pub async fn send_json(&self, method: Method, path: &str, body: Value) -> (Status, Value) {
+        let response = self
+            .client
+            .req(method, path.to_string())
+            .body(body.to_string())
+            .dispatch()
+            .await;
+        let status = response.status();
+        (status, body_json(response).await)
+    }
+}
+
+/// The body of a response as JSON, or null if it isn't any. `LocalResponse::into_json` can
+/// hang, so this goes through a string.
+pub async fn body_json(response: LocalResponse<'_>) -> Value {
+    response
+        .into_string()
+        .await
+        .and_then(|body| serde_json::from_str(&body).ok())
+        .unwrap_or(Value::Null)
+}
+
+/// A request received by `MockHttp`
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub method: String,
+    /// Including the query
+    pub path: String,
+    pub body: String,
+}
+
+/// A tiny HTTP server standing in for identity providers and other services. It records every
+/// request and answers it with a status code and a JSON body.
+pub struct MockHttp {
+    pub url: String,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+}
+
+impl MockHttp {
+    /// `respond` gets the server's URL, since responses often refer to it, and returns the
+    /// request handler
+    pub async fn start<F>(respond: impl FnOnce(&str) -> F) -> MockHttp
+    where
+        F: Fn(&MockRequest) -> (u16, String) + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let respond = Arc::new(respond(&url));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = requests.clone();
+        task::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let respond = respond.clone();
+                let recorded = recorded.clone();
+                task::spawn(async move {
+                    if let Some(request) = read_request(stream).await {
+                        let (request, mut stream) = request;
+                        let (status, body) = respond(&request);
+                        recorded.lock().unwrap().push(request);
+                        let response = format!(
+                            "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\n\
+                             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                            status,
+                            body.len(),
+                            body
+                        );
+                        let _ = stream.write_all(response.as_bytes()).await;
+                    }
+                });
+            }
+        });
+
+        MockHttp { url, requests }
+    }
+
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn read_request(mut stream: TcpStream) -> Option<(MockRequest, TcpStream)> {
+    let mut data = Vec::new();
+    let mut buffer = [0u8; 4096];
+    let header_end = loop {
+        let read = stream.read(&mut buffer).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        data.extend_from_slice(&buffer[..read]);
+        if let Some(end) = data.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&data[..header_end]).into_owned();
+    let mut lines = head.lines();
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+    let content_length = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+
+    while data.len() < header_end + content_length {
+        let read = stream.read(&mut buffer).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        data.extend_from_slice(&buffer[..read]);
+    }
+    let body = String::from_utf8_lossy(&data[header_end..header_end + content_length]).into_owned();
+    Some((MockRequest { method, path, body }, stream))
+}
@@ -0,0 +1,343 @@
+//! Single sign-on against a mock identity provider
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use reqwest::Url;
+use rocket::http::Status;
+use rocket::serde::json::{json, serde_json, Value};
+use sha2::{Digest, Sha256};
+
+use super::{MockHttp, TestServer};
+use crate::oidc::{Oidc, OidcConfig};
+use crate::schema::*;
+use crate::unix_timestamp;
+use diesel::prelude::*;
+
+const CLIENT_ID: &str = "uptitle";
+
+/// Serves discovery and the token endpoint. The token endpoint hands out an ID token with
+/// whatever claims were set last.
+struct MockIssuer {
+    http: MockHttp,
+    claims: Arc<Mutex<Value>>,
+}
+
+impl MockIssuer {
+    async fn start() -> MockIssuer {
+        let claims = Arc::new(Mutex::new(json!({})));
+        let token_claims = claims.clone();
+        let http = MockHttp::start(|url| {
+            let url = url.to_string();
+            move |request| match (request.method.as_str(), request.path.as_str()) {
+                // Whatever path the client thinks the issuer has
+                ("GET", path) if path.ends_with("/.well-known/openid-configuration") => (
+                    200,
+                    json!({
+                        "issuer": url,
+                        "authorization_endpoint": format!("{}/authorize", url),
+                        "token_endpoint": format!("{}/token", url),
+                    })
+                    .to_string(),
+                ),
+                ("POST", "/token") => {
+                    let payload = base64::encode_config(
+                        token_claims.lock().unwrap().to_string(),
+                        base64::URL_SAFE_NO_PAD,
+                    );
+                    (
+                        200,
+                        json!({
+                            "access_token": "access",
+                            "token_type": "Bearer",
+                            "id_token": format!("eyJhbGciOiJSUzI1NiJ9.{}.signature", payload),
+                        })
+                        .to_string(),
+                    )
+                }
+                _ => (404, "{}".to_string()),
+            }
+        })
+        .await;
+        MockIssuer { http, claims }
+    }
+
+    fn config(&self) -> OidcConfig {
+        OidcConfig {
+            issuer: self.http.url.clone(),
+            client_id: CLIENT_ID.to_string(),
+            client_secret: None,
+            redirect_uri: "http://localhost/api/oidc/callback".to_string(),
+            post_login_redirect: "/".to_string(),
+            password_login: true,
+        }
+    }
+
+    /// Claims that pass every check
+    fn valid_claims(&self, nonce: &str) -> Value {
+        json!({
+            "iss": self.http.url,
+            "sub": "subject-1",
+            "aud": CLIENT_ID,
+            "exp": unix_timestamp() + 300,
+            "nonce": nonce,
+            "email": "carol@example.com",
+            "email_verified": true,
+            "preferred_username": "carol",
+        })
+    }
+
+    fn set_claims(&self, claims: Value) {
+        *self.claims.lock().unwrap() = claims;
+    }
+
+    fn token_requests(&self) -> Vec<HashMap<String, String>> {
+        self.http
+            .requests()
+            .into_iter()
+            .filter(|request| request.path == "/token")
+            .map(|request| query(&format!("{}/token?{}", self.http.url, request.body)))
+            .collect()
+    }
+
+    async fn server(&self) -> TestServer {
+        let url = self.http.url.clone();
+        TestServer::with_config(|figment| {
+            figment
+                .merge(("oidc.issuer", url))
+                .merge(("oidc.client_id", CLIENT_ID))
+                .merge(("oidc.redirect_uri", "http://localhost/api/oidc/callback"))
+        })
+        .await
+    }
+}
+
+fn query(url: &str) -> HashMap<String, String> {
+    Url::parse(url)
+        .unwrap()
+        .query_pairs()
+        .into_owned()
+        .collect()
+}
+
+/// Start logging in, and return the parameters of the authorization request
+async fn start_login(server: &TestServer) -> HashMap<String, String> {
+    let response = server.client.get("/api/oidc/login").dispatch().await;
+    assert_eq!(response.status(), Status::SeeOther);
+    query(response.headers().get_one("Location").unwrap())
+}
+
+/// Log in through the mock issuer with the claims changed by `claims`
+async fn log_in(
+    server: &TestServer,
+    issuer: &MockIssuer,
+    claims: impl FnOnce(&mut Value),
+) -> Status {
+    let params = start_login(server).await;
+    let mut id_claims = issuer.valid_claims(&params["nonce"]);
+    claims(&mut id_claims);
+    issuer.set_claims(id_claims);
+    server
+        .client
+        .get(format!(
+            "/api/oidc/callback?code=the-code&state={}",
+            params["state"]
+        ))
+        .dispatch()
+        .await
+        .status()
+}
+
+async fn get_profile(server: &TestServer) -> Value {
+    server
+        .client
+        .get("/api/user/profile")
+        .dispatch()
+        .await
+        .into_string()
+        .await
+        .and_then(|body| serde_json::from_str(&body).ok())
+        .unwrap()
+}
+
+#[rocket::async_test]
+async fn login_uses_pkce() {
+    let issuer = MockIssuer::start().await;
+    let server = issuer.server().await;
+
+    let params = start_login(&server).await;
+    assert_eq!(params["response_type"], "code");
+    assert_eq!(params["client_id"], CLIENT_ID);
+    assert_eq!(params["code_challenge_method"], "S256");
+    issuer.set_claims(issuer.valid_claims(&params["nonce"]));
+
+    let response = server
+        .client
+        .get(format!(
+            "/api/oidc/callback?code=the-code&state={}",
+            params["state"]
+        ))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(response.headers().get_one("Location"), Some("/"));
+
+    let requests = issuer.token_requests();
+    assert_eq!(requests.len(), 1);
+    let form = &requests[0];
+    assert_eq!(form["grant_type"], "authorization_code");
+    assert_eq!(form["code"], "the-code");
+    assert_eq!(form["client_id"], CLIENT_ID);
+    let challenge = base64::encode_config(
+        Sha256::digest(form["code_verifier"].as_bytes()),
+        base64::URL_SAFE_NO_PAD,
+    );
+    assert_eq!(challenge, params["code_challenge"]);
+
+    let profile = get_profile(&server).await;
+    assert_eq!(profile["username"], "carol");
+    assert_eq!(profile["single_sign_on"], true);
+    assert_eq!(profile["email_verified"], true);
+}
+
+#[rocket::async_test]
+async fn callback_checks_the_state() {
+    let issuer = MockIssuer::start().await;
+    let server = issuer.server().await;
+
+    let params = start_login(&server).await;
+    issuer.set_claims(issuer.valid_claims(&params["nonce"]));
+    let response = server
+        .client
+        .get("/api/oidc/callback?code=the-code&state=forged")
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+    assert!(issuer.token_requests().is_empty());
+
+    // The flow is used up, even by a failed attempt
+    let response = server
+        .client
+        .get(format!(
+            "/api/oidc/callback?code=the-code&state={}",
+            params["state"]
+        ))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+    assert!(issuer.token_requests().is_empty());
+}
+
+#[rocket::async_test]
+async fn callback_rejects_invalid_id_tokens() {
+    let issuer = MockIssuer::start().await;
+    let server = issuer.server().await;
+
+    let status = log_in(&server, &issuer, |claims| claims["nonce"] = json!("other")).await;
+    assert_eq!(status, Status::Unauthorized);
+    let response = server.client.get("/api/user/profile").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+type ClaimsChange = fn(&mut Value);
+
+#[rocket::async_test]
+async fn exchange_checks_the_id_token() {
+    let issuer = MockIssuer::start().await;
+    let oidc = Oidc::new(issuer.config());
+    let now = unix_timestamp();
+
+    let cases: Vec<(ClaimsChange, Result<(), &str>)> = vec![
+        (|_| {}, Ok(())),
+        (
+            |claims| claims["iss"] = json!("https://elsewhere.example"),
+            Err("ID token has the wrong issuer"),
+        ),
+        (
+            |claims| claims["aud"] = json!("another-client"),
+            Err("ID token is meant for a different client"),
+        ),
+        (
+            |claims| claims["aud"] = json!(["another-client", CLIENT_ID]),
+            Ok(()),
+        ),
+        (
+            |claims| claims["aud"] = json!(["another-client"]),
+            Err("ID token is meant for a different client"),
+        ),
+        (
+            |claims| claims["exp"] = json!(unix_timestamp() - 1),
+            Err("ID token has expired"),
+        ),
+        (
+            |claims| claims["nonce"] = json!("replayed"),
+            Err("ID token has the wrong nonce"),
+        ),
+        (
+            |claims| {
+                claims.as_object_mut().unwrap().remove("nonce");
+            },
+            Err("ID token has the wrong nonce"),
+        ),
+    ];
+    for (i, (change, expected)) in cases.into_iter().enumerate() {
+        let (url, flow) = oidc.authorization_url().await.unwrap();
+        let mut claims = issuer.valid_claims(&query(&url)["nonce"]);
+        change(&mut claims);
+        issuer.set_claims(claims);
+        let result = oidc.exchange(&flow, "the-code", now).await.map(|_| ());
+        assert_eq!(result, expected, "case {}", i);
+    }
+}
+
+#[rocket::async_test]
+async fn discovery_checks_the_issuer() {
+    let issuer = MockIssuer::start().await;
+    let oidc = Oidc::new(OidcConfig {
+        issuer: format!("{}/realms/other", issuer.http.url),
+        ..issuer.config()
+    });
+    assert_eq!(
+        oidc.authorization_url().await.map(|_| ()),
+        Err("Identity provider reports a different issuer")
+    );
+}
+
+#[rocket::async_test]
+async fn only_verified_emails_link_accounts() {
+    let issuer = MockIssuer::start().await;
+    let server = issuer.server().await;
+    let alice = server.register("alice").await;
+    server
+        .run(move |conn| {
+            diesel::update(user::table.find(alice))
+                .set((
+                    user::email.eq(Some("alice@example.com")),
+                    user::email_verified.eq(1),
+                ))
+                .execute(conn)
+                .unwrap()
+        })
+        .await;
+
+    // Without the claim, the identity provider might not have checked the address
+    let status = log_in(&server, &issuer, |claims| {
+        claims["sub"] = json!("subject-1");
+        claims["email"] = json!("Alice@example.com");
+        claims["preferred_username"] = json!("alice");
+        claims.as_object_mut().unwrap().remove("email_verified");
+    })
+    .await;
+    assert_eq!(status, Status::SeeOther);
+    let profile = get_profile(&server).await;
+    assert_eq!(profile["username"], "alice2");
+    assert_eq!(profile["email_verified"], false);
+
+    let status = log_in(&server, &issuer, |claims| {
+        claims["sub"] = json!("subject-2");
+        claims["email"] = json!("Alice@example.com");
+    })
+    .await;
+    assert_eq!(status, Status::SeeOther);
+    assert_eq!(get_profile(&server).await["username"], "alice");
+}
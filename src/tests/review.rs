@@ -0,0 +1,165 @@
+//! The review status of subtitles
+
+use rocket::http::{Method, Status};
+use rocket::serde::json::{json, Value};
+
+use super::TestServer;
+use crate::models::*;
+
+async fn set_status(server: &TestServer, project: i32, subtitle: i32, status: &str) -> Status {
+    let (status, _) = server
+        .send_json(
+            Method::Patch,
+            &format!("/api/project/{}/subtitle/{}/status", project, subtitle),
+            json!({ "status": status }),
+        )
+        .await;
+    status
+}
+
+async fn edit(server: &TestServer, project: i32, subtitle: i32, body: Value) {
+    let (status, _) = server
+        .send_json(
+            Method::Patch,
+            &format!("/api/project/{}/subtitle/{}", project, subtitle),
+            body,
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+}
+
+async fn status_of(server: &TestServer, project: i32, subtitle: i32) -> String {
+    server
+        .subtitles(project)
+        .await
+        .into_iter()
+        .find(|s| s.id == subtitle)
+        .unwrap()
+        .status
+}
+
+#[rocket::async_test]
+async fn editing_an_approved_subtitle_needs_another_review() {
+    let server = TestServer::new().await;
+    let alice = server.register("alice").await;
+    let (_, project) = server.create_project(alice).await;
+    let first = server.create_subtitle(project, 0, "One").await;
+    let second = server.create_subtitle(project, 2000, "Two").await;
+    for subtitle in [first, second] {
+        assert_eq!(
+            set_status(&server, project, subtitle, status::APPROVED).await,
+            Status::Ok
+        );
+    }
+
+    edit(&server, project, first, json!({ "text": "Uno" })).await;
+    assert_eq!(
+        status_of(&server, project, first).await,
+        status::NEEDS_REVIEW
+    );
+    edit(&server, project, second, json!({ "start": 2500 })).await;
+    assert_eq!(
+        status_of(&server, project, second).await,
+        status::NEEDS_REVIEW
+    );
+
+    // The change shows up in the status history
+    let history = server
+        .get_json(&format!(
+            "/api/project/{}/subtitle/{}/status/history",
+            project, first
+        ))
+        .await;
+    let changes: Vec<(&str, &str)> = history
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|change| {
+            (
+                change["from"].as_str().unwrap(),
+                change["to"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        changes,
+        [
+            (status::DRAFT, status::APPROVED),
+            (status::APPROVED, status::NEEDS_REVIEW)
+        ]
+    );
+
+    // Undoing the edit brings the text back, but not the approval
+    let undo = format!("/api/project/{}/undo", project);
+    assert_eq!(
+        server.client.post(undo).dispatch().await.status(),
+        Status::Ok
+    );
+    assert_eq!(
+        status_of(&server, project, second).await,
+        status::NEEDS_REVIEW
+    );
+
+    // Saving the same text again isn't a change
+    assert_eq!(
+        set_status(&server, project, first, status::APPROVED).await,
+        Status::Ok
+    );
+    edit(&server, project, first, json!({ "text": "Uno" })).await;
+    assert_eq!(status_of(&server, project, first).await, status::APPROVED);
+}
+
+#[rocket::async_test]
+async fn only_reviewers_approve_and_reject() {
+    let server = TestServer::new().await;
+    let bob = server.register("bob").await;
+    let carol = server.register("carol").await;
+    let alice = server.register("alice").await;
+    let (workspace, project) = server.create_project(alice).await;
+    server.add_member(workspace, bob, role::MEMBER).await;
+    server.add_member(workspace, carol, role::REVIEWER).await;
+    let subtitle = server.create_subtitle(project, 0, "One").await;
+
+    server.login("bob").await;
+    assert_eq!(
+        set_status(&server, project, subtitle, "done").await,
+        Status::BadRequest
+    );
+    assert_eq!(
+        set_status(&server, project, subtitle, status::NEEDS_REVIEW).await,
+        Status::Ok
+    );
+    for to in [status::APPROVED, status::REJECTED] {
+        assert_eq!(
+            set_status(&server, project, subtitle, to).await,
+            Status::Forbidden
+        );
+    }
+
+    server.login("carol").await;
+    assert_eq!(
+        set_status(&server, project, subtitle, status::APPROVED).await,
+        Status::Ok
+    );
+
+    // Members can't reopen an approved subtitle either
+    server.login("bob").await;
+    assert_eq!(
+        set_status(&server, project, subtitle, status::DRAFT).await,
+        Status::Forbidden
+    );
+    assert_eq!(
+        status_of(&server, project, subtitle).await,
+        status::APPROVED
+    );
+
+    server.login("alice").await;
+    assert_eq!(
+        set_status(&server, project, subtitle, status::REJECTED).await,
+        Status::Ok
+    );
+    assert_eq!(
+        status_of(&server, project, subtitle).await,
+        status::REJECTED
+    );
+}
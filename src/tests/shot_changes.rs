@@ -0,0 +1,139 @@
+//! Finding shot changes and checking cues against them
+
+use rocket::serde::json::{json, Value};
+
+use super::TestServer;
+use crate::media::{parse_showinfo, ShotChanges};
+use crate::schema::*;
+use crate::timecode::FrameRate;
+use diesel::prelude::*;
+
+/// What ffmpeg writes to stderr when the showinfo filter is at the end of the chain
+const SHOWINFO_LOG: &str = "\
+Input #0, mov,mp4,m4a,3gp,3g2,mj2, from 'video.mp4':
+  Duration: 00:00:16.02, start: 0.000000, bitrate: 1205 kb/s
+  Stream #0:0[0x1](und): Video: h264 (High) (avc1 / 0x31637661), yuv420p(tv, bt709), 1920x1080, 1070 kb/s, 29.97 fps
+[Parsed_showinfo_2 @ 0x5581c8a3e0c0] config in time_base: 1/30000, frame_rate: 30000/1001
+[Parsed_showinfo_2 @ 0x5581c8a3e0c0] config out time_base: 0/0, frame_rate: 0/0
+[Parsed_showinfo_2 @ 0x5581c8a3e0c0] n:   0 pts: 360360 pts_time:12.012   duration:   1001 duration_time:0.0333667 fmt:yuv420p sar:1/1 s:320x180 i:P iskey:0 type:B checksum:9A3B1C2D
+[Parsed_showinfo_2 @ 0x5581c8a3e0c0] color_range:tv color_space:bt709 color_primaries:bt709 color_trc:bt709
+[Parsed_showinfo_2 @ 0x5581c8a3e0c0] n:   1 pts: 150150 pts_time:5.005   duration:   1001 duration_time:0.0333667 fmt:yuv420p sar:1/1 s:320x180 i:P iskey:1 type:I checksum:1F2E3D4C
+[Parsed_showinfo_2 @ 0x5581c8a3e0c0] n:   2 pts: 360360 pts_time:12.012   duration:   1001 duration_time:0.0333667 fmt:yuv420p sar:1/1 s:320x180 i:P iskey:0 type:P checksum:9A3B1C2D
+[out_#0/null @ 0x5581c8a40a80] video:0kB audio:0kB subtitle:0kB other streams:0kB global headers:0kB muxing overhead: unknown
+[Parsed_scale_0 @ 0x5581c8a3d2c0] pts_time:99.000 isn't from showinfo
+";
+
+#[test]
+fn showinfo_frames_are_read_in_order() {
+    assert_eq!(parse_showinfo(SHOWINFO_LOG), [5005, 12012]);
+    assert!(parse_showinfo("").is_empty());
+}
+
+fn shot_changes(times: &[i32]) -> ShotChanges {
+    ShotChanges {
+        times: times.to_vec(),
+        // 40 milliseconds a frame
+        rate: FrameRate::new(25, 1).unwrap(),
+    }
+}
+
+#[test]
+fn nearest_shot_change_is_counted_in_frames() {
+    let cuts = shot_changes(&[1000, 5000]);
+    assert_eq!(cuts.nearest(0), Some((1000, 25)));
+    assert_eq!(cuts.nearest(1000), Some((1000, 0)));
+    assert_eq!(cuts.nearest(1080), Some((1000, 2)));
+    assert_eq!(cuts.nearest(3100), Some((5000, 47)));
+    assert_eq!(cuts.nearest(i32::MAX).map(|(cut, _)| cut), Some(5000));
+    assert_eq!(shot_changes(&[]).nearest(1000), None);
+}
+
+#[test]
+fn boundaries_close_to_a_shot_change_straddle_it() {
+    let cuts = shot_changes(&[1000, 5000]);
+    assert_eq!(cuts.straddles(1000, 6), None);
+    assert_eq!(cuts.straddles(1080, 6), Some((1000, 2)));
+    assert_eq!(cuts.straddles(760, 6), Some((1000, 6)));
+    assert_eq!(cuts.straddles(1240, 6), Some((1000, 6)));
+    assert_eq!(cuts.straddles(1280, 6), None);
+    assert_eq!(cuts.straddles(1080, 1), None);
+}
+
+#[test]
+fn boundaries_snap_to_close_shot_changes() {
+    let cuts = shot_changes(&[1000, 5000]);
+    assert_eq!(cuts.snap(1000, 6), 1000);
+    assert_eq!(cuts.snap(1240, 6), 1000);
+    assert_eq!(cuts.snap(4800, 6), 5000);
+    assert_eq!(cuts.snap(1280, 6), 1280);
+    assert_eq!(cuts.snap(1080, 1), 1080);
+    assert_eq!(shot_changes(&[]).snap(1080, 6), 1080);
+}
+
+#[rocket::async_test]
+async fn cues_near_shot_changes_are_flagged() {
+    let (server, _, project) = TestServer::with_project(|figment| figment).await;
+    let video = server.attach_video(project, 16000).await;
+    let check = |query: &str| format!("/api/project/{}/shot_changes/check{}", project, query);
+
+    let first = server
+        .create_subtitle(project, 1080, "Near both cuts")
+        .await;
+    server.create_subtitle(project, 5000, "On a cut").await;
+    let third = server
+        .create_subtitle(project, 9760, "Just before a cut")
+        .await;
+
+    let response = server.client.get(check("")).dispatch().await;
+    assert_eq!(
+        response.status().code,
+        404,
+        "shot changes aren't detected yet"
+    );
+
+    server
+        .run(move |conn| {
+            diesel::update(video::table.find(video))
+                .set((
+                    video::frame_rate_num.eq(Some(25)),
+                    video::frame_rate_den.eq(Some(1)),
+                    video::shot_changes_detected.eq(Some(0)),
+                ))
+                .execute(conn)
+                .unwrap();
+            for time in [1000, 2000, 5000, 10000] {
+                diesel::insert_into(shot_change::table)
+                    .values((shot_change::video.eq(video), shot_change::time.eq(time)))
+                    .execute(conn)
+                    .unwrap();
+            }
+        })
+        .await;
+
+    let warning = |subtitle: i32, boundary: &str, time: i32, shot_change: i32, frames: i64| {
+        json!({
+            "subtitle": subtitle,
+            "boundary": boundary,
+            "time": time,
+            "shot_change": shot_change,
+            "frames": frames,
+        })
+    };
+    assert_eq!(
+        server.get_json(&check("")).await,
+        Value::Array(vec![
+            warning(first, "start", 1080, 1000, 2),
+            warning(first, "end", 2080, 2000, 2),
+            warning(third, "start", 9760, 10000, 6),
+        ])
+    );
+    assert_eq!(
+        server.get_json(&check("?frames=2")).await,
+        Value::Array(vec![
+            warning(first, "start", 1080, 1000, 2),
+            warning(first, "end", 2080, 2000, 2),
+        ])
+    );
+    let response = server.client.get(check("?frames=0")).dispatch().await;
+    assert_eq!(response.status().code, 400);
+}
@@ -0,0 +1,182 @@
+//! Cues made from speech regions, and transcripts aligned to them
+
+use rocket::http::{Method, Status};
+use rocket::serde::json::json;
+
+use super::TestServer;
+use crate::schema::*;
+use crate::speech::{align_transcript, cues_from_regions, AlignmentSettings, CueSettings};
+use diesel::prelude::*;
+
+const END: i32 = i32::MAX;
+
+#[test]
+fn cues_split_long_regions() {
+    let settings = CueSettings::default();
+    let cues = cues_from_regions(&[(0, 500), (2000, 16000)], &[], &settings);
+    assert_eq!(cues, [(0, 1000), (2000, 8960), (9040, 16000)]);
+
+    // Cues too close to an existing one are left out
+    let cues = cues_from_regions(&[(0, 500), (2000, 3000)], &[(1050, 1900)], &settings);
+    assert_eq!(cues, [(2000, 3000)]);
+}
+
+#[test]
+fn cues_at_the_end_of_the_range_dont_overflow() {
+    let settings = CueSettings {
+        min_duration: CueSettings::MAX / 2,
+        max_duration: CueSettings::MAX,
+        min_gap: CueSettings::MAX,
+    };
+    let cues = cues_from_regions(&[(0, END - 10), (END - 5, END)], &[(END, END)], &settings);
+    assert!(!cues.is_empty());
+    for &(start, end) in &cues {
+        assert!(0 <= start && start <= end, "{:?}", cues);
+    }
+    assert_eq!(cues.last().unwrap().1, END);
+}
+
+#[test]
+fn aligning_at_the_end_of_the_range_doesnt_overflow() {
+    let settings = AlignmentSettings {
+        max_cps: 0.001,
+        cue: CueSettings {
+            min_duration: CueSettings::MAX / 2,
+            max_duration: CueSettings::MAX,
+            min_gap: CueSettings::MAX,
+        },
+    };
+    let regions = [(END - 20_000, END - 10_000), (END - 5_000, END)];
+    let cues = align_transcript(&[10, 10, 10], &regions, &settings);
+    assert_eq!(cues.len(), 3);
+    for cue in &cues {
+        assert!(cue.start <= cue.end, "{:?}", cues);
+        assert!((0.0..=1.0).contains(&cue.confidence));
+    }
+}
+
+fn alignment_settings() -> AlignmentSettings {
+    AlignmentSettings {
+        max_cps: 17.0,
+        cue: CueSettings::default(),
+    }
+}
+
+#[test]
+fn aligning_is_deterministic() {
+    let regions = [(0, 4000), (4500, 12000), (13000, 20000)];
+    let lengths = [10, 40, 20, 30];
+    let align = || {
+        align_transcript(&lengths, &regions, &alignment_settings())
+            .into_iter()
+            .map(|cue| (cue.start, cue.end, cue.confidence))
+            .collect::<Vec<_>>()
+    };
+    let cues = align();
+    assert_eq!(cues.len(), lengths.len());
+    assert_eq!(align(), cues);
+}
+
+#[test]
+fn longer_lines_get_longer_cues() {
+    let cues = align_transcript(&[10, 20, 40], &[(0, 12000)], &alignment_settings());
+    let durations: Vec<i32> = cues.iter().map(|cue| cue.end - cue.start).collect();
+    assert_eq!(durations.len(), 3);
+    for pair in durations.windows(2) {
+        // Twice the text, about twice the time, less the gap between the cues
+        let ratio = pair[1] as f64 / pair[0] as f64;
+        assert!((1.9..=2.1).contains(&ratio), "{:?}", durations);
+    }
+
+    // Lines too long to read in their share of the speech get more time
+    let settings = AlignmentSettings {
+        max_cps: 5.0,
+        ..alignment_settings()
+    };
+    let cues = align_transcript(&[10, 30], &[(0, 4000)], &settings);
+    assert!(cues[1].end - cues[1].start >= 6000, "{:?}", cues);
+    assert!(cues[1].confidence < 1.0);
+}
+
+#[rocket::async_test]
+async fn aligned_cues_keep_their_confidence() {
+    let (server, _, project) = TestServer::with_project(|figment| figment).await;
+    let video = server.attach_video(project, 16000).await;
+    server
+        .run(move |conn| {
+            diesel::update(video::table.find(video))
+                .set(video::speech_detected.eq(Some(0)))
+                .execute(conn)
+                .unwrap();
+            for (start, end) in [(0, 3000), (4000, 9000)] {
+                diesel::insert_into(speech_region::table)
+                    .values((
+                        speech_region::video.eq(video),
+                        speech_region::start.eq(start),
+                        speech_region::end.eq(end),
+                    ))
+                    .execute(conn)
+                    .unwrap();
+            }
+        })
+        .await;
+
+    let (status, cues) = server
+        .send_json(
+            Method::Post,
+            &format!("/api/project/{}/transcript/align", project),
+            json!({ "text": "A line that fills the first region\nNot much\nAnd a last line" }),
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+    let confidences: Vec<(i64, f64)> = cues
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|cue| {
+            (
+                cue["subtitle"].as_i64().unwrap(),
+                cue["confidence"].as_f64().unwrap(),
+            )
+        })
+        .collect();
+    let stored: Vec<(i64, f64)> = server
+        .subtitles(project)
+        .await
+        .into_iter()
+        .map(|subtitle| (subtitle.id as i64, subtitle.confidence.unwrap()))
+        .collect();
+    assert_eq!(stored, confidences);
+}
+
+#[rocket::async_test]
+async fn cue_limits_are_bounded() {
+    let (server, _, project) = TestServer::with_project(|figment| figment).await;
+    server.attach_video(project, 16000).await;
+
+    for limits in [
+        json!({ "max_duration": i32::MAX }),
+        json!({ "min_duration": i32::MAX, "max_duration": i32::MAX }),
+        json!({ "min_gap": i32::MAX }),
+    ] {
+        let (status, _) = server
+            .send_json(
+                Method::Post,
+                &format!("/api/project/{}/speech/cues", project),
+                limits.clone(),
+            )
+            .await;
+        assert_eq!(status, Status::BadRequest, "{}", limits);
+
+        let mut alignment = limits.clone();
+        alignment["text"] = json!("Hello");
+        let (status, _) = server
+            .send_json(
+                Method::Post,
+                &format!("/api/project/{}/transcript/align", project),
+                alignment,
+            )
+            .await;
+        assert_eq!(status, Status::BadRequest, "{}", limits);
+    }
+}
@@ -0,0 +1,231 @@
+//! Draft subtitles from the fake transcriber
+
+use std::path::Path;
+use std::sync::Arc;
+
+use rocket::http::{Method, Status};
+use rocket::serde::json::json;
+use rocket::tokio::sync::broadcast::{channel, Receiver};
+
+use super::TestServer;
+use crate::models::*;
+use crate::schema::*;
+use crate::transcribe::{FakeTranscriber, TranscriptionRequest};
+use crate::{run_transcription, DbConn, SubtitleEvent, SubtitleEventType, Transcription};
+use diesel::prelude::*;
+
+/// A server that can transcribe, with Alice logged in and a project with a 16 second video.
+/// Returns Alice's id and the project.
+async fn setup() -> (TestServer, i32, Project) {
+    let (server, alice, project) =
+        TestServer::with_project(|figment| figment.merge(("transcriber.kind", "fake"))).await;
+    server.attach_video(project, 16000).await;
+    let project = server
+        .run(move |conn| project::table.find(project).first::<Project>(conn).unwrap())
+        .await;
+    (server, alice, project)
+}
+
+/// Transcribe the project's video with the fake transcriber. Returns the result and the
+/// events that were sent.
+async fn transcribe(
+    server: &TestServer,
+    user: i32,
+    project: &Project,
+    replace: bool,
+) -> (Result<usize, String>, Vec<SubtitleEvent>) {
+    let db = DbConn::get_one(server.client.rocket()).await.unwrap();
+    let (sender, mut receiver) = channel(64);
+    let request = TranscriptionRequest {
+        audio: Path::new("audio.wav"),
+        language: Some("en"),
+        duration: Some(16000),
+    };
+    let result = run_transcription(
+        &db,
+        &sender,
+        &FakeTranscriber,
+        project,
+        user,
+        &request,
+        replace,
+    )
+    .await;
+    (result, received(&mut receiver))
+}
+
+fn received(receiver: &mut Receiver<SubtitleEvent>) -> Vec<SubtitleEvent> {
+    let mut events = Vec::new();
+    while let Ok(event) = receiver.try_recv() {
+        events.push(event);
+    }
+    events
+}
+
+/// Where each subtitle is, what it says and the mark a machine left on it
+type Row = (i32, i32, String, Option<String>, Option<f64>);
+
+fn rows(subtitles: &[Subtitle]) -> Vec<Row> {
+    subtitles
+        .iter()
+        .map(|subtitle| {
+            (
+                subtitle.start,
+                subtitle.end,
+                subtitle.text.clone(),
+                subtitle.origin.clone(),
+                subtitle.confidence,
+            )
+        })
+        .collect()
+}
+
+#[rocket::async_test]
+async fn transcription_creates_marked_cues() {
+    let (server, alice, project) = setup().await;
+
+    let (result, events) = transcribe(&server, alice, &project, false).await;
+    assert_eq!(result, Ok(4));
+    let progress: Vec<u8> = events
+        .iter()
+        .filter_map(|event| match &event.info {
+            SubtitleEventType::Transcription(data) => {
+                assert_eq!(data.state, "running");
+                Some(data.progress)
+            }
+            _ => None,
+        })
+        .collect();
+    assert_eq!(progress, [25, 50, 75, 100]);
+    let created = events
+        .iter()
+        .filter(|event| matches!(event.info, SubtitleEventType::SubtitleCreate(_)))
+        .count();
+    assert_eq!(created, 4);
+
+    let transcribed = Some(origin::TRANSCRIPTION.to_string());
+    let cue = |number: i32, confidence| {
+        let start = (number - 1) * 4000;
+        let text = format!("Transcribed cue {} (en)", number);
+        (
+            start,
+            start + 3000,
+            text,
+            transcribed.clone(),
+            Some(confidence),
+        )
+    };
+    assert_eq!(
+        rows(&server.subtitles(project.id).await),
+        [cue(1, 0.5), cue(2, 0.6), cue(3, 0.7), cue(4, 0.8)]
+    );
+
+    // All in one operation, with the mark recorded
+    let revisions = server
+        .run(|conn| {
+            subtitle_revision::table
+                .load::<SubtitleRevision>(conn)
+                .unwrap()
+        })
+        .await;
+    assert_eq!(revisions.len(), 4);
+    assert!(revisions.iter().all(|revision| revision.operation.is_some()
+        && revision.operation == revisions[0].operation
+        && revision.after_origin == transcribed));
+}
+
+#[rocket::async_test]
+async fn transcription_only_replaces_when_asked() {
+    let (server, alice, project) = setup().await;
+    let existing = server
+        .create_subtitle(project.id, 500, "Typed by hand")
+        .await;
+
+    let (status, _) = server
+        .send_json(
+            Method::Post,
+            &format!("/api/project/{}/transcribe", project.id),
+            json!({}),
+        )
+        .await;
+    assert_eq!(status, Status::Conflict);
+
+    // Subtitles added while the transcriber was running are kept too
+    let (result, events) = transcribe(&server, alice, &project, false).await;
+    assert_eq!(
+        result,
+        Err("The project's subtitles were changed or locked during the transcription".to_string())
+    );
+    assert!(!events
+        .iter()
+        .any(|event| matches!(event.info, SubtitleEventType::SubtitleCreate(_))));
+    let ids: Vec<i32> = server
+        .subtitles(project.id)
+        .await
+        .iter()
+        .map(|subtitle| subtitle.id)
+        .collect();
+    assert_eq!(ids, [existing]);
+
+    let (result, events) = transcribe(&server, alice, &project, true).await;
+    assert_eq!(result, Ok(4));
+    assert!(events.iter().any(|event| matches!(
+        &event.info,
+        SubtitleEventType::SubtitleDelete(data) if data.subtitle == existing
+    )));
+    let subtitles = server.subtitles(project.id).await;
+    assert_eq!(subtitles.len(), 4);
+    assert!(subtitles.iter().all(|subtitle| subtitle.id != existing));
+}
+
+#[rocket::async_test]
+async fn undoing_a_transcription_keeps_the_mark() {
+    let (server, alice, project) = setup().await;
+    server
+        .create_subtitle(project.id, 500, "Typed by hand")
+        .await;
+    let (result, _) = transcribe(&server, alice, &project, true).await;
+    assert_eq!(result, Ok(4));
+    let transcribed = server.subtitles(project.id).await;
+
+    let undo = format!("/api/project/{}/undo", project.id);
+    let redo = format!("/api/project/{}/redo", project.id);
+    let response = server.client.post(undo.clone()).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let restored = server.subtitles(project.id).await;
+    assert_eq!(restored.len(), 1);
+    assert_eq!(restored[0].text, "Typed by hand");
+    assert_eq!(restored[0].origin, None);
+    assert_eq!(restored[0].confidence, None);
+
+    let response = server.client.post(redo).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let redone = server.subtitles(project.id).await;
+    let ids = |subtitles: &[Subtitle]| subtitles.iter().map(|s| s.id).collect::<Vec<_>>();
+    assert_eq!(ids(&redone), ids(&transcribed));
+    assert_eq!(rows(&redone), rows(&transcribed));
+
+    // And once more, undoing the typed subtitle's deletion
+    let response = server.client.post(undo).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(server.subtitles(project.id).await[0].origin, None);
+}
+
+#[test]
+fn a_project_is_transcribed_once_at_a_time() {
+    let transcription = Transcription {
+        transcriber: Arc::new(FakeTranscriber),
+        running: Arc::default(),
+    };
+    let running = transcription.start(1).unwrap();
+    assert!(transcription.start(1).is_none());
+    assert!(transcription.start(2).is_some());
+
+    // Also when the transcription panics
+    let result = std::panic::catch_unwind(move || {
+        let _running = running;
+        panic!("the transcriber crashed");
+    });
+    assert!(result.is_err());
+    assert!(transcription.start(1).is_some());
+}
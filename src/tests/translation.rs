@@ -0,0 +1,289 @@
+//! Machine translation with the fake translator, and the translators themselves
+
+use rocket::http::{Method, Status};
+use rocket::serde::json::{json, serde_json, Value};
+
+use super::{MockHttp, MockRequest, TestServer};
+use crate::models::*;
+use crate::translate::TranslatorConfig;
+
+/// A server that can translate, with Alice logged in and a project with three subtitles.
+/// Returns the project and the subtitle ids.
+async fn setup() -> (TestServer, i32, Vec<i32>) {
+    let (server, _, project) =
+        TestServer::with_project(|figment| figment.merge(("translator.kind", "fake"))).await;
+    let mut ids = Vec::new();
+    for (start, text) in [(0, "One"), (2000, "Two"), (4000, "Three")] {
+        ids.push(server.create_subtitle(project, start, text).await);
+    }
+    (server, project, ids)
+}
+
+async fn translate(server: &TestServer, project: i32, body: Value) -> (Status, Value) {
+    server
+        .send_json(
+            Method::Post,
+            &format!("/api/project/{}/translate", project),
+            body,
+        )
+        .await
+}
+
+async fn post(server: &TestServer, path: String) -> Status {
+    server.client.post(path).dispatch().await.status()
+}
+
+/// The text and origin of each subtitle
+async fn texts(server: &TestServer, project: i32) -> Vec<(String, Option<String>)> {
+    server
+        .subtitles(project)
+        .await
+        .into_iter()
+        .map(|subtitle| (subtitle.text, subtitle.origin))
+        .collect()
+}
+
+fn translated(text: &str) -> (String, Option<String>) {
+    (text.to_string(), Some(origin::TRANSLATION.to_string()))
+}
+
+fn typed(text: &str) -> (String, Option<String>) {
+    (text.to_string(), None)
+}
+
+#[rocket::async_test]
+async fn translation_needs_a_translator() {
+    let server = TestServer::new().await;
+    let alice = server.register("alice").await;
+    let (_, project) = server.create_project(alice).await;
+    let (status, _) = translate(&server, project, json!({ "target": "nl" })).await;
+    assert_eq!(status, Status::NotImplemented);
+}
+
+#[rocket::async_test]
+async fn translation_marks_the_subtitles() {
+    let (server, project, ids) = setup().await;
+
+    let (status, _) = translate(&server, project, json!({ "target": "n l" })).await;
+    assert_eq!(status, Status::BadRequest);
+    let (status, _) = translate(
+        &server,
+        project,
+        json!({ "target": "nl", "subtitles": [0] }),
+    )
+    .await;
+    assert_eq!(status, Status::NotFound);
+
+    let (status, result) = translate(
+        &server,
+        project,
+        json!({ "target": "nl", "subtitles": [ids[0], ids[2]] }),
+    )
+    .await;
+    assert_eq!(status, Status::Ok);
+    let result: Vec<(i64, &str, &str)> = result
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|subtitle| {
+            (
+                subtitle["id"].as_i64().unwrap(),
+                subtitle["text"].as_str().unwrap(),
+                subtitle["origin"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        result,
+        [
+            (ids[0] as i64, "[nl] One", origin::TRANSLATION),
+            (ids[2] as i64, "[nl] Three", origin::TRANSLATION),
+        ]
+    );
+    assert_eq!(
+        texts(&server, project).await,
+        [
+            translated("[nl] One"),
+            typed("Two"),
+            translated("[nl] Three")
+        ]
+    );
+
+    // Editing the text takes the mark away
+    let (status, _) = server
+        .send_json(
+            Method::Patch,
+            &format!("/api/project/{}/subtitle/{}", project, ids[0]),
+            json!({ "text": "Een" }),
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(texts(&server, project).await[0], typed("Een"));
+}
+
+#[rocket::async_test]
+async fn undoing_a_translation_keeps_the_mark() {
+    let (server, project, ids) = setup().await;
+    let (status, _) = translate(&server, project, json!({ "target": "nl" })).await;
+    assert_eq!(status, Status::Ok);
+    let (status, _) = server
+        .send_json(
+            Method::Patch,
+            &format!("/api/project/{}/subtitle/{}", project, ids[1]),
+            json!({ "text": "Twee" }),
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+    let all_translated = [
+        translated("[nl] One"),
+        translated("[nl] Two"),
+        translated("[nl] Three"),
+    ];
+    let untranslated = [typed("One"), typed("Two"), typed("Three")];
+
+    let undo = format!("/api/project/{}/undo", project);
+    let redo = format!("/api/project/{}/redo", project);
+    assert_eq!(post(&server, undo.clone()).await, Status::Ok);
+    assert_eq!(texts(&server, project).await, all_translated);
+    assert_eq!(post(&server, undo.clone()).await, Status::Ok);
+    assert_eq!(texts(&server, project).await, untranslated);
+    assert_eq!(post(&server, redo.clone()).await, Status::Ok);
+    assert_eq!(texts(&server, project).await, all_translated);
+    assert_eq!(post(&server, redo).await, Status::Ok);
+    assert_eq!(
+        texts(&server, project).await,
+        [
+            translated("[nl] One"),
+            typed("Twee"),
+            translated("[nl] Three")
+        ]
+    );
+
+    // Reverting to the translation marks it again too
+    let history = server
+        .get_json(&format!(
+            "/api/project/{}/subtitle/{}/history",
+            project, ids[1]
+        ))
+        .await;
+    let translation = history
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|revision| revision["after"]["text"] == "[nl] Two")
+        .unwrap();
+    let revert = format!(
+        "/api/project/{}/subtitle/{}/history/{}/revert",
+        project, ids[1], translation["id"]
+    );
+    assert_eq!(post(&server, revert).await, Status::Ok);
+    assert_eq!(texts(&server, project).await, all_translated);
+}
+
+/// A LibreTranslate stand-in that prefixes texts with the target language, and fails on a
+/// text saying "fail"
+async fn libretranslate() -> MockHttp {
+    MockHttp::start(|_| {
+        |request: &MockRequest| {
+            let body: Value = serde_json::from_str(&request.body).unwrap();
+            let texts = body["q"].as_array().unwrap();
+            if texts.iter().any(|text| text == "fail") {
+                return (400, json!({ "error": "Unsupported text" }).to_string());
+            }
+            let translated: Vec<String> = texts
+                .iter()
+                .map(|text| {
+                    format!(
+                        "{}: {}",
+                        body["target"].as_str().unwrap(),
+                        text.as_str().unwrap()
+                    )
+                })
+                .collect();
+            (200, json!({ "translatedText": translated }).to_string())
+        }
+    })
+    .await
+}
+
+#[rocket::async_test]
+async fn http_translation_is_sent_in_batches() {
+    let http = libretranslate().await;
+    let translator = TranslatorConfig::Http {
+        url: format!("{}/", http.url),
+        api_key: Some("key".to_string()),
+    }
+    .build();
+
+    let texts: Vec<String> = (0..120).map(|n| n.to_string()).collect();
+    let translations = translator.translate(&texts, None, "nl").await.unwrap();
+    let expected: Vec<String> = texts.iter().map(|text| format!("nl: {}", text)).collect();
+    assert_eq!(translations, expected);
+
+    let requests = http.requests();
+    let batches: Vec<Value> = requests
+        .iter()
+        .map(|request| {
+            assert_eq!(
+                (request.method.as_str(), request.path.as_str()),
+                ("POST", "/translate")
+            );
+            serde_json::from_str(&request.body).unwrap()
+        })
+        .collect();
+    let sizes: Vec<usize> = batches
+        .iter()
+        .map(|batch| batch["q"].as_array().unwrap().len())
+        .collect();
+    assert_eq!(sizes, [50, 50, 20]);
+    for batch in &batches {
+        assert_eq!(batch["source"], "auto");
+        assert_eq!(batch["target"], "nl");
+        assert_eq!(batch["api_key"], "key");
+    }
+}
+
+#[rocket::async_test]
+async fn http_translation_errors_are_reported() {
+    let http = libretranslate().await;
+    let translator = TranslatorConfig::Http {
+        url: http.url.clone(),
+        api_key: None,
+    }
+    .build();
+
+    let mut texts: Vec<String> = (0..60).map(|n| n.to_string()).collect();
+    texts[55] = "fail".to_string();
+    let error = translator
+        .translate(&texts, Some("en"), "nl")
+        .await
+        .unwrap_err();
+    assert_eq!(error, "400 Bad Request: Unsupported text");
+    // The first batch went through, the second one failed
+    assert_eq!(http.requests().len(), 2);
+}
+
+#[rocket::async_test]
+async fn command_translation_reads_while_writing() {
+    let translator = TranslatorConfig::Command {
+        program: "cat".into(),
+        args: Vec::new(),
+    }
+    .build();
+
+    // Much more than fits in a pipe, so `cat` has to be read from while it's written to
+    let texts: Vec<String> = (0..20_000).map(|n| format!("{:0100}", n)).collect();
+    let translations = translator.translate(&texts, None, "nl").await.unwrap();
+    assert_eq!(translations, texts);
+
+    let translator = TranslatorConfig::Command {
+        program: "sh".into(),
+        args: vec![
+            "-c".to_string(),
+            "echo {target} unsupported >&2; exit 1".to_string(),
+        ],
+    }
+    .build();
+    let error = translator.translate(&texts, None, "xx").await.unwrap_err();
+    assert_eq!(error, "xx unsupported");
+}
@@ -0,0 +1,86 @@
+//! Undoing and redoing each user's own operations
+
+use rocket::http::{Method, Status};
+use rocket::serde::json::json;
+
+use super::TestServer;
+use crate::models::*;
+
+async fn post(server: &TestServer, project: i32, action: &str) -> Status {
+    let path = format!("/api/project/{}/{}", project, action);
+    server.client.post(path).dispatch().await.status()
+}
+
+async fn texts(server: &TestServer, project: i32) -> Vec<String> {
+    server
+        .subtitles(project)
+        .await
+        .into_iter()
+        .map(|subtitle| subtitle.text)
+        .collect()
+}
+
+#[rocket::async_test]
+async fn redo_replays_in_order() {
+    let (server, _, project) = TestServer::with_project(|figment| figment).await;
+    server.create_subtitle(project, 0, "One").await;
+    server.create_subtitle(project, 2000, "Two").await;
+    server.create_subtitle(project, 4000, "Three").await;
+
+    assert_eq!(post(&server, project, "undo").await, Status::Ok);
+    assert_eq!(post(&server, project, "undo").await, Status::Ok);
+    assert_eq!(texts(&server, project).await, ["One"]);
+
+    // Redoing goes forward again from the earliest operation that was undone
+    assert_eq!(post(&server, project, "redo").await, Status::Ok);
+    assert_eq!(texts(&server, project).await, ["One", "Two"]);
+    assert_eq!(post(&server, project, "redo").await, Status::Ok);
+    assert_eq!(texts(&server, project).await, ["One", "Two", "Three"]);
+    assert_eq!(post(&server, project, "redo").await, Status::NotFound);
+
+    // Something new drops what's left to redo
+    assert_eq!(post(&server, project, "undo").await, Status::Ok);
+    server.create_subtitle(project, 6000, "Four").await;
+    assert_eq!(post(&server, project, "redo").await, Status::NotFound);
+    assert_eq!(texts(&server, project).await, ["One", "Two", "Four"]);
+}
+
+#[rocket::async_test]
+async fn undo_wont_overwrite_someone_elses_change() {
+    let server = TestServer::new().await;
+    let bob = server.register("bob").await;
+    let alice = server.register("alice").await;
+    let (workspace, project) = server.create_project(alice).await;
+    server.add_member(workspace, bob, role::MEMBER).await;
+    let first = server.create_subtitle(project, 0, "One").await;
+    let edit = |subtitle: i32, text: &'static str| {
+        let server = &server;
+        async move {
+            let (status, _) = server
+                .send_json(
+                    Method::Patch,
+                    &format!("/api/project/{}/subtitle/{}", project, subtitle),
+                    json!({ "text": text }),
+                )
+                .await;
+            assert_eq!(status, Status::Ok);
+        }
+    };
+    edit(first, "Uno").await;
+
+    server.login("bob").await;
+    // Bob has nothing of his own to undo
+    assert_eq!(post(&server, project, "undo").await, Status::NotFound);
+    edit(first, "Eins").await;
+
+    server.login("alice").await;
+    assert_eq!(post(&server, project, "undo").await, Status::Conflict);
+    assert_eq!(texts(&server, project).await, ["Eins"]);
+
+    // Once Bob takes his change back, Alice's undo goes through
+    server.login("bob").await;
+    assert_eq!(post(&server, project, "undo").await, Status::Ok);
+    server.login("alice").await;
+    assert_eq!(post(&server, project, "undo").await, Status::Ok);
+    assert_eq!(texts(&server, project).await, ["One"]);
+}
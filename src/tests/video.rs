@@ -0,0 +1,20 @@
+//! Replacing the video of a project
+
+use crate::remap_time;
+
+#[test]
+fn remap_time_scales_then_moves() {
+    assert_eq!(remap_time(1000, 1.0, 0), 1000);
+    assert_eq!(remap_time(1000, 25.0 / 24.0, 0), 1042);
+    assert_eq!(remap_time(1000, 1.0, 500), 1500);
+    assert_eq!(remap_time(1000, 2.0, -500), 1500);
+}
+
+#[test]
+fn remap_time_clamps_instead_of_overflowing() {
+    assert_eq!(remap_time(1000, 1.0, -5000), 0);
+    assert_eq!(remap_time(i32::MAX, 1.0, i32::MAX), i32::MAX);
+    assert_eq!(remap_time(2_000_000_000, 1e300, 0), i32::MAX);
+    assert_eq!(remap_time(i32::MAX, f64::MAX, i32::MAX), i32::MAX);
+    assert_eq!(remap_time(1000, 1e-300, i32::MIN), 0);
+}
@@ -0,0 +1,86 @@
+//! Workspace and project lists
+
+use rocket::http::{Method, Status};
+use rocket::serde::json::{json, Value};
+
+use super::TestServer;
+use crate::page_size;
+
+fn ids(list: &Value) -> Vec<i64> {
+    list.as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["id"].as_i64().unwrap())
+        .collect()
+}
+
+#[test]
+fn page_sizes_are_clamped() {
+    assert_eq!(page_size(None), 50);
+    assert_eq!(page_size(Some(10)), 10);
+    assert_eq!(page_size(Some(0)), 1);
+    assert_eq!(page_size(Some(-5)), 1);
+    assert_eq!(page_size(Some(1000)), 200);
+}
+
+#[rocket::async_test]
+async fn workspaces_are_listed_a_page_at_a_time() {
+    let server = TestServer::new().await;
+    let alice = server.register("alice").await;
+    let mut workspaces = Vec::new();
+    for _ in 0..3 {
+        workspaces.push(server.create_project(alice).await.0 as i64);
+    }
+
+    let first = server.get_json("/api/workspace/list?limit=2").await;
+    assert_eq!(ids(&first), workspaces[..2]);
+    assert_eq!(first[0]["projects"], 1);
+    let second = server
+        .get_json(&format!(
+            "/api/workspace/list?limit=2&after={}",
+            workspaces[1]
+        ))
+        .await;
+    assert_eq!(ids(&second), workspaces[2..]);
+    let all = server.get_json("/api/workspace/list").await;
+    assert_eq!(ids(&all), workspaces);
+}
+
+#[rocket::async_test]
+async fn projects_are_listed_a_page_at_a_time() {
+    let server = TestServer::new().await;
+    let alice = server.register("alice").await;
+    let (workspace, first) = server.create_project(alice).await;
+    let mut projects = vec![first as i64];
+    for name in ["Two", "Three", "Four"] {
+        let (status, id) = server
+            .send_json(
+                Method::Post,
+                "/api/project/create",
+                json!({ "name": name, "workspace": workspace }),
+            )
+            .await;
+        assert_eq!(status, Status::Ok);
+        projects.push(id.as_i64().unwrap());
+    }
+
+    let list = server.get_json("/api/workspace/list").await;
+    assert_eq!(list[0]["projects"], 4);
+
+    let path = format!("/api/workspace/{}/projects", workspace);
+    let page = server.get_json(&format!("{}?limit=3", path)).await;
+    assert_eq!(ids(&page), projects[..3]);
+    let page = server
+        .get_json(&format!("{}?limit=3&after={}", path, projects[2]))
+        .await;
+    assert_eq!(ids(&page), projects[3..]);
+    let page = server
+        .get_json(&format!("{}?after={}", path, projects[3]))
+        .await;
+    assert_eq!(ids(&page), Vec::<i64>::new());
+
+    // Other users can't page through them
+    server.register("bob").await;
+    let response = server.client.get(path).dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+}
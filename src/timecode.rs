@@ -0,0 +1,324 @@
+//! Frame rates and SMPTE timecodes. Subtitle times are stored in milliseconds, projects with a
+//! frame rate can snap them to frames and show them as `HH:MM:SS:FF`.
+
+/// Largest denominator of a frame rate, the one of NTSC rates like 30000/1001
+pub const MAX_DENOMINATOR: i32 = 1001;
+
+fn gcd(a: i32, b: i32) -> i32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Frames per second as a fraction, like 30000/1001 for 29.97
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameRate {
+    pub num: i32,
+    pub den: i32,
+}
+
+impl FrameRate {
+    /// Parse a frame rate like "25", "29.97" or "30000/1001". The usual NTSC rates written as
+    /// decimals, like 23.976, are read as their exact fraction.
+    pub fn parse(rate: &str) -> Result<FrameRate, &'static str> {
+        let rate = rate.trim();
+        if let Some((num, den)) = rate.split_once('/') {
+            let num: i32 = num.trim().parse().map_err(|_| "Invalid frame rate")?;
+            let den: i32 = den.trim().parse().map_err(|_| "Invalid frame rate")?;
+            return FrameRate::new(num, den);
+        }
+
+        let fps: f64 = rate.parse().map_err(|_| "Invalid frame rate")?;
+        let whole = fps.round();
+        if (fps - whole).abs() < 0.001 {
+            return FrameRate::new(whole as i32, 1);
+        }
+        let ntsc = (fps * 1001.0 / 1000.0).round();
+        if (fps - ntsc * 1000.0 / 1001.0).abs() < 0.01 {
+            return FrameRate::new(ntsc as i32 * 1000, 1001);
+        }
+        Err("Frame rates have to be whole numbers, NTSC rates like 29.97, or fractions")
+    }
+
+    /// A frame rate from a fraction, which is reduced first. Rates have to be between 1 and 1000
+    /// frames per second, with a denominator of at most `MAX_DENOMINATOR`, which keeps the
+    /// frame arithmetic well within an `i64`.
+    pub fn new(num: i32, den: i32) -> Result<FrameRate, &'static str> {
+        if num <= 0 || den <= 0 {
+            return Err("Invalid frame rate");
+        }
+        let divisor = gcd(num, den);
+        let (num, den) = (num / divisor, den / divisor);
+        if den > MAX_DENOMINATOR || num < den || num / den > 1000 {
+            return Err("Invalid frame rate");
+        }
+        Ok(FrameRate { num, den })
+    }
+
+    pub fn fps(&self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+
+    /// Frames counted per second in timecodes, like 30 for 29.97
+    fn nominal(&self) -> i64 {
+        (self.num as i64 + self.den as i64 / 2) / self.den as i64
+    }
+
+    /// Drop-frame timecode only exists for 29.97 and 59.94
+    pub fn supports_drop_frame(&self) -> bool {
+        self.den == 1001 && (self.num == 30000 || self.num == 60000)
+    }
+
+    /// The frame that is showing at a time, rounded to the nearest frame boundary
+    pub fn frame_at(&self, ms: i32) -> i64 {
+        let (num, den) = (self.num as i64, self.den as i64);
+        (ms as i64 * num * 2 + 1000 * den) / (2000 * den)
+    }
+
+    /// Start time of a frame, rounded to the millisecond. Frames past the end of the time
+    /// range start at its end.
+    pub fn frame_start(&self, frame: i64) -> i32 {
+        i32::try_from(self.frame_start_ms(frame)).unwrap_or(i32::MAX)
+    }
+
+    fn frame_start_ms(&self, frame: i64) -> i64 {
+        let (num, den) = (self.num as i64, self.den as i64);
+        (frame * 1000 * den * 2 + num) / (2 * num)
+    }
+
+    /// Move a time to the nearest frame boundary
+    pub fn snap(&self, ms: i32) -> i32 {
+        self.frame_start(self.frame_at(ms.max(0)))
+    }
+
+    /// Snap both ends of a cue, keeping it at least a frame long
+    pub fn snap_cue(&self, start: i32, end: i32) -> (i32, i32) {
+        let start_frame = self.frame_at(start.max(0));
+        let end_frame = self.frame_at(end.max(0)).max(start_frame + 1);
+        (self.frame_start(start_frame), self.frame_start(end_frame))
+    }
+}
+
+/// How a project writes timecodes
+#[derive(Debug, Clone, Copy)]
+pub struct Timecode {
+    pub rate: FrameRate,
+    /// Skip frame numbers 0 and 1 (0 to 3 at 59.94) every minute except every tenth, so the
+    /// timecode keeps up with the clock. Written with `;` before the frames.
+    pub drop_frame: bool,
+}
+
+impl Timecode {
+    /// Frame numbers skipped at the start of a minute
+    fn dropped_per_minute(&self) -> i64 {
+        if self.drop_frame {
+            self.rate.nominal() / 15
+        } else {
+            0
+        }
+    }
+
+    /// Format a time as `HH:MM:SS:FF`, or `HH:MM:SS;FF` for drop-frame
+    pub fn format(&self, ms: i32) -> String {
+        let nominal = self.rate.nominal();
+        let mut frame = self.rate.frame_at(ms.max(0));
+
+        let dropped = self.dropped_per_minute();
+        if dropped > 0 {
+            let per_ten_minutes = nominal * 600 - dropped * 9;
+            let per_minute = nominal * 60 - dropped;
+            let tens = frame / per_ten_minutes;
+            let rest = frame % per_ten_minutes;
+            frame += dropped * 9 * tens;
+            if rest > dropped {
+                frame += dropped * ((rest - dropped) / per_minute);
+            }
+        }
+
+        format!(
+            "{:02}:{:02}:{:02}{}{:02}",
+            frame / (nominal * 3600),
+            frame / (nominal * 60) % 60,
+            frame / nominal % 60,
+            if self.drop_frame { ';' } else { ':' },
+            frame % nominal
+        )
+    }
+
+    /// Parse `HH:MM:SS:FF` to milliseconds. Either separator is accepted before the frames.
+    pub fn parse(&self, timecode: &str) -> Result<i32, &'static str> {
+        let invalid = "Timecodes have to look like HH:MM:SS:FF";
+        let parts: Vec<i64> = timecode
+            .trim()
+            .split([':', ';', '.'])
+            .map(|part| part.parse::<i64>().map_err(|_| invalid))
+            .collect::<Result<_, _>>()?;
+        let (hours, minutes, seconds, frames) = match parts[..] {
+            [hours, minutes, seconds, frames] => (hours, minutes, seconds, frames),
+            _ => return Err(invalid),
+        };
+
+        let nominal = self.rate.nominal();
+        if !(0..24).contains(&hours) || !(0..60).contains(&minutes) || !(0..60).contains(&seconds) {
+            return Err(invalid);
+        }
+        if !(0..nominal).contains(&frames) {
+            return Err("Frame number is too high for the frame rate");
+        }
+        let dropped = self.dropped_per_minute();
+        if seconds == 0 && minutes % 10 != 0 && frames < dropped {
+            return Err("That frame number is skipped in drop-frame timecode");
+        }
+
+        let total_minutes = hours * 60 + minutes;
+        let frame = (hours * 3600 + minutes * 60 + seconds) * nominal + frames
+            - dropped * (total_minutes - total_minutes / 10);
+        i32::try_from(self.rate.frame_start_ms(frame)).map_err(|_| "Timecode is too long")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timecode(rate: &str, drop_frame: bool) -> Timecode {
+        Timecode {
+            rate: FrameRate::parse(rate).unwrap(),
+            drop_frame,
+        }
+    }
+
+    /// Every frame of the first eleven minutes, and a few around the hour, format to a timecode
+    /// that parses back to the same frame
+    fn assert_round_trips(timecode: &Timecode) {
+        let hour = timecode.rate.frame_at(3_600_000);
+        let frames = (0..timecode.rate.frame_at(660_000)).chain(hour - 100..hour + 100);
+        for frame in frames {
+            let ms = timecode.rate.frame_start(frame);
+            let formatted = timecode.format(ms);
+            assert_eq!(timecode.parse(&formatted), Ok(ms), "frame {}", frame);
+        }
+    }
+
+    #[test]
+    fn drop_frame_29_97() {
+        let timecode = timecode("29.97", true);
+        assert_round_trips(&timecode);
+
+        let format = |frame| timecode.format(timecode.rate.frame_start(frame));
+        assert_eq!(format(0), "00:00:00;00");
+        assert_eq!(format(1799), "00:00:59;29");
+        assert_eq!(format(1800), "00:01:00;02");
+        assert_eq!(format(3597), "00:01:59;29");
+        assert_eq!(format(3598), "00:02:00;02");
+        assert_eq!(format(17981), "00:09:59;29");
+        assert_eq!(format(17982), "00:10:00;00");
+        assert_eq!(format(19782), "00:11:00;02");
+        assert_eq!(format(107892), "01:00:00;00");
+
+        assert_eq!(timecode.parse("00:01:00;02"), Ok(60060));
+        // Drop-frame stays within a millisecond of the clock
+        assert_eq!(timecode.parse("00:10:00;00"), Ok(599999));
+        assert_eq!(timecode.parse("01:00:00;00"), Ok(3599996));
+        assert!(timecode.parse("00:01:00;00").is_err());
+        assert!(timecode.parse("00:01:00;01").is_err());
+        assert!(timecode.parse("00:00:59;30").is_err());
+    }
+
+    #[test]
+    fn drop_frame_59_94() {
+        let timecode = timecode("60000/1001", true);
+        assert!(timecode.rate.supports_drop_frame());
+        assert_round_trips(&timecode);
+
+        let format = |frame| timecode.format(timecode.rate.frame_start(frame));
+        assert_eq!(format(3599), "00:00:59;59");
+        assert_eq!(format(3600), "00:01:00;04");
+        assert_eq!(format(7195), "00:01:59;59");
+        assert_eq!(format(7196), "00:02:00;04");
+        assert_eq!(format(35963), "00:09:59;59");
+        assert_eq!(format(35964), "00:10:00;00");
+        assert_eq!(format(215784), "01:00:00;00");
+
+        assert_eq!(timecode.parse("00:01:00;04"), Ok(60060));
+        assert_eq!(timecode.parse("00:10:00;00"), Ok(599999));
+        assert!(timecode.parse("00:02:00;03").is_err());
+        assert!(timecode.parse("00:20:00;00").is_ok());
+    }
+
+    #[test]
+    fn non_drop_frame_29_97() {
+        let timecode = timecode("30000/1001", false);
+        assert_round_trips(&timecode);
+
+        // The timecode falls behind the clock by 3.6 seconds an hour
+        assert_eq!(timecode.format(60060), "00:01:00:00");
+        assert_eq!(timecode.parse("00:01:00:00"), Ok(60060));
+        assert_eq!(timecode.format(3_600_000), "00:59:56:12");
+        // Frames 0 and 1 exist at every minute
+        assert_eq!(timecode.parse("00:01:00:01"), Ok(60093));
+    }
+
+    #[test]
+    fn ntsc_23_976() {
+        let timecode = timecode("23.976", false);
+        assert_eq!(
+            timecode.rate,
+            FrameRate {
+                num: 24000,
+                den: 1001
+            }
+        );
+        assert!(!timecode.rate.supports_drop_frame());
+        assert_round_trips(&timecode);
+
+        assert_eq!(timecode.format(1001), "00:00:01:00");
+        assert_eq!(timecode.format(59017), "00:00:58:23");
+        assert_eq!(timecode.format(60060), "00:01:00:00");
+        assert_eq!(timecode.parse("00:00:01:00"), Ok(1001));
+        assert_eq!(timecode.parse("00:10:00:00"), Ok(600600));
+        assert!(timecode.parse("00:00:00:24").is_err());
+    }
+
+    #[test]
+    fn frame_rates_are_bounded() {
+        assert_eq!(FrameRate::new(50, 2), Ok(FrameRate { num: 25, den: 1 }));
+        assert_eq!(
+            FrameRate::new(i32::MAX, i32::MAX),
+            Ok(FrameRate { num: 1, den: 1 })
+        );
+        assert!(FrameRate::new(30000, 1001).is_ok());
+        assert!(FrameRate::new(i32::MAX, i32::MAX - 1).is_err());
+        assert!(FrameRate::new(1000, 1002).is_err());
+        assert!(FrameRate::new(1, 2).is_err());
+        assert!(FrameRate::new(1001, 1).is_err());
+        assert!(FrameRate::new(0, 1).is_err());
+        assert!(FrameRate::new(25, -1).is_err());
+        assert!(FrameRate::parse("2147483647/2147483646").is_err());
+
+        // The extremes don't overflow at the end of the time range
+        for rate in [
+            FrameRate::new(1000 * MAX_DENOMINATOR, MAX_DENOMINATOR).unwrap(),
+            FrameRate::new(MAX_DENOMINATOR, MAX_DENOMINATOR - 1).unwrap(),
+            FrameRate::new(1, 1).unwrap(),
+        ] {
+            let timecode = Timecode {
+                rate,
+                drop_frame: false,
+            };
+            assert!(rate.snap(i32::MAX) > 0);
+            assert!(!timecode.format(i32::MAX).is_empty());
+        }
+    }
+
+    #[test]
+    fn format_rounds_to_the_nearest_frame() {
+        let timecode = timecode("25", false);
+        assert_eq!(timecode.format(19), "00:00:00:00");
+        assert_eq!(timecode.format(20), "00:00:00:01");
+        assert_eq!(timecode.format(61_000), "00:01:01:00");
+        assert_eq!(timecode.parse("00:01:01:00"), Ok(61_000));
+    }
+}
@@ -0,0 +1,215 @@
+//! Speech-to-text for draft subtitles. The `transcriber` table in Rocket.toml, or
+//! `ROCKET_TRANSCRIBER={kind="command",program="...",model="..."}`, picks the implementation.
+//! Without it, projects can't be transcribed.
+
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+
+use rocket::serde::Deserialize;
+
+/// A cue as heard by a transcriber, in milliseconds
+#[derive(Debug, Clone)]
+pub struct TranscribedCue {
+    pub start: i32,
+    pub end: i32,
+    pub text: String,
+    /// From 0 to 1, if the transcriber knows
+    pub confidence: Option<f64>,
+}
+
+pub struct TranscriptionRequest<'a> {
+    /// 16 kHz mono WAV file
+    pub audio: &'a Path,
+    /// Language code of the speech, like `en`. Transcribers that can detect it get `None`
+    /// when it's not known.
+    pub language: Option<&'a str>,
+    /// Milliseconds, if known
+    pub duration: Option<i32>,
+}
+
+pub trait Transcriber: Send + Sync {
+    /// Turn speech into cues, calling `progress` with a percentage now and then. This blocks
+    /// until it's done, so call it inside `block_in_place`.
+    fn transcribe(
+        &self,
+        request: &TranscriptionRequest,
+        progress: &mut dyn FnMut(u8),
+    ) -> Result<Vec<TranscribedCue>, String>;
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", tag = "kind", rename_all = "lowercase")]
+pub enum TranscriberConfig {
+    /// whisper.cpp's `whisper-cli`, or a program that takes the same arguments and writes the
+    /// same JSON
+    Command {
+        program: PathBuf,
+        /// Path of the model file, passed with `-m`
+        model: PathBuf,
+        /// More arguments, like `["-t", "8"]` for the number of threads
+        #[serde(default)]
+        args: Vec<String>,
+    },
+    /// Made-up cues, for trying out the rest of the server without a speech model
+    Fake,
+}
+
+impl TranscriberConfig {
+    pub fn build(self) -> Arc<dyn Transcriber> {
+        match self {
+            TranscriberConfig::Command {
+                program,
+                model,
+                args,
+            } => Arc::new(CommandTranscriber {
+                program,
+                model,
+                args,
+            }),
+            TranscriberConfig::Fake => Arc::new(FakeTranscriber),
+        }
+    }
+}
+
+/// Runs whisper.cpp, and reads the JSON file it writes next to the audio
+pub struct CommandTranscriber {
+    program: PathBuf,
+    model: PathBuf,
+    args: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct WhisperOutput {
+    transcription: Vec<WhisperSegment>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct WhisperSegment {
+    /// Milliseconds
+    offsets: WhisperOffsets,
+    text: String,
+    /// Only written with `--output-json-full`
+    #[serde(default)]
+    tokens: Vec<WhisperToken>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct WhisperOffsets {
+    from: i32,
+    to: i32,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct WhisperToken {
+    text: String,
+    /// Probability of the token
+    p: f64,
+}
+
+impl Transcriber for CommandTranscriber {
+    fn transcribe(
+        &self,
+        request: &TranscriptionRequest,
+        progress: &mut dyn FnMut(u8),
+    ) -> Result<Vec<TranscribedCue>, String> {
+        let output_base = request.audio.with_extension("");
+        let mut child = Command::new(&self.program)
+            .arg("-m")
+            .arg(&self.model)
+            .arg("-f")
+            .arg(request.audio)
+            .arg("-of")
+            .arg(&output_base)
+            .args(["--output-json-full", "--print-progress", "-l"])
+            .arg(request.language.unwrap_or("auto"))
+            .args(&self.args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("could not run {}: {}", self.program.display(), e))?;
+
+        // Progress is logged like "whisper_print_progress_callback: progress =  40%"
+        let mut last_line = String::new();
+        for line in BufReader::new(child.stderr.take().unwrap()).lines() {
+            let line = line.map_err(|e| e.to_string())?;
+            if let Some((_, percent)) = line.split_once("progress =") {
+                if let Ok(percent) = percent.trim().trim_end_matches('%').parse() {
+                    progress(percent);
+                }
+            }
+            if !line.trim().is_empty() {
+                last_line = line;
+            }
+        }
+        let status = child.wait().map_err(|e| e.to_string())?;
+        if !status.success() {
+            return Err(last_line.trim().to_string());
+        }
+
+        let json_path = output_base.with_extension("json");
+        let json = std::fs::read(&json_path).map_err(|e| format!("no output: {}", e))?;
+        let _ = std::fs::remove_file(&json_path);
+        let output: WhisperOutput = rocket::serde::json::from_slice(&json)
+            .map_err(|e| format!("unexpected output: {}", e))?;
+
+        Ok(output
+            .transcription
+            .into_iter()
+            .filter(|segment| !segment.text.trim().is_empty())
+            .map(|segment| {
+                // Special tokens like [_BEG_] don't say anything about the text
+                let probabilities: Vec<f64> = segment
+                    .tokens
+                    .iter()
+                    .filter(|token| !token.text.starts_with("[_"))
+                    .map(|token| token.p)
+                    .collect();
+                let confidence = (!probabilities.is_empty())
+                    .then(|| probabilities.iter().sum::<f64>() / probabilities.len() as f64);
+                TranscribedCue {
+                    start: segment.offsets.from,
+                    end: segment.offsets.to,
+                    text: segment.text.trim().to_string(),
+                    confidence,
+                }
+            })
+            .collect())
+    }
+}
+
+/// A numbered cue every four seconds, without listening to the audio
+pub struct FakeTranscriber;
+
+impl Transcriber for FakeTranscriber {
+    fn transcribe(
+        &self,
+        request: &TranscriptionRequest,
+        progress: &mut dyn FnMut(u8),
+    ) -> Result<Vec<TranscribedCue>, String> {
+        let duration = request.duration.unwrap_or(60_000);
+        let count = (duration / 4000).max(1);
+        let cues = (0..count)
+            .map(|index| {
+                progress((index * 100 / count) as u8);
+                TranscribedCue {
+                    start: index * 4000,
+                    end: index * 4000 + 3000,
+                    text: format!(
+                        "Transcribed cue {} ({})",
+                        index + 1,
+                        request.language.unwrap_or("unknown language")
+                    ),
+                    confidence: Some(0.5 + (index % 5) as f64 / 10.0),
+                }
+            })
+            .collect();
+        progress(100);
+        Ok(cues)
+    }
+}
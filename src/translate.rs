@@ -0,0 +1,210 @@
+//! Machine translation of subtitles. The `translator` table in Rocket.toml, or
+//! `ROCKET_TRANSLATOR={kind="http",url="..."}`, picks the implementation. Without it, projects
+//! can't be translated.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+
+use rocket::serde::json::serde_json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::tokio::task;
+
+#[rocket::async_trait]
+pub trait Translator: Send + Sync {
+    /// Translate texts into the `target` language, keeping their order. Without a `source`
+    /// language, the translator detects it.
+    async fn translate(
+        &self,
+        texts: &[String],
+        source: Option<&str>,
+        target: &str,
+    ) -> Result<Vec<String>, String>;
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", tag = "kind", rename_all = "lowercase")]
+pub enum TranslatorConfig {
+    /// A LibreTranslate server, or another service with the same `/translate` API
+    Http {
+        /// Like `http://localhost:5000`
+        url: String,
+        api_key: Option<String>,
+    },
+    /// A program that reads a JSON array of texts on stdin and writes a JSON array of their
+    /// translations to stdout. `{source}` and `{target}` in the arguments are replaced by the
+    /// language codes, with `auto` for an unknown source.
+    Command {
+        program: PathBuf,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+    /// Marks texts with the target language instead of translating them, for trying out the
+    /// rest of the server
+    Fake,
+}
+
+impl TranslatorConfig {
+    pub fn build(self) -> Arc<dyn Translator> {
+        match self {
+            TranslatorConfig::Http { url, api_key } => Arc::new(HttpTranslator {
+                url: format!("{}/translate", url.trim_end_matches('/')),
+                api_key,
+                client: reqwest::Client::new(),
+            }),
+            TranslatorConfig::Command { program, args } => {
+                Arc::new(CommandTranslator { program, args })
+            }
+            TranslatorConfig::Fake => Arc::new(FakeTranslator),
+        }
+    }
+}
+
+pub struct HttpTranslator {
+    url: String,
+    api_key: Option<String>,
+    client: reqwest::Client,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct TranslateRequest<'a> {
+    q: &'a [String],
+    source: &'a str,
+    target: &'a str,
+    format: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    api_key: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+struct TranslateResponse {
+    translated_text: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct TranslateError {
+    error: String,
+}
+
+/// Texts per request, so a long project doesn't run into the server's limits
+const HTTP_BATCH_SIZE: usize = 50;
+
+#[rocket::async_trait]
+impl Translator for HttpTranslator {
+    async fn translate(
+        &self,
+        texts: &[String],
+        source: Option<&str>,
+        target: &str,
+    ) -> Result<Vec<String>, String> {
+        let mut translations = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(HTTP_BATCH_SIZE) {
+            let request = TranslateRequest {
+                q: batch,
+                source: source.unwrap_or("auto"),
+                target,
+                format: "text",
+                api_key: self.api_key.as_deref(),
+            };
+            let response = self
+                .client
+                .post(&self.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(serde_json::to_string(&request).map_err(|e| e.to_string())?)
+                .send()
+                .await
+                .map_err(|e| format!("could not reach {}: {}", self.url, e))?;
+            let status = response.status();
+            let body = response.text().await.map_err(|e| e.to_string())?;
+            if !status.is_success() {
+                let error = serde_json::from_str::<TranslateError>(&body)
+                    .map(|error| error.error)
+                    .unwrap_or(body);
+                return Err(format!("{}: {}", status, error));
+            }
+
+            let response: TranslateResponse =
+                serde_json::from_str(&body).map_err(|e| format!("unexpected response: {}", e))?;
+            translations.extend(response.translated_text);
+        }
+        Ok(translations)
+    }
+}
+
+pub struct CommandTranslator {
+    program: PathBuf,
+    args: Vec<String>,
+}
+
+#[rocket::async_trait]
+impl Translator for CommandTranslator {
+    async fn translate(
+        &self,
+        texts: &[String],
+        source: Option<&str>,
+        target: &str,
+    ) -> Result<Vec<String>, String> {
+        let input = serde_json::to_vec(texts).map_err(|e| e.to_string())?;
+        let program = self.program.clone();
+        let args: Vec<String> = self
+            .args
+            .iter()
+            .map(|arg| {
+                arg.replace("{source}", source.unwrap_or("auto"))
+                    .replace("{target}", target)
+            })
+            .collect();
+
+        let (output, written) = task::spawn_blocking(move || {
+            let mut child = Command::new(&program)
+                .args(&args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(|e| format!("could not run {}: {}", program.display(), e))?;
+            // Writing on another thread, since a program that answers while it reads would
+            // block on a full stdout while this blocks on a full stdin
+            let mut stdin = child.stdin.take().unwrap();
+            let writer = thread::spawn(move || stdin.write_all(&input));
+            let output = child.wait_with_output().map_err(|e| e.to_string())?;
+            let written = writer
+                .join()
+                .map_err(|_| "could not write to the program".to_string())?;
+            Ok::<_, String>((output, written))
+        })
+        .await
+        .map_err(|e| e.to_string())??;
+        // A program that fails without reading everything says more about why than the
+        // broken pipe does
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+        }
+        written.map_err(|e| e.to_string())?;
+
+        serde_json::from_slice(&output.stdout).map_err(|e| format!("unexpected output: {}", e))
+    }
+}
+
+/// Puts the target language in front of every text
+pub struct FakeTranslator;
+
+#[rocket::async_trait]
+impl Translator for FakeTranslator {
+    async fn translate(
+        &self,
+        texts: &[String],
+        _source: Option<&str>,
+        target: &str,
+    ) -> Result<Vec<String>, String> {
+        Ok(texts
+            .iter()
+            .map(|text| format!("[{}] {}", target, text))
+            .collect())
+    }
+}